@@ -1,13 +1,31 @@
 //! HardClaw CLI - Command line interface for the HardClaw protocol
 
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
 
 use hardclaw::{
-    crypto::{Keypair, hash_data},
+    crypto::{Hash, Keypair, hash_data},
+    state::ChainState,
     types::{Address, JobPacket, JobType, HclawAmount, VerificationSpec},
 };
 
+/// Data directory of a node started without `--data-dir`
+const DEFAULT_DATA_DIR: &str = ".hardclaw";
+
 fn main() {
+    // The node's data directory, for reading its chain store
+    let mut data_dir = PathBuf::from(DEFAULT_DATA_DIR);
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("-d" | "--data-dir", Some(path)) => data_dir = PathBuf::from(path),
+            _ => {
+                eprintln!("Usage: hardclaw-cli [-d|--data-dir <PATH>]");
+                return;
+            }
+        }
+    }
+
     println!("╔════════════════════════════════════════════╗");
     println!("║       HardClaw CLI v{}             ║", hardclaw::VERSION);
     println!("║   Proof-of-Verification Protocol          ║");
@@ -24,7 +42,7 @@ fn main() {
     println!("  keygen          - Generate a new keypair");
     println!("  balance <addr>  - Check account balance");
     println!("  submit <job>    - Submit a job");
-    println!("  status <id>     - Check job status");
+    println!("  status <id>     - Check job or payment status");
     println!("  verify <id>     - Verify a solution");
     println!("  help            - Show this help");
     println!("  quit            - Exit");
@@ -127,10 +145,13 @@ fn main() {
 
             "status" => {
                 if parts.len() < 2 {
-                    println!("Usage: status <job_id>");
+                    println!("Usage: status <job_or_tx_id>");
                     continue;
                 }
-                println!("Job {} status: Unknown (not connected to network)", parts[1]);
+                match Hash::from_hex(parts[1]) {
+                    Ok(id) => print_status(&data_dir, &id),
+                    Err(e) => println!("Invalid ID: {e}"),
+                }
             }
 
             "verify" => {
@@ -146,7 +167,7 @@ fn main() {
                 println!("  keygen          - Generate a new keypair");
                 println!("  balance <addr>  - Check account balance");
                 println!("  submit          - Submit a job interactively");
-                println!("  status <id>     - Check job status");
+                println!("  status <id>     - Check job or payment status");
                 println!("  verify <id>     - Verify a solution");
                 println!("  help            - Show this help");
                 println!("  quit            - Exit");
@@ -165,3 +186,26 @@ fn main() {
         println!();
     }
 }
+
+/// Report a job or transaction from the local node's chain store
+///
+/// Reads a copy of the store, so the node may keep running.
+fn print_status(data_dir: &Path, id: &Hash) {
+    let chain_dir = data_dir.join("chain");
+    let state = match ChainState::open_read_only(&chain_dir) {
+        Ok(state) => state,
+        Err(e) => {
+            println!("Cannot open chain store at {}: {e}", chain_dir.display());
+            return;
+        }
+    };
+
+    if let Some(job) = state.get_job(id) {
+        println!("Job {}: {:?}", id, job.status);
+    }
+    let confirmation = state.confirmation(id);
+    println!("Confirmation: {confirmation}");
+    if !confirmation.is_final() {
+        println!("Finalized height: {} (wait for it to pass before acting)", state.finalized_height());
+    }
+}
@@ -3,17 +3,21 @@
 //! Verifiers assemble blocks by:
 //! 1. Pulling solutions from the mempool
 //! 2. Running verification on each
-//! 3. Creating a block with verified solutions
+//! 3. Creating a block with pending transactions and verified solutions
 //! 4. Broadcasting for attestations
 
 use std::collections::VecDeque;
+use std::sync::Arc;
+
+use serde::Serialize;
 
 use crate::crypto::{Hash, Keypair};
 use crate::types::{
-    Block, JobPacket, SolutionCandidate, VerificationResult, HclawAmount,
+    now_millis, Block, BlockError, EpochCommitment, JobPacket, SolutionCandidate, Timestamp, Transaction,
+    VerificationKind, VerificationResult, VerifierAttestation, HclawAmount,
 };
 
-use super::{ConsensusError, ProofOfVerification};
+use super::{ConsensusError, ProofOfVerification, SolutionVerifier, Verdict, VerifierRegistry};
 
 /// Configuration for block production
 #[derive(Clone, Debug)]
@@ -39,6 +43,26 @@ impl Default for BlockProducerConfig {
     }
 }
 
+impl BlockProducerConfig {
+    /// Check a block against the size and solution limits
+    ///
+    /// # Errors
+    /// Returns error if the block carries too many verifications or is too large
+    pub fn check_limits(&self, block: &Block) -> Result<(), BlockError> {
+        if block.verifications.len() > self.max_solutions_per_block {
+            return Err(BlockError::TooManySolutions {
+                count: block.verifications.len(),
+                limit: self.max_solutions_per_block,
+            });
+        }
+        let size = block.size();
+        if size > self.max_block_size {
+            return Err(BlockError::TooLarge { size, limit: self.max_block_size });
+        }
+        Ok(())
+    }
+}
+
 /// Block producer (verifier/miner role)
 pub struct BlockProducer {
     /// Configuration
@@ -47,12 +71,15 @@ pub struct BlockProducer {
     keypair: Keypair,
     /// PoV consensus engine
     pov: ProofOfVerification,
-    /// Pending verifications for current block
-    pending_verifications: VecDeque<VerificationResult>,
+    /// Pending verifications for current block, each with the solution it
+    /// settles
+    pending_verifications: VecDeque<(VerificationResult, SolutionCandidate)>,
     /// Current chain height
     current_height: u64,
     /// Current parent hash
     current_parent: Hash,
+    /// Attestation round the next block is proposed in
+    current_round: u32,
 }
 
 impl BlockProducer {
@@ -66,6 +93,7 @@ impl BlockProducer {
             pending_verifications: VecDeque::new(),
             current_height: 0,
             current_parent: Hash::ZERO,
+            current_round: 0,
         }
     }
 
@@ -75,22 +103,57 @@ impl BlockProducer {
         self.current_parent = parent_hash;
     }
 
+    /// Set the attestation round for the next block
+    ///
+    /// A proposer must never sign two different blocks in the same height
+    /// and round.
+    pub const fn set_round(&mut self, round: u32) {
+        self.current_round = round;
+    }
+
+    /// Check specs of `kind` with `backend` from now on
+    ///
+    /// Returns the backend it replaces, if any.
+    pub fn register_verifier(
+        &mut self,
+        kind: VerificationKind,
+        backend: Box<dyn SolutionVerifier>,
+    ) -> Option<Arc<dyn SolutionVerifier>> {
+        self.pov.register_verifier(kind, backend)
+    }
+
     /// Process a solution candidate
     ///
-    /// Returns the verification result if successful.
+    /// Returns the verification result if successful. Failed results are
+    /// queued too, so the block carrying them rejects the solution.
     pub fn verify_solution(
         &mut self,
         job: &JobPacket,
         solution: &SolutionCandidate,
     ) -> Result<VerificationResult, ConsensusError> {
         let result = self.pov.verify_solution(job, solution, &self.keypair)?;
+        self.pending_verifications.push_back((result.clone(), solution.clone()));
+        Ok(result)
+    }
 
-        // Only add passed verifications to pending
-        if result.passed {
-            self.pending_verifications.push_back(result.clone());
-        }
+    /// Backends checking each kind of spec
+    #[must_use]
+    pub const fn verifiers(&self) -> &VerifierRegistry {
+        self.pov.verifiers()
+    }
 
-        Ok(result)
+    /// Sign a verdict reached off this thread into a result, queuing it for
+    /// the next block whether it passed or not
+    pub fn record_verdict(
+        &mut self,
+        job: &JobPacket,
+        solution: &SolutionCandidate,
+        verdict: Verdict,
+        verification_time_ms: u64,
+    ) -> VerificationResult {
+        let result = self.pov.record_verdict(job, solution, verdict, verification_time_ms, &self.keypair);
+        self.pending_verifications.push_back((result.clone(), solution.clone()));
+        result
     }
 
     /// Check if we should produce a block
@@ -101,40 +164,128 @@ impl BlockProducer {
 
     /// Produce a new block from pending verifications
     pub fn produce_block(&mut self, state_root: Hash) -> Result<Block, ConsensusError> {
-        if self.pending_verifications.is_empty() {
+        self.produce_block_with(Vec::new(), Vec::new(), None, |_| Ok(state_root))
+    }
+
+    /// Produce a new block, computing its state root from the selected body
+    ///
+    /// `transactions` are included ahead of the verifications, in order, and
+    /// `parent_attestations` are carried for liveness accounting. Items are
+    /// added by encoded size until the next one would not fit in
+    /// `max_block_size`; the rest stay queued or with the caller. A block
+    /// that starts an epoch must carry its `epoch` commitment.
+    /// `state_root_for` receives the unsigned block exactly as it will be
+    /// included, and must return the state root after executing it. If it
+    /// fails, the verifications are dry-run one at a time on top of the
+    /// transactions, and only those that fail to settle are dropped, so a
+    /// bad result cannot wedge production or take the rest of the batch
+    /// with it.
+    ///
+    /// # Errors
+    /// Returns error if nothing is pending, `state_root_for` fails on the
+    /// transactions alone, or nothing is left to include
+    pub fn produce_block_with<F>(
+        &mut self,
+        transactions: Vec<Transaction>,
+        parent_attestations: Vec<VerifierAttestation>,
+        epoch: Option<EpochCommitment>,
+        mut state_root_for: F,
+    ) -> Result<Block, ConsensusError>
+    where
+        F: FnMut(&Block) -> Result<Hash, ConsensusError>,
+    {
+        if transactions.is_empty() && self.pending_verifications.is_empty() {
             return Err(ConsensusError::VerificationFailed {
-                reason: "No verifications to include in block".to_string(),
+                reason: "No transactions or verifications to include in block".to_string(),
             });
         }
 
-        // Take up to max_solutions_per_block verifications
-        let mut verifications = Vec::new();
-        let mut total_size = 0;
-
-        while let Some(verification) = self.pending_verifications.pop_front() {
-            // Estimate size (rough approximation)
-            let estimated_size = 256; // Approximate size per verification
-
-            if verifications.len() >= self.config.max_solutions_per_block
-                || total_size + estimated_size > self.config.max_block_size
+        // Room for the block as it will be sent, with our own attestation
+        let timestamp = now_millis();
+        let mut empty = self
+            .draft(Vec::new(), Vec::new(), timestamp)
+            .with_parent_attestations(parent_attestations.clone())
+            .with_epoch(epoch);
+        empty.add_attestation(self.pov.create_attestation(&empty, Vec::new(), &self.keypair));
+        let mut total_size = empty.size();
+
+        // Transactions in order, while they fit
+        let mut transactions = transactions;
+        let fitting = transactions
+            .iter()
+            .take_while(|tx| {
+                let size = encoded_size(tx);
+                let fits = total_size.saturating_add(size) <= self.config.max_block_size;
+                if fits {
+                    total_size += size;
+                }
+                fits
+            })
+            .count();
+        transactions.truncate(fitting);
+
+        // Then up to max_solutions_per_block verifications
+        let mut settled = Vec::new();
+
+        while let Some((verification, solution)) = self.pending_verifications.pop_front() {
+            // The result, its solution, and its ID in our attestation
+            let size = encoded_size(&verification)
+                .saturating_add(encoded_size(&solution))
+                .saturating_add(encoded_size(&solution.id));
+
+            if settled.len() >= self.config.max_solutions_per_block
+                || total_size.saturating_add(size) > self.config.max_block_size
             {
                 // Put it back and stop
-                self.pending_verifications.push_front(verification);
+                self.pending_verifications.push_front((verification, solution));
                 break;
             }
 
-            verifications.push(verification);
-            total_size += estimated_size;
+            settled.push((verification, solution));
+            total_size += size;
         }
 
         // Create the block
-        let mut block = Block::new(
-            self.current_height + 1,
-            self.current_parent,
-            *self.keypair.public_key(),
-            verifications,
-            state_root,
-        );
+        let producer = &*self;
+        let assemble = move |settled: &[(VerificationResult, SolutionCandidate)]| {
+            let (verifications, solutions) = settled.iter().cloned().unzip();
+            producer.draft(transactions.clone(), verifications, timestamp)
+                .with_solutions(solutions)
+                .with_parent_attestations(parent_attestations.clone())
+                .with_epoch(epoch)
+        };
+        let mut block = assemble(&settled);
+        let state_root = match state_root_for(&block) {
+            Ok(root) => root,
+            Err(_) if !settled.is_empty() => {
+                // Leave out only the verifications that fail to settle,
+                // such as a second pass for a job the first one closes
+                block = assemble(&[]);
+                let mut root = state_root_for(&block)?;
+                let mut kept = Vec::new();
+                for pending in settled {
+                    kept.push(pending);
+                    let candidate = assemble(&kept);
+                    match state_root_for(&candidate) {
+                        Ok(with) => {
+                            block = candidate;
+                            root = with;
+                        }
+                        Err(_) => {
+                            kept.pop();
+                        }
+                    }
+                }
+                root
+            }
+            Err(e) => return Err(e),
+        };
+        if block.transactions.is_empty() && block.verifications.is_empty() {
+            return Err(ConsensusError::VerificationFailed {
+                reason: "No transactions or verifications left to include in block".to_string(),
+            });
+        }
+        let mut block = block.with_state_root(state_root);
 
         // Sign the block
         block.proposer_signature = self.keypair.sign(&block.signing_bytes());
@@ -155,6 +306,36 @@ impl BlockProducer {
         Ok(block)
     }
 
+    /// Unsigned block on the current parent with the given body
+    fn draft(&self, transactions: Vec<Transaction>, verifications: Vec<VerificationResult>, timestamp: Timestamp) -> Block {
+        Block::with_transactions(
+            self.current_height + 1,
+            self.current_parent,
+            *self.keypair.public_key(),
+            transactions,
+            verifications,
+            Hash::ZERO,
+        )
+        .with_timestamp(timestamp)
+        .with_round(self.current_round)
+    }
+
+    /// Put a candidate block's verifications back in front of the queue
+    ///
+    /// Used when the block did not gather enough attestations.
+    pub fn requeue(&mut self, block: Block) {
+        for pending in block.verifications.into_iter().zip(block.solutions).rev() {
+            self.pending_verifications.push_front(pending);
+        }
+    }
+
+    /// Attest to another proposer's block after re-checking it
+    #[must_use]
+    pub fn attest(&self, block: &Block) -> VerifierAttestation {
+        let verified_solutions = block.verifications.iter().map(|v| v.solution_id).collect();
+        self.pov.create_attestation(block, verified_solutions, &self.keypair)
+    }
+
     /// Get the number of pending verifications
     #[must_use]
     pub fn pending_count(&self) -> usize {
@@ -173,6 +354,14 @@ impl BlockProducer {
     }
 }
 
+/// Bytes `value` takes up in an encoded block
+fn encoded_size<T: Serialize>(value: &T) -> usize {
+    bincode::serialized_size(value)
+        .ok()
+        .and_then(|size| usize::try_from(size).ok())
+        .unwrap_or(usize::MAX)
+}
+
 /// Statistics about block production
 #[derive(Clone, Debug, Default)]
 pub struct BlockProducerStats {
@@ -191,8 +380,10 @@ pub struct BlockProducerStats {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{JobType, VerificationSpec};
+    use crate::types::{Address, JobStatus, JobType, SolutionStatus, TransactionKind, VerificationSpec};
     use crate::crypto::hash_data;
+    use crate::mempool::Mempool;
+    use crate::state::{AccountState, ChainState};
 
     fn create_test_job_solution() -> (JobPacket, SolutionCandidate) {
         let requester_kp = Keypair::generate();
@@ -252,14 +443,70 @@ mod tests {
         assert_eq!(block.header.height, 1);
         assert_eq!(block.verifications.len(), 1);
         assert!(!block.attestations.is_empty());
+
+        // What we produce passes the checks everyone else runs
+        assert!(block.verify_integrity().is_ok());
+        assert!(block.verify_signatures().is_ok());
+        assert!(BlockProducerConfig::default().check_limits(&block).is_ok());
+
+        let tight = BlockProducerConfig { max_solutions_per_block: 0, ..BlockProducerConfig::default() };
+        assert!(matches!(tight.check_limits(&block), Err(BlockError::TooManySolutions { count: 1, limit: 0 })));
+        let small = BlockProducerConfig { max_block_size: 64, ..BlockProducerConfig::default() };
+        assert!(matches!(small.check_limits(&block), Err(BlockError::TooLarge { limit: 64, .. })));
     }
 
     #[test]
-    fn test_failed_verification_not_added() {
+    fn test_block_fits_size_limit() {
+        let kp = Keypair::generate();
+        let recipient = Address::from_public_key(kp.public_key());
+        let config = BlockProducerConfig { max_block_size: 4_000, ..BlockProducerConfig::default() };
+        let mut producer = BlockProducer::new(kp, config.clone());
+
+        // More transactions and verifications than one block holds
+        let sender = Keypair::generate();
+        let account = AccountState::new(HclawAmount::from_hclaw(1_000));
+        let mut mempool = Mempool::new();
+        for nonce in 0..60 {
+            let kind = TransactionKind::Transfer { to: recipient, amount: HclawAmount::from_hclaw(1) };
+            let mut tx = Transaction::new(*sender.public_key(), nonce, kind);
+            tx.signature = sender.sign(&tx.signing_bytes());
+            mempool.add_transaction(tx, &account).unwrap();
+        }
+        let transactions = mempool.ready_transactions(1000, |_| 0);
+        assert_eq!(transactions.len(), 60);
+        for _ in 0..10 {
+            let (job, solution) = create_test_job_solution();
+            producer.verify_solution(&job, &solution).unwrap();
+        }
+
+        let block = producer
+            .produce_block_with(transactions.clone(), Vec::new(), None, |_| Ok(Hash::ZERO))
+            .unwrap();
+        assert!(config.check_limits(&block).is_ok());
+        assert!(!block.transactions.is_empty());
+        assert!(block.transactions.len() < transactions.len());
+        assert!(block.transactions.iter().zip(&transactions).all(|(included, tx)| included.id == tx.id));
+
+        // Verifications that did not fit wait for the next block
+        assert_eq!(block.verifications.len() + producer.pending_count(), 10);
+        let block = producer.produce_block(Hash::ZERO).unwrap();
+        assert!(config.check_limits(&block).is_ok());
+        assert!(!block.verifications.is_empty());
+    }
+
+    #[test]
+    fn test_failed_verification_rejects_solution() {
         let kp = Keypair::generate();
         let mut producer = BlockProducer::new(kp, BlockProducerConfig::default());
 
         let (job, _) = create_test_job_solution();
+        let mut state = ChainState::new();
+        // Backdated so the next block is strictly later, even within the same millisecond
+        let genesis = Block::genesis(*producer.keypair.public_key()).with_timestamp(now_millis() - 1000);
+        state.apply_block(genesis.clone()).unwrap();
+        state.get_or_create_account(&job.requester_address).credit(HclawAmount::from_hclaw(100));
+        state.store_job(job.clone()).unwrap();
+        producer.set_chain_state(0, genesis.hash);
 
         // Create bad solution
         let solver_kp = Keypair::generate();
@@ -274,7 +521,51 @@ mod tests {
         let result = producer.verify_solution(&job, &bad_solution).unwrap();
         assert!(!result.passed);
 
-        // Should not be added to pending
+        // Still queued, so the next block settles it as rejected
+        assert_eq!(producer.pending_count(), 1);
+        let block = producer
+            .produce_block_with(Vec::new(), Vec::new(), None, |block| {
+                state.state_root_after(block).map_err(|e| ConsensusError::VerificationFailed { reason: e.to_string() })
+            })
+            .unwrap();
+        assert_eq!(block.verifications.len(), 1);
+        state.apply_block(block).unwrap();
+        assert_eq!(state.get_solution(&bad_solution.id).unwrap().status, SolutionStatus::Rejected);
+        assert_eq!(state.get_job(&job.id).unwrap().status, JobStatus::Pending);
+    }
+
+    #[test]
+    fn test_unsettleable_verification_is_dropped_alone() {
+        let kp = Keypair::generate();
+        let mut producer = BlockProducer::new(kp, BlockProducerConfig::default());
+
+        let (job, first) = create_test_job_solution();
+        let mut state = ChainState::new();
+        // Backdated so the next block is strictly later, even within the same millisecond
+        let genesis = Block::genesis(*producer.keypair.public_key()).with_timestamp(now_millis() - 1000);
+        state.apply_block(genesis.clone()).unwrap();
+        state.get_or_create_account(&job.requester_address).credit(HclawAmount::from_hclaw(100));
+        state.store_job(job.clone()).unwrap();
+        producer.set_chain_state(0, genesis.hash);
+
+        // A second pass for the job the first one closes, and a pass for a
+        // job that never reached the chain
+        let solver_kp = Keypair::generate();
+        let mut second = SolutionCandidate::new(job.id, *solver_kp.public_key(), first.output.clone());
+        second.signature = solver_kp.sign(&second.signing_bytes());
+        let (unknown, orphan) = create_test_job_solution();
+        for (job, solution) in [(&job, &first), (&job, &second), (&unknown, &orphan)] {
+            assert!(producer.verify_solution(job, solution).unwrap().passed);
+        }
+
+        let block = producer
+            .produce_block_with(Vec::new(), Vec::new(), None, |block| {
+                state.state_root_after(block).map_err(|e| ConsensusError::VerificationFailed { reason: e.to_string() })
+            })
+            .unwrap();
+        assert_eq!(block.solutions.iter().map(|s| s.id).collect::<Vec<_>>(), vec![first.id]);
         assert_eq!(producer.pending_count(), 0);
+        state.apply_block(block).unwrap();
+        assert_eq!(state.get_job(&job.id).unwrap().status, JobStatus::Completed);
     }
 }
@@ -0,0 +1,181 @@
+//! The node's chain as consensus sees it.
+//!
+//! `ChainService` owns the `ChainState` together with a `SupplyManager` fed
+//! the block times of the canonical chain, and answers `ConsensusParticipant`
+//! queries from them. When the head moves to another branch the block times
+//! are replayed from the new chain, so the difficulty always describes the
+//! canonical one.
+
+use crate::crypto::Hash;
+use crate::state::{ChainState, ImportOutcome, StateError};
+use crate::tokenomics::SupplyManager;
+use crate::types::{Block, VerifierAttestation};
+
+use super::{ConsensusError, ConsensusParticipant};
+
+/// Chain state plus the supply figures derived from it
+#[derive(Debug)]
+pub struct ChainService {
+    /// Block tree and state
+    state: ChainState,
+    /// Difficulty and supply, fed every canonical block time
+    supply: SupplyManager,
+    /// Last canonical block fed to `supply`, by height and hash
+    synced: Option<(u64, Hash)>,
+}
+
+impl ChainService {
+    /// Serve a chain, replaying the block times it already holds
+    #[must_use]
+    pub fn new(state: ChainState) -> Self {
+        let mut service = Self { state, supply: SupplyManager::new(), synced: None };
+        service.sync_supply();
+        service
+    }
+
+    /// The chain state
+    #[must_use]
+    pub const fn state(&self) -> &ChainState {
+        &self.state
+    }
+
+    /// Difficulty and supply figures for the canonical chain
+    #[must_use]
+    pub const fn supply(&self) -> &SupplyManager {
+        &self.supply
+    }
+
+    /// Give the chain state back
+    #[must_use]
+    pub fn into_state(self) -> ChainState {
+        self.state
+    }
+
+    /// Import a block into the tree (see `ChainState::import_block`)
+    ///
+    /// # Errors
+    /// Returns error if the block is rejected
+    pub fn import_block(&mut self, block: Block) -> Result<ImportOutcome, StateError> {
+        let outcome = self.state.import_block(block);
+        self.sync_supply();
+        outcome
+    }
+
+    /// Record an attestation (see `ChainState::add_attestation`)
+    ///
+    /// # Errors
+    /// Returns error if the attestation is rejected
+    pub fn add_attestation(&mut self, attestation: VerifierAttestation) -> Result<ImportOutcome, StateError> {
+        let outcome = self.state.add_attestation(attestation);
+        self.sync_supply();
+        outcome
+    }
+
+    /// Feed `supply` the canonical blocks it has not seen, starting over if
+    /// the last one it saw was reorganised away
+    fn sync_supply(&mut self) {
+        if self.synced.is_some_and(|(_, hash)| !self.state.is_canonical(&hash)) {
+            self.supply = SupplyManager::new();
+            self.synced = None;
+        }
+
+        let start = self.synced.map_or_else(|| self.state.history_start(), |(height, _)| height + 1);
+        for height in start..self.state.height() {
+            let Some(header) = self.state.get_header_at_height(height) else {
+                break;
+            };
+            let parent = height.checked_sub(1).and_then(|h| self.state.get_header_at_height(h));
+            if let Some(parent) = parent {
+                let elapsed = header.timestamp.saturating_sub(parent.timestamp);
+                self.supply.record_block_time(u64::try_from(elapsed).unwrap_or(0));
+            }
+            self.synced = Some((height, header.compute_hash()));
+        }
+    }
+}
+
+impl ConsensusParticipant for ChainService {
+    fn chain_tip(&self) -> Option<&Block> {
+        self.state.tip()
+    }
+
+    fn get_block(&self, hash: &Hash) -> Option<&Block> {
+        self.state.get_block(hash)
+    }
+
+    fn get_block_by_height(&self, height: u64) -> Option<&Block> {
+        self.state.get_block_at_height(height)
+    }
+
+    fn add_block(&mut self, block: Block) -> Result<(), ConsensusError> {
+        self.import_block(block).map(|_| ()).map_err(|e| match e {
+            StateError::InvalidParent => ConsensusError::InvalidParent,
+            StateError::InvalidHeight { expected, got } => ConsensusError::HeightMismatch { expected, got },
+            e => ConsensusError::VerificationFailed { reason: e.to_string() },
+        })
+    }
+
+    fn current_difficulty(&self) -> u64 {
+        self.supply.difficulty()
+    }
+
+    fn active_verifier_count(&self) -> usize {
+        self.state.validator_set().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+    use crate::types::{Address, HclawAmount};
+
+    /// Next empty block, 1ms after the tip (far faster than the target)
+    fn next_block(state: &mut ChainState, kp: &Keypair) -> Block {
+        let parent = state.tip().unwrap();
+        let block = Block::new(state.height(), parent.hash, *kp.public_key(), Vec::new(), Hash::ZERO)
+            .with_timestamp(parent.header.timestamp + 1)
+            .with_epoch(state.epoch_commitment());
+        let root = state.state_root_after(&block).unwrap();
+        let mut block = block.with_state_root(root);
+        block.proposer_signature = kp.sign(&block.signing_bytes());
+        let mut attestation = VerifierAttestation::new(*kp.public_key(), block.hash, Vec::new());
+        attestation.signature = kp.sign(&attestation.signing_bytes());
+        block.add_attestation(attestation);
+        block
+    }
+
+    #[test]
+    fn test_chain_service_tracks_canonical_chain() {
+        let kp = Keypair::generate();
+        let mut state = ChainState::new();
+        state.apply_block(Block::genesis(*kp.public_key())).unwrap();
+        state
+            .get_or_create_account(&Address::from_public_key(kp.public_key()))
+            .staked = HclawAmount::from_hclaw(1000);
+
+        let mut service = ChainService::new(state);
+        assert_eq!(service.active_verifier_count(), 1);
+        assert_eq!(service.current_difficulty(), 1);
+
+        for _ in 0..SupplyManager::DEFAULT_ADJUSTMENT_WINDOW {
+            let block = next_block(&mut service.state, &kp);
+            service.add_block(block).unwrap();
+        }
+        let tip = service.chain_tip().unwrap().clone();
+        assert_eq!(tip.header.height, SupplyManager::DEFAULT_ADJUSTMENT_WINDOW);
+        assert_eq!(service.get_block_by_height(tip.header.height).unwrap().hash, tip.hash);
+        assert!(service.get_block(&tip.hash).is_some());
+        // A full window of fast blocks raises the difficulty
+        assert_eq!(service.current_difficulty(), 2);
+
+        // A restarted service replays the same figures
+        let difficulty = service.current_difficulty();
+        let service = ChainService::new(service.into_state());
+        assert_eq!(service.current_difficulty(), difficulty);
+
+        let mut service = service;
+        let orphan = Block::new(tip.header.height + 1, Hash::ZERO, *kp.public_key(), Vec::new(), Hash::ZERO);
+        assert!(service.add_block(orphan).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+//! Double-signing detection.
+//!
+//! Every proposal, committed block and attestation a node sees goes through
+//! the `EquivocationDetector`. It remembers the first correctly signed
+//! proposal and attestation from each key in each height and round; a second
+//! one for a different block is turned into `Evidence`, which the node
+//! gossips and includes in a block to slash the offender.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::crypto::{Hash, PublicKey};
+use crate::types::{
+    AttestedHeader, Block, BlockHeader, Evidence, Id, SignedProposal, VerifierAttestation,
+};
+
+/// Signer, height and round a signature is bound to
+type Slot = (PublicKey, u64, u32);
+
+/// Watches signed proposals and attestations for conflicts
+#[derive(Clone, Debug, Default)]
+pub struct EquivocationDetector {
+    /// First proposal seen from each proposer in each slot
+    proposals: HashMap<Slot, SignedProposal>,
+    /// First attestation seen from each verifier in each slot
+    attestations: HashMap<Slot, AttestedHeader>,
+    /// Headers of the blocks seen, so bare attestations can be placed
+    headers: HashMap<Hash, BlockHeader>,
+    /// Offences already turned into evidence
+    reported: HashSet<Id>,
+}
+
+impl EquivocationDetector {
+    /// Create an empty detector
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a proposed or committed block and every attestation it carries
+    ///
+    /// Returns evidence for each conflict found for the first time.
+    pub fn observe_block(&mut self, block: &Block) -> Vec<Evidence> {
+        if block.header.compute_hash() != block.hash {
+            return Vec::new();
+        }
+        self.headers.insert(block.hash, block.header.clone());
+
+        let mut found: Vec<Evidence> = self.observe_proposal(block).into_iter().collect();
+        for attestation in &block.attestations {
+            found.extend(self.observe_attestation(attestation));
+        }
+        found
+    }
+
+    /// Record a block proposal
+    ///
+    /// Returns evidence if the proposer already signed another block in the
+    /// same height and round.
+    pub fn observe_proposal(&mut self, block: &Block) -> Option<Evidence> {
+        let proposal = SignedProposal::from_block(block);
+        if proposal.verify_signature().is_err() {
+            return None;
+        }
+
+        let slot = (proposal.header.proposer, proposal.header.height, proposal.header.round);
+        let first = self.proposals.entry(slot).or_insert_with(|| proposal.clone());
+        if first.header.compute_hash() == proposal.header.compute_hash() {
+            return None;
+        }
+        let evidence = Evidence::DoubleProposal { first: first.clone(), second: proposal };
+        self.report(evidence)
+    }
+
+    /// Record an attestation for a block seen before
+    ///
+    /// Attestations for unknown blocks are ignored. Returns evidence if the
+    /// verifier already attested to another block in the same height and round.
+    pub fn observe_attestation(&mut self, attestation: &VerifierAttestation) -> Option<Evidence> {
+        let header = self.headers.get(&attestation.block_hash)?;
+        let attested = AttestedHeader { header: header.clone(), attestation: attestation.clone() };
+        if attested.verify().is_err() {
+            return None;
+        }
+
+        let slot = (attestation.verifier, header.height, header.round);
+        let first = self.attestations.entry(slot).or_insert_with(|| attested.clone());
+        if first.attestation.block_hash == attestation.block_hash {
+            return None;
+        }
+        let evidence = Evidence::DoubleAttestation { first: first.clone(), second: attested };
+        self.report(evidence)
+    }
+
+    /// Block that `verifier` attested to in a height and round, if any
+    #[must_use]
+    pub fn attested_to(&self, verifier: &PublicKey, height: u64, round: u32) -> Option<Hash> {
+        self.attestations
+            .get(&(*verifier, height, round))
+            .map(|attested| attested.attestation.block_hash)
+    }
+
+    /// Forget everything below `height`
+    pub fn prune_below(&mut self, height: u64) {
+        self.proposals.retain(|(_, h, _), _| *h >= height);
+        self.attestations.retain(|(_, h, _), _| *h >= height);
+        self.headers.retain(|_, header| header.height >= height);
+    }
+
+    /// Keep evidence that verifies and has not been reported yet
+    fn report(&mut self, evidence: Evidence) -> Option<Evidence> {
+        (evidence.verify().is_ok() && self.reported.insert(evidence.id())).then_some(evidence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{hash_data, Keypair};
+
+    fn proposal(kp: &Keypair, height: u64, round: u32, parent: &[u8]) -> Block {
+        let mut block = Block::new(height, hash_data(parent), *kp.public_key(), Vec::new(), Hash::ZERO)
+            .with_round(round);
+        block.proposer_signature = kp.sign(&block.signing_bytes());
+        block
+    }
+
+    fn attestation(kp: &Keypair, block: &Block) -> VerifierAttestation {
+        let mut attestation = VerifierAttestation::new(*kp.public_key(), block.hash, Vec::new());
+        attestation.signature = kp.sign(&attestation.signing_bytes());
+        attestation
+    }
+
+    #[test]
+    fn test_detects_double_proposal_once() {
+        let proposer = Keypair::generate();
+        let mut detector = EquivocationDetector::new();
+
+        let first = proposal(&proposer, 3, 0, b"a");
+        assert!(detector.observe_block(&first).is_empty());
+        assert!(detector.observe_block(&first).is_empty());
+        // A new round at the same height is not an offence
+        assert!(detector.observe_block(&proposal(&proposer, 3, 1, b"a")).is_empty());
+
+        let second = proposal(&proposer, 3, 0, b"b");
+        let found = detector.observe_block(&second);
+        assert_eq!(found.len(), 1);
+        assert!(matches!(found[0], Evidence::DoubleProposal { .. }));
+        assert_eq!(found[0].offender(), proposer.public_key());
+        assert!(found[0].verify().is_ok());
+
+        // Seeing the conflicting block again does not report it twice
+        assert!(detector.observe_block(&second).is_empty());
+
+        // Unsigned blocks prove nothing
+        assert!(detector.observe_block(&proposal(&proposer, 4, 0, b"a")).is_empty());
+        let unsigned = Block::new(4, hash_data(b"b"), *proposer.public_key(), Vec::new(), Hash::ZERO);
+        assert!(detector.observe_block(&unsigned).is_empty());
+    }
+
+    #[test]
+    fn test_detects_double_attestation() {
+        let verifier = Keypair::generate();
+        let mut detector = EquivocationDetector::new();
+        let a = proposal(&Keypair::generate(), 3, 0, b"a");
+        let b = proposal(&Keypair::generate(), 3, 0, b"b");
+
+        // Attestations for blocks never seen cannot be checked
+        assert!(detector.observe_attestation(&attestation(&verifier, &a)).is_none());
+
+        let mut with_attestation = a.clone();
+        with_attestation.add_attestation(attestation(&verifier, &a));
+        assert!(detector.observe_block(&with_attestation).is_empty());
+        assert_eq!(detector.attested_to(verifier.public_key(), 3, 0), Some(a.hash));
+
+        assert!(detector.observe_block(&b).is_empty());
+        let evidence = detector.observe_attestation(&attestation(&verifier, &b)).unwrap();
+        assert!(matches!(evidence, Evidence::DoubleAttestation { .. }));
+        assert_eq!(evidence.block_hashes(), (a.hash, b.hash));
+
+        detector.prune_below(4);
+        assert!(detector.attested_to(verifier.public_key(), 3, 0).is_none());
+    }
+}
@@ -0,0 +1,384 @@
+//! Verification off the node's event loop.
+//!
+//! `VerificationExecutor` runs `SolutionVerifier` backends on a fixed pool of
+//! worker threads fed through a bounded queue, and never blocks its caller:
+//! solutions are submitted, and finished verdicts picked up on a later poll.
+//!
+//! Every solution gets two budgets:
+//! - CPU: how long its backend may run once a worker has picked it up;
+//! - wall clock: how long, queueing included, the node waits for it.
+//!
+//! A solution that overruns either fails verification. Workers cannot be
+//! preempted, so a backend that hangs keeps its worker until it returns and
+//! its late verdict is dropped. `capacity` tells the node how many more
+//! solutions to take from the mempool; the rest wait there.
+
+use std::collections::HashMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::crypto::Hash;
+use crate::types::{JobPacket, SolutionCandidate};
+
+use super::{ConsensusError, SolutionVerifier, Verdict, VerifierRegistry};
+
+/// Sizing and budgets of a `VerificationExecutor`
+#[derive(Clone, Debug)]
+pub struct ExecutorConfig {
+    /// Worker threads
+    pub workers: usize,
+    /// Solutions that may be queued or running at once
+    pub max_in_flight: usize,
+    /// Time a backend may run on its worker, per solution
+    pub cpu_budget_ms: u64,
+    /// Time from submission until a solution is given up on
+    pub wall_budget_ms: u64,
+}
+
+impl Default for ExecutorConfig {
+    fn default() -> Self {
+        Self {
+            workers: 4,
+            max_in_flight: 100,
+            cpu_budget_ms: 1_000,
+            wall_budget_ms: 5_000,
+        }
+    }
+}
+
+/// A solution whose verification has finished or was given up on
+#[derive(Debug)]
+pub struct Completed {
+    /// The job
+    pub job: JobPacket,
+    /// The solution
+    pub solution: SolutionCandidate,
+    /// What the backend decided, or why it could not
+    pub verdict: Result<Verdict, ConsensusError>,
+    /// Time the verification took, or its budget if it overran
+    pub verification_time_ms: u64,
+    /// Whether the solution failed for overrunning a budget
+    pub timed_out: bool,
+}
+
+/// Work handed to a worker
+struct Task {
+    backend: Arc<dyn SolutionVerifier>,
+    job: JobPacket,
+    solution: SolutionCandidate,
+    /// Past this, nobody is waiting for the verdict any more
+    deadline: Instant,
+}
+
+/// A worker's verdict
+struct Finished {
+    solution_id: Hash,
+    verdict: Result<Verdict, ConsensusError>,
+    run_time: Duration,
+}
+
+/// A submitted solution the executor is waiting on
+#[derive(Debug)]
+struct InFlight {
+    job: JobPacket,
+    solution: SolutionCandidate,
+    submitted: Instant,
+}
+
+/// Runs verification backends on a bounded pool of worker threads
+#[derive(Debug)]
+pub struct VerificationExecutor {
+    config: ExecutorConfig,
+    /// Queue the workers take tasks from
+    tasks: SyncSender<Task>,
+    /// Verdicts coming back from the workers
+    finished: Receiver<Finished>,
+    /// Submitted solutions, by ID
+    in_flight: HashMap<Hash, InFlight>,
+}
+
+impl VerificationExecutor {
+    /// Start the worker threads
+    ///
+    /// # Panics
+    /// If the OS refuses to start a thread
+    #[must_use]
+    pub fn new(config: ExecutorConfig) -> Self {
+        let (tasks, queue) = mpsc::sync_channel(config.max_in_flight.max(1));
+        let (done, finished) = mpsc::channel();
+        let queue = Arc::new(Mutex::new(queue));
+
+        for i in 0..config.workers.max(1) {
+            let queue = Arc::clone(&queue);
+            let done = done.clone();
+            thread::Builder::new()
+                .name(format!("verify-{i}"))
+                .spawn(move || work(&queue, &done))
+                .expect("spawn verification worker");
+        }
+
+        Self {
+            config,
+            tasks,
+            finished,
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// Sizing and budgets
+    #[must_use]
+    pub const fn config(&self) -> &ExecutorConfig {
+        &self.config
+    }
+
+    /// Number of solutions queued or running
+    #[must_use]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.len()
+    }
+
+    /// Number of solutions that can be submitted now
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.config.max_in_flight.saturating_sub(self.in_flight.len())
+    }
+
+    /// Queue a solution for the backend that checks its job's spec
+    ///
+    /// A solution already in flight is not queued again.
+    ///
+    /// # Errors
+    /// Returns error if the solution is not for `job`, no backend checks the
+    /// job's spec, or the executor is at capacity
+    pub fn submit(
+        &mut self,
+        registry: &VerifierRegistry,
+        job: JobPacket,
+        solution: SolutionCandidate,
+    ) -> Result<(), ConsensusError> {
+        if solution.job_id != job.id {
+            return Err(ConsensusError::SolutionMismatch);
+        }
+        if self.in_flight.contains_key(&solution.id) {
+            return Ok(());
+        }
+        if self.capacity() == 0 {
+            return Err(ConsensusError::ExecutorFull);
+        }
+        let kind = job.verification.kind();
+        let backend = registry.get(kind).ok_or(ConsensusError::UnsupportedSpec(kind))?;
+
+        let submitted = Instant::now();
+        let task = Task {
+            backend,
+            job: job.clone(),
+            solution: solution.clone(),
+            deadline: submitted + Duration::from_millis(self.config.wall_budget_ms),
+        };
+        match self.tasks.try_send(task) {
+            Ok(()) => {}
+            // Tasks given up on can still sit in the queue until a worker skips them
+            Err(TrySendError::Full(_)) => return Err(ConsensusError::ExecutorFull),
+            Err(TrySendError::Disconnected(_)) => {
+                return Err(ConsensusError::VerificationFailed {
+                    reason: "verification workers have stopped".to_string(),
+                })
+            }
+        }
+        self.in_flight.insert(solution.id, InFlight { job, solution, submitted });
+        Ok(())
+    }
+
+    /// Pick up finished verdicts, and give up on solutions past their
+    /// wall-clock budget
+    pub fn poll(&mut self) -> Vec<Completed> {
+        let mut completed = Vec::new();
+
+        while let Ok(finished) = self.finished.try_recv() {
+            // Verdicts for solutions already given up on are dropped
+            let Some(entry) = self.in_flight.remove(&finished.solution_id) else {
+                continue;
+            };
+            let run_ms = millis(finished.run_time);
+            let timed_out = run_ms > self.config.cpu_budget_ms;
+            let verdict = if timed_out {
+                Ok(Verdict::fail(format!(
+                    "verification exceeded its CPU budget of {} ms",
+                    self.config.cpu_budget_ms
+                )))
+            } else {
+                finished.verdict
+            };
+            completed.push(Completed {
+                job: entry.job,
+                solution: entry.solution,
+                verdict,
+                verification_time_ms: run_ms,
+                timed_out,
+            });
+        }
+
+        let budget = Duration::from_millis(self.config.wall_budget_ms);
+        let expired: Vec<Hash> = self
+            .in_flight
+            .iter()
+            .filter(|(_, entry)| entry.submitted.elapsed() >= budget)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in expired {
+            if let Some(entry) = self.in_flight.remove(&id) {
+                completed.push(Completed {
+                    job: entry.job,
+                    solution: entry.solution,
+                    verdict: Ok(Verdict::fail(format!(
+                        "verification timed out after {} ms",
+                        self.config.wall_budget_ms
+                    ))),
+                    verification_time_ms: self.config.wall_budget_ms,
+                    timed_out: true,
+                });
+            }
+        }
+
+        completed
+    }
+}
+
+impl Default for VerificationExecutor {
+    fn default() -> Self {
+        Self::new(ExecutorConfig::default())
+    }
+}
+
+/// Worker loop: run tasks until the executor is dropped
+fn work(queue: &Mutex<Receiver<Task>>, done: &Sender<Finished>) {
+    loop {
+        // Hold the lock only while waiting for the next task
+        let next = match queue.lock() {
+            Ok(queue) => queue.recv(),
+            Err(_) => return,
+        };
+        let Ok(task) = next else {
+            return;
+        };
+        if Instant::now() >= task.deadline {
+            continue;
+        }
+
+        let start = Instant::now();
+        let verdict = panic::catch_unwind(AssertUnwindSafe(|| task.backend.verify(&task.job, &task.solution)))
+            .unwrap_or_else(|_| {
+                Err(ConsensusError::VerificationFailed {
+                    reason: "verifier panicked".to_string(),
+                })
+            });
+        let finished = Finished {
+            solution_id: task.solution.id,
+            verdict,
+            run_time: start.elapsed(),
+        };
+        if done.send(finished).is_err() {
+            return;
+        }
+    }
+}
+
+/// Whole milliseconds in `duration`
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{hash_data, Keypair};
+    use crate::types::{HclawAmount, JobType, VerificationKind, VerificationSpec};
+
+    /// Passes every solution after sleeping for the given time
+    struct Slow(Duration);
+
+    impl SolutionVerifier for Slow {
+        fn verify(&self, _job: &JobPacket, _solution: &SolutionCandidate) -> Result<Verdict, ConsensusError> {
+            thread::sleep(self.0);
+            Ok(Verdict::pass())
+        }
+    }
+
+    fn job_and_solution(output: &[u8]) -> (JobPacket, SolutionCandidate) {
+        let job = JobPacket::new(
+            JobType::Deterministic,
+            *Keypair::generate().public_key(),
+            b"input".to_vec(),
+            "Test job".to_string(),
+            HclawAmount::from_hclaw(10),
+            HclawAmount::from_hclaw(1),
+            VerificationSpec::HashMatch { expected_hash: hash_data(b"right") },
+            3600,
+        );
+        let solution = SolutionCandidate::new(job.id, *Keypair::generate().public_key(), output.to_vec());
+        (job, solution)
+    }
+
+    /// Poll until `count` solutions have completed
+    fn wait_for(executor: &mut VerificationExecutor, count: usize) -> Vec<Completed> {
+        let mut completed = Vec::new();
+        while completed.len() < count {
+            completed.extend(executor.poll());
+            thread::sleep(Duration::from_millis(5));
+        }
+        completed
+    }
+
+    #[test]
+    fn test_executor_verifies_on_workers() {
+        let registry = VerifierRegistry::new();
+        let config = ExecutorConfig { workers: 2, max_in_flight: 2, ..ExecutorConfig::default() };
+        let mut executor = VerificationExecutor::new(config);
+
+        let (job, right) = job_and_solution(b"right");
+        let (other, wrong) = job_and_solution(b"wrong");
+        executor.submit(&registry, job.clone(), right.clone()).unwrap();
+        executor.submit(&registry, other, wrong.clone()).unwrap();
+
+        // At capacity: the rest waits in the mempool
+        assert_eq!(executor.capacity(), 0);
+        let (third, solution) = job_and_solution(b"right");
+        assert!(matches!(executor.submit(&registry, third, solution), Err(ConsensusError::ExecutorFull)));
+        assert!(matches!(executor.submit(&registry, job, wrong.clone()), Err(ConsensusError::SolutionMismatch)));
+
+        let completed = wait_for(&mut executor, 2);
+        assert_eq!(executor.in_flight(), 0);
+        for done in completed {
+            assert!(!done.timed_out);
+            assert_eq!(done.verdict.unwrap().passed, done.solution.id == right.id);
+        }
+    }
+
+    #[test]
+    fn test_executor_enforces_budgets() {
+        let mut registry = VerifierRegistry::empty();
+        registry.register(VerificationKind::HashMatch, Box::new(Slow(Duration::from_millis(50))));
+
+        // The backend finishes, but ran longer than it may
+        let config = ExecutorConfig { cpu_budget_ms: 10, ..ExecutorConfig::default() };
+        let mut executor = VerificationExecutor::new(config);
+        let (job, solution) = job_and_solution(b"right");
+        executor.submit(&registry, job, solution).unwrap();
+        let done = wait_for(&mut executor, 1).pop().unwrap();
+        assert!(done.timed_out);
+        assert!(!done.verdict.unwrap().passed);
+
+        // Nobody waits for the backend to finish
+        let config = ExecutorConfig { wall_budget_ms: 10, ..ExecutorConfig::default() };
+        let mut executor = VerificationExecutor::new(config);
+        let (job, solution) = job_and_solution(b"right");
+        executor.submit(&registry, job, solution).unwrap();
+        let done = wait_for(&mut executor, 1).pop().unwrap();
+        assert!(done.timed_out);
+        assert_eq!(done.verification_time_ms, 10);
+        assert!(!done.verdict.unwrap().passed);
+    }
+}
@@ -18,13 +18,32 @@
 
 mod pov;
 mod block_producer;
+mod chain;
+mod evidence;
+mod executor;
+mod round;
+mod schedule;
+mod scoring;
+mod validators;
+mod verifiers;
 
 pub use pov::ProofOfVerification;
 pub use block_producer::{BlockProducer, BlockProducerConfig};
+pub use chain::ChainService;
+pub use evidence::EquivocationDetector;
+pub use executor::{Completed, ExecutorConfig, VerificationExecutor};
+pub use round::{AttestationRound, ROUND_TIMEOUT_MS};
+pub use schedule::{fallback_rank, ProposerSchedule, SLOT_DURATION_MS};
+pub use scoring::score_builtin;
+pub use validators::ValidatorSet;
+pub use verifiers::{
+    HashMatchVerifier, JsonSchemaVerifier, ObjectiveThresholdVerifier, SchellingPointVerifier, TestVectorsVerifier,
+    VerifierRegistry, WasmModuleVerifier,
+};
 
 use thiserror::Error;
 
-use crate::types::{Block, SolutionCandidate, JobPacket, VerificationResult};
+use crate::types::{Block, SolutionCandidate, JobPacket, VerificationKind};
 
 /// Consensus errors
 #[derive(Debug, Error)]
@@ -73,16 +92,63 @@ pub enum ConsensusError {
     /// Solution doesn't match job
     #[error("solution doesn't match job specification")]
     SolutionMismatch,
+
+    /// No backend checks this kind of verification spec
+    #[error("no verifier for {0} specs")]
+    UnsupportedSpec(VerificationKind),
+
+    /// Too many solutions already waiting for verification
+    #[error("verification queue is full")]
+    ExecutorFull,
+}
+
+/// Outcome of checking a solution against its job
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Verdict {
+    /// Whether the solution passed
+    pub passed: bool,
+    /// Why it failed, if it did
+    pub error: Option<String>,
+    /// Score of the output, for specs that score solutions
+    pub score: Option<i64>,
+}
+
+impl Verdict {
+    /// A passing verdict
+    #[must_use]
+    pub const fn pass() -> Self {
+        Self { passed: true, error: None, score: None }
+    }
+
+    /// A failing verdict with its reason
+    #[must_use]
+    pub fn fail(reason: impl Into<String>) -> Self {
+        Self { passed: false, error: Some(reason.into()), score: None }
+    }
+
+    /// Attach the output's score
+    #[must_use]
+    pub const fn with_score(mut self, score: i64) -> Self {
+        self.score = Some(score);
+        self
+    }
 }
 
 /// Trait for verifying solutions
+///
+/// One backend per kind of `VerificationSpec`; the verifier running it signs
+/// the verdict into a `VerificationResult`.
 pub trait SolutionVerifier: Send + Sync {
     /// Verify a solution against its job specification
+    ///
+    /// # Errors
+    /// Returns error if the job's spec is not one this backend checks, or
+    /// the solution cannot be checked at all
     fn verify(
         &self,
         job: &JobPacket,
         solution: &SolutionCandidate,
-    ) -> Result<VerificationResult, ConsensusError>;
+    ) -> Result<Verdict, ConsensusError>;
 }
 
 /// Trait for consensus participation
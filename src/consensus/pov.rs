@@ -3,18 +3,22 @@
 //! "We do not trust; we verify."
 
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Instant;
 
-use crate::crypto::{hash_data, Hash, Keypair};
+use crate::crypto::{Hash, Keypair};
+use crate::state::ChainState;
 use crate::types::{
     Block, JobPacket, SolutionCandidate,
-    VerificationResult, VerificationSpec, VerifierAttestation, now_millis,
+    VerificationKind, VerificationResult, VerifierAttestation, now_millis,
 };
 
-use super::{ConsensusError, SolutionVerifier};
+use super::{ConsensusError, SolutionVerifier, Verdict, VerifierRegistry};
 
 /// Proof-of-Verification consensus engine
 pub struct ProofOfVerification {
+    /// Backends that check each kind of verification spec
+    verifiers: VerifierRegistry,
     /// Verification results cache
     verification_cache: HashMap<Hash, VerificationResult>,
     /// Maximum age for cached results (in milliseconds)
@@ -31,12 +35,30 @@ impl ProofOfVerification {
     /// Create a new PoV engine
     #[must_use]
     pub fn new() -> Self {
+        Self::with_verifiers(VerifierRegistry::new())
+    }
+
+    /// Create an engine checking solutions with the given backends
+    #[must_use]
+    pub fn with_verifiers(verifiers: VerifierRegistry) -> Self {
         Self {
+            verifiers,
             verification_cache: HashMap::new(),
             cache_ttl_ms: 60_000, // 1 minute
         }
     }
 
+    /// Check specs of `kind` with `backend` from now on
+    ///
+    /// Returns the backend it replaces, if any.
+    pub fn register_verifier(
+        &mut self,
+        kind: VerificationKind,
+        backend: Box<dyn SolutionVerifier>,
+    ) -> Option<Arc<dyn SolutionVerifier>> {
+        self.verifiers.register(kind, backend)
+    }
+
     /// Verify a solution against its job specification
     ///
     /// This is the core "mining" operation in HardClaw.
@@ -58,130 +80,80 @@ impl ProofOfVerification {
 
         let start = Instant::now();
 
-        // Perform verification based on job type
-        let (passed, error) = match &job.verification {
-            VerificationSpec::HashMatch { expected_hash } => {
-                self.verify_hash_match(&solution.output, expected_hash)
-            }
+        // Check with the backend for the job's spec
+        let verdict = self.verifiers.verify(job, solution)?;
 
-            VerificationSpec::WasmVerifier { module_hash, entry_point } => {
-                self.verify_wasm(&job.input, &solution.output, module_hash, entry_point)
-            }
+        let verification_time_ms = start.elapsed().as_millis() as u64;
 
-            VerificationSpec::SchellingPoint { .. } => {
-                // Schelling point verification is handled separately
-                return Err(ConsensusError::VerificationFailed {
-                    reason: "Subjective tasks require Schelling consensus".to_string(),
-                });
-            }
-        };
+        Ok(self.record_verdict(job, solution, verdict, verification_time_ms, verifier_keypair))
+    }
 
-        let verification_time_ms = start.elapsed().as_millis() as u64;
+    /// Backends checking each kind of spec
+    #[must_use]
+    pub const fn verifiers(&self) -> &VerifierRegistry {
+        &self.verifiers
+    }
 
+    /// Sign a verdict reached elsewhere (e.g. on a `VerificationExecutor`
+    /// worker) into a result, and cache it
+    pub fn record_verdict(
+        &mut self,
+        job: &JobPacket,
+        solution: &SolutionCandidate,
+        verdict: Verdict,
+        verification_time_ms: u64,
+        verifier_keypair: &Keypair,
+    ) -> VerificationResult {
         // Create and sign the verification result
         let mut result = VerificationResult::new(
             solution.id,
             job.id,
             *verifier_keypair.public_key(),
-            passed,
-            error,
+            verdict.passed,
+            verdict.error,
             verification_time_ms,
-        );
+        )
+        .with_score(verdict.score);
 
         result.signature = verifier_keypair.sign(&result.signing_bytes());
 
         // Cache the result
         self.cache_result(solution.id, result.clone());
 
-        Ok(result)
-    }
-
-    /// Verify hash match (for deterministic tasks)
-    fn verify_hash_match(&self, output: &[u8], expected_hash: &Hash) -> (bool, Option<String>) {
-        let actual_hash = hash_data(output);
-
-        if actual_hash == *expected_hash {
-            (true, None)
-        } else {
-            (false, Some(format!(
-                "Hash mismatch: expected {}, got {}",
-                expected_hash.to_hex(),
-                actual_hash.to_hex()
-            )))
-        }
+        result
     }
 
-    /// Verify using WASM module
+    /// Validate a complete block on top of the tip of `state`
     ///
-    /// NOTE: Full WASM verification would require a WASM runtime.
-    /// This is a placeholder that validates the module hash.
-    fn verify_wasm(
-        &self,
-        _input: &[u8],
-        _output: &[u8],
-        module_hash: &Hash,
-        _entry_point: &str,
-    ) -> (bool, Option<String>) {
-        // In a full implementation, this would:
-        // 1. Load the WASM module from storage
-        // 2. Verify its hash matches module_hash
-        // 3. Execute the entry_point function with (input, output)
-        // 4. Return the boolean result
-
-        // For now, we just validate that a module hash was provided
-        if *module_hash == Hash::ZERO {
-            return (false, Some("Invalid WASM module hash".to_string()));
-        }
-
-        // Placeholder: would execute WASM here
-        (true, None)
-    }
-
-    /// Validate a complete block
-    pub fn validate_block(
-        &self,
-        block: &Block,
-        parent: Option<&Block>,
-        active_verifiers: usize,
-    ) -> Result<(), ConsensusError> {
+    /// Attestations are counted against the validator set `state` holds for
+    /// the block's epoch.
+    pub fn validate_block(&self, block: &Block, state: &ChainState) -> Result<(), ConsensusError> {
         // Check parent reference
-        if let Some(parent_block) = parent {
-            if block.header.parent_hash != parent_block.hash {
+        match state.tip() {
+            Some(tip) if block.header.parent_hash != tip.hash => {
                 return Err(ConsensusError::InvalidParent);
             }
-
-            if block.header.height != parent_block.header.height + 1 {
-                return Err(ConsensusError::HeightMismatch {
-                    expected: parent_block.header.height + 1,
-                    got: block.header.height,
-                });
-            }
-        } else if block.header.height != 0 {
-            // Non-genesis block must have a parent
-            return Err(ConsensusError::InvalidParent);
+            None if block.header.height != 0 => return Err(ConsensusError::InvalidParent),
+            _ => {}
+        }
+        if block.header.height != state.height() {
+            return Err(ConsensusError::HeightMismatch {
+                expected: state.height(),
+                got: block.header.height,
+            });
         }
 
-        // Check block integrity
-        block.verify_integrity().map_err(|e| ConsensusError::VerificationFailed {
+        // Structure, signatures, limits and timestamp
+        state.validate_block(block, now_millis()).map_err(|e| ConsensusError::VerificationFailed {
             reason: e.to_string(),
         })?;
 
-        // Check consensus threshold (66%)
-        if !block.has_consensus(active_verifiers) {
-            let percentage = block.consensus_percentage(active_verifiers) * 100.0;
-            return Err(ConsensusError::InsufficientConsensus { percentage });
-        }
-
-        // Verify all attestation signatures
-        for attestation in &block.attestations {
-            attestation.verify_signature().map_err(|_| {
-                ConsensusError::VerificationFailed {
-                    reason: "Invalid attestation signature".to_string(),
-                }
-            })?;
+        // Every attestation must be a member's, and their stake must reach 66%
+        let validators = state.validator_set();
+        if validators.is_empty() {
+            return Ok(());
         }
-
-        Ok(())
+        validators.check_block(block)
     }
 
     /// Create an attestation for a block
@@ -223,24 +195,11 @@ impl ProofOfVerification {
     }
 }
 
-impl SolutionVerifier for ProofOfVerification {
-    fn verify(
-        &self,
-        _job: &JobPacket,
-        _solution: &SolutionCandidate,
-    ) -> Result<VerificationResult, ConsensusError> {
-        // This trait method can't mutate self, so we create a temporary keypair
-        // In practice, the verify_solution method with keypair should be used
-        Err(ConsensusError::VerificationFailed {
-            reason: "Use verify_solution with keypair instead".to_string(),
-        })
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{JobType, HclawAmount};
+    use crate::crypto::hash_data;
+    use crate::types::{Address, JobType, HclawAmount, VerificationSpec};
 
     fn create_test_job_and_solution() -> (JobPacket, SolutionCandidate, Keypair, Keypair) {
         let requester_kp = Keypair::generate();
@@ -344,22 +303,39 @@ mod tests {
         let verifier_kp = Keypair::generate();
         let pov = ProofOfVerification::new();
 
-        // Create genesis block
+        let mut state = ChainState::new();
         let genesis = Block::genesis(*verifier_kp.public_key());
-
-        // Genesis should validate with 0 verifiers (special case)
-        // Actually, with 0 verifiers, consensus check would fail, so we need at least 1
-        // Let's add an attestation
-        let mut genesis_with_attestation = genesis.clone();
-        let attestation = VerifierAttestation::new(
-            *verifier_kp.public_key(),
-            genesis.hash,
-            Vec::new(),
-        );
-        let mut attestation = attestation;
-        attestation.signature = verifier_kp.sign(&attestation.signing_bytes());
-        genesis_with_attestation.add_attestation(attestation);
-
-        assert!(pov.validate_block(&genesis_with_attestation, None, 1).is_ok());
+        assert!(pov.validate_block(&genesis, &state).is_ok());
+        state.apply_block(genesis).unwrap();
+        state
+            .get_or_create_account(&Address::from_public_key(verifier_kp.public_key()))
+            .staked = HclawAmount::from_hclaw(1000);
+
+        let sign = |block: Block, kp: &Keypair| {
+            let mut block = block;
+            block.proposer_signature = kp.sign(&block.signing_bytes());
+            block
+        };
+        let tip = state.tip().unwrap();
+        let (parent, timestamp) = (tip.hash, tip.header.timestamp + 1);
+        let root = state.compute_state_root();
+        let block = Block::new(1, parent, *verifier_kp.public_key(), Vec::new(), root).with_timestamp(timestamp);
+        let block = sign(block, &verifier_kp);
+
+        // The set is read from the state: an unattested block has no quorum
+        assert!(pov.validate_block(&block, &state).is_err());
+        let mut attested = block.clone();
+        attested.add_attestation(pov.create_attestation(&block, Vec::new(), &verifier_kp));
+        assert!(pov.validate_block(&attested, &state).is_ok());
+
+        // The same block means nothing attested by a verifier outside the set
+        let outsider = Keypair::generate();
+        let mut foreign = block.clone();
+        foreign.add_attestation(pov.create_attestation(&block, Vec::new(), &outsider));
+        assert!(pov.validate_block(&foreign, &state).is_err());
+
+        let orphan = Block::new(1, hash_data(b"elsewhere"), *verifier_kp.public_key(), Vec::new(), root);
+        let orphan = sign(orphan.with_timestamp(timestamp), &verifier_kp);
+        assert!(matches!(pov.validate_block(&orphan, &state), Err(ConsensusError::InvalidParent)));
     }
 }
@@ -0,0 +1,149 @@
+//! Attestation rounds.
+//!
+//! A proposer does not commit its block straight away. It gossips the
+//! candidate, verifiers re-execute it against their own state and answer
+//! with signed attestations, and the block is committed only once the
+//! attesting stake reaches quorum in the parent state's `ValidatorSet`. A
+//! round that misses the threshold within `ROUND_TIMEOUT_MS` is abandoned:
+//! its verifications go back to the producer and the next round proposes a
+//! fresh candidate (or, once the slot has passed, the next proposer in line
+//! takes over). Candidates carry their round number in the header, so a
+//! re-proposal at the same height is not double signing.
+
+use crate::types::{Block, Timestamp, VerifierAttestation};
+
+use super::{ConsensusError, ValidatorSet};
+
+/// How long a round waits for attestations before it is abandoned
+pub const ROUND_TIMEOUT_MS: i64 = 3_000;
+
+/// One attempt to gather attestations for a candidate block
+#[derive(Clone, Debug)]
+pub struct AttestationRound {
+    /// Round number at this height (0 for the first attempt)
+    number: u32,
+    /// Candidate block, collecting attestations
+    block: Block,
+    /// Active set at the parent state, which the quorum is measured against
+    validators: ValidatorSet,
+    /// When the round started
+    started_at: Timestamp,
+}
+
+impl AttestationRound {
+    /// Start a round for a candidate that already carries the proposer's attestation
+    #[must_use]
+    pub const fn new(
+        number: u32,
+        block: Block,
+        validators: ValidatorSet,
+        started_at: Timestamp,
+    ) -> Self {
+        Self {
+            number,
+            block,
+            validators,
+            started_at,
+        }
+    }
+
+    /// Round number at this height
+    #[must_use]
+    pub const fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// The candidate block
+    #[must_use]
+    pub const fn block(&self) -> &Block {
+        &self.block
+    }
+
+    /// Record a verifier's attestation for the candidate
+    ///
+    /// Returns `false` if the verifier had already attested.
+    ///
+    /// # Errors
+    /// Returns error if the attestation is for another block, comes from
+    /// outside the validator set, or its signature is invalid
+    pub fn add_attestation(&mut self, attestation: VerifierAttestation) -> Result<bool, ConsensusError> {
+        self.validators.check_attestation(&self.block, &attestation)?;
+
+        if self.block.attestations.iter().any(|a| a.verifier == attestation.verifier) {
+            return Ok(false);
+        }
+        self.block.add_attestation(attestation);
+        Ok(true)
+    }
+
+    /// Whether the attestations reached quorum
+    ///
+    /// With no staked verifiers yet, the proposer's own attestation is enough.
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.validators.is_empty() || self.validators.has_quorum(&self.block)
+    }
+
+    /// Whether the round ran out of time at `now`
+    #[must_use]
+    pub const fn is_expired(&self, now: Timestamp) -> bool {
+        now.saturating_sub(self.started_at) >= ROUND_TIMEOUT_MS
+    }
+
+    /// Take the block, with every attestation gathered
+    #[must_use]
+    pub fn into_block(self) -> Block {
+        self.block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{Hash, Keypair};
+    use crate::types::{Address, HclawAmount};
+
+    fn attestation(kp: &Keypair, block: &Block) -> VerifierAttestation {
+        let mut attestation = VerifierAttestation::new(*kp.public_key(), block.hash, Vec::new());
+        attestation.signature = kp.sign(&attestation.signing_bytes());
+        attestation
+    }
+
+    #[test]
+    fn test_round_completes_at_threshold() {
+        let keys: Vec<_> = (0..3).map(|_| Keypair::generate()).collect();
+        let validators = ValidatorSet::new(
+            keys.iter()
+                .map(|kp| (Address::from_public_key(kp.public_key()), HclawAmount::from_hclaw(1000))),
+        );
+        let proposer = &keys[0];
+        let mut block = Block::new(1, Hash::ZERO, *proposer.public_key(), Vec::new(), Hash::ZERO);
+        block.add_attestation(attestation(proposer, &block));
+        let mut round = AttestationRound::new(0, block.clone(), validators, 0);
+        assert!(!round.is_complete());
+
+        // Duplicates, foreign blocks, outsiders and forged signatures do not count
+        assert!(!round.add_attestation(attestation(proposer, &block)).unwrap());
+        let other = Block::new(1, Hash::ZERO, *keys[1].public_key(), Vec::new(), Hash::ZERO);
+        assert!(round.add_attestation(attestation(&keys[1], &other)).is_err());
+        assert!(round.add_attestation(attestation(&Keypair::generate(), &block)).is_err());
+        let mut forged = attestation(&Keypair::generate(), &block);
+        forged.verifier = *keys[1].public_key();
+        assert!(round.add_attestation(forged).is_err());
+        assert!(!round.is_complete());
+
+        assert!(round.add_attestation(attestation(&keys[1], &block)).unwrap());
+        assert!(round.is_complete());
+        assert_eq!(round.into_block().attestations.len(), 2);
+    }
+
+    #[test]
+    fn test_round_times_out() {
+        let block = Block::new(1, Hash::ZERO, *Keypair::generate().public_key(), Vec::new(), Hash::ZERO);
+        let round = AttestationRound::new(2, block, ValidatorSet::default(), 1_000);
+
+        assert_eq!(round.number(), 2);
+        assert!(!round.is_expired(1_000 + ROUND_TIMEOUT_MS - 1));
+        assert!(round.is_expired(1_000 + ROUND_TIMEOUT_MS));
+    }
+}
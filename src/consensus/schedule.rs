@@ -0,0 +1,193 @@
+//! Proposer schedule.
+//!
+//! Every height has an ordered line of proposers drawn from the staked
+//! verifier set. Each draw picks a verifier with probability proportional
+//! to its stake, seeded by the parent block hash, so every node that agrees
+//! on the parent and the stakes agrees on the line. The first in line is
+//! the leader. If no block has appeared `SLOT_DURATION_MS` after the parent,
+//! the next in line may propose too, and so on down the line.
+
+use crate::crypto::{Hash, Hasher};
+use crate::types::{Address, HclawAmount};
+
+/// How long each proposer in line has before the next one may step in
+pub const SLOT_DURATION_MS: i64 = 5_000;
+
+/// Stake-weighted proposer order over a fixed verifier set
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProposerSchedule {
+    /// Eligible verifiers and their stake, sorted by address
+    validators: Vec<(Address, HclawAmount)>,
+}
+
+impl ProposerSchedule {
+    /// Build a schedule over verifiers and their stake
+    ///
+    /// Verifiers without stake are left out.
+    #[must_use]
+    pub fn new<I>(validators: I) -> Self
+    where
+        I: IntoIterator<Item = (Address, HclawAmount)>,
+    {
+        let mut validators: Vec<_> = validators
+            .into_iter()
+            .filter(|(_, stake)| *stake > HclawAmount::ZERO)
+            .collect();
+        validators.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        Self { validators }
+    }
+
+    /// Whether no verifier is eligible
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.validators.is_empty()
+    }
+
+    /// Number of eligible verifiers
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.validators.len()
+    }
+
+    /// Everyone in line for the block at `height` on top of `parent`, leader first
+    #[must_use]
+    pub fn proposers(&self, parent: &Hash, height: u64) -> Vec<Address> {
+        let mut remaining = self.validators.clone();
+        let mut line = Vec::with_capacity(remaining.len());
+
+        for round in 0u64.. {
+            if remaining.is_empty() {
+                break;
+            }
+            let total = remaining
+                .iter()
+                .fold(0u128, |sum, (_, stake)| sum.saturating_add(stake.raw()));
+            let mut point = draw(parent, height, round) % total;
+            let index = remaining
+                .iter()
+                .position(|(_, stake)| {
+                    if point < stake.raw() {
+                        return true;
+                    }
+                    point -= stake.raw();
+                    false
+                })
+                .unwrap_or(remaining.len() - 1);
+            line.push(remaining.remove(index).0);
+        }
+
+        line
+    }
+
+    /// Leader for the block at `height` on top of `parent`
+    #[must_use]
+    pub fn leader(&self, parent: &Hash, height: u64) -> Option<Address> {
+        self.proposers(parent, height).first().copied()
+    }
+
+    /// Position of `proposer` in line for the block at `height` (0 = leader)
+    #[must_use]
+    pub fn rank(&self, parent: &Hash, height: u64, proposer: &Address) -> Option<usize> {
+        self.proposers(parent, height)
+            .iter()
+            .position(|address| address == proposer)
+    }
+
+    /// Whether `proposer` may produce the block at `height`, `elapsed_ms`
+    /// after its parent's timestamp
+    ///
+    /// An empty schedule (no staked verifiers yet) lets anyone propose.
+    #[must_use]
+    pub fn is_eligible(&self, parent: &Hash, height: u64, proposer: &Address, elapsed_ms: i64) -> bool {
+        if self.is_empty() {
+            return true;
+        }
+        self.rank(parent, height, proposer)
+            .is_some_and(|rank| rank <= fallback_rank(elapsed_ms))
+    }
+}
+
+/// Deepest position in line allowed to propose `elapsed_ms` after the parent
+#[must_use]
+pub fn fallback_rank(elapsed_ms: i64) -> usize {
+    usize::try_from(elapsed_ms.max(0) / SLOT_DURATION_MS).unwrap_or(usize::MAX)
+}
+
+/// Random draw for one round of the line
+fn draw(parent: &Hash, height: u64, round: u64) -> u128 {
+    let mut hasher = Hasher::new();
+    hasher
+        .update(b"proposer/")
+        .update(parent.as_bytes())
+        .update(&height.to_le_bytes())
+        .update(&round.to_le_bytes());
+    let digest = hasher.finalize();
+
+    let mut bytes = [0u8; 16];
+    bytes.copy_from_slice(&digest.as_bytes()[..16]);
+    u128::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{hash_data, Keypair};
+
+    fn address() -> Address {
+        Address::from_public_key(Keypair::generate().public_key())
+    }
+
+    #[test]
+    fn test_line_is_deterministic_and_complete() {
+        let validators: Vec<_> = (1..=4).map(|n| (address(), HclawAmount::from_hclaw(n * 1000))).collect();
+        let schedule = ProposerSchedule::new(validators.clone());
+        let reversed = ProposerSchedule::new(validators.iter().rev().copied());
+        let parent = hash_data(b"parent");
+
+        let line = schedule.proposers(&parent, 7);
+        assert_eq!(line, reversed.proposers(&parent, 7));
+        assert_eq!(line.len(), 4);
+        for (address, _) in &validators {
+            assert!(line.contains(address));
+        }
+
+        // Unstaked verifiers never get a turn
+        let idle = address();
+        let schedule = ProposerSchedule::new(vec![(idle, HclawAmount::ZERO), validators[0]]);
+        assert_eq!(schedule.len(), 1);
+        assert_eq!(schedule.leader(&parent, 7), Some(validators[0].0));
+    }
+
+    #[test]
+    fn test_leadership_follows_stake() {
+        let whale = address();
+        let minnow = address();
+        let schedule = ProposerSchedule::new(vec![
+            (whale, HclawAmount::from_hclaw(99_000)),
+            (minnow, HclawAmount::from_hclaw(1_000)),
+        ]);
+
+        let led = (0..200u64)
+            .filter(|height| schedule.leader(&hash_data(&height.to_le_bytes()), *height) == Some(whale))
+            .count();
+        assert!(led > 180, "whale led {led} of 200 heights");
+    }
+
+    #[test]
+    fn test_fallback_after_missed_slot() {
+        let validators: Vec<_> = (0..3).map(|_| (address(), HclawAmount::from_hclaw(1000))).collect();
+        let schedule = ProposerSchedule::new(validators);
+        let parent = hash_data(b"parent");
+        let line = schedule.proposers(&parent, 1);
+
+        assert!(schedule.is_eligible(&parent, 1, &line[0], 0));
+        assert!(!schedule.is_eligible(&parent, 1, &line[1], SLOT_DURATION_MS - 1));
+        assert!(schedule.is_eligible(&parent, 1, &line[1], SLOT_DURATION_MS));
+        assert!(!schedule.is_eligible(&parent, 1, &line[2], SLOT_DURATION_MS));
+        assert!(schedule.is_eligible(&parent, 1, &line[0], 10 * SLOT_DURATION_MS));
+        assert!(!schedule.is_eligible(&parent, 1, &address(), 10 * SLOT_DURATION_MS));
+
+        // Before anyone stakes, anyone may propose
+        assert!(ProposerSchedule::default().is_eligible(&parent, 1, &address(), 0));
+    }
+}
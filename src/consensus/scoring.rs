@@ -0,0 +1,109 @@
+//! Built-in scoring functions for `ObjectiveThreshold` jobs.
+//!
+//! Scores are integers and computed with checked arithmetic, so every
+//! verifier arrives at the same score or the same error.
+
+use serde::Deserialize;
+
+use crate::types::BuiltinScorer;
+
+/// Score `output` against `input` with a built-in scoring function
+///
+/// # Errors
+/// Returns why the input or output cannot be scored
+pub fn score_builtin(scorer: BuiltinScorer, input: &[u8], output: &[u8]) -> Result<i64, String> {
+    match scorer {
+        BuiltinScorer::TourLength => tour_length(input, output),
+        BuiltinScorer::KnapsackValue => knapsack_value(input, output),
+    }
+}
+
+#[derive(Deserialize)]
+struct Knapsack {
+    capacity: u64,
+    items: Vec<Item>,
+}
+
+#[derive(Deserialize)]
+struct Item {
+    weight: u64,
+    value: u64,
+}
+
+fn tour_length(input: &[u8], output: &[u8]) -> Result<i64, String> {
+    let distances: Vec<Vec<u64>> = parse("input", input)?;
+    let tour: Vec<usize> = parse("output", output)?;
+    let cities = distances.len();
+    if distances.iter().any(|row| row.len() != cities) {
+        return Err("input: distance matrix is not square".to_string());
+    }
+    check_distinct(&tour, cities)?;
+    if tour.len() != cities {
+        return Err(format!("output: tour visits {} of {cities} cities", tour.len()));
+    }
+
+    let mut length: u64 = 0;
+    for (i, from) in tour.iter().enumerate() {
+        let to = tour[(i + 1) % tour.len()];
+        length = length.checked_add(distances[*from][to]).ok_or("tour length overflows")?;
+    }
+    i64::try_from(length).map_err(|_| "tour length overflows".to_string())
+}
+
+fn knapsack_value(input: &[u8], output: &[u8]) -> Result<i64, String> {
+    let knapsack: Knapsack = parse("input", input)?;
+    let packed: Vec<usize> = parse("output", output)?;
+    check_distinct(&packed, knapsack.items.len())?;
+
+    let (mut weight, mut value): (u64, u64) = (0, 0);
+    for index in packed {
+        let item = &knapsack.items[index];
+        weight = weight.checked_add(item.weight).ok_or("packed weight overflows")?;
+        value = value.checked_add(item.value).ok_or("packed value overflows")?;
+    }
+    if weight > knapsack.capacity {
+        return Err(format!("output: packed weight {weight} exceeds capacity {}", knapsack.capacity));
+    }
+    i64::try_from(value).map_err(|_| "packed value overflows".to_string())
+}
+
+fn parse<'a, T: Deserialize<'a>>(what: &str, bytes: &'a [u8]) -> Result<T, String> {
+    serde_json::from_slice(bytes).map_err(|e| format!("{what}: {e}"))
+}
+
+/// Check that every index is below `len` and appears once
+fn check_distinct(indices: &[usize], len: usize) -> Result<(), String> {
+    let mut seen = vec![false; len];
+    for &index in indices {
+        match seen.get_mut(index) {
+            None => return Err(format!("output: index {index} is out of range")),
+            Some(true) => return Err(format!("output: index {index} appears twice")),
+            Some(seen) => *seen = true,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_scores() {
+        let distances = b"[[0, 2, 9], [2, 0, 4], [9, 4, 0]]";
+        assert_eq!(score_builtin(BuiltinScorer::TourLength, distances, b"[0, 1, 2]"), Ok(15));
+        assert_eq!(score_builtin(BuiltinScorer::TourLength, distances, b"[2, 1, 0]"), Ok(15));
+        assert!(score_builtin(BuiltinScorer::TourLength, distances, b"[0, 1]").is_err());
+        assert!(score_builtin(BuiltinScorer::TourLength, distances, b"[0, 1, 1]").is_err());
+        assert!(score_builtin(BuiltinScorer::TourLength, distances, b"[0, 1, 3]").is_err());
+
+        let knapsack = br#"{"capacity": 10, "items": [{"weight": 5, "value": 10}, {"weight": 4, "value": 40}, {"weight": 6, "value": 30}]}"#;
+        assert_eq!(score_builtin(BuiltinScorer::KnapsackValue, knapsack, b"[1, 2]"), Ok(70));
+        assert_eq!(score_builtin(BuiltinScorer::KnapsackValue, knapsack, b"[]"), Ok(0));
+        assert_eq!(
+            score_builtin(BuiltinScorer::KnapsackValue, knapsack, b"[0, 2]"),
+            Err("output: packed weight 11 exceeds capacity 10".to_string())
+        );
+        assert!(score_builtin(BuiltinScorer::KnapsackValue, knapsack, b"not json").is_err());
+    }
+}
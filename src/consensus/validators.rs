@@ -0,0 +1,224 @@
+//! Validator set and attestation quorum.
+//!
+//! A block is final once verifiers holding at least
+//! `CONSENSUS_THRESHOLD_PERCENT` of the active stake have attested to it.
+//! The set is taken from the state the block builds on: each member counts
+//! once, weighted by its effective stake, and attestations from keys outside
+//! the set are refused.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::crypto::PublicKey;
+use crate::types::{Address, Block, HclawAmount, VerifierAttestation};
+use crate::CONSENSUS_THRESHOLD_PERCENT;
+
+use super::{ConsensusError, ProposerSchedule};
+
+/// Active verifiers and the stake each one attests with
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidatorSet {
+    /// Effective stake by verifier address
+    stakes: HashMap<Address, HclawAmount>,
+    /// Sum of all members' stake
+    total_stake: HclawAmount,
+}
+
+impl ValidatorSet {
+    /// Build a set from verifiers and their effective stake
+    ///
+    /// Verifiers without stake are left out.
+    #[must_use]
+    pub fn new<I>(validators: I) -> Self
+    where
+        I: IntoIterator<Item = (Address, HclawAmount)>,
+    {
+        let stakes: HashMap<_, _> = validators
+            .into_iter()
+            .filter(|(_, stake)| *stake > HclawAmount::ZERO)
+            .collect();
+        let total_stake = stakes
+            .values()
+            .fold(HclawAmount::ZERO, |sum, stake| sum.saturating_add(*stake));
+        Self { stakes, total_stake }
+    }
+
+    /// Whether the set has no members
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.stakes.is_empty()
+    }
+
+    /// Number of members
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.stakes.len()
+    }
+
+    /// Addresses of all members
+    pub fn members(&self) -> impl Iterator<Item = &Address> + '_ {
+        self.stakes.keys()
+    }
+
+    /// Proposer line over the members, weighted by their stake
+    #[must_use]
+    pub fn proposer_schedule(&self) -> ProposerSchedule {
+        ProposerSchedule::new(self.stakes.iter().map(|(address, stake)| (*address, *stake)))
+    }
+
+    /// Stake of all members together
+    #[must_use]
+    pub const fn total_stake(&self) -> HclawAmount {
+        self.total_stake
+    }
+
+    /// Stake a verifier attests with, if it is a member
+    #[must_use]
+    pub fn stake_of(&self, verifier: &PublicKey) -> Option<HclawAmount> {
+        self.stakes.get(&Address::from_public_key(verifier)).copied()
+    }
+
+    /// Check that an attestation is for `block` and signed by a member
+    ///
+    /// # Errors
+    /// Returns error if the attestation names another block, its signer is
+    /// not in the set, or its signature is invalid
+    pub fn check_attestation(
+        &self,
+        block: &Block,
+        attestation: &VerifierAttestation,
+    ) -> Result<(), ConsensusError> {
+        if attestation.block_hash != block.hash {
+            return Err(ConsensusError::VerificationFailed {
+                reason: "attestation is for a different block".to_string(),
+            });
+        }
+        if self.stake_of(&attestation.verifier).is_none() {
+            return Err(ConsensusError::VerificationFailed {
+                reason: format!("attestation from unknown verifier {}", attestation.verifier),
+            });
+        }
+        attestation
+            .verify_signature()
+            .map_err(|_| ConsensusError::VerificationFailed {
+                reason: "invalid attestation signature".to_string(),
+            })
+    }
+
+    /// Stake of the members who attested to `block`, each counted once
+    #[must_use]
+    pub fn attested_stake(&self, block: &Block) -> HclawAmount {
+        let mut seen = HashSet::new();
+        block
+            .attestations
+            .iter()
+            .filter(|a| a.block_hash == block.hash && seen.insert(a.verifier))
+            .filter_map(|a| self.stake_of(&a.verifier))
+            .fold(HclawAmount::ZERO, HclawAmount::saturating_add)
+    }
+
+    /// Whether the attested stake reaches the consensus threshold
+    #[must_use]
+    pub fn has_quorum(&self, block: &Block) -> bool {
+        let total = self.total_stake.raw();
+        total > 0
+            && self.attested_stake(block).raw().saturating_mul(100)
+                >= total.saturating_mul(u128::from(CONSENSUS_THRESHOLD_PERCENT))
+    }
+
+    /// Share of the stake that attested to `block`, in percent
+    #[must_use]
+    pub fn quorum_percentage(&self, block: &Block) -> f64 {
+        let total = self.total_stake.raw();
+        if total == 0 {
+            return 0.0;
+        }
+        // Tenths of a percent keep the value small enough for an exact f64
+        let permille = self.attested_stake(block).raw().saturating_mul(1000) / total;
+        f64::from(u32::try_from(permille).unwrap_or(u32::MAX)) / 10.0
+    }
+
+    /// Check every attestation on `block` and that together they reach quorum
+    ///
+    /// # Errors
+    /// Returns error if any attestation is invalid or the attested stake is
+    /// below the threshold
+    pub fn check_block(&self, block: &Block) -> Result<(), ConsensusError> {
+        for attestation in &block.attestations {
+            self.check_attestation(block, attestation)?;
+        }
+        if !self.has_quorum(block) {
+            return Err(ConsensusError::InsufficientConsensus {
+                percentage: self.quorum_percentage(block),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{Hash, Keypair};
+
+    fn attestation(kp: &Keypair, block: &Block) -> VerifierAttestation {
+        let mut attestation = VerifierAttestation::new(*kp.public_key(), block.hash, Vec::new());
+        attestation.signature = kp.sign(&attestation.signing_bytes());
+        attestation
+    }
+
+    fn member(kp: &Keypair, hclaw: u64) -> (Address, HclawAmount) {
+        (Address::from_public_key(kp.public_key()), HclawAmount::from_hclaw(hclaw))
+    }
+
+    #[test]
+    fn test_quorum_is_stake_weighted_and_deduplicated() {
+        let whale = Keypair::generate();
+        let minnows: Vec<_> = (0..3).map(|_| Keypair::generate()).collect();
+        let set = ValidatorSet::new(
+            std::iter::once(member(&whale, 7_000)).chain(minnows.iter().map(|kp| member(kp, 1_000))),
+        );
+        assert_eq!(set.len(), 4);
+        assert_eq!(set.total_stake(), HclawAmount::from_hclaw(10_000));
+
+        // Three minnows attesting over and over are still 30%
+        let mut block = Block::new(1, Hash::ZERO, *whale.public_key(), Vec::new(), Hash::ZERO);
+        for _ in 0..3 {
+            for kp in &minnows {
+                block.add_attestation(attestation(kp, &block));
+            }
+        }
+        assert_eq!(set.attested_stake(&block), HclawAmount::from_hclaw(3_000));
+        assert!(!set.has_quorum(&block));
+        assert!(matches!(
+            set.check_block(&block),
+            Err(ConsensusError::InsufficientConsensus { percentage }) if (percentage - 30.0).abs() < f64::EPSILON
+        ));
+
+        // The whale alone carries 70%
+        let mut block = Block::new(1, Hash::ZERO, *whale.public_key(), Vec::new(), Hash::ZERO);
+        block.add_attestation(attestation(&whale, &block));
+        assert!(set.check_block(&block).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_foreign_attestations() {
+        let member_kp = Keypair::generate();
+        let set = ValidatorSet::new(vec![member(&member_kp, 1_000)]);
+        let block = Block::new(1, Hash::ZERO, *member_kp.public_key(), Vec::new(), Hash::ZERO);
+        let other = Block::new(2, Hash::ZERO, *member_kp.public_key(), Vec::new(), Hash::ZERO);
+
+        assert!(set.check_attestation(&block, &attestation(&member_kp, &block)).is_ok());
+        assert!(set.check_attestation(&block, &attestation(&member_kp, &other)).is_err());
+        assert!(set.check_attestation(&block, &attestation(&Keypair::generate(), &block)).is_err());
+
+        // An outsider's attestation fails the whole block even alongside quorum
+        let mut block = block;
+        block.add_attestation(attestation(&member_kp, &block));
+        block.add_attestation(attestation(&Keypair::generate(), &block));
+        assert!(set.has_quorum(&block));
+        assert!(set.check_block(&block).is_err());
+
+        // No members, no quorum
+        assert!(!ValidatorSet::default().has_quorum(&block));
+    }
+}
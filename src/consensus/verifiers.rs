@@ -0,0 +1,525 @@
+//! Verification backends.
+//!
+//! Each kind of `VerificationSpec` is checked by a `SolutionVerifier`.
+//! `ProofOfVerification` looks the backend up in a `VerifierRegistry` by the
+//! job's `VerificationKind`, so a node can swap in its own backends or add
+//! them for kinds the crate does not check itself.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::crypto::hash_data;
+use crate::schema::SchemaRegistry;
+use crate::types::{JobPacket, Scorer, SolutionCandidate, VerificationKind, VerificationSpec};
+use crate::wasm::{MemoryModules, ModuleSource, WasmError, WasmRuntime};
+
+use super::{score_builtin, ConsensusError, SolutionVerifier, Verdict};
+
+/// Checks `HashMatch` specs: the output must hash to the expected hash
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HashMatchVerifier;
+
+impl SolutionVerifier for HashMatchVerifier {
+    fn verify(&self, job: &JobPacket, solution: &SolutionCandidate) -> Result<Verdict, ConsensusError> {
+        let VerificationSpec::HashMatch { expected_hash } = &job.verification else {
+            return Err(ConsensusError::UnsupportedSpec(job.verification.kind()));
+        };
+
+        let actual_hash = hash_data(&solution.output);
+        if actual_hash == *expected_hash {
+            Ok(Verdict::pass())
+        } else {
+            Ok(Verdict::fail(format!(
+                "Hash mismatch: expected {}, got {}",
+                expected_hash.to_hex(),
+                actual_hash.to_hex()
+            )))
+        }
+    }
+}
+
+/// Checks `WasmVerifier` specs by running the module in the sandboxed
+/// `WasmRuntime`
+///
+/// A module this node does not have, or whose bytes do not match its hash,
+/// says nothing about the solution, so no verdict is reached; every other
+/// failure fails the solution with the error's code and message.
+pub struct WasmModuleVerifier {
+    runtime: WasmRuntime,
+    modules: Arc<dyn ModuleSource>,
+}
+
+impl Default for WasmModuleVerifier {
+    fn default() -> Self {
+        Self::new(WasmRuntime::default(), Arc::new(MemoryModules::new()))
+    }
+}
+
+impl std::fmt::Debug for WasmModuleVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WasmModuleVerifier").field("runtime", &self.runtime).finish_non_exhaustive()
+    }
+}
+
+impl WasmModuleVerifier {
+    /// Run modules from `modules` in `runtime`
+    #[must_use]
+    pub fn new(runtime: WasmRuntime, modules: Arc<dyn ModuleSource>) -> Self {
+        Self { runtime, modules }
+    }
+}
+
+impl SolutionVerifier for WasmModuleVerifier {
+    fn verify(&self, job: &JobPacket, solution: &SolutionCandidate) -> Result<Verdict, ConsensusError> {
+        let VerificationSpec::WasmVerifier { module_hash, entry_point } = &job.verification else {
+            return Err(ConsensusError::UnsupportedSpec(job.verification.kind()));
+        };
+
+        let outcome = self
+            .modules
+            .load(module_hash)
+            .ok_or(WasmError::ModuleNotFound(*module_hash))
+            .and_then(|wasm| self.runtime.run(&wasm, module_hash, entry_point, &job.input, &solution.output));
+        match outcome {
+            Ok(true) => Ok(Verdict::pass()),
+            Ok(false) => Ok(Verdict::fail("rejected: entry point rejected the output")),
+            Err(e) if e.is_local() => Err(ConsensusError::VerificationFailed { reason: e.to_string() }),
+            Err(e) => Ok(Verdict::fail(format!("{}: {e}", e.code()))),
+        }
+    }
+}
+
+/// Checks `JsonSchema` specs: the output must be JSON that satisfies the
+/// schema registered under the spec's hash
+///
+/// A schema this node has not registered says nothing about the solution,
+/// so no verdict is reached. A failing verdict names the path of the first
+/// violation.
+#[derive(Debug, Default)]
+pub struct JsonSchemaVerifier {
+    schemas: Arc<SchemaRegistry>,
+}
+
+impl JsonSchemaVerifier {
+    /// Check outputs against the schemas in `schemas`
+    #[must_use]
+    pub const fn new(schemas: Arc<SchemaRegistry>) -> Self {
+        Self { schemas }
+    }
+}
+
+impl SolutionVerifier for JsonSchemaVerifier {
+    fn verify(&self, job: &JobPacket, solution: &SolutionCandidate) -> Result<Verdict, ConsensusError> {
+        let VerificationSpec::JsonSchema { schema_hash } = &job.verification else {
+            return Err(ConsensusError::UnsupportedSpec(job.verification.kind()));
+        };
+
+        let schema = self.schemas.get(schema_hash).ok_or_else(|| ConsensusError::VerificationFailed {
+            reason: format!("schema {schema_hash} is not registered"),
+        })?;
+        let output: serde_json::Value = match serde_json::from_slice(&solution.output) {
+            Ok(output) => output,
+            Err(e) => return Ok(Verdict::fail(format!("output is not valid JSON: {e}"))),
+        };
+        Ok(match schema.validate(&output) {
+            Ok(()) => Verdict::pass(),
+            Err(violation) => Verdict::fail(violation.to_string()),
+        })
+    }
+}
+
+/// Checks `ObjectiveThreshold` specs: scores the output with a built-in
+/// function or a WASM module's scoring function, then compares the score to
+/// the target
+///
+/// Modules are handled as by `WasmModuleVerifier`. A passing or failing
+/// verdict carries the score; an output that cannot be scored fails.
+pub struct ObjectiveThresholdVerifier {
+    runtime: WasmRuntime,
+    modules: Arc<dyn ModuleSource>,
+}
+
+impl Default for ObjectiveThresholdVerifier {
+    fn default() -> Self {
+        Self::new(WasmRuntime::default(), Arc::new(MemoryModules::new()))
+    }
+}
+
+impl std::fmt::Debug for ObjectiveThresholdVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ObjectiveThresholdVerifier").field("runtime", &self.runtime).finish_non_exhaustive()
+    }
+}
+
+impl ObjectiveThresholdVerifier {
+    /// Run scoring modules from `modules` in `runtime`
+    #[must_use]
+    pub fn new(runtime: WasmRuntime, modules: Arc<dyn ModuleSource>) -> Self {
+        Self { runtime, modules }
+    }
+}
+
+impl SolutionVerifier for ObjectiveThresholdVerifier {
+    fn verify(&self, job: &JobPacket, solution: &SolutionCandidate) -> Result<Verdict, ConsensusError> {
+        let VerificationSpec::ObjectiveThreshold { scorer, comparison, target } = &job.verification else {
+            return Err(ConsensusError::UnsupportedSpec(job.verification.kind()));
+        };
+
+        let scored = match scorer {
+            Scorer::Builtin(builtin) => score_builtin(*builtin, &job.input, &solution.output),
+            Scorer::Wasm { module_hash, function } => {
+                let outcome = self
+                    .modules
+                    .load(module_hash)
+                    .ok_or(WasmError::ModuleNotFound(*module_hash))
+                    .and_then(|wasm| self.runtime.score(&wasm, module_hash, function, &job.input, &solution.output));
+                match outcome {
+                    Ok(score) => Ok(score),
+                    Err(e) if e.is_local() => return Err(ConsensusError::VerificationFailed { reason: e.to_string() }),
+                    Err(e) => Err(format!("{}: {e}", e.code())),
+                }
+            }
+        };
+        let score = match scored {
+            Ok(score) => score,
+            Err(reason) => return Ok(Verdict::fail(format!("cannot score output: {reason}"))),
+        };
+
+        let verdict = if comparison.holds(score, *target) {
+            Verdict::pass()
+        } else {
+            Verdict::fail(format!("score {score} is not {comparison} {target}"))
+        };
+        Ok(verdict.with_score(score))
+    }
+}
+
+/// Checks `TestVectors` specs: runs the output, a WASM program, over each
+/// revealed vector and compares what it produces with the expected output
+///
+/// The vectors come from the chain's copy of the job, which checked them
+/// against the job's commitment when they were revealed; until then no
+/// verdict is reached. A verdict carries the share of vectors passed, in
+/// basis points, as its score; a vector the program fails to run on counts
+/// as failed.
+#[derive(Debug, Default)]
+pub struct TestVectorsVerifier {
+    runtime: WasmRuntime,
+}
+
+impl TestVectorsVerifier {
+    /// Run solution programs in `runtime`
+    #[must_use]
+    pub const fn new(runtime: WasmRuntime) -> Self {
+        Self { runtime }
+    }
+}
+
+impl SolutionVerifier for TestVectorsVerifier {
+    fn verify(&self, job: &JobPacket, solution: &SolutionCandidate) -> Result<Verdict, ConsensusError> {
+        let VerificationSpec::TestVectors { entry_point, min_pass_percent, .. } = &job.verification else {
+            return Err(ConsensusError::UnsupportedSpec(job.verification.kind()));
+        };
+        let vectors = job.revealed_vectors.as_deref().unwrap_or_default();
+        if vectors.is_empty() {
+            return Err(ConsensusError::VerificationFailed { reason: "test vectors are not revealed yet".to_string() });
+        }
+        if let Err(e) = self.runtime.check_program(&solution.output, entry_point) {
+            return Ok(Verdict::fail(format!("{}: {e}", e.code())).with_score(0));
+        }
+
+        let passed = vectors
+            .iter()
+            .filter(|vector| {
+                self.runtime
+                    .execute(&solution.output, entry_point, &vector.input)
+                    .is_ok_and(|output| output == vector.expected)
+            })
+            .count();
+        let total = vectors.len();
+        let score = i64::try_from(passed * 10_000 / total).unwrap_or(i64::MAX);
+        let verdict = if passed * 100 >= usize::from(*min_pass_percent) * total {
+            Verdict::pass()
+        } else {
+            Verdict::fail(format!("passed {passed} of {total} test vectors, {min_pass_percent}% required"))
+        };
+        Ok(verdict.with_score(score))
+    }
+}
+
+/// Refuses `SchellingPoint` specs, which are settled by voting instead
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SchellingPointVerifier;
+
+impl SolutionVerifier for SchellingPointVerifier {
+    fn verify(&self, _job: &JobPacket, _solution: &SolutionCandidate) -> Result<Verdict, ConsensusError> {
+        Err(ConsensusError::VerificationFailed {
+            reason: "Subjective tasks require Schelling consensus".to_string(),
+        })
+    }
+}
+
+/// Verification backends by the kind of spec they check
+pub struct VerifierRegistry {
+    backends: HashMap<VerificationKind, Arc<dyn SolutionVerifier>>,
+}
+
+impl Default for VerifierRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register(VerificationKind::HashMatch, Box::new(HashMatchVerifier));
+        registry.register(VerificationKind::WasmVerifier, Box::new(WasmModuleVerifier::default()));
+        registry.register(VerificationKind::SchellingPoint, Box::new(SchellingPointVerifier));
+        registry.register(VerificationKind::JsonSchema, Box::new(JsonSchemaVerifier::default()));
+        registry.register(VerificationKind::ObjectiveThreshold, Box::new(ObjectiveThresholdVerifier::default()));
+        registry.register(VerificationKind::TestVectors, Box::new(TestVectorsVerifier::default()));
+        registry
+    }
+}
+
+impl std::fmt::Debug for VerifierRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.backends.keys()).finish()
+    }
+}
+
+impl VerifierRegistry {
+    /// Registry with the crate's own backends for every kind
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registry with no backends
+    #[must_use]
+    pub fn empty() -> Self {
+        Self { backends: HashMap::new() }
+    }
+
+    /// Check specs of `kind` with `backend` from now on
+    ///
+    /// Returns the backend it replaces, if any.
+    pub fn register(
+        &mut self,
+        kind: VerificationKind,
+        backend: Box<dyn SolutionVerifier>,
+    ) -> Option<Arc<dyn SolutionVerifier>> {
+        self.backends.insert(kind, Arc::from(backend))
+    }
+
+    /// Backend that checks specs of `kind`
+    ///
+    /// The handle can be moved to another thread to run the check there.
+    #[must_use]
+    pub fn get(&self, kind: VerificationKind) -> Option<Arc<dyn SolutionVerifier>> {
+        self.backends.get(&kind).cloned()
+    }
+
+    /// Check a solution with the backend for its job's spec
+    ///
+    /// # Errors
+    /// Returns error if no backend is registered for the spec, or the
+    /// backend cannot check the solution
+    pub fn verify(&self, job: &JobPacket, solution: &SolutionCandidate) -> Result<Verdict, ConsensusError> {
+        let kind = job.verification.kind();
+        self.get(kind)
+            .ok_or(ConsensusError::UnsupportedSpec(kind))?
+            .verify(job, solution)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+    use crate::types::{BuiltinScorer, Comparison, HclawAmount, JobType, TestVector};
+
+    fn job_with(verification: VerificationSpec) -> JobPacket {
+        JobPacket::new(
+            JobType::Deterministic,
+            *Keypair::generate().public_key(),
+            b"input".to_vec(),
+            "Test job".to_string(),
+            HclawAmount::from_hclaw(10),
+            HclawAmount::from_hclaw(1),
+            verification,
+            3600,
+        )
+    }
+
+    /// Passes every solution whose output is not empty
+    struct NonEmpty;
+
+    impl SolutionVerifier for NonEmpty {
+        fn verify(&self, _job: &JobPacket, solution: &SolutionCandidate) -> Result<Verdict, ConsensusError> {
+            Ok(if solution.output.is_empty() { Verdict::fail("empty output") } else { Verdict::pass() })
+        }
+    }
+
+    #[test]
+    fn test_registry_dispatches_by_kind() {
+        let job = job_with(VerificationSpec::HashMatch { expected_hash: hash_data(b"right") });
+        let solver = *Keypair::generate().public_key();
+        let right = SolutionCandidate::new(job.id, solver, b"right".to_vec());
+        let wrong = SolutionCandidate::new(job.id, solver, b"wrong".to_vec());
+
+        let mut registry = VerifierRegistry::new();
+        assert!(registry.verify(&job, &right).unwrap().passed);
+        assert!(!registry.verify(&job, &wrong).unwrap().passed);
+
+        // A backend handed another kind of spec refuses it
+        let wasm = VerificationSpec::WasmVerifier { module_hash: hash_data(b"module"), entry_point: "verify".to_string() };
+        assert!(matches!(
+            HashMatchVerifier.verify(&job_with(wasm), &right),
+            Err(ConsensusError::UnsupportedSpec(VerificationKind::WasmVerifier))
+        ));
+
+        // Our own backend takes over the kind
+        assert!(registry.register(VerificationKind::HashMatch, Box::new(NonEmpty)).is_some());
+        assert!(registry.verify(&job, &wrong).unwrap().passed);
+
+        assert!(matches!(
+            VerifierRegistry::empty().verify(&job, &right),
+            Err(ConsensusError::UnsupportedSpec(VerificationKind::HashMatch))
+        ));
+    }
+
+    #[test]
+    fn test_wasm_verifier_runs_registered_module() {
+        let wasm = wat::parse_str(
+            r#"(module
+                 (memory (export "memory") 1)
+                 (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                 (func (export "nonempty") (param i32 i32 i32 i32) (result i32)
+                   (i32.ne (local.get 3) (i32.const 0))))"#,
+        )
+        .unwrap();
+        let modules = Arc::new(MemoryModules::new());
+        let verifier = WasmModuleVerifier::new(WasmRuntime::default(), modules.clone());
+        let module_hash = hash_data(&wasm);
+        let job = job_with(VerificationSpec::WasmVerifier { module_hash, entry_point: "nonempty".to_string() });
+        let solver = *Keypair::generate().public_key();
+        let output = SolutionCandidate::new(job.id, solver, b"output".to_vec());
+
+        // Without the module there is no verdict either way
+        assert!(matches!(verifier.verify(&job, &output), Err(ConsensusError::VerificationFailed { .. })));
+
+        modules.insert(wasm);
+        assert!(verifier.verify(&job, &output).unwrap().passed);
+        let empty = SolutionCandidate::new(job.id, solver, Vec::new());
+        assert!(!verifier.verify(&job, &empty).unwrap().passed);
+    }
+
+    #[test]
+    fn test_objective_threshold_records_score() {
+        let solver = *Keypair::generate().public_key();
+        let knapsack = br#"{"capacity": 10, "items": [{"weight": 5, "value": 10}, {"weight": 4, "value": 40}, {"weight": 6, "value": 30}]}"#;
+        let mut job = job_with(VerificationSpec::ObjectiveThreshold {
+            scorer: Scorer::Builtin(BuiltinScorer::KnapsackValue),
+            comparison: Comparison::AtLeast,
+            target: 60,
+        });
+        job.input = knapsack.to_vec();
+        let verifier = ObjectiveThresholdVerifier::default();
+
+        let best = verifier.verify(&job, &SolutionCandidate::new(job.id, solver, b"[1, 2]".to_vec())).unwrap();
+        assert_eq!((best.passed, best.score), (true, Some(70)));
+        let short = verifier.verify(&job, &SolutionCandidate::new(job.id, solver, b"[0, 1]".to_vec())).unwrap();
+        assert_eq!((short.passed, short.score), (false, Some(50)));
+        assert_eq!(short.error.as_deref(), Some("score 50 is not >= 60"));
+        let overweight = verifier.verify(&job, &SolutionCandidate::new(job.id, solver, b"[0, 2]".to_vec())).unwrap();
+        assert_eq!((overweight.passed, overweight.score), (false, None));
+
+        // Scored by a module: the output's length in bytes
+        let wasm = wat::parse_str(
+            r#"(module
+                 (memory (export "memory") 1)
+                 (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                 (func (export "length") (param i32 i32 i32 i32) (result i64)
+                   (i64.extend_i32_u (local.get 3))))"#,
+        )
+        .unwrap();
+        let modules = Arc::new(MemoryModules::new());
+        let verifier = ObjectiveThresholdVerifier::new(WasmRuntime::default(), modules.clone());
+        let job = job_with(VerificationSpec::ObjectiveThreshold {
+            scorer: Scorer::Wasm { module_hash: hash_data(&wasm), function: "length".to_string() },
+            comparison: Comparison::LessThan,
+            target: 4,
+        });
+        let output = SolutionCandidate::new(job.id, solver, b"abc".to_vec());
+        assert!(matches!(verifier.verify(&job, &output), Err(ConsensusError::VerificationFailed { .. })));
+        modules.insert(wasm);
+        let verdict = verifier.verify(&job, &output).unwrap();
+        assert_eq!((verdict.passed, verdict.score), (true, Some(3)));
+    }
+
+    #[test]
+    fn test_vectors_verifier_runs_program_over_revealed_vectors() {
+        // Echoes its input, except that it turns "2" into "4"
+        let program = wat::parse_str(
+            r#"(module
+                 (memory (export "memory") 1)
+                 (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                 (func (export "run") (param $ptr i32) (param $len i32) (result i64)
+                   (if (i32.eq (i32.load8_u (local.get $ptr)) (i32.const 50))
+                     (then (i32.store8 (local.get $ptr) (i32.const 52))))
+                   (i64.or (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                           (i64.extend_i32_u (local.get $len)))))"#,
+        )
+        .unwrap();
+        let vectors = vec![
+            TestVector { input: b"2".to_vec(), expected: b"4".to_vec() },
+            TestVector { input: b"3".to_vec(), expected: b"6".to_vec() },
+        ];
+        let mut job = job_with(VerificationSpec::TestVectors {
+            commitment: TestVector::commit(&vectors, &[1; 32]),
+            entry_point: "run".to_string(),
+            min_pass_percent: 50,
+            reveal_at: 0,
+        });
+        let solver = *Keypair::generate().public_key();
+        let solution = SolutionCandidate::new(job.id, solver, program);
+        let verifier = TestVectorsVerifier::default();
+
+        // Nothing to check against before the reveal
+        assert!(matches!(verifier.verify(&job, &solution), Err(ConsensusError::VerificationFailed { .. })));
+
+        job.revealed_vectors = Some(vectors);
+        let verdict = verifier.verify(&job, &solution).unwrap();
+        assert_eq!((verdict.passed, verdict.score), (true, Some(5_000)));
+
+        if let VerificationSpec::TestVectors { min_pass_percent, .. } = &mut job.verification {
+            *min_pass_percent = 100;
+        }
+        let verdict = verifier.verify(&job, &solution).unwrap();
+        assert_eq!(verdict.error.as_deref(), Some("passed 1 of 2 test vectors, 100% required"));
+
+        let garbage = SolutionCandidate::new(job.id, solver, b"not wasm".to_vec());
+        let verdict = verifier.verify(&job, &garbage).unwrap();
+        assert_eq!((verdict.passed, verdict.score), (false, Some(0)));
+    }
+
+    #[test]
+    fn test_json_schema_verifier_reports_violation_path() {
+        let schema = br#"{"type": "object", "required": ["label"], "properties": {"label": {"enum": ["spam", "ham"]}}}"#;
+        let schemas = Arc::new(SchemaRegistry::new());
+        let verifier = JsonSchemaVerifier::new(schemas.clone());
+        let job = job_with(VerificationSpec::JsonSchema { schema_hash: hash_data(schema) });
+        let solver = *Keypair::generate().public_key();
+        let output = |bytes: &[u8]| SolutionCandidate::new(job.id, solver, bytes.to_vec());
+
+        // Without the schema there is no verdict either way
+        assert!(matches!(
+            verifier.verify(&job, &output(br#"{"label": "spam"}"#)),
+            Err(ConsensusError::VerificationFailed { .. })
+        ));
+
+        assert_eq!(schemas.register(schema).unwrap(), hash_data(schema));
+        assert!(verifier.verify(&job, &output(br#"{"label": "spam"}"#)).unwrap().passed);
+
+        let verdict = verifier.verify(&job, &output(br#"{"label": "eggs"}"#)).unwrap();
+        assert_eq!(verdict.error.as_deref(), Some(r#"$.label: "eggs" is not one of the allowed values"#));
+        let verdict = verifier.verify(&job, &output(b"spam")).unwrap();
+        assert!(verdict.error.unwrap().starts_with("output is not valid JSON"));
+    }
+}
@@ -221,6 +221,12 @@ impl Keypair {
         &self.public
     }
 
+    /// Get the secret key
+    #[must_use]
+    pub const fn secret_key(&self) -> &SecretKey {
+        &self.secret
+    }
+
     /// Sign a message
     #[must_use]
     pub fn sign(&self, message: &[u8]) -> Signature {
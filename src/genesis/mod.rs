@@ -0,0 +1,379 @@
+//! Genesis configuration.
+//!
+//! A genesis spec fixes everything the first block depends on: the chain
+//! id, a timestamp, initial balances, the staked verifier set and the token
+//! economics parameters. Every node that loads the same spec derives the
+//! same genesis block and state root.
+//!
+//! Keys, addresses and amounts are written as strings so the file can be
+//! edited by hand:
+//!
+//! ```json
+//! {
+//!   "chain_id": "hardclaw-devnet",
+//!   "timestamp": 1767225600000,
+//!   "accounts": [{ "address": "0x…", "balance": "1000.0" }],
+//!   "validators": [{ "public_key": "…", "stake": "5000.0" }],
+//!   "economics": { "solver_share": 95, "verifier_share": 4, "burn_share": 1,
+//!                  "min_burn_to_request": "0.001", "target_block_reward": "10.0" }
+//! }
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{hash_data, Hash, PublicKey};
+use crate::state::{AccountState, ChainState};
+use crate::tokenomics::TokenEconomicsConfig;
+use crate::types::{serde_decimal, Address, Block, HclawAmount, Timestamp, MAX_SUPPLY};
+
+/// An account funded at genesis
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisAccount {
+    /// Account address (hex, `0x` prefix optional)
+    pub address: String,
+    /// Initial balance
+    #[serde(with = "serde_decimal")]
+    pub balance: HclawAmount,
+}
+
+/// A verifier staked at genesis
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisValidator {
+    /// Verifier public key (hex)
+    pub public_key: String,
+    /// Stake, credited to the verifier's account and locked
+    #[serde(with = "serde_decimal")]
+    pub stake: HclawAmount,
+}
+
+/// Genesis spec file contents
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GenesisSpec {
+    /// Name of the chain; nodes with different ids never share blocks
+    pub chain_id: String,
+    /// Genesis block timestamp (unix millis)
+    pub timestamp: Timestamp,
+    /// Initial account balances
+    #[serde(default)]
+    pub accounts: Vec<GenesisAccount>,
+    /// Initial staked verifier set; the first one proposes genesis
+    pub validators: Vec<GenesisValidator>,
+    /// Token economics parameters
+    #[serde(default)]
+    pub economics: TokenEconomicsConfig,
+}
+
+impl GenesisSpec {
+    /// A spec with a single staked verifier and no other accounts
+    ///
+    /// Used for local networks that have no shared spec yet.
+    #[must_use]
+    pub fn single_validator(
+        chain_id: String,
+        validator: &PublicKey,
+        stake: HclawAmount,
+        timestamp: Timestamp,
+    ) -> Self {
+        Self {
+            chain_id,
+            timestamp,
+            accounts: Vec::new(),
+            validators: vec![GenesisValidator {
+                public_key: validator.to_hex(),
+                stake,
+            }],
+            economics: TokenEconomicsConfig::default(),
+        }
+    }
+
+    /// Parse a spec from JSON
+    ///
+    /// # Errors
+    /// Returns error if the JSON is malformed or the spec is invalid
+    pub fn from_json(json: &str) -> Result<Self, GenesisError> {
+        let spec: Self =
+            serde_json::from_str(json).map_err(|e| GenesisError::Parse(e.to_string()))?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    /// Serialize the spec as pretty JSON
+    ///
+    /// # Errors
+    /// Returns error if serialization fails
+    pub fn to_json(&self) -> Result<String, GenesisError> {
+        serde_json::to_string_pretty(self).map_err(|e| GenesisError::Parse(e.to_string()))
+    }
+
+    /// Load a spec file
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be read or the spec is invalid
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, GenesisError> {
+        let json = std::fs::read_to_string(path).map_err(|e| GenesisError::Io(e.to_string()))?;
+        Self::from_json(&json)
+    }
+
+    /// Write the spec to a file, creating parent directories
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be written
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), GenesisError> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| GenesisError::Io(e.to_string()))?;
+        }
+        std::fs::write(path, self.to_json()?).map_err(|e| GenesisError::Io(e.to_string()))
+    }
+
+    /// Check the spec is internally consistent
+    ///
+    /// # Errors
+    /// Returns error describing the first problem found
+    pub fn validate(&self) -> Result<(), GenesisError> {
+        if self.chain_id.trim().is_empty() {
+            return Err(GenesisError::Invalid("chain_id is empty".to_string()));
+        }
+        if !self.economics.is_valid() {
+            return Err(GenesisError::Invalid("fee shares must sum to 100".to_string()));
+        }
+        if self.validators.is_empty() {
+            return Err(GenesisError::Invalid("at least one validator is required".to_string()));
+        }
+
+        let mut seen = HashSet::new();
+        for account in &self.accounts {
+            if !seen.insert(parse_address(&account.address)?) {
+                return Err(GenesisError::Invalid(format!(
+                    "duplicate account {}",
+                    account.address
+                )));
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for validator in &self.validators {
+            if !seen.insert(parse_public_key(&validator.public_key)?) {
+                return Err(GenesisError::Invalid(format!(
+                    "duplicate validator {}",
+                    validator.public_key
+                )));
+            }
+            if validator.stake.is_zero() {
+                return Err(GenesisError::Invalid(format!(
+                    "validator {} has no stake",
+                    validator.public_key
+                )));
+            }
+        }
+
+        let supply = self
+            .allocations()?
+            .iter()
+            .try_fold(HclawAmount::ZERO, |total, (_, account)| total.checked_add(account.balance));
+        if supply.is_none_or(|total| total.raw() > MAX_SUPPLY) {
+            return Err(GenesisError::Invalid("allocations exceed max supply".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Initial account states, ordered by address
+    ///
+    /// Validator stake is added to the validator's balance and locked.
+    ///
+    /// # Errors
+    /// Returns error if an address or key does not parse
+    pub fn allocations(&self) -> Result<Vec<(Address, AccountState)>, GenesisError> {
+        let mut accounts: HashMap<Address, AccountState> = HashMap::new();
+
+        for account in &self.accounts {
+            let entry = accounts.entry(parse_address(&account.address)?).or_default();
+            entry.credit(account.balance);
+        }
+        for validator in &self.validators {
+            let key = parse_public_key(&validator.public_key)?;
+            let entry = accounts.entry(Address::from_public_key(&key)).or_default();
+            entry.credit(validator.stake);
+            entry.staked = entry.staked.saturating_add(validator.stake);
+        }
+
+        let mut accounts: Vec<_> = accounts.into_iter().collect();
+        accounts.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        Ok(accounts)
+    }
+
+    /// Public keys of the initial verifier set, in spec order
+    ///
+    /// # Errors
+    /// Returns error if a key does not parse
+    pub fn validator_keys(&self) -> Result<Vec<PublicKey>, GenesisError> {
+        self.validators
+            .iter()
+            .map(|v| parse_public_key(&v.public_key))
+            .collect()
+    }
+
+    /// Commitment to every parameter in the spec
+    ///
+    /// Taken by the genesis block as its parent hash, so specs that differ in
+    /// anything (including economics, which the state root does not cover)
+    /// produce different chains.
+    ///
+    /// # Errors
+    /// Returns error if an address or key does not parse
+    pub fn hash(&self) -> Result<Hash, GenesisError> {
+        let mut data = Vec::new();
+        data.extend_from_slice(b"hardclaw-genesis");
+        data.extend_from_slice(&(self.chain_id.len() as u64).to_le_bytes());
+        data.extend_from_slice(self.chain_id.as_bytes());
+        data.extend_from_slice(&self.timestamp.to_le_bytes());
+
+        for (address, account) in self.allocations()? {
+            data.extend_from_slice(address.as_bytes());
+            data.extend_from_slice(&account.balance.raw().to_le_bytes());
+            data.extend_from_slice(&account.staked.raw().to_le_bytes());
+        }
+        for key in self.validator_keys()? {
+            data.extend_from_slice(key.as_bytes());
+        }
+
+        let economics = &self.economics;
+        data.extend_from_slice(&[
+            economics.solver_share,
+            economics.verifier_share,
+            economics.burn_share,
+        ]);
+        data.extend_from_slice(&economics.min_burn_to_request.raw().to_le_bytes());
+        data.extend_from_slice(&economics.target_block_reward.raw().to_le_bytes());
+
+        Ok(hash_data(&data))
+    }
+
+    /// Derive the genesis block without touching any existing state
+    ///
+    /// # Errors
+    /// Returns error if the spec is invalid
+    pub fn block(&self) -> Result<Block, GenesisError> {
+        let mut state = ChainState::new();
+        state
+            .init_genesis(self)
+            .map_err(|e| GenesisError::Invalid(e.to_string()))?;
+        state
+            .tip()
+            .cloned()
+            .ok_or_else(|| GenesisError::Invalid("genesis block was not applied".to_string()))
+    }
+}
+
+fn parse_address(s: &str) -> Result<Address, GenesisError> {
+    Address::from_hex(s).map_err(|e| GenesisError::Invalid(format!("address {s}: {e}")))
+}
+
+fn parse_public_key(s: &str) -> Result<PublicKey, GenesisError> {
+    PublicKey::from_hex(s).map_err(|e| GenesisError::Invalid(format!("public key {s}: {e}")))
+}
+
+/// Genesis spec errors
+#[derive(Debug, thiserror::Error)]
+pub enum GenesisError {
+    /// Spec file could not be read or written
+    #[error("genesis file error: {0}")]
+    Io(String),
+    /// Spec is not valid JSON for this format
+    #[error("failed to parse genesis spec: {0}")]
+    Parse(String),
+    /// Spec is well-formed but inconsistent
+    #[error("invalid genesis spec: {0}")]
+    Invalid(String),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+
+    fn spec() -> GenesisSpec {
+        let validator = Keypair::generate();
+        let mut spec = GenesisSpec::single_validator(
+            "hardclaw-test".to_string(),
+            validator.public_key(),
+            HclawAmount::from_hclaw(5000),
+            1_767_225_600_000,
+        );
+        spec.accounts.push(GenesisAccount {
+            address: Address::from_public_key(Keypair::generate().public_key()).to_hex(),
+            balance: HclawAmount::from_decimal_str("1000.5").unwrap(),
+        });
+        spec
+    }
+
+    #[test]
+    fn test_genesis_is_deterministic() {
+        let spec = spec();
+        let reparsed = GenesisSpec::from_json(&spec.to_json().unwrap()).unwrap();
+        assert_eq!(reparsed, spec);
+
+        let a = spec.block().unwrap();
+        let b = reparsed.block().unwrap();
+        assert_eq!(a.hash, b.hash);
+        assert_eq!(a.header.state_root, b.header.state_root);
+        assert_eq!(a.header.timestamp, spec.timestamp);
+        assert_eq!(a.header.parent_hash, spec.hash().unwrap());
+
+        // Any parameter change gives a different chain
+        let mut other = spec;
+        other.chain_id = "hardclaw-other".to_string();
+        assert_ne!(other.block().unwrap().hash, a.hash);
+    }
+
+    #[test]
+    fn test_genesis_allocations() {
+        let spec = spec();
+        let block = spec.block().unwrap();
+
+        let mut state = ChainState::new();
+        state.init_genesis(&spec).unwrap();
+        assert_eq!(state.tip().unwrap().hash, block.hash);
+
+        let validator = Address::from_public_key(&spec.validator_keys().unwrap()[0]);
+        let account = state.get_account(&validator).unwrap();
+        assert_eq!(account.staked, HclawAmount::from_hclaw(5000));
+        assert_eq!(account.available_balance(), HclawAmount::ZERO);
+
+        let funded = Address::from_hex(&spec.accounts[0].address).unwrap();
+        assert_eq!(state.balance_of(&funded), HclawAmount::from_decimal_str("1000.5").unwrap());
+
+        // Restarting with the same spec is accepted, a different one is not
+        assert_eq!(state.init_genesis(&spec).unwrap(), block.hash);
+        let mut other = spec;
+        other.timestamp += 1;
+        assert!(matches!(
+            state.init_genesis(&other),
+            Err(crate::state::StateError::GenesisMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_specs_rejected() {
+        let mut no_validators = spec();
+        no_validators.validators.clear();
+        assert!(matches!(no_validators.validate(), Err(GenesisError::Invalid(_))));
+
+        let mut bad_shares = spec();
+        bad_shares.economics.burn_share = 50;
+        assert!(matches!(bad_shares.validate(), Err(GenesisError::Invalid(_))));
+
+        let mut bad_key = spec();
+        bad_key.validators[0].public_key = "not hex".to_string();
+        assert!(matches!(bad_key.validate(), Err(GenesisError::Invalid(_))));
+
+        assert!(matches!(
+            GenesisSpec::from_json("{\"chain_id\": 1}"),
+            Err(GenesisError::Parse(_))
+        ));
+    }
+}
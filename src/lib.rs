@@ -36,6 +36,9 @@ pub mod mempool;
 pub mod state;
 pub mod network;
 pub mod wallet;
+pub mod genesis;
+pub mod wasm;
+pub mod schema;
 
 pub use types::{
     Address, JobPacket, SolutionCandidate, Block, BlockHeader,
@@ -54,6 +57,9 @@ pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 /// Consensus threshold (66% = 2/3 majority)
 pub const CONSENSUS_THRESHOLD: f64 = 0.66;
 
+/// Consensus threshold as a whole percentage, for stake arithmetic
+pub const CONSENSUS_THRESHOLD_PERCENT: u8 = 66;
+
 /// Schelling redundancy (jobs sent to N solvers for subjective tasks)
 pub const SCHELLING_REDUNDANCY: usize = 5;
 
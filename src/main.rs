@@ -2,19 +2,32 @@
 //!
 //! Run a full node that participates in the HardClaw network.
 
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tokio::sync::RwLock;
-use tracing::{info, warn, Level};
+use libp2p::PeerId;
+use tokio::sync::{mpsc, RwLock};
+use tracing::{debug, info, warn, Level};
 use tracing_subscriber::FmtSubscriber;
 
 use hardclaw::{
-    crypto::Keypair,
-    types::{Address, Block},
+    consensus::{
+        AttestationRound, ConsensusError, EquivocationDetector, JsonSchemaVerifier, ObjectiveThresholdVerifier,
+        WasmModuleVerifier,
+    },
+    crypto::{hash_data, Hash, Keypair, PublicKey, SecretKey},
+    genesis::GenesisSpec,
+    types::{now_millis, Address, Evidence, Transaction, TransactionKind, VerificationKind, VerificationSpec},
     verifier::{Verifier, VerifierConfig},
+    wallet::Wallet,
+    wasm::{ModuleCache, ModuleSource, WasmRuntime},
+    schema::SchemaRegistry,
     tokenomics::TokenEconomics,
     mempool::Mempool,
-    state::ChainState,
-    network::{NetworkConfig, NetworkNode, NetworkEvent, PeerInfo},
+    state::{ChainState, ImportOutcome, RetentionMode, StateSnapshot},
+    network::{
+        NetworkCommand, NetworkConfig, NetworkEvent, NetworkMessage, NetworkNode, PeerInfo,
+        SyncRequest, SyncResponse, MAX_BLOCKS_PER_REQUEST, MAX_SYNC_RESPONSE_BYTES,
+    },
 };
 
 /// Node configuration
@@ -32,6 +45,12 @@ struct NodeConfig {
     port: u16,
     /// External address for NAT traversal
     external_addr: Option<String>,
+    /// Block to fast sync from instead of starting at genesis
+    trusted_block: Option<Hash>,
+    /// How much block history to keep
+    retention: RetentionMode,
+    /// Genesis spec file (default: `genesis.json` in the data directory)
+    genesis: Option<String>,
 }
 
 impl Default for NodeConfig {
@@ -43,6 +62,9 @@ impl Default for NodeConfig {
             data_dir: ".hardclaw".to_string(),
             port: 9000,
             external_addr: None,
+            trusted_block: None,
+            retention: RetentionMode::Archive,
+            genesis: None,
         }
     }
 }
@@ -53,6 +75,8 @@ struct HardClawNode {
     keypair: Keypair,
     /// Configuration
     config: NodeConfig,
+    /// Genesis spec the chain starts from
+    genesis: GenesisSpec,
     /// Chain state
     state: Arc<RwLock<ChainState>>,
     /// Mempool
@@ -61,41 +85,85 @@ struct HardClawNode {
     economics: Arc<RwLock<TokenEconomics>>,
     /// Verifier (if running as verifier)
     verifier: Option<Verifier>,
+    /// Bytes of published verifier modules
+    modules: Arc<ModuleCache>,
+    /// Commands to the network task (set once it is running)
+    network: Option<mpsc::Sender<NetworkCommand>>,
+    /// Our candidate block, while it gathers attestations
+    round: Option<AttestationRound>,
+    /// Height and number of the next round we start
+    next_round: (u64, u32),
+    /// Watches proposals and attestations for double signing
+    detector: EquivocationDetector,
+    /// Last snapshot exported for peers, by block (`None` if too large to send)
+    served_snapshot: Option<(Hash, Option<Arc<StateSnapshot>>)>,
 }
 
 impl HardClawNode {
     /// Create a new node
-    fn new(keypair: Keypair, config: NodeConfig) -> Self {
+    fn new(keypair: Keypair, config: NodeConfig) -> anyhow::Result<Self> {
+        let modules = Arc::new(ModuleCache::open(Path::new(&config.data_dir).join("modules"))?);
         let verifier = if config.is_verifier {
-            Some(Verifier::new(
-                Keypair::generate(),
+            let mut verifier = Verifier::new(
+                copy_keypair(&keypair)?,
                 config.verifier.clone(),
-            ))
+            );
+            verifier.register_verifier(
+                VerificationKind::WasmVerifier,
+                Box::new(WasmModuleVerifier::new(WasmRuntime::default(), modules.clone())),
+            );
+            verifier.register_verifier(
+                VerificationKind::ObjectiveThreshold,
+                Box::new(ObjectiveThresholdVerifier::new(WasmRuntime::default(), modules.clone())),
+            );
+            let schemas = load_schemas(&config.data_dir)?;
+            verifier.register_verifier(VerificationKind::JsonSchema, Box::new(JsonSchemaVerifier::new(schemas)));
+            Some(verifier)
         } else {
             None
         };
 
-        Self {
+        let validator = verifier.as_ref().map_or(*keypair.public_key(), |v| *v.public_key());
+        let genesis = load_genesis(&config, &validator)?;
+        info!("Chain {} (genesis spec {})", genesis.chain_id, genesis.hash()?);
+
+        // Chain state lives under the data directory so it survives restarts
+        let mut state = ChainState::open(Path::new(&config.data_dir).join("chain"))?;
+        state.set_retention(config.retention);
+        state.set_block_limits(config.verifier.block_config.clone());
+        let economics = TokenEconomics::new(genesis.economics.clone());
+
+        Ok(Self {
             keypair,
             config,
-            state: Arc::new(RwLock::new(ChainState::new())),
+            genesis,
+            state: Arc::new(RwLock::new(state)),
             mempool: Arc::new(RwLock::new(Mempool::new())),
-            economics: Arc::new(RwLock::new(TokenEconomics::default())),
+            economics: Arc::new(RwLock::new(economics)),
             verifier,
-        }
+            modules,
+            network: None,
+            round: None,
+            next_round: (0, 0),
+            detector: EquivocationDetector::new(),
+            served_snapshot: None,
+        })
     }
 
     /// Initialize the node
     async fn init(&mut self) -> anyhow::Result<()> {
         info!("Initializing HardClaw node...");
 
-        // Initialize genesis block if needed
+        // Start from the genesis spec, or check the stored chain matches it
         let mut state = self.state.write().await;
-        if state.height() == 0 {
-            info!("Creating genesis block...");
-            let genesis = Block::genesis(*self.keypair.public_key());
-            state.apply_block(genesis)?;
+        if self.awaiting_snapshot(&state) {
+            if let Some(trusted) = self.config.trusted_block {
+                info!("Waiting for a peer to serve a snapshot at block {}", trusted);
+            }
+            return Ok(());
         }
+        let genesis = state.init_genesis(&self.genesis)?;
+        info!("Genesis block {}", genesis);
 
         info!("Node initialized at height {}", state.height());
         Ok(())
@@ -127,6 +195,8 @@ impl HardClawNode {
 
         // Start network
         network.start().await?;
+        self.network = Some(network.commands());
+        tokio::spawn(async move { network.run().await });
 
         if self.verifier.is_some() {
             info!("Running as verifier");
@@ -154,33 +224,141 @@ impl HardClawNode {
     }
 
     /// Handle network events
-    async fn handle_network_event(&self, event: NetworkEvent) {
+    async fn handle_network_event(&mut self, event: NetworkEvent) {
         match event {
             NetworkEvent::PeerConnected(peer) => {
                 info!("Peer connected: {}", peer);
+                self.request(peer, SyncRequest::Status).await;
             }
             NetworkEvent::PeerDisconnected(peer) => {
                 info!("Peer disconnected: {}", peer);
             }
+            NetworkEvent::TransactionReceived(tx) => {
+                info!("Received transaction: {}", tx.id);
+                let st = self.state.read().await;
+                if let TransactionKind::SubmitJob(job) = &tx.kind {
+                    if let Err(e) = st.check_job_admission(job) {
+                        warn!("Rejected job submission {}: {}", tx.id, e);
+                        return;
+                    }
+                }
+                let sender = st.get_account(&tx.sender_address()).cloned().unwrap_or_default();
+                drop(st);
+                self.cache_modules(std::slice::from_ref(&tx));
+                let mut mp = self.mempool.write().await;
+                let job = match &tx.kind {
+                    TransactionKind::SubmitJob(job) => Some(job.as_ref().clone()),
+                    _ => None,
+                };
+                match mp.add_transaction(tx, &sender) {
+                    // Solutions can queue for a job before its submission lands on chain
+                    Ok(()) => {
+                        if let Some(job) = job {
+                            if let Err(e) = mp.add_job(job) {
+                                debug!("Submitted job not queued: {}", e);
+                            }
+                        }
+                    }
+                    Err(e) => warn!("Failed to add transaction to mempool: {}", e),
+                }
+            }
             NetworkEvent::JobReceived(job) => {
                 info!("Received job: {}", job.id);
-                let mut mp = self.mempool.write().await;
-                if let Err(e) = mp.add_job(job) {
+                // Jobs only enter chain state through a SubmitJob transaction,
+                // which escrows the bounty
+                if let Err(e) = self.state.read().await.check_job_admission(&job) {
+                    warn!("Rejected job {}: {}", job.id, e);
+                    return;
+                }
+                if let Err(e) = self.mempool.write().await.add_job(job) {
                     warn!("Failed to add job to mempool: {}", e);
                 }
             }
             NetworkEvent::SolutionReceived(solution) => {
                 info!("Received solution: {}", solution.id);
+                // Solutions reach chain state only with the block settling them
+                if let Err(e) = self.mempool.write().await.add_solution(solution) {
+                    warn!("Failed to add solution to mempool: {}", e);
+                }
             }
             NetworkEvent::BlockReceived(block) => {
                 info!("Received block {} at height {}", block.hash, block.header.height);
+                let found = self.detector.observe_block(&block);
+                self.report_evidence(found).await;
                 let mut st = self.state.write().await;
-                if let Err(e) = st.apply_block(block) {
-                    warn!("Failed to apply block: {}", e);
+                if self.awaiting_snapshot(&st) {
+                    return;
+                }
+                self.cache_modules(&block.transactions);
+                match st.import_block(block) {
+                    Ok(outcome) => log_import(&outcome, &st),
+                    Err(e) => warn!("Failed to import block: {}", e),
+                }
+                self.detector.prune_below(st.finalized_height());
+                self.prune_mempool(&st).await;
+            }
+            NetworkEvent::ProposalReceived(block) => {
+                let found = self.detector.observe_block(&block);
+                self.report_evidence(found).await;
+                let Some(verifier) = &self.verifier else {
+                    return;
+                };
+                if block.header.proposer == *verifier.public_key() {
+                    return;
+                }
+                info!("Received proposal {} at height {}", block.hash, block.header.height);
+                // Never attest to two blocks in one height and round
+                let (height, round) = (block.header.height, block.header.round);
+                if self
+                    .detector
+                    .attested_to(verifier.public_key(), height, round)
+                    .is_some_and(|attested| attested != block.hash)
+                {
+                    info!("Not attesting to block {}: already attested at height {} round {}", block.hash, height, round);
+                    return;
+                }
+                // Attest only to blocks that re-execute to the same state root
+                // here and whose verdicts our own backends agree with
+                let checked = self
+                    .state
+                    .write()
+                    .await
+                    .check_candidate(&block, |job, solution| verifier.recheck(job, solution));
+                match checked {
+                    Ok(()) => {
+                        let attestation = verifier.attest(&block);
+                        self.detector.observe_attestation(&attestation);
+                        self.send(NetworkCommand::Broadcast(Box::new(NetworkMessage::Attestation(attestation)))).await;
+                    }
+                    Err(e) => warn!("Not attesting to block {}: {}", block.hash, e),
                 }
             }
             NetworkEvent::AttestationReceived(attestation) => {
                 info!("Received attestation for block {}", attestation.block_hash);
+                let found = self.detector.observe_attestation(&attestation);
+                self.report_evidence(found.into_iter().collect()).await;
+                if let Some(round) = self.round.as_mut().filter(|r| r.block().hash == attestation.block_hash) {
+                    if let Err(e) = round.add_attestation(attestation) {
+                        warn!("Rejected attestation: {}", e);
+                    }
+                    if round.is_complete() {
+                        if let Some(round) = self.round.take() {
+                            self.commit_round(round).await;
+                        }
+                    }
+                    return;
+                }
+                let mut st = self.state.write().await;
+                match st.add_attestation(attestation) {
+                    Ok(outcome) => log_import(&outcome, &st),
+                    Err(e) => warn!("Failed to record attestation: {}", e),
+                }
+            }
+            NetworkEvent::EvidenceReceived(evidence) => {
+                info!("Received evidence against {} at height {}", evidence.offender(), evidence.height());
+                if let Err(e) = self.mempool.write().await.add_evidence(*evidence) {
+                    debug!("Evidence not queued: {}", e);
+                }
             }
             NetworkEvent::PeersDiscovered(peers) => {
                 info!("Discovered {} peers via DHT", peers.len());
@@ -188,28 +366,315 @@ impl HardClawNode {
             NetworkEvent::Started { peer_id, listen_addr } => {
                 info!("Network started: {} @ {}", peer_id, listen_addr);
             }
+            NetworkEvent::SyncRequest { peer, id, request } => {
+                debug!("Sync request from {}: {:?}", peer, request);
+                let response = self.serve_sync(request).await;
+                self.send(NetworkCommand::Respond { id, response }).await;
+            }
+            NetworkEvent::SyncResponse { peer, response } => {
+                self.handle_sync_response(peer, response).await;
+            }
             NetworkEvent::Error(e) => {
                 warn!("Network error: {}", e);
             }
         }
     }
 
+    /// Answer a peer's sync request from our canonical chain
+    async fn serve_sync(&mut self, request: SyncRequest) -> SyncResponse {
+        let state = self.state.read().await;
+        match request {
+            SyncRequest::Status => SyncResponse::Status {
+                height: state.height(),
+                tip: state.tip().map(|t| t.hash),
+                history_start: state.history_start(),
+                finalized: state.finalized_height(),
+            },
+            SyncRequest::Snapshot { block } => {
+                SyncResponse::Snapshot(serve_snapshot(&mut self.served_snapshot, &state, block))
+            }
+            SyncRequest::Blocks { from, .. } if from < state.history_start() => {
+                SyncResponse::Error(format!(
+                    "history before height {} is pruned",
+                    state.history_start()
+                ))
+            }
+            SyncRequest::Blocks { from, limit } => {
+                let limit = u64::from(limit.min(MAX_BLOCKS_PER_REQUEST));
+                let blocks = (from..from.saturating_add(limit))
+                    .map_while(|height| state.get_block_at_height(height).cloned())
+                    .collect();
+                SyncResponse::Blocks(blocks)
+            }
+            SyncRequest::Confirmation { id } => SyncResponse::Confirmation {
+                id,
+                status: state.confirmation(&id),
+            },
+            SyncRequest::Module { hash } => SyncResponse::Module {
+                hash,
+                wasm: self.modules.load(&hash),
+            },
+        }
+    }
+
+    /// Act on a peer's answer to one of our sync requests
+    async fn handle_sync_response(&self, peer: PeerId, response: SyncResponse) {
+        match response {
+            SyncResponse::Status { height, history_start, .. } => {
+                let st = self.state.read().await;
+                if self.awaiting_snapshot(&st) {
+                    if let Some(block) = self.config.trusted_block {
+                        self.request(peer, SyncRequest::Snapshot { block }).await;
+                    }
+                } else if height > st.height() && history_start > st.height() {
+                    info!("Peer {} has pruned the blocks we need (from {})", peer, history_start);
+                } else if height > st.height() {
+                    info!("Peer {} is at height {}, syncing from {}", peer, height, st.height());
+                    self.request_blocks(peer, st.height()).await;
+                }
+            }
+            SyncResponse::Snapshot(Some(snapshot)) => {
+                let mut st = self.state.write().await;
+                if !self.awaiting_snapshot(&st) {
+                    return;
+                }
+                if self.config.trusted_block != Some(snapshot.block_hash()) {
+                    warn!("Peer {} sent a snapshot for an untrusted block", peer);
+                    return;
+                }
+                let header = snapshot.block.header.clone();
+                match st.import_snapshot(Arc::unwrap_or_clone(snapshot), &header) {
+                    Ok(()) => {
+                        info!("Imported snapshot at height {}", header.height);
+                        // Picks up the spec's fee shares; there is no genesis block to check
+                        if let Err(e) = st.init_genesis(&self.genesis) {
+                            warn!("Genesis spec does not apply: {}", e);
+                        }
+                        // Upload transactions before the snapshot are not replayed here
+                        let missing: Vec<Hash> = st
+                            .module_hashes()
+                            .filter(|hash| !self.modules.contains(hash))
+                            .copied()
+                            .collect();
+                        for hash in missing {
+                            self.request(peer, SyncRequest::Module { hash }).await;
+                        }
+                        self.request_blocks(peer, st.height()).await;
+                    }
+                    Err(e) => warn!("Rejected snapshot from {}: {}", peer, e),
+                }
+            }
+            SyncResponse::Snapshot(None) => {
+                info!("Peer {} cannot serve the trusted snapshot", peer);
+            }
+            SyncResponse::Blocks(blocks) => {
+                let mut st = self.state.write().await;
+                if self.awaiting_snapshot(&st) {
+                    return;
+                }
+                let full = blocks.len() == MAX_BLOCKS_PER_REQUEST as usize;
+                for block in blocks {
+                    self.cache_modules(&block.transactions);
+                    if let Err(e) = st.import_block(block) {
+                        warn!("Failed to import synced block: {}", e);
+                        return;
+                    }
+                }
+                info!("Synced to height {}", st.height());
+                self.prune_mempool(&st).await;
+                if full {
+                    self.request_blocks(peer, st.height()).await;
+                }
+            }
+            SyncResponse::Confirmation { id, status } => {
+                info!("Peer {} reports {} as {}", peer, id, status);
+            }
+            SyncResponse::Module { hash, wasm: Some(wasm) } if hash_data(&wasm) == hash => {
+                match self.modules.insert(&wasm) {
+                    Ok(_) => info!("Fetched module {} from {}", hash, peer),
+                    Err(e) => warn!("Failed to cache module {}: {}", hash, e),
+                }
+            }
+            SyncResponse::Module { hash, wasm: Some(_) } => {
+                warn!("Peer {} sent bytes that do not match module {}", peer, hash);
+            }
+            SyncResponse::Module { hash, wasm: None } => {
+                info!("Peer {} does not have module {}", peer, hash);
+            }
+            SyncResponse::Error(e) => {
+                warn!("Peer {} failed a sync request: {}", peer, e);
+            }
+        }
+    }
+
+    /// Keep the bytes of modules published by these transactions
+    fn cache_modules(&self, transactions: &[Transaction]) {
+        for tx in transactions {
+            if let TransactionKind::UploadModule(wasm) = &tx.kind {
+                if let Err(e) = self.modules.insert(wasm) {
+                    warn!("Failed to cache module from {}: {}", tx.id, e);
+                }
+            }
+        }
+    }
+
+    /// Drop mempool transactions whose nonces the canonical chain has used,
+    /// and evidence against verifiers that can no longer be slashed
+    async fn prune_mempool(&self, state: &ChainState) {
+        let validators = state.validator_set();
+        let mut mempool = self.mempool.write().await;
+        mempool.prune_transactions(|address| {
+            state.get_account(address).map_or(0, |account| account.nonce)
+        });
+        mempool.prune_evidence(|offender| validators.stake_of(offender).is_some());
+    }
+
+    /// Queue and gossip newly detected double signing
+    async fn report_evidence(&self, found: Vec<Evidence>) {
+        for evidence in found {
+            warn!(
+                "Verifier {} double-signed at height {} round {}",
+                evidence.offender(),
+                evidence.height(),
+                evidence.round()
+            );
+            if self.mempool.write().await.add_evidence(evidence.clone()).is_ok() {
+                self.send(NetworkCommand::Broadcast(Box::new(NetworkMessage::Evidence(Box::new(evidence))))).await;
+            }
+        }
+    }
+
+    /// Whether we are still waiting to fast sync from the trusted block
+    fn awaiting_snapshot(&self, state: &ChainState) -> bool {
+        self.config.trusted_block.is_some() && state.tip().is_none()
+    }
+
+    /// Ask a peer for the next batch of canonical blocks
+    async fn request_blocks(&self, peer: PeerId, from: u64) {
+        let request = SyncRequest::Blocks { from, limit: MAX_BLOCKS_PER_REQUEST };
+        self.request(peer, request).await;
+    }
+
+    async fn request(&self, peer: PeerId, request: SyncRequest) {
+        self.send(NetworkCommand::Request { peer, request }).await;
+    }
+
+    async fn send(&self, command: NetworkCommand) {
+        if let Some(network) = &self.network {
+            if network.send(command).await.is_err() {
+                warn!("Network task has stopped");
+            }
+        }
+    }
+
+    /// Move our open round along: commit it once it has enough attestations,
+    /// abandon it once it times out or the chain moves past its parent
+    ///
+    /// Returns whether we are free to propose again.
+    async fn advance_round(&mut self) -> bool {
+        let Some(round) = self.round.take() else {
+            return true;
+        };
+        if round.is_complete() {
+            self.commit_round(round).await;
+            return true;
+        }
+
+        let stale = self.state.read().await.tip().map(|tip| tip.hash) != Some(round.block().header.parent_hash);
+        if !stale && !round.is_expired(now_millis()) {
+            self.round = Some(round);
+            return false;
+        }
+        let block = round.block();
+        if stale {
+            info!("Abandoning block {}: the chain moved on", block.hash);
+        } else {
+            warn!(
+                "Round {} for block {} at height {} timed out with {} attestation(s)",
+                round.number(),
+                block.hash,
+                block.header.height,
+                block.attestations.len()
+            );
+        }
+        if let Some(verifier) = self.verifier.as_mut() {
+            verifier.abandon_block(round.into_block());
+        }
+        true
+    }
+
+    /// Commit a block that gathered enough attestations and announce it
+    async fn commit_round(&mut self, round: AttestationRound) {
+        let block = round.into_block();
+        self.cache_modules(&block.transactions);
+        let mut state = self.state.write().await;
+        if let Err(e) = state.apply_block(block.clone()) {
+            warn!("Dropping attested block {}: {}", block.hash, e);
+            drop(state);
+            if let Some(verifier) = self.verifier.as_mut() {
+                verifier.abandon_block(block);
+            }
+            return;
+        }
+        info!(
+            "Committed block {} at height {} with {} attestation(s)",
+            block.hash,
+            block.header.height,
+            block.attestations.len()
+        );
+        self.prune_mempool(&state).await;
+        drop(state);
+        self.send(NetworkCommand::Broadcast(Box::new(NetworkMessage::NewBlock(block)))).await;
+    }
+
     /// Process one verifier tick
     async fn process_verifier_tick(&mut self) -> anyhow::Result<()> {
+        if !self.advance_round().await {
+            return Ok(());
+        }
+
         let verifier = self.verifier.as_mut().expect("verifier mode");
-        // Process pending solutions from mempool
+        // Hand the verification workers as many pending solutions as they
+        // have room for; the rest wait in the mempool
         let solutions = {
+            let state = self.state.read().await;
             let mut mempool = self.mempool.write().await;
-            mempool.pop_solutions(100)
+            let mut ready = Vec::new();
+            for (job, solution) in mempool.pop_solutions(verifier.verification_capacity()) {
+                if !matches!(job.verification, VerificationSpec::TestVectors { .. }) {
+                    ready.push((job, solution));
+                    continue;
+                }
+                // Checked against the chain's copy of the job once its vectors
+                // are revealed there, and only if its solver opened a lock
+                // with this output
+                match state.get_job(&job.id) {
+                    Some(chain_job) if chain_job.revealed_vectors.is_some() => {
+                        if chain_job.is_locked_in(&solution.solver_address, &hash_data(&solution.output)) {
+                            ready.push((chain_job.clone(), solution));
+                        }
+                    }
+                    _ => {
+                        let _ = mempool.add_solution(solution);
+                    }
+                }
+            }
+            ready
         };
 
         for (job, solution) in solutions {
-            match verifier.process_solution(&job, &solution) {
+            if let Err(e) = verifier.submit_solution(job, solution) {
+                warn!("Verification error: {}", e);
+            }
+        }
+
+        for outcome in verifier.collect_verifications() {
+            match outcome {
                 Ok((result, is_honey_pot)) => {
                     if result.passed {
-                        info!("Solution {} verified for job {}", solution.id, job.id);
+                        info!("Solution {} verified for job {}", result.solution_id, result.job_id);
                     } else {
-                        info!("Solution {} rejected for job {}", solution.id, job.id);
+                        info!("Solution {} rejected for job {}", result.solution_id, result.job_id);
                     }
                     if is_honey_pot {
                         info!("Honey pot detected!");
@@ -221,12 +686,77 @@ impl HardClawNode {
             }
         }
 
-        // Try to produce a block
-        let state_root = self.state.read().await.compute_state_root();
-        if let Some(block) = verifier.try_produce_block(state_root)? {
-            info!("Produced block {} at height {}", block.hash, block.header.height);
-            let mut state = self.state.write().await;
-            state.apply_block(block)?;
+        // Try to produce a block on top of our tip, if it is our turn
+        let mut state = self.state.write().await;
+        if let Some(tip) = state.tip() {
+            verifier.set_chain_tip(tip.header.height, tip.hash);
+        }
+        if !state.can_propose(verifier.address(), now_millis()) {
+            return Ok(());
+        }
+
+        // Include whatever mempool transactions still apply; drop the rest
+        let mut candidates = self.mempool.read().await.ready_transactions(1000, |address| {
+            state.get_account(address).map_or(0, |account| account.nonce)
+        });
+
+        // Report pending evidence from our own account, after our queued transactions
+        let reporter = Address::from_public_key(self.keypair.public_key());
+        let first_nonce = candidates
+            .iter()
+            .filter(|tx| tx.sender_address() == reporter)
+            .map(|tx| tx.nonce + 1)
+            .max()
+            .unwrap_or_else(|| state.get_account(&reporter).map_or(0, |account| account.nonce));
+        let evidence = self.mempool.read().await.pending_evidence();
+        for (nonce, evidence) in (first_nonce..).zip(evidence) {
+            let kind = TransactionKind::ReportEvidence(Box::new(evidence));
+            let mut tx = Transaction::new(*self.keypair.public_key(), nonce, kind);
+            tx.signature = self.keypair.sign(&tx.signing_bytes());
+            candidates.push(tx);
+        }
+        let transactions = state.executable_transactions(candidates.clone(), now_millis());
+        if transactions.len() < candidates.len() {
+            let mut mempool = self.mempool.write().await;
+            for tx in &candidates {
+                if !transactions.iter().any(|included| included.id == tx.id) {
+                    mempool.remove_transaction(&tx.id);
+                }
+            }
+        }
+
+        // A fresh candidate at a height we already proposed at goes in a new round
+        let height = state.height();
+        let number = if self.next_round.0 == height { self.next_round.1 } else { 0 };
+        verifier.set_round(number);
+
+        let parent_attestations = state.tip_attestations();
+        let epoch = state.epoch_commitment();
+        let produced = verifier.try_produce_block_with(transactions, parent_attestations, epoch, |block| {
+            state.state_root_after(block).map_err(|e| {
+                ConsensusError::VerificationFailed { reason: e.to_string() }
+            })
+        });
+        match produced {
+            Ok(Some(block)) => {
+                let validators = state.validator_set();
+                drop(state);
+
+                self.next_round = (height, number + 1);
+                let found = self.detector.observe_block(&block);
+                self.report_evidence(found).await;
+                let round = AttestationRound::new(number, block, validators, now_millis());
+                if round.is_complete() {
+                    self.commit_round(round).await;
+                } else {
+                    info!("Proposed block {} at height {} (round {})", round.block().hash, height, number);
+                    let proposal = NetworkMessage::ProposeBlock(round.block().clone());
+                    self.send(NetworkCommand::Broadcast(Box::new(proposal))).await;
+                    self.round = Some(round);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Block production failed: {}", e),
         }
 
         Ok(())
@@ -234,6 +764,117 @@ impl HardClawNode {
 
 }
 
+/// Load the node key, creating it on first run so the node keeps its identity
+fn load_node_key(data_dir: &str) -> anyhow::Result<Keypair> {
+    let path = Path::new(data_dir).join("node_key.json");
+    let wallet = if path.exists() {
+        Wallet::load(&path)?
+    } else {
+        let mut wallet = Wallet::generate_with_name("node".to_string());
+        wallet.save(&path)?;
+        info!("Generated node key at {}", path.display());
+        wallet
+    };
+    copy_keypair(wallet.keypair())
+}
+
+/// Register every `*.json` schema under `<data_dir>/schemas`, named by the
+/// hash of its bytes
+fn load_schemas(data_dir: &str) -> anyhow::Result<Arc<SchemaRegistry>> {
+    let dir = Path::new(data_dir).join("schemas");
+    std::fs::create_dir_all(&dir)?;
+    let schemas = SchemaRegistry::new();
+    for entry in std::fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        match schemas.register(&std::fs::read(&path)?) {
+            Ok(hash) => info!("Registered schema {} from {}", hash, path.display()),
+            Err(e) => warn!("Skipping schema {}: {}", path.display(), e),
+        }
+    }
+    Ok(Arc::new(schemas))
+}
+
+/// Second handle on the same key (keypairs deliberately do not implement Clone)
+fn copy_keypair(keypair: &Keypair) -> anyhow::Result<Keypair> {
+    Ok(Keypair::from_secret(SecretKey::from_bytes(keypair.secret_key().to_bytes())?))
+}
+
+/// Log how a block or attestation changed the canonical chain
+fn log_import(outcome: &ImportOutcome, state: &ChainState) {
+    match outcome {
+        ImportOutcome::Extended => info!(
+            "Chain extended to height {} (finalized {})",
+            state.height(),
+            state.finalized_height()
+        ),
+        ImportOutcome::Reorganized { disconnected, connected } => info!(
+            "Reorganized: {} block(s) reverted, {} applied, now at height {}",
+            disconnected.len(),
+            connected.len(),
+            state.height()
+        ),
+        ImportOutcome::Stored | ImportOutcome::Duplicate => {}
+    }
+}
+
+/// Snapshot at a canonical block for a peer, reusing the last one exported
+///
+/// Snapshots too large for a sync response are refused rather than cut off
+/// on the wire.
+fn serve_snapshot(
+    served: &mut Option<(Hash, Option<Arc<StateSnapshot>>)>,
+    state: &ChainState,
+    block: Hash,
+) -> Option<Arc<StateSnapshot>> {
+    let height = state.get_header(&block).filter(|_| state.is_canonical(&block))?.height;
+    if let Some((_, snapshot)) = served.as_ref().filter(|(hash, _)| *hash == block) {
+        return snapshot.clone();
+    }
+
+    let snapshot = match state.export_snapshot(height) {
+        Ok(snapshot) => Arc::new(snapshot),
+        Err(e) => {
+            warn!("Cannot export snapshot at {}: {}", block, e);
+            return None;
+        }
+    };
+    let size = SyncResponse::Snapshot(Some(Arc::clone(&snapshot))).encoded_len();
+    let snapshot = if size <= MAX_SYNC_RESPONSE_BYTES {
+        Some(snapshot)
+    } else {
+        warn!("Snapshot at {} is {} bytes, over the {} byte sync response limit", block, size, MAX_SYNC_RESPONSE_BYTES);
+        None
+    };
+    *served = Some((block, snapshot.clone()));
+    snapshot
+}
+
+/// Load the genesis spec, writing a single-validator devnet spec if none exists
+///
+/// Only the default location is filled in; an explicit `--genesis` path must exist.
+fn load_genesis(config: &NodeConfig, validator: &PublicKey) -> anyhow::Result<GenesisSpec> {
+    let path = config
+        .genesis
+        .as_ref()
+        .map_or_else(|| Path::new(&config.data_dir).join("genesis.json"), PathBuf::from);
+    if config.genesis.is_some() || path.exists() {
+        return Ok(GenesisSpec::load(&path)?);
+    }
+
+    warn!("No genesis spec at {}; writing a single-validator devnet spec", path.display());
+    let spec = GenesisSpec::single_validator(
+        "hardclaw-devnet".to_string(),
+        validator,
+        config.verifier.min_stake,
+        now_millis(),
+    );
+    spec.save(&path)?;
+    Ok(spec)
+}
+
 fn parse_args() -> NodeConfig {
     let args: Vec<String> = std::env::args().collect();
     let mut config = NodeConfig::default();
@@ -260,6 +901,37 @@ fn parse_args() -> NodeConfig {
                     config.external_addr = Some(args[i].clone());
                 }
             }
+            "--data-dir" | "-d" => {
+                i += 1;
+                if i < args.len() {
+                    config.data_dir = args[i].clone();
+                }
+            }
+            "--trusted-block" => {
+                i += 1;
+                if i < args.len() {
+                    match Hash::from_hex(&args[i]) {
+                        Ok(hash) => config.trusted_block = Some(hash),
+                        Err(e) => {
+                            eprintln!("Invalid --trusted-block hash: {e}");
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            "--genesis" | "-g" => {
+                i += 1;
+                if i < args.len() {
+                    config.genesis = Some(args[i].clone());
+                }
+            }
+            "--prune" => {
+                i += 1;
+                if i < args.len() {
+                    let keep_blocks = args[i].parse().unwrap_or(1000);
+                    config.retention = RetentionMode::Pruned { keep_blocks };
+                }
+            }
             "--no-official-bootstrap" => {
                 config.network.use_official_bootstrap = false;
             }
@@ -285,7 +957,11 @@ fn print_help() {
     println!("    -v, --verifier              Run as a verifier node");
     println!("    -p, --port <PORT>           Listen port (default: 9000)");
     println!("    -b, --bootstrap <ADDR>      Bootstrap peer address");
+    println!("    -d, --data-dir <PATH>       Data directory (default: .hardclaw)");
     println!("    --external-addr <ADDR>      External address for NAT traversal");
+    println!("    -g, --genesis <PATH>        Genesis spec (default: <data-dir>/genesis.json)");
+    println!("    --trusted-block <HASH>      Fast sync from a snapshot at this block");
+    println!("    --prune <N>                 Keep only the last N block bodies (default: archive)");
     println!("    --no-official-bootstrap     Don't use official bootstrap nodes");
     println!("    -h, --help                  Print help");
 }
@@ -313,14 +989,14 @@ async fn main() -> anyhow::Result<()> {
     // Parse config
     let config = parse_args();
 
-    // Generate or load keypair
-    let keypair = Keypair::generate();
+    // Load the node key, or generate one on first run
+    let keypair = load_node_key(&config.data_dir)?;
     let address = Address::from_public_key(keypair.public_key());
 
     info!("Node address: {}", address);
 
     // Create and run node
-    let mut node = HardClawNode::new(keypair, config);
+    let mut node = HardClawNode::new(keypair, config)?;
     node.init().await?;
     node.run().await?;
 
@@ -1,12 +1,15 @@
 //! Transaction and Job Mempool.
 //!
-//! Holds pending jobs and solutions waiting to be included in blocks.
+//! Holds pending transactions, jobs, solutions and double-signing evidence
+//! waiting to be included in blocks.
 
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
 use std::cmp::Ordering;
 
+use crate::state::AccountState;
+use crate::crypto::PublicKey;
 use crate::types::{
-    Id, JobPacket, SolutionCandidate,
+    Address, Evidence, Id, JobPacket, SolutionCandidate, Transaction,
     Timestamp, now_millis,
 };
 
@@ -41,8 +44,12 @@ impl Ord for PrioritizedJob {
     }
 }
 
-/// Mempool for pending jobs and solutions
+/// Mempool for pending transactions, jobs, solutions and evidence
 pub struct Mempool {
+    /// Pending transactions by ID
+    transactions: HashMap<Id, Transaction>,
+    /// Transaction IDs by sender, ordered by nonce
+    transactions_by_sender: HashMap<Address, BTreeMap<u64, Id>>,
     /// Pending jobs by ID
     jobs: HashMap<Id, JobPacket>,
     /// Priority queue for job selection
@@ -51,10 +58,14 @@ pub struct Mempool {
     solutions: HashMap<Id, SolutionCandidate>,
     /// Solutions indexed by job ID
     solutions_by_job: HashMap<Id, Vec<Id>>,
+    /// Double-signing evidence waiting to be reported, by evidence ID
+    evidence: HashMap<Id, Evidence>,
     /// Maximum jobs in mempool
     max_jobs: usize,
     /// Maximum solutions in mempool
     max_solutions: usize,
+    /// Maximum transactions in mempool
+    max_transactions: usize,
 }
 
 impl Default for Mempool {
@@ -68,17 +79,131 @@ impl Mempool {
     pub const DEFAULT_MAX_JOBS: usize = 10_000;
     /// Default max solutions
     pub const DEFAULT_MAX_SOLUTIONS: usize = 50_000;
+    /// Default max transactions
+    pub const DEFAULT_MAX_TRANSACTIONS: usize = 50_000;
+    /// How far past an account's nonce a queued transaction may be
+    pub const MAX_NONCE_GAP: u64 = 64;
 
     /// Create new mempool
     #[must_use]
     pub fn new() -> Self {
         Self {
+            transactions: HashMap::new(),
+            transactions_by_sender: HashMap::new(),
             jobs: HashMap::new(),
             job_queue: BinaryHeap::new(),
             solutions: HashMap::new(),
             solutions_by_job: HashMap::new(),
+            evidence: HashMap::new(),
             max_jobs: Self::DEFAULT_MAX_JOBS,
             max_solutions: Self::DEFAULT_MAX_SOLUTIONS,
+            max_transactions: Self::DEFAULT_MAX_TRANSACTIONS,
+        }
+    }
+
+    /// Add a transaction, checked against the sender's current account state
+    ///
+    /// # Errors
+    /// Returns error if the transaction is invalid, its nonce is already used
+    /// or too far ahead, the sender cannot cover it, or the mempool is full
+    pub fn add_transaction(
+        &mut self,
+        tx: Transaction,
+        sender: &AccountState,
+    ) -> Result<(), MempoolError> {
+        if self.transactions.contains_key(&tx.id) {
+            return Err(MempoolError::DuplicateTransaction);
+        }
+
+        tx.validate()
+            .map_err(|e| MempoolError::InvalidTransaction(e.to_string()))?;
+
+        if tx.nonce < sender.nonce {
+            return Err(MempoolError::StaleNonce { expected: sender.nonce, got: tx.nonce });
+        }
+        if tx.nonce > sender.nonce.saturating_add(Self::MAX_NONCE_GAP) {
+            return Err(MempoolError::NonceTooHigh { expected: sender.nonce, got: tx.nonce });
+        }
+        if tx.cost() > sender.available_balance() {
+            return Err(MempoolError::InsufficientBalance);
+        }
+
+        let queued = self.transactions_by_sender.entry(tx.sender_address()).or_default();
+        if queued.contains_key(&tx.nonce) {
+            return Err(MempoolError::NonceInUse);
+        }
+        if self.transactions.len() >= self.max_transactions {
+            return Err(MempoolError::Full);
+        }
+
+        queued.insert(tx.nonce, tx.id);
+        self.transactions.insert(tx.id, tx);
+
+        Ok(())
+    }
+
+    /// Get a transaction by ID
+    #[must_use]
+    pub fn get_transaction(&self, id: &Id) -> Option<&Transaction> {
+        self.transactions.get(id)
+    }
+
+    /// Transactions that can run next, in nonce order per sender
+    ///
+    /// For each sender, takes the run of consecutive nonces starting at
+    /// `nonce_of(sender)`; anything after a gap waits.
+    pub fn ready_transactions<F>(&self, limit: usize, nonce_of: F) -> Vec<Transaction>
+    where
+        F: Fn(&Address) -> u64,
+    {
+        let mut ready = Vec::new();
+
+        for (sender, queued) in &self.transactions_by_sender {
+            let next = nonce_of(sender);
+            for ((nonce, id), expected) in queued.range(next..).zip(next..) {
+                if ready.len() >= limit {
+                    return ready;
+                }
+                if *nonce != expected {
+                    break;
+                }
+                if let Some(tx) = self.transactions.get(id) {
+                    ready.push(tx.clone());
+                }
+            }
+        }
+
+        ready
+    }
+
+    /// Remove a transaction
+    pub fn remove_transaction(&mut self, id: &Id) {
+        if let Some(tx) = self.transactions.remove(id) {
+            let sender = tx.sender_address();
+            if let Some(queued) = self.transactions_by_sender.get_mut(&sender) {
+                queued.remove(&tx.nonce);
+                if queued.is_empty() {
+                    self.transactions_by_sender.remove(&sender);
+                }
+            }
+        }
+    }
+
+    /// Drop transactions whose nonce has already been used on chain
+    pub fn prune_transactions<F>(&mut self, nonce_of: F)
+    where
+        F: Fn(&Address) -> u64,
+    {
+        let stale: Vec<Id> = self.transactions_by_sender
+            .iter()
+            .flat_map(|(sender, queued)| {
+                let next = nonce_of(sender);
+                queued.range(..next).map(|(_, id)| *id)
+            })
+            .collect();
+
+        for id in stale {
+            self.remove_transaction(&id);
         }
     }
 
@@ -216,6 +341,36 @@ impl Mempool {
         }
     }
 
+    /// Add double-signing evidence to report
+    ///
+    /// # Errors
+    /// Returns error if the evidence does not prove double signing or is
+    /// already pending
+    pub fn add_evidence(&mut self, evidence: Evidence) -> Result<(), MempoolError> {
+        evidence
+            .verify()
+            .map_err(|e| MempoolError::InvalidEvidence(e.to_string()))?;
+        if self.evidence.contains_key(&evidence.id()) {
+            return Err(MempoolError::DuplicateEvidence);
+        }
+        self.evidence.insert(evidence.id(), evidence);
+        Ok(())
+    }
+
+    /// Evidence waiting to be reported
+    #[must_use]
+    pub fn pending_evidence(&self) -> Vec<Evidence> {
+        self.evidence.values().cloned().collect()
+    }
+
+    /// Drop evidence against offenders that can no longer be slashed
+    pub fn prune_evidence<F>(&mut self, slashable: F)
+    where
+        F: Fn(&PublicKey) -> bool,
+    {
+        self.evidence.retain(|_, evidence| slashable(evidence.offender()));
+    }
+
     /// Remove expired jobs
     pub fn cleanup_expired(&mut self) {
         let expired: Vec<Id> = self.jobs
@@ -233,6 +388,7 @@ impl Mempool {
     #[must_use]
     pub fn size(&self) -> MempoolSize {
         MempoolSize {
+            transactions: self.transactions.len(),
             jobs: self.jobs.len(),
             solutions: self.solutions.len(),
         }
@@ -242,6 +398,8 @@ impl Mempool {
 /// Mempool size information
 #[derive(Clone, Debug)]
 pub struct MempoolSize {
+    /// Number of transactions
+    pub transactions: usize,
     /// Number of jobs
     pub jobs: usize,
     /// Number of solutions
@@ -266,13 +424,47 @@ pub enum MempoolError {
     /// Job expired
     #[error("job has expired")]
     Expired,
+    /// Duplicate transaction
+    #[error("transaction already exists")]
+    DuplicateTransaction,
+    /// Transaction failed validation
+    #[error("invalid transaction: {0}")]
+    InvalidTransaction(String),
+    /// Transaction nonce was already used on chain
+    #[error("stale nonce: expected at least {expected}, got {got}")]
+    StaleNonce {
+        /// Sender's next nonce
+        expected: u64,
+        /// Nonce the transaction carries
+        got: u64,
+    },
+    /// Transaction nonce is too far ahead of the account
+    #[error("nonce too far ahead: account at {expected}, got {got}")]
+    NonceTooHigh {
+        /// Sender's next nonce
+        expected: u64,
+        /// Nonce the transaction carries
+        got: u64,
+    },
+    /// Another pending transaction from the sender uses this nonce
+    #[error("nonce already pending")]
+    NonceInUse,
+    /// Sender cannot cover the transaction
+    #[error("insufficient balance")]
+    InsufficientBalance,
+    /// Evidence does not prove double signing
+    #[error("invalid evidence: {0}")]
+    InvalidEvidence(String),
+    /// Evidence for this offence is already pending
+    #[error("evidence already pending")]
+    DuplicateEvidence,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::crypto::{Hash, Keypair};
-    use crate::types::{JobType, HclawAmount, VerificationSpec};
+    use crate::types::{JobType, HclawAmount, TransactionKind, VerificationSpec};
 
     fn create_test_job(bounty: u64) -> JobPacket {
         let kp = Keypair::generate();
@@ -333,4 +525,64 @@ mod tests {
         let solutions = mempool.solutions_for_job(&job.id);
         assert_eq!(solutions.len(), 2);
     }
+
+    fn create_test_transfer(kp: &Keypair, nonce: u64) -> Transaction {
+        let to = Address::from_public_key(Keypair::generate().public_key());
+        let kind = TransactionKind::Transfer { to, amount: HclawAmount::from_hclaw(10) };
+        let mut tx = Transaction::new(*kp.public_key(), nonce, kind);
+        tx.signature = kp.sign(&tx.signing_bytes());
+        tx
+    }
+
+    #[test]
+    fn test_transaction_admission() {
+        let mut mempool = Mempool::new();
+        let kp = Keypair::generate();
+        let mut account = AccountState::new(HclawAmount::from_hclaw(100));
+        account.nonce = 1;
+
+        let stale = create_test_transfer(&kp, 0);
+        assert!(matches!(
+            mempool.add_transaction(stale, &account),
+            Err(MempoolError::StaleNonce { expected: 1, got: 0 })
+        ));
+
+        let tx = create_test_transfer(&kp, 1);
+        mempool.add_transaction(tx.clone(), &account).unwrap();
+        assert!(matches!(
+            mempool.add_transaction(tx, &account),
+            Err(MempoolError::DuplicateTransaction)
+        ));
+        assert!(matches!(
+            mempool.add_transaction(create_test_transfer(&kp, 1), &account),
+            Err(MempoolError::NonceInUse)
+        ));
+
+        let broke = AccountState::default();
+        assert!(matches!(
+            mempool.add_transaction(create_test_transfer(&kp, 2), &broke),
+            Err(MempoolError::InsufficientBalance)
+        ));
+    }
+
+    #[test]
+    fn test_ready_transactions_follow_nonces() {
+        let mut mempool = Mempool::new();
+        let kp = Keypair::generate();
+        let account = AccountState::new(HclawAmount::from_hclaw(100));
+
+        for nonce in [0, 1, 3] {
+            mempool.add_transaction(create_test_transfer(&kp, nonce), &account).unwrap();
+        }
+
+        // Nonce 3 waits behind the gap at 2
+        let ready = mempool.ready_transactions(10, |_| 0);
+        let nonces: Vec<u64> = ready.iter().map(|tx| tx.nonce).collect();
+        assert_eq!(nonces, vec![0, 1]);
+
+        // Once 0 and 1 are on chain they are pruned
+        mempool.prune_transactions(|_| 2);
+        assert_eq!(mempool.size().transactions, 1);
+        assert!(mempool.ready_transactions(10, |_| 2).is_empty());
+    }
 }
@@ -4,8 +4,14 @@
 //! - Kademlia DHT for internet-wide peer discovery
 //! - Gossipsub for message propagation
 //! - mDNS for local network discovery (optional, for development)
+//! - Request-response for chain sync (see `sync`)
+
+mod sync;
+
+pub use sync::{SyncRequest, SyncResponse, MAX_BLOCKS_PER_REQUEST, MAX_SYNC_RESPONSE_BYTES, SYNC_PROTOCOL};
 
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::hash::{Hash as StdHash, Hasher};
 use std::time::Duration;
 
@@ -16,15 +22,16 @@ use libp2p::{
     kad::{self, store::MemoryStore, Mode},
     mdns,
     noise,
+    request_response::{self, ProtocolSupport, ResponseChannel},
     swarm::{NetworkBehaviour, SwarmEvent},
-    tcp, yamux, Multiaddr, PeerId, Swarm,
+    tcp, yamux, Multiaddr, PeerId, StreamProtocol, Swarm,
 };
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use tracing::{debug, info, warn};
 
 use crate::crypto::{Hash, PublicKey};
-use crate::types::{Block, JobPacket, SolutionCandidate, VerifierAttestation};
+use crate::types::{Block, Evidence, JobPacket, SolutionCandidate, Transaction, VerifierAttestation};
 
 /// Protocol version string
 const PROTOCOL_VERSION: &str = "/hardclaw/1.0.0";
@@ -32,14 +39,24 @@ const PROTOCOL_VERSION: &str = "/hardclaw/1.0.0";
 /// Kademlia protocol name
 const KAD_PROTOCOL: &str = "/hardclaw/kad/1.0.0";
 
+/// Largest gossip message: the largest valid block
+/// (`BlockProducerConfig::max_block_size`) with room for gossipsub framing
+pub const MAX_GOSSIP_MESSAGE_BYTES: usize = 2 * 1024 * 1024;
+
+/// Gossipsub topic for transactions
+const TOPIC_TRANSACTIONS: &str = "hardclaw/transactions";
 /// Gossipsub topic for jobs
 const TOPIC_JOBS: &str = "hardclaw/jobs";
 /// Gossipsub topic for solutions
 const TOPIC_SOLUTIONS: &str = "hardclaw/solutions";
 /// Gossipsub topic for blocks
 const TOPIC_BLOCKS: &str = "hardclaw/blocks";
+/// Gossipsub topic for candidate blocks awaiting attestations
+const TOPIC_PROPOSALS: &str = "hardclaw/proposals";
 /// Gossipsub topic for attestations
 const TOPIC_ATTESTATIONS: &str = "hardclaw/attestations";
+/// Gossipsub topic for double-signing evidence
+const TOPIC_EVIDENCE: &str = "hardclaw/evidence";
 
 /// Official HardClaw bootstrap nodes
 /// These are well-known nodes that help new peers join the network
@@ -53,14 +70,20 @@ pub const BOOTSTRAP_NODES: &[&str] = &[
 /// Network message types (serialized for gossipsub)
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum NetworkMessage {
+    /// New signed transaction
+    NewTransaction(Transaction),
     /// New job announcement
     NewJob(JobPacket),
     /// New solution submission
     NewSolution(SolutionCandidate),
-    /// New block proposal
+    /// New committed block
     NewBlock(Block),
+    /// Candidate block asking verifiers for attestations
+    ProposeBlock(Block),
     /// Block attestation
     Attestation(VerifierAttestation),
+    /// Proof that a verifier signed two conflicting blocks
+    Evidence(Box<Evidence>),
     /// Request block by hash
     GetBlock(Hash),
     /// Request job by ID
@@ -122,14 +145,20 @@ pub enum NetworkEvent {
     PeerConnected(PeerId),
     /// Peer disconnected
     PeerDisconnected(PeerId),
+    /// Received a new transaction from the network
+    TransactionReceived(Transaction),
     /// Received a new job from the network
     JobReceived(JobPacket),
     /// Received a new solution from the network
     SolutionReceived(SolutionCandidate),
     /// Received a new block from the network
     BlockReceived(Block),
+    /// Received a candidate block to re-check and attest
+    ProposalReceived(Block),
     /// Received an attestation from the network
     AttestationReceived(VerifierAttestation),
+    /// Received double-signing evidence from the network
+    EvidenceReceived(Box<Evidence>),
     /// Network started successfully
     Started {
         /// Our peer ID
@@ -139,10 +168,47 @@ pub enum NetworkEvent {
     },
     /// Discovered new peers via DHT
     PeersDiscovered(Vec<PeerId>),
+    /// A peer asked us for sync data; answer with `NetworkCommand::Respond`
+    SyncRequest {
+        /// Requesting peer
+        peer: PeerId,
+        /// Handle to pass back with the response
+        id: u64,
+        /// What the peer wants
+        request: SyncRequest,
+    },
+    /// A peer answered one of our sync requests
+    SyncResponse {
+        /// Responding peer
+        peer: PeerId,
+        /// The answer
+        response: SyncResponse,
+    },
     /// Network error
     Error(String),
 }
 
+/// Commands the application sends to a running network node
+#[derive(Clone, Debug)]
+pub enum NetworkCommand {
+    /// Publish a message over gossipsub
+    Broadcast(Box<NetworkMessage>),
+    /// Send a sync request to a peer
+    Request {
+        /// Peer to ask
+        peer: PeerId,
+        /// The request
+        request: SyncRequest,
+    },
+    /// Answer a sync request received as `NetworkEvent::SyncRequest`
+    Respond {
+        /// Handle from the request event
+        id: u64,
+        /// The answer
+        response: SyncResponse,
+    },
+}
+
 /// Combined network behaviour
 #[derive(NetworkBehaviour)]
 #[behaviour(to_swarm = "HardClawBehaviourEvent")]
@@ -155,6 +221,8 @@ struct HardClawBehaviour {
     gossipsub: gossipsub::Behaviour,
     /// mDNS for local peer discovery
     mdns: mdns::tokio::Behaviour,
+    /// Chain sync requests
+    sync: request_response::cbor::Behaviour<SyncRequest, SyncResponse>,
 }
 
 /// Combined network behaviour event
@@ -168,6 +236,8 @@ pub enum HardClawBehaviourEvent {
     Gossipsub(gossipsub::Event),
     /// mDNS event
     Mdns(mdns::Event),
+    /// Sync event
+    Sync(request_response::Event<SyncRequest, SyncResponse>),
 }
 
 impl From<kad::Event> for HardClawBehaviourEvent {
@@ -194,6 +264,12 @@ impl From<mdns::Event> for HardClawBehaviourEvent {
     }
 }
 
+impl From<request_response::Event<SyncRequest, SyncResponse>> for HardClawBehaviourEvent {
+    fn from(event: request_response::Event<SyncRequest, SyncResponse>) -> Self {
+        Self::Sync(event)
+    }
+}
+
 /// Network node with real libp2p implementation
 pub struct NetworkNode {
     /// The libp2p swarm
@@ -206,14 +282,25 @@ pub struct NetworkNode {
     event_tx: mpsc::Sender<NetworkEvent>,
     /// Topics we're subscribed to
     topics: Topics,
+    /// Sender handed out by `commands`
+    command_tx: mpsc::Sender<NetworkCommand>,
+    /// Commands from the application
+    command_rx: mpsc::Receiver<NetworkCommand>,
+    /// Sync requests waiting for the application's answer
+    pending_responses: HashMap<u64, ResponseChannel<SyncResponse>>,
+    /// Next sync request handle
+    next_request_id: u64,
 }
 
 /// Gossipsub topics
 struct Topics {
+    transactions: IdentTopic,
     jobs: IdentTopic,
     solutions: IdentTopic,
     blocks: IdentTopic,
+    proposals: IdentTopic,
     attestations: IdentTopic,
+    evidence: IdentTopic,
 }
 
 impl NetworkNode {
@@ -227,6 +314,7 @@ impl NetworkNode {
     ) -> Result<(Self, mpsc::Receiver<NetworkEvent>), NetworkError> {
         // Create event channel
         let (event_tx, event_rx) = mpsc::channel(1000);
+        let (command_tx, command_rx) = mpsc::channel(1000);
 
         // Build the swarm
         let swarm = libp2p::SwarmBuilder::with_new_identity()
@@ -260,6 +348,7 @@ impl NetworkNode {
 
                 let gossipsub_config = gossipsub::ConfigBuilder::default()
                     .heartbeat_interval(Duration::from_secs(1))
+                    .max_transmit_size(MAX_GOSSIP_MESSAGE_BYTES)
                     .validation_mode(ValidationMode::Strict)
                     .message_id_fn(message_id_fn)
                     .build()
@@ -277,11 +366,19 @@ impl NetworkNode {
                     key.public().to_peer_id(),
                 )?;
 
+                // Configure chain sync
+                let sync = request_response::cbor::Behaviour::new(
+                    [(StreamProtocol::new(SYNC_PROTOCOL), ProtocolSupport::Full)],
+                    request_response::Config::default()
+                        .with_request_timeout(Duration::from_mins(1)),
+                );
+
                 Ok(HardClawBehaviour {
                     kademlia,
                     identify,
                     gossipsub,
                     mdns,
+                    sync,
                 })
             })
             .map_err(|e| NetworkError::InitFailed(e.to_string()))?
@@ -290,10 +387,13 @@ impl NetworkNode {
 
         // Create topics
         let topics = Topics {
+            transactions: IdentTopic::new(TOPIC_TRANSACTIONS),
             jobs: IdentTopic::new(TOPIC_JOBS),
             solutions: IdentTopic::new(TOPIC_SOLUTIONS),
             blocks: IdentTopic::new(TOPIC_BLOCKS),
+            proposals: IdentTopic::new(TOPIC_PROPOSALS),
             attestations: IdentTopic::new(TOPIC_ATTESTATIONS),
+            evidence: IdentTopic::new(TOPIC_EVIDENCE),
         };
 
         Ok((
@@ -303,6 +403,10 @@ impl NetworkNode {
                 local_peer,
                 event_tx,
                 topics,
+                command_tx,
+                command_rx,
+                pending_responses: HashMap::new(),
+                next_request_id: 0,
             },
             event_rx,
         ))
@@ -314,12 +418,24 @@ impl NetworkNode {
         *self.swarm.local_peer_id()
     }
 
+    /// Channel for controlling the node once `run` has taken it over
+    #[must_use]
+    pub fn commands(&self) -> mpsc::Sender<NetworkCommand> {
+        self.command_tx.clone()
+    }
+
     /// Start the network node
     ///
     /// # Errors
     /// Returns error if network initialization fails
     pub async fn start(&mut self) -> Result<(), NetworkError> {
         // Subscribe to all topics
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&self.topics.transactions)
+            .map_err(|e| NetworkError::InitFailed(e.to_string()))?;
+
         self.swarm
             .behaviour_mut()
             .gossipsub
@@ -338,12 +454,24 @@ impl NetworkNode {
             .subscribe(&self.topics.blocks)
             .map_err(|e| NetworkError::InitFailed(e.to_string()))?;
 
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&self.topics.proposals)
+            .map_err(|e| NetworkError::InitFailed(e.to_string()))?;
+
         self.swarm
             .behaviour_mut()
             .gossipsub
             .subscribe(&self.topics.attestations)
             .map_err(|e| NetworkError::InitFailed(e.to_string()))?;
 
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&self.topics.evidence)
+            .map_err(|e| NetworkError::InitFailed(e.to_string()))?;
+
         // Parse and listen on the configured address
         let listen_addr: Multiaddr = self
             .config
@@ -438,6 +566,9 @@ impl NetworkNode {
                 event = self.swarm.select_next_some() => {
                     self.handle_swarm_event(event).await;
                 }
+                Some(command) = self.command_rx.recv() => {
+                    self.handle_command(command);
+                }
                 _ = dht_refresh_interval.tick() => {
                     // Periodically refresh DHT routing table
                     if let Err(e) = self.swarm.behaviour_mut().kademlia.bootstrap() {
@@ -448,6 +579,69 @@ impl NetworkNode {
         }
     }
 
+    /// Handle a command from the application
+    fn handle_command(&mut self, command: NetworkCommand) {
+        match command {
+            NetworkCommand::Broadcast(message) => {
+                if let Err(e) = self.broadcast(&message) {
+                    debug!(error = %e, "Broadcast failed (may be normal if no peers)");
+                }
+            }
+            NetworkCommand::Request { peer, request } => {
+                self.swarm.behaviour_mut().sync.send_request(&peer, request);
+            }
+            NetworkCommand::Respond { id, response } => {
+                let Some(channel) = self.pending_responses.remove(&id) else {
+                    warn!(id, "No pending sync request to respond to");
+                    return;
+                };
+                if self
+                    .swarm
+                    .behaviour_mut()
+                    .sync
+                    .send_response(channel, response)
+                    .is_err()
+                {
+                    debug!(id, "Sync peer went away before the response was sent");
+                }
+            }
+        }
+    }
+
+    /// Handle a sync protocol event
+    async fn handle_sync_event(&mut self, event: request_response::Event<SyncRequest, SyncResponse>) {
+        match event {
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Request { request, channel, .. },
+            } => {
+                let id = self.next_request_id;
+                self.next_request_id += 1;
+                self.pending_responses.insert(id, channel);
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::SyncRequest { peer, id, request })
+                    .await;
+            }
+            request_response::Event::Message {
+                peer,
+                message: request_response::Message::Response { response, .. },
+            } => {
+                let _ = self
+                    .event_tx
+                    .send(NetworkEvent::SyncResponse { peer, response })
+                    .await;
+            }
+            request_response::Event::OutboundFailure { peer, error, .. } => {
+                warn!(peer = %peer, error = %error, "Sync request failed");
+            }
+            request_response::Event::InboundFailure { peer, error, .. } => {
+                debug!(peer = %peer, error = %error, "Failed to answer sync request");
+            }
+            request_response::Event::ResponseSent { .. } => {}
+        }
+    }
+
     /// Handle a swarm event
     async fn handle_swarm_event(&mut self, event: SwarmEvent<HardClawBehaviourEvent>) {
         match event {
@@ -532,6 +726,10 @@ impl NetworkNode {
                 }
             }
 
+            SwarmEvent::Behaviour(HardClawBehaviourEvent::Sync(event)) => {
+                self.handle_sync_event(event).await;
+            }
+
             SwarmEvent::ConnectionEstablished {
                 peer_id, endpoint, ..
             } => {
@@ -578,10 +776,21 @@ impl NetworkNode {
     }
 
     /// Handle a gossipsub message
-    async fn handle_gossipsub_message(&self, message: &gossipsub::Message) {
+    ///
+    /// Takes `&mut self` so the `run` future stays `Send` (the swarm is not `Sync`).
+    #[allow(clippy::needless_pass_by_ref_mut)]
+    async fn handle_gossipsub_message(&mut self, message: &gossipsub::Message) {
         let topic = message.topic.as_str();
 
         match topic {
+            TOPIC_TRANSACTIONS => {
+                if let Ok(tx) = bincode::deserialize::<Transaction>(&message.data) {
+                    debug!(tx_id = %tx.id, "Received transaction from network");
+                    let _ = self.event_tx.send(NetworkEvent::TransactionReceived(tx)).await;
+                } else {
+                    warn!("Failed to deserialize transaction message");
+                }
+            }
             TOPIC_JOBS => {
                 if let Ok(job) = bincode::deserialize::<JobPacket>(&message.data) {
                     debug!(job_id = %job.id, "Received job from network");
@@ -609,6 +818,14 @@ impl NetworkNode {
                     warn!("Failed to deserialize block message");
                 }
             }
+            TOPIC_PROPOSALS => {
+                if let Ok(block) = bincode::deserialize::<Block>(&message.data) {
+                    debug!(block_hash = %block.hash, height = block.header.height, "Received block proposal from network");
+                    let _ = self.event_tx.send(NetworkEvent::ProposalReceived(block)).await;
+                } else {
+                    warn!("Failed to deserialize block proposal");
+                }
+            }
             TOPIC_ATTESTATIONS => {
                 if let Ok(attestation) = bincode::deserialize::<VerifierAttestation>(&message.data)
                 {
@@ -621,6 +838,17 @@ impl NetworkNode {
                     warn!("Failed to deserialize attestation message");
                 }
             }
+            TOPIC_EVIDENCE => {
+                if let Ok(evidence) = bincode::deserialize::<Evidence>(&message.data) {
+                    debug!(offender = %evidence.offender(), height = evidence.height(), "Received evidence from network");
+                    let _ = self
+                        .event_tx
+                        .send(NetworkEvent::EvidenceReceived(Box::new(evidence)))
+                        .await;
+                } else {
+                    warn!("Failed to deserialize evidence message");
+                }
+            }
             _ => {
                 debug!(topic = %topic, "Unknown topic");
             }
@@ -632,6 +860,24 @@ impl NetworkNode {
         self.dial_and_add_to_dht(addr).await
     }
 
+    /// Broadcast a transaction to the network
+    ///
+    /// # Errors
+    /// Returns error if the transaction cannot be serialized or published
+    pub fn broadcast_transaction(&mut self, tx: &Transaction) -> Result<(), NetworkError> {
+        let data =
+            bincode::serialize(tx).map_err(|e| NetworkError::SendFailed(e.to_string()))?;
+
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.topics.transactions.clone(), data)
+            .map_err(|e| NetworkError::SendFailed(e.to_string()))?;
+
+        debug!(tx_id = %tx.id, "Broadcast transaction to network");
+        Ok(())
+    }
+
     /// Broadcast a job to the network
     pub fn broadcast_job(&mut self, job: &JobPacket) -> Result<(), NetworkError> {
         let data =
@@ -677,6 +923,24 @@ impl NetworkNode {
         Ok(())
     }
 
+    /// Broadcast a candidate block for verifiers to attest
+    ///
+    /// # Errors
+    /// Returns error if the block cannot be serialized or published
+    pub fn broadcast_proposal(&mut self, block: &Block) -> Result<(), NetworkError> {
+        let data =
+            bincode::serialize(block).map_err(|e| NetworkError::SendFailed(e.to_string()))?;
+
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.topics.proposals.clone(), data)
+            .map_err(|e| NetworkError::SendFailed(e.to_string()))?;
+
+        debug!(block_hash = %block.hash, height = block.header.height, "Broadcast block proposal to network");
+        Ok(())
+    }
+
     /// Broadcast an attestation to the network
     pub fn broadcast_attestation(
         &mut self,
@@ -695,13 +959,34 @@ impl NetworkNode {
         Ok(())
     }
 
+    /// Broadcast double-signing evidence to the network
+    ///
+    /// # Errors
+    /// Returns error if the evidence cannot be serialized or published
+    pub fn broadcast_evidence(&mut self, evidence: &Evidence) -> Result<(), NetworkError> {
+        let data =
+            bincode::serialize(evidence).map_err(|e| NetworkError::SendFailed(e.to_string()))?;
+
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(self.topics.evidence.clone(), data)
+            .map_err(|e| NetworkError::SendFailed(e.to_string()))?;
+
+        debug!(offender = %evidence.offender(), height = evidence.height(), "Broadcast evidence to network");
+        Ok(())
+    }
+
     /// Broadcast any network message (convenience method)
     pub fn broadcast(&mut self, message: &NetworkMessage) -> Result<(), NetworkError> {
         match message {
+            NetworkMessage::NewTransaction(tx) => self.broadcast_transaction(tx),
             NetworkMessage::NewJob(job) => self.broadcast_job(job),
             NetworkMessage::NewSolution(solution) => self.broadcast_solution(solution),
             NetworkMessage::NewBlock(block) => self.broadcast_block(block),
+            NetworkMessage::ProposeBlock(block) => self.broadcast_proposal(block),
             NetworkMessage::Attestation(attestation) => self.broadcast_attestation(attestation),
+            NetworkMessage::Evidence(evidence) => self.broadcast_evidence(evidence),
             _ => {
                 debug!(message = ?message, "Unhandled broadcast message type");
                 Ok(())
@@ -776,6 +1061,24 @@ pub trait NetworkHandler: Send + Sync {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::consensus::BlockProducerConfig;
+    use crate::crypto::Keypair;
+    use crate::types::TransactionKind;
+    use crate::wasm::WasmLimits;
+
+    #[test]
+    fn test_largest_block_fits_gossip() {
+        let limits = BlockProducerConfig::default();
+        assert!(limits.max_block_size * 2 <= MAX_GOSSIP_MESSAGE_BYTES);
+
+        // The largest module upload fits in a block, so it can be gossiped
+        let kp = Keypair::generate();
+        let wasm = vec![0; WasmLimits::default().max_module_bytes];
+        let upload = Transaction::new(*kp.public_key(), 0, TransactionKind::UploadModule(wasm));
+        let block = Block::with_transactions(1, Hash::ZERO, *kp.public_key(), vec![upload], Vec::new(), Hash::ZERO);
+        assert!(limits.check_limits(&block).is_ok());
+        assert!(bincode::serialize(&block).unwrap().len() < MAX_GOSSIP_MESSAGE_BYTES);
+    }
 
     #[test]
     fn test_network_config_default() {
@@ -0,0 +1,125 @@
+//! Chain sync protocol.
+//!
+//! Request-response messages a node uses to catch up with a peer: ask for
+//! its status, fetch a state snapshot at a trusted block, then fetch the
+//! canonical blocks after it in batches. Peers can also ask how settled a
+//! transaction or job payout is, and fetch the bytes of published verifier
+//! modules.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::Hash;
+use crate::state::{Confirmation, StateSnapshot};
+use crate::types::{Block, Id};
+
+/// Sync protocol name
+pub const SYNC_PROTOCOL: &str = "/hardclaw/sync/1.0.0";
+
+/// Maximum number of blocks served per request
+pub const MAX_BLOCKS_PER_REQUEST: u32 = 64;
+
+/// Largest response a peer reads; libp2p's cbor codec cuts longer ones off
+pub const MAX_SYNC_RESPONSE_BYTES: usize = 10 * 1024 * 1024;
+
+/// Sync request
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SyncRequest {
+    /// Ask for the peer's canonical height and tip
+    Status,
+    /// Ask for a state snapshot at a canonical block
+    Snapshot {
+        /// Block to snapshot at
+        block: Hash,
+    },
+    /// Ask for canonical blocks starting at a height
+    Blocks {
+        /// First height wanted
+        from: u64,
+        /// Maximum number of blocks (capped at `MAX_BLOCKS_PER_REQUEST`)
+        limit: u32,
+    },
+    /// Ask for the confirmation status of a transaction or job payout
+    Confirmation {
+        /// Transaction or job ID
+        id: Id,
+    },
+    /// Ask for the bytes of a published verifier module
+    Module {
+        /// Module hash
+        hash: Hash,
+    },
+}
+
+/// Sync response
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SyncResponse {
+    /// Canonical height (number of blocks), tip and available history
+    Status {
+        /// Chain height
+        height: u64,
+        /// Tip hash, if any block exists
+        tip: Option<Hash>,
+        /// Lowest height whose block the peer can serve
+        history_start: u64,
+        /// Highest height that can no longer be reverted
+        finalized: u64,
+    },
+    /// Snapshot, or `None` if the peer cannot produce one at that block
+    Snapshot(Option<Arc<StateSnapshot>>),
+    /// Canonical blocks in height order
+    Blocks(Vec<Block>),
+    /// Confirmation status of a transaction or job payout
+    Confirmation {
+        /// Transaction or job ID
+        id: Id,
+        /// How settled it is on the peer's canonical chain
+        status: Confirmation,
+    },
+    /// Bytes of a verifier module
+    Module {
+        /// Module hash
+        hash: Hash,
+        /// Module bytes, or `None` if the peer does not have them
+        wasm: Option<Vec<u8>>,
+    },
+    /// Request could not be served
+    Error(String),
+}
+
+impl SyncResponse {
+    /// Length of the response as the sync codec sends it
+    #[must_use]
+    pub fn encoded_len(&self) -> usize {
+        cbor4ii::serde::to_vec(Vec::new(), self).map_or(usize::MAX, |bytes| bytes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+
+    #[test]
+    fn test_sync_message_roundtrip() {
+        let block = Block::genesis(*Keypair::generate().public_key());
+        let response = SyncResponse::Blocks(vec![block.clone()]);
+
+        let bytes = serde_json::to_vec(&response).unwrap();
+        match serde_json::from_slice(&bytes).unwrap() {
+            SyncResponse::Blocks(blocks) => assert_eq!(blocks[0].hash, block.hash),
+            other => panic!("wrong response: {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_encoded_len_against_response_limit() {
+        let block = Block::genesis(*Keypair::generate().public_key());
+        assert!(SyncResponse::Blocks(vec![block]).encoded_len() < MAX_SYNC_RESPONSE_BYTES);
+
+        let module = |len: usize| SyncResponse::Module { hash: Hash::ZERO, wasm: Some(vec![0; len]) };
+        assert!(module(1024).encoded_len() > 1024);
+        assert!(module(MAX_SYNC_RESPONSE_BYTES).encoded_len() > MAX_SYNC_RESPONSE_BYTES);
+    }
+}
@@ -6,6 +6,7 @@
 //! - Mining the genesis block
 
 use std::io::{self, stdout};
+use std::path::PathBuf;
 use std::time::Duration;
 
 use crossterm::{
@@ -24,8 +25,9 @@ use ratatui::{
 };
 
 use hardclaw::{
+    genesis::GenesisSpec,
     wallet::Wallet,
-    types::Block as HcBlock,
+    types::{now_millis, timestamp_to_datetime, Block as HcBlock},
 };
 
 /// Application state
@@ -37,7 +39,7 @@ enum AppState {
     LoadWallet,
     WalletLoaded { address: String },
     MineGenesis,
-    GenesisMined { block_hash: String },
+    GenesisMined { block_hash: String, chain_id: String, timestamp: String, path: String },
     NodeRunning,
     Help,
     Quit,
@@ -222,13 +224,48 @@ impl App {
         }
     }
 
+    /// Load the genesis spec, or write one with this wallet as the only validator
+    fn genesis_spec(wallet: &Wallet) -> Result<GenesisSpec, String> {
+        let path = genesis_path();
+        if path.exists() {
+            return GenesisSpec::load(&path).map_err(|e| e.to_string());
+        }
+
+        let spec = GenesisSpec::single_validator(
+            "hardclaw-devnet".to_string(),
+            wallet.public_key(),
+            hardclaw::verifier::VerifierConfig::default().min_stake,
+            now_millis(),
+        );
+        spec.save(&path).map_err(|e| e.to_string())?;
+        Ok(spec)
+    }
+
     fn mine_genesis(&mut self) {
-        if let Some(wallet) = &self.wallet {
-            let genesis = HcBlock::genesis(*wallet.keypair().public_key());
-            let block_hash = genesis.hash.to_hex();
-            self.genesis_block = Some(genesis);
-            self.message = None;
-            self.state = AppState::GenesisMined { block_hash };
+        let Some(wallet) = &self.wallet else {
+            return;
+        };
+
+        let genesis = Self::genesis_spec(wallet)
+            .and_then(|spec| spec.block().map(|block| (spec, block)).map_err(|e| e.to_string()));
+        match genesis {
+            Ok((spec, block)) => {
+                let block_hash = block.hash.to_hex();
+                let timestamp = timestamp_to_datetime(spec.timestamp)
+                    .map_or_else(|| spec.timestamp.to_string(), |t| t.to_rfc3339());
+                self.genesis_block = Some(block);
+                self.message = None;
+                self.state = AppState::GenesisMined {
+                    block_hash,
+                    chain_id: spec.chain_id,
+                    timestamp,
+                    path: genesis_path().display().to_string(),
+                };
+            }
+            Err(e) => {
+                self.message = Some(format!("Failed to build genesis: {}", e));
+                self.state = AppState::MainMenu;
+            }
         }
     }
 
@@ -261,8 +298,8 @@ impl App {
                 self.render_wallet_loaded(frame, chunks[1], address);
             }
             AppState::MineGenesis => self.render_mine_genesis(frame, chunks[1]),
-            AppState::GenesisMined { block_hash } => {
-                self.render_genesis_mined(frame, chunks[1], block_hash);
+            AppState::GenesisMined { block_hash, chain_id, timestamp, path } => {
+                self.render_genesis_mined(frame, chunks[1], block_hash, chain_id, timestamp, path);
             }
             AppState::Help => self.render_help(frame, chunks[1]),
             AppState::NodeRunning => self.render_node_running(frame, chunks[1]),
@@ -552,9 +589,19 @@ impl App {
                 Style::default().fg(Color::Cyan),
             )),
             Line::from(""),
-            Line::from("Genesis parameters:"),
-            Line::from(Span::styled("  - Height: 0", Style::default().fg(Color::DarkGray))),
-            Line::from(Span::styled("  - Parent: 0x0000...0000", Style::default().fg(Color::DarkGray))),
+            Line::from("Genesis spec:"),
+            Line::from(Span::styled(
+                format!("  {}", genesis_path().display()),
+                Style::default().fg(Color::Cyan),
+            )),
+            Line::from(Span::styled(
+                if genesis_path().exists() {
+                    "  - Using the existing spec"
+                } else {
+                    "  - Will be created with you as the only validator"
+                },
+                Style::default().fg(Color::DarkGray),
+            )),
             Line::from(Span::styled("  - Consensus: Proof-of-Verification", Style::default().fg(Color::DarkGray))),
             Line::from(""),
             Line::from(""),
@@ -571,7 +618,15 @@ impl App {
         frame.render_widget(paragraph, centered_rect(65, 70, area));
     }
 
-    fn render_genesis_mined(&self, frame: &mut Frame, area: Rect, block_hash: &str) {
+    fn render_genesis_mined(
+        &self,
+        frame: &mut Frame,
+        area: Rect,
+        block_hash: &str,
+        chain_id: &str,
+        timestamp: &str,
+        path: &str,
+    ) {
         let text = vec![
             Line::from(""),
             Line::from(Span::styled(
@@ -591,9 +646,12 @@ impl App {
             )),
             Line::from(""),
             Line::from("Block Details:"),
+            Line::from(Span::styled(format!("  Chain:      {}", chain_id), Style::default().fg(Color::White))),
             Line::from(Span::styled("  Height:     0", Style::default().fg(Color::White))),
-            Line::from(Span::styled("  Timestamp:  Now", Style::default().fg(Color::White))),
-            Line::from(Span::styled("  Txns:       0", Style::default().fg(Color::White))),
+            Line::from(Span::styled(format!("  Timestamp:  {}", timestamp), Style::default().fg(Color::White))),
+            Line::from(Span::styled(format!("  Spec:       {}", path), Style::default().fg(Color::White))),
+            Line::from(""),
+            Line::from("Share the spec file so other nodes start on this chain."),
             Line::from(""),
             Line::from(""),
             Line::from(Span::styled(
@@ -687,6 +745,11 @@ impl App {
     }
 }
 
+/// Where the TUI reads and writes the genesis spec
+fn genesis_path() -> PathBuf {
+    Wallet::default_dir().join("genesis.json")
+}
+
 /// Helper function to create a centered rect
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::default()
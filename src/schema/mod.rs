@@ -0,0 +1,87 @@
+//! JSON Schema checks for structured outputs.
+//!
+//! Jobs with a `VerificationSpec::JsonSchema` spec expect JSON output (an
+//! extracted record, a tool call plan, a classification) and accept any
+//! output that satisfies the schema. The schema is named by the hash of its
+//! bytes and must be registered with each verifier's `SchemaRegistry`.
+//!
+//! Only a deterministic subset of JSON Schema is supported, so every
+//! verifier reaches the same verdict (see `JsonSchema` for the keywords).
+//! Schemas using anything else are refused when registered rather than
+//! partially checked.
+
+mod validate;
+
+pub use validate::{JsonSchema, Violation};
+
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use thiserror::Error;
+
+use crate::crypto::{hash_data, Hash};
+
+/// Errors from compiling a schema
+#[derive(Debug, Error)]
+pub enum SchemaError {
+    /// Schema is not valid JSON
+    #[error("schema is not valid JSON: {0}")]
+    InvalidJson(String),
+    /// Schema uses a keyword outside the supported subset
+    #[error("unsupported keyword `{0}`")]
+    Unsupported(String),
+    /// A keyword has a value of the wrong shape
+    #[error("invalid `{keyword}`: {reason}")]
+    InvalidKeyword {
+        /// Keyword at fault
+        keyword: String,
+        /// What is wrong with it
+        reason: String,
+    },
+}
+
+/// Compiled schemas by the hash of their bytes
+#[derive(Debug, Default)]
+pub struct SchemaRegistry {
+    schemas: RwLock<HashMap<Hash, Arc<JsonSchema>>>,
+}
+
+impl SchemaRegistry {
+    /// Create an empty registry
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compile and add a schema, returning the hash jobs name it by
+    ///
+    /// # Errors
+    /// Returns error if the schema is not valid JSON or uses unsupported
+    /// keywords
+    pub fn register(&self, schema: &[u8]) -> Result<Hash, SchemaError> {
+        let compiled = JsonSchema::from_slice(schema)?;
+        let hash = hash_data(schema);
+        if let Ok(mut schemas) = self.schemas.write() {
+            schemas.insert(hash, Arc::new(compiled));
+        }
+        Ok(hash)
+    }
+
+    /// Schema with this hash, if registered
+    #[must_use]
+    pub fn get(&self, hash: &Hash) -> Option<Arc<JsonSchema>> {
+        self.schemas.read().ok()?.get(hash).cloned()
+    }
+
+    /// Number of registered schemas
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.schemas.read().map_or(0, |schemas| schemas.len())
+    }
+
+    /// Whether no schema is registered
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
@@ -0,0 +1,470 @@
+//! Schema compilation and validation.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+
+use regex::{Regex, RegexBuilder};
+use serde_json::{Map, Number, Value};
+
+use super::SchemaError;
+
+/// Keywords that only describe a schema and are ignored when checking
+const ANNOTATIONS: &[&str] = &["$schema", "$id", "$comment", "title", "description", "default", "examples"];
+
+/// Largest compiled `pattern`, in bytes
+const MAX_PATTERN_SIZE: usize = 1 << 20;
+
+/// A compiled schema
+///
+/// Supported keywords:
+/// - any value: `type` (a name or a list of names), `enum`, `const`;
+/// - numbers: `minimum`, `maximum`, `exclusiveMinimum`, `exclusiveMaximum`;
+/// - strings: `minLength`, `maxLength` (in characters), `pattern` (matches
+///   anywhere unless anchored);
+/// - arrays: `items`, `minItems`, `maxItems`;
+/// - objects: `properties`, `required`, `additionalProperties`.
+///
+/// Annotations such as `title` and `description` are ignored.
+#[derive(Debug)]
+pub struct JsonSchema {
+    root: Node,
+}
+
+impl JsonSchema {
+    /// Compile a schema from its JSON bytes
+    ///
+    /// # Errors
+    /// Returns error if the bytes are not JSON or the schema uses
+    /// unsupported keywords
+    pub fn from_slice(schema: &[u8]) -> Result<Self, SchemaError> {
+        let value: Value = serde_json::from_slice(schema).map_err(|e| SchemaError::InvalidJson(e.to_string()))?;
+        Self::compile(&value)
+    }
+
+    /// Compile a schema
+    ///
+    /// # Errors
+    /// Returns error if the schema uses unsupported keywords or a keyword
+    /// has a value of the wrong shape
+    pub fn compile(schema: &Value) -> Result<Self, SchemaError> {
+        Ok(Self { root: Node::compile(schema)? })
+    }
+
+    /// Check a value against the schema
+    ///
+    /// Checks run depth-first, with an object's required fields before its
+    /// properties and properties in key order, so every node reports the same
+    /// first violation.
+    ///
+    /// # Errors
+    /// Returns the first violation found
+    pub fn validate(&self, value: &Value) -> Result<(), Violation> {
+        self.root.check(value, &mut String::from("$"))
+    }
+}
+
+/// Where a value first breaks its schema
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Violation {
+    /// Path to the offending value, e.g. `$.records[2].price`
+    pub path: String,
+    /// What is wrong with it
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+impl std::error::Error for Violation {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum JsonType {
+    Null,
+    Boolean,
+    Integer,
+    Number,
+    String,
+    Array,
+    Object,
+}
+
+impl JsonType {
+    fn parse(name: &str) -> Option<Self> {
+        Some(match name {
+            "null" => Self::Null,
+            "boolean" => Self::Boolean,
+            "integer" => Self::Integer,
+            "number" => Self::Number,
+            "string" => Self::String,
+            "array" => Self::Array,
+            "object" => Self::Object,
+            _ => return None,
+        })
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Null => "null",
+            Self::Boolean => "boolean",
+            Self::Integer => "integer",
+            Self::Number => "number",
+            Self::String => "string",
+            Self::Array => "array",
+            Self::Object => "object",
+        }
+    }
+
+    fn of(value: &Value) -> Self {
+        match value {
+            Value::Null => Self::Null,
+            Value::Bool(_) => Self::Boolean,
+            Value::Number(n) if is_integer(n) => Self::Integer,
+            Value::Number(_) => Self::Number,
+            Value::String(_) => Self::String,
+            Value::Array(_) => Self::Array,
+            Value::Object(_) => Self::Object,
+        }
+    }
+
+    fn matches(self, value: &Value) -> bool {
+        let actual = Self::of(value);
+        // Every integer is also a number
+        actual == self || (self == Self::Number && actual == Self::Integer)
+    }
+}
+
+/// What an object allows besides its listed properties
+#[derive(Debug, Default)]
+enum Additional {
+    #[default]
+    Allowed,
+    Forbidden,
+    Schema(Box<Node>),
+}
+
+#[derive(Debug, Default)]
+struct Node {
+    types: Vec<JsonType>,
+    allowed: Option<Vec<Value>>,
+    constant: Option<Value>,
+    minimum: Option<f64>,
+    maximum: Option<f64>,
+    exclusive_minimum: Option<f64>,
+    exclusive_maximum: Option<f64>,
+    min_length: Option<u64>,
+    max_length: Option<u64>,
+    pattern: Option<Regex>,
+    items: Option<Box<Self>>,
+    min_items: Option<u64>,
+    max_items: Option<u64>,
+    properties: BTreeMap<String, Self>,
+    required: Vec<String>,
+    additional: Additional,
+}
+
+impl Node {
+    fn compile(schema: &Value) -> Result<Self, SchemaError> {
+        let keywords = schema.as_object().ok_or_else(|| invalid("schema", "expected an object"))?;
+        let mut node = Self::default();
+        for (keyword, value) in keywords {
+            match keyword.as_str() {
+                "type" => node.types = types(value)?,
+                "enum" => node.allowed = Some(value.as_array().ok_or_else(|| invalid(keyword, "expected an array"))?.clone()),
+                "const" => node.constant = Some(value.clone()),
+                "minimum" => node.minimum = Some(number(keyword, value)?),
+                "maximum" => node.maximum = Some(number(keyword, value)?),
+                "exclusiveMinimum" => node.exclusive_minimum = Some(number(keyword, value)?),
+                "exclusiveMaximum" => node.exclusive_maximum = Some(number(keyword, value)?),
+                "minLength" => node.min_length = Some(count(keyword, value)?),
+                "maxLength" => node.max_length = Some(count(keyword, value)?),
+                "pattern" => node.pattern = Some(pattern(value)?),
+                "items" => node.items = Some(Box::new(Self::compile(value)?)),
+                "minItems" => node.min_items = Some(count(keyword, value)?),
+                "maxItems" => node.max_items = Some(count(keyword, value)?),
+                "properties" => {
+                    let properties = value.as_object().ok_or_else(|| invalid(keyword, "expected an object"))?;
+                    for (name, schema) in properties {
+                        node.properties.insert(name.clone(), Self::compile(schema)?);
+                    }
+                }
+                "required" => node.required = names(keyword, value)?,
+                "additionalProperties" => {
+                    node.additional = match value {
+                        Value::Bool(true) => Additional::Allowed,
+                        Value::Bool(false) => Additional::Forbidden,
+                        schema => Additional::Schema(Box::new(Self::compile(schema)?)),
+                    };
+                }
+                annotation if ANNOTATIONS.contains(&annotation) => {}
+                other => return Err(SchemaError::Unsupported(other.to_string())),
+            }
+        }
+        Ok(node)
+    }
+
+    fn check(&self, value: &Value, path: &mut String) -> Result<(), Violation> {
+        if !self.types.is_empty() && !self.types.iter().any(|t| t.matches(value)) {
+            let expected: Vec<_> = self.types.iter().map(|t| t.name()).collect();
+            return Err(violation(
+                path,
+                format!("expected {}, found {}", expected.join(" or "), JsonType::of(value).name()),
+            ));
+        }
+        if self.allowed.as_ref().is_some_and(|allowed| !allowed.contains(value)) {
+            return Err(violation(path, format!("{value} is not one of the allowed values")));
+        }
+        if let Some(constant) = self.constant.as_ref().filter(|c| *c != value) {
+            return Err(violation(path, format!("expected {constant}, found {value}")));
+        }
+        match value {
+            Value::Number(n) => self.check_number(n, path),
+            Value::String(s) => self.check_string(s, path),
+            Value::Array(items) => self.check_array(items, path),
+            Value::Object(fields) => self.check_object(fields, path),
+            Value::Null | Value::Bool(_) => Ok(()),
+        }
+    }
+
+    fn check_number(&self, n: &Number, path: &str) -> Result<(), Violation> {
+        let Some(x) = n.as_f64() else {
+            return Ok(());
+        };
+        let reason = if self.minimum.is_some_and(|min| x < min) {
+            "less than the minimum"
+        } else if self.exclusive_minimum.is_some_and(|min| x <= min) {
+            "not above the exclusive minimum"
+        } else if self.maximum.is_some_and(|max| x > max) {
+            "greater than the maximum"
+        } else if self.exclusive_maximum.is_some_and(|max| x >= max) {
+            "not below the exclusive maximum"
+        } else {
+            return Ok(());
+        };
+        Err(violation(path, format!("{n} is {reason}")))
+    }
+
+    fn check_string(&self, s: &str, path: &str) -> Result<(), Violation> {
+        let length = s.chars().count() as u64;
+        if let Some(min) = self.min_length.filter(|min| length < *min) {
+            return Err(violation(path, format!("length {length} is below the minimum of {min}")));
+        }
+        if let Some(max) = self.max_length.filter(|max| length > *max) {
+            return Err(violation(path, format!("length {length} is above the maximum of {max}")));
+        }
+        if let Some(pattern) = self.pattern.as_ref().filter(|p| !p.is_match(s)) {
+            return Err(violation(path, format!("does not match pattern `{pattern}`")));
+        }
+        Ok(())
+    }
+
+    fn check_array(&self, items: &[Value], path: &mut String) -> Result<(), Violation> {
+        let length = items.len() as u64;
+        if let Some(min) = self.min_items.filter(|min| length < *min) {
+            return Err(violation(path, format!("{length} items, expected at least {min}")));
+        }
+        if let Some(max) = self.max_items.filter(|max| length > *max) {
+            return Err(violation(path, format!("{length} items, expected at most {max}")));
+        }
+        let Some(schema) = &self.items else {
+            return Ok(());
+        };
+        for (index, item) in items.iter().enumerate() {
+            let len = path.len();
+            let _ = write!(path, "[{index}]");
+            schema.check(item, path)?;
+            path.truncate(len);
+        }
+        Ok(())
+    }
+
+    fn check_object(&self, fields: &Map<String, Value>, path: &mut String) -> Result<(), Violation> {
+        if let Some(missing) = self.required.iter().find(|name| !fields.contains_key(*name)) {
+            return Err(violation(path, format!("missing required field `{missing}`")));
+        }
+        for (name, field) in fields {
+            let len = path.len();
+            push_field(path, name);
+            let schema = match (self.properties.get(name), &self.additional) {
+                (Some(schema), _) => schema,
+                (None, Additional::Schema(schema)) => schema.as_ref(),
+                (None, Additional::Allowed) => {
+                    path.truncate(len);
+                    continue;
+                }
+                (None, Additional::Forbidden) => return Err(violation(path, "field is not allowed".to_string())),
+            };
+            schema.check(field, path)?;
+            path.truncate(len);
+        }
+        Ok(())
+    }
+}
+
+fn violation(path: &str, message: String) -> Violation {
+    Violation { path: path.to_string(), message }
+}
+
+/// Append `.name`, or `["name"]` if it is not a plain identifier
+fn push_field(path: &mut String, name: &str) {
+    let plain = name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if plain {
+        path.push('.');
+        path.push_str(name);
+    } else {
+        path.push('[');
+        path.push_str(&Value::from(name).to_string());
+        path.push(']');
+    }
+}
+
+fn is_integer(n: &Number) -> bool {
+    n.is_i64() || n.is_u64() || n.as_f64().is_some_and(|x| x.is_finite() && x.fract() == 0.0)
+}
+
+fn invalid(keyword: &str, reason: &str) -> SchemaError {
+    SchemaError::InvalidKeyword { keyword: keyword.to_string(), reason: reason.to_string() }
+}
+
+fn types(value: &Value) -> Result<Vec<JsonType>, SchemaError> {
+    let names = match value {
+        Value::String(name) => vec![name.clone()],
+        _ => names("type", value)?,
+    };
+    names
+        .iter()
+        .map(|name| JsonType::parse(name).ok_or_else(|| invalid("type", &format!("unknown type `{name}`"))))
+        .collect()
+}
+
+fn names(keyword: &str, value: &Value) -> Result<Vec<String>, SchemaError> {
+    value
+        .as_array()
+        .ok_or_else(|| invalid(keyword, "expected an array of strings"))?
+        .iter()
+        .map(|name| {
+            name.as_str()
+                .map(str::to_string)
+                .ok_or_else(|| invalid(keyword, "expected an array of strings"))
+        })
+        .collect()
+}
+
+fn number(keyword: &str, value: &Value) -> Result<f64, SchemaError> {
+    value.as_f64().ok_or_else(|| invalid(keyword, "expected a number"))
+}
+
+fn count(keyword: &str, value: &Value) -> Result<u64, SchemaError> {
+    value.as_u64().ok_or_else(|| invalid(keyword, "expected a non-negative integer"))
+}
+
+fn pattern(value: &Value) -> Result<Regex, SchemaError> {
+    let source = value.as_str().ok_or_else(|| invalid("pattern", "expected a string"))?;
+    RegexBuilder::new(source)
+        .size_limit(MAX_PATTERN_SIZE)
+        .build()
+        .map_err(|e| invalid("pattern", &e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn records_schema() -> JsonSchema {
+        JsonSchema::compile(&json!({
+            "title": "Extracted records",
+            "type": "object",
+            "required": ["records"],
+            "additionalProperties": false,
+            "properties": {
+                "records": {
+                    "type": "array",
+                    "minItems": 1,
+                    "items": {
+                        "type": "object",
+                        "required": ["sku", "price", "category"],
+                        "properties": {
+                            "sku": { "type": "string", "pattern": "^[A-Z]{3}-[0-9]+$" },
+                            "price": { "type": "number", "minimum": 0, "exclusiveMaximum": 10000 },
+                            "quantity": { "type": "integer", "minimum": 1 },
+                            "category": { "enum": ["food", "tools"] }
+                        }
+                    }
+                }
+            }
+        }))
+        .unwrap()
+    }
+
+    fn first_violation(schema: &JsonSchema, value: &Value) -> String {
+        schema.validate(value).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_reports_first_violation_path() {
+        let schema = records_schema();
+        let good = json!({ "records": [{ "sku": "ABC-1", "price": 2.5, "quantity": 3, "category": "food" }] });
+        assert!(schema.validate(&good).is_ok());
+
+        let cases = [
+            (json!({}), "$: missing required field `records`"),
+            (json!({ "records": [] }), "$.records: 0 items, expected at least 1"),
+            // Fields are checked in key order
+            (json!({ "records": [], "extra": 1 }), "$.extra: field is not allowed"),
+            (
+                json!({ "records": [{ "sku": "ABC-1", "price": 1, "category": "food" }, { "sku": "ABC-2", "category": "food" }] }),
+                "$.records[1]: missing required field `price`",
+            ),
+            (
+                json!({ "records": [{ "sku": "abc", "price": 1, "category": "food" }] }),
+                "$.records[0].sku: does not match pattern `^[A-Z]{3}-[0-9]+$`",
+            ),
+            (
+                json!({ "records": [{ "sku": "ABC-1", "price": -1, "category": "food" }] }),
+                "$.records[0].price: -1 is less than the minimum",
+            ),
+            (
+                json!({ "records": [{ "sku": "ABC-1", "price": 10000, "category": "food" }] }),
+                "$.records[0].price: 10000 is not below the exclusive maximum",
+            ),
+            (
+                json!({ "records": [{ "sku": "ABC-1", "price": 1, "quantity": 1.5, "category": "food" }] }),
+                "$.records[0].quantity: expected integer, found number",
+            ),
+            (
+                json!({ "records": [{ "sku": "ABC-1", "price": "1", "category": "toys" }] }),
+                "$.records[0].category: \"toys\" is not one of the allowed values",
+            ),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(first_violation(&schema, &value), expected);
+        }
+    }
+
+    #[test]
+    fn test_unsupported_keywords_are_refused() {
+        assert!(matches!(
+            JsonSchema::compile(&json!({ "oneOf": [{ "type": "string" }] })),
+            Err(SchemaError::Unsupported(k)) if k == "oneOf"
+        ));
+        assert!(matches!(
+            JsonSchema::compile(&json!({ "type": "text" })),
+            Err(SchemaError::InvalidKeyword { .. })
+        ));
+        assert!(matches!(
+            JsonSchema::compile(&json!({ "pattern": "(" })),
+            Err(SchemaError::InvalidKeyword { .. })
+        ));
+
+        // Fields that are not identifiers are quoted in paths
+        let schema = JsonSchema::compile(&json!({ "properties": { "unit price": { "maxLength": 2 } } })).unwrap();
+        assert_eq!(
+            first_violation(&schema, &json!({ "unit price": "abc" })),
+            "$[\"unit price\"]: length 3 is above the maximum of 2"
+        );
+    }
+}
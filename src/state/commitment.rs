@@ -0,0 +1,406 @@
+//! State commitment.
+//!
+//! The state root is the root of a sparse Merkle tree holding one leaf per
+//! account, job, solution and published module. Keys are domain-separated
+//! hashes of the record ID; values hash every consensus-relevant field (all
+//! `AccountState` fields including `staked`, `escrowed`, `tombstoned`,
+//! liveness and jail, job and solution status, module uploader and
+//! exports). From epoch 1 on, one more leaf holds the commitment to the
+//! epoch's validator set, and once anything is burned another holds the
+//! running total. Fee shares come from the genesis spec, which the genesis
+//! block commits to, so the root carries no other totals.
+//!
+//! Records changed since the last commit are folded into the tree lazily,
+//! so reading the root never mutates state. Solutions only enter state with
+//! the block settling them, so gossip never reaches the root.
+//!
+//! Pruned nodes drop closed jobs and their solutions but keep their leaves
+//! ("retired" leaves), so the root does not depend on the retention mode.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{hash_data, Hash, Hasher};
+use crate::types::{Address, HclawAmount, Id, JobPacket, SolutionCandidate, TestVector};
+
+use super::smt::{SparseMerkleProof, SparseMerkleTree};
+use super::{AccountState, ChainState, Epoch, ModuleRecord};
+
+/// Proof that an account has a given state (or does not exist) under a state root
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AccountProof {
+    /// Account being proven
+    pub address: Address,
+    /// Its state, or `None` if the account does not exist
+    pub account: Option<AccountState>,
+    /// Merkle path to the account's leaf
+    pub proof: SparseMerkleProof,
+}
+
+/// Check an account proof against a state root (e.g. a block header's `state_root`)
+#[must_use]
+pub fn verify_account_proof(root: &Hash, proof: &AccountProof) -> bool {
+    let value = proof.account.as_ref().map(account_leaf);
+    proof
+        .proof
+        .verify(root, &account_key(&proof.address), value.as_ref())
+}
+
+impl ChainState {
+    /// Compute the state root over all accounts, jobs and solutions
+    #[must_use]
+    pub fn compute_state_root(&self) -> Hash {
+        self.current_tree().root()
+    }
+
+    /// Prove the current state of an account
+    ///
+    /// The proof checks against `compute_state_root()`, which is the tip's
+    /// `state_root` as long as nothing changed since the tip was applied.
+    #[must_use]
+    pub fn prove_account(&self, address: &Address) -> AccountProof {
+        AccountProof {
+            address: *address,
+            account: self.accounts.get(address).cloned(),
+            proof: self.current_tree().prove(&account_key(address)),
+        }
+    }
+
+    /// Fold records changed since the last commit into the state tree
+    pub(super) fn sync_state_tree(&mut self) {
+        let mut tree = std::mem::take(&mut self.state_tree);
+        self.apply_changes(&mut tree);
+        self.state_tree = tree;
+    }
+
+    /// Build the state tree from scratch (after loading from disk)
+    pub(super) fn rebuild_state_tree(&mut self) {
+        self.state_tree = build_tree(
+            &self.accounts,
+            &self.jobs,
+            &self.solutions,
+            &self.modules,
+            &self.retired,
+            &self.epoch,
+            self.total_burned,
+        );
+    }
+
+    /// The committed tree plus any uncommitted changes
+    fn current_tree(&self) -> SparseMerkleTree {
+        let mut tree = self.state_tree.clone();
+        self.apply_changes(&mut tree);
+        tree
+    }
+
+    fn apply_changes(&self, tree: &mut SparseMerkleTree) {
+        for address in &self.dirty_accounts {
+            let key = account_key(address);
+            match self.accounts.get(address) {
+                Some(account) => tree.insert(key, account_leaf(account)),
+                None => tree.remove(&key),
+            }
+        }
+        for id in &self.dirty_jobs {
+            let key = job_key(id);
+            match self.jobs.get(id) {
+                Some(job) => tree.insert(key, job_leaf(job)),
+                None => self.remove_leaf(tree, key),
+            }
+        }
+        for id in &self.dirty_solutions {
+            let key = solution_key(id);
+            match self.solutions.get(id) {
+                Some(solution) => tree.insert(key, solution_leaf(solution)),
+                None => self.remove_leaf(tree, key),
+            }
+        }
+        for hash in &self.dirty_modules {
+            let key = module_key(hash);
+            match self.modules.get(hash) {
+                Some(module) => tree.insert(key, module_leaf(module)),
+                None => tree.remove(&key),
+            }
+        }
+        match epoch_leaf(&self.epoch) {
+            Some(leaf) if tree.get(&epoch_key()) != Some(leaf) => tree.insert(epoch_key(), leaf),
+            Some(_) => {}
+            None => tree.remove(&epoch_key()),
+        }
+        match burned_leaf(self.total_burned) {
+            Some(leaf) if tree.get(&burned_key()) != Some(leaf) => tree.insert(burned_key(), leaf),
+            Some(_) => {}
+            None => tree.remove(&burned_key()),
+        }
+    }
+
+    /// Remove a record's leaf unless the record was retired by pruning
+    fn remove_leaf(&self, tree: &mut SparseMerkleTree, key: Hash) {
+        match self.retired.get(&key) {
+            Some(leaf) => tree.insert(key, *leaf),
+            None => tree.remove(&key),
+        }
+    }
+}
+
+/// Build a state tree over a full set of records and retired leaves
+pub(super) fn build_tree(
+    accounts: &HashMap<Address, AccountState>,
+    jobs: &HashMap<Id, JobPacket>,
+    solutions: &HashMap<Id, SolutionCandidate>,
+    modules: &HashMap<Hash, ModuleRecord>,
+    retired: &HashMap<Hash, Hash>,
+    epoch: &Epoch,
+    total_burned: HclawAmount,
+) -> SparseMerkleTree {
+    let mut tree = SparseMerkleTree::new();
+    for (key, leaf) in retired {
+        tree.insert(*key, *leaf);
+    }
+    for (address, account) in accounts {
+        tree.insert(account_key(address), account_leaf(account));
+    }
+    for job in jobs.values() {
+        tree.insert(job_key(&job.id), job_leaf(job));
+    }
+    for solution in solutions.values() {
+        tree.insert(solution_key(&solution.id), solution_leaf(solution));
+    }
+    for (hash, module) in modules {
+        tree.insert(module_key(hash), module_leaf(module));
+    }
+    if let Some(leaf) = epoch_leaf(epoch) {
+        tree.insert(epoch_key(), leaf);
+    }
+    if let Some(leaf) = burned_leaf(total_burned) {
+        tree.insert(burned_key(), leaf);
+    }
+    tree
+}
+
+/// Tree key and leaf of a job, kept when the job is retired
+pub(super) fn job_entry(job: &JobPacket) -> (Hash, Hash) {
+    (job_key(&job.id), job_leaf(job))
+}
+
+/// Tree key and leaf of a solution, kept when the solution is retired
+pub(super) fn solution_entry(solution: &SolutionCandidate) -> (Hash, Hash) {
+    (solution_key(&solution.id), solution_leaf(solution))
+}
+
+fn tree_key(domain: &[u8], id: &[u8]) -> Hash {
+    let mut hasher = Hasher::new();
+    hasher.update(domain).update(id);
+    hasher.finalize()
+}
+
+fn account_key(address: &Address) -> Hash {
+    tree_key(b"account/", address.as_bytes())
+}
+
+pub(super) fn job_key(id: &Id) -> Hash {
+    tree_key(b"job/", id.as_bytes())
+}
+
+pub(super) fn solution_key(id: &Id) -> Hash {
+    tree_key(b"solution/", id.as_bytes())
+}
+
+fn module_key(hash: &Hash) -> Hash {
+    tree_key(b"module/", hash.as_bytes())
+}
+
+fn epoch_key() -> Hash {
+    tree_key(b"epoch", &[])
+}
+
+fn burned_key() -> Hash {
+    tree_key(b"burned", &[])
+}
+
+/// Leaf of the total burned; none while nothing is
+fn burned_leaf(total: HclawAmount) -> Option<Hash> {
+    (total > HclawAmount::ZERO).then(|| hash_data(&total.raw().to_le_bytes()))
+}
+
+/// Leaf of the current epoch's commitment; epoch 0 has none
+fn epoch_leaf(epoch: &Epoch) -> Option<Hash> {
+    (epoch.number > 0).then(|| {
+        let commitment = epoch.commitment();
+        let mut hasher = Hasher::new();
+        hasher
+            .update(&commitment.number.to_le_bytes())
+            .update(commitment.validators_root.as_bytes())
+            .update(&commitment.total_stake.raw().to_le_bytes());
+        hasher.finalize()
+    })
+}
+
+fn account_leaf(account: &AccountState) -> Hash {
+    let mut hasher = Hasher::new();
+    hasher
+        .update(&account.balance.raw().to_le_bytes())
+        .update(&account.nonce.to_le_bytes())
+        .update(&account.staked.raw().to_le_bytes())
+        .update(&account.escrowed.raw().to_le_bytes())
+        .update(&account.total_rewards.raw().to_le_bytes())
+        .update(&account.total_spent.raw().to_le_bytes())
+        .update(&account.total_earned.raw().to_le_bytes())
+        .update(&[u8::from(account.tombstoned)])
+        .update(&account.liveness.missed.to_le_bytes())
+        .update(&account.liveness.recorded.to_le_bytes())
+        .update(&account.liveness.last_signed.to_le_bytes())
+        .update(&account.jailed_until.map_or([0u8; 9], |height| {
+            let mut bytes = [1u8; 9];
+            bytes[1..].copy_from_slice(&height.to_le_bytes());
+            bytes
+        }));
+    hasher.finalize()
+}
+
+fn job_leaf(job: &JobPacket) -> Hash {
+    let mut hasher = Hasher::new();
+    hasher
+        .update(job.id.as_bytes())
+        .update(&[job.status as u8]);
+    // Commit-reveal records of `TestVectors` jobs; other jobs leave these empty
+    for lock in &job.locked_solutions {
+        hasher
+            .update(b"L")
+            .update(lock.solver.as_bytes())
+            .update(lock.commitment.as_bytes())
+            .update(&lock.bond.raw().to_le_bytes());
+        if let Some(output_hash) = &lock.opened {
+            hasher.update(b"O").update(output_hash.as_bytes());
+        }
+    }
+    if let Some(vectors) = &job.revealed_vectors {
+        hasher.update(b"V").update(hash_data(&TestVector::encode_all(vectors)).as_bytes());
+    }
+    hasher.finalize()
+}
+
+fn solution_leaf(solution: &SolutionCandidate) -> Hash {
+    let mut hasher = Hasher::new();
+    hasher
+        .update(solution.id.as_bytes())
+        .update(solution.job_id.as_bytes())
+        .update(&[solution.status as u8]);
+    hasher.finalize()
+}
+
+fn module_leaf(module: &ModuleRecord) -> Hash {
+    let mut hasher = Hasher::new();
+    hasher
+        .update(module.uploader.as_bytes())
+        .update(&module.size.to_le_bytes());
+    for names in [&module.entry_points, &module.scorers] {
+        hasher.update(&(names.len() as u64).to_le_bytes());
+        for name in names {
+            hasher.update(&(name.len() as u64).to_le_bytes()).update(name.as_bytes());
+        }
+    }
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+    use crate::mempool::Mempool;
+    use crate::state::tests::{draft, seal, signed_result};
+    use crate::types::{Block, HclawAmount, JobType, VerificationSpec};
+
+    fn address() -> Address {
+        Address::from_public_key(Keypair::generate().public_key())
+    }
+
+    #[test]
+    fn test_account_proof_roundtrip() {
+        let mut state = ChainState::new();
+        let alice = address();
+        let bob = address();
+        state.get_or_create_account(&alice).credit(HclawAmount::from_hclaw(10));
+        state.get_or_create_account(&bob).staked = HclawAmount::from_hclaw(3);
+
+        let root = state.compute_state_root();
+        let proof = state.prove_account(&alice);
+        assert!(verify_account_proof(&root, &proof));
+
+        // Tampered balance fails
+        let mut forged = proof.clone();
+        forged.account.as_mut().unwrap().balance = HclawAmount::from_hclaw(1_000);
+        assert!(!verify_account_proof(&root, &forged));
+
+        // Absent accounts are provable too
+        let nobody = state.prove_account(&address());
+        assert!(nobody.account.is_none());
+        assert!(verify_account_proof(&root, &nobody));
+    }
+
+    #[test]
+    fn test_root_covers_stake() {
+        let mut state = ChainState::new();
+        let alice = address();
+        state.get_or_create_account(&alice).credit(HclawAmount::from_hclaw(10));
+        let before = state.compute_state_root();
+
+        state.get_or_create_account(&alice).staked = HclawAmount::from_hclaw(5);
+        assert_ne!(state.compute_state_root(), before);
+    }
+
+    #[test]
+    fn test_sync_keeps_root() {
+        let mut state = ChainState::new();
+        state.get_or_create_account(&address()).credit(HclawAmount::from_hclaw(1));
+        let root = state.compute_state_root();
+
+        state.sync_state_tree();
+        assert_eq!(state.state_tree.root(), root);
+        assert_eq!(state.compute_state_root(), root);
+    }
+
+    #[test]
+    fn test_gossip_does_not_reach_root() {
+        let proposer = Keypair::generate();
+        let requester = Keypair::generate();
+        let mut job = JobPacket::new(
+            JobType::Deterministic,
+            *requester.public_key(),
+            b"input".to_vec(),
+            "Test".to_string(),
+            HclawAmount::from_hclaw(10),
+            HclawAmount::from_hclaw(1),
+            VerificationSpec::HashMatch { expected_hash: hash_data(b"output") },
+            3600,
+        );
+        job.signature = requester.sign(&job.signing_bytes());
+        let genesis = Block::genesis(*proposer.public_key());
+        let node = || {
+            let mut state = ChainState::new();
+            state.apply_block(genesis.clone()).unwrap();
+            state.get_or_create_account(&job.requester_address).credit(HclawAmount::from_hclaw(100));
+            state.store_job(job.clone()).unwrap();
+            state
+        };
+        let (mut gossiped, mut quiet) = (node(), node());
+
+        // Only the first node hears the solutions
+        let solution = SolutionCandidate::new(job.id, *Keypair::generate().public_key(), b"output".to_vec());
+        let unsettled = SolutionCandidate::new(job.id, *Keypair::generate().public_key(), b"other".to_vec());
+        let mut mempool = Mempool::new();
+        mempool.add_job(job.clone()).unwrap();
+        mempool.add_solution(solution.clone()).unwrap();
+        mempool.add_solution(unsettled.clone()).unwrap();
+
+        let block = draft(&gossiped, &proposer, Vec::new(), vec![signed_result(&proposer, &solution, true)]);
+        let block = seal(&mut gossiped, &proposer, block);
+        gossiped.apply_block(block.clone()).unwrap();
+        quiet.apply_block(block).unwrap();
+
+        assert_eq!(gossiped.compute_state_root(), quiet.compute_state_root());
+        assert!(gossiped.get_solution(&solution.id).is_some());
+        assert!(gossiped.get_solution(&unsettled.id).is_none());
+    }
+}
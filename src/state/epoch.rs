@@ -0,0 +1,184 @@
+//! Validator epochs.
+//!
+//! The validator set only changes at epoch boundaries, every `EPOCH_LENGTH`
+//! heights. The first block of an epoch freezes the verifiers eligible in
+//! its parent's state into an `Epoch` and commits to it in its header; the
+//! proposer schedule and the validator set are read from it until the next
+//! boundary. Stake, unstake, slashing and jailing during an epoch take
+//! effect from the next one.
+//!
+//! Epoch 0 has no parent state to freeze, so until the first boundary the
+//! set follows the current state, which lets a new chain take on its
+//! first verifiers.
+
+use serde::{Deserialize, Serialize};
+
+use crate::consensus::{ProposerSchedule, ValidatorSet};
+use crate::crypto::{hash_data, merkle_root, Hash};
+use crate::types::{Address, Block, EpochCommitment, HclawAmount};
+
+use super::execution::BlockUndo;
+use super::{ChainState, StateError};
+
+/// Number of heights in an epoch
+pub const EPOCH_LENGTH: u64 = 100;
+
+/// Verifier set frozen for an epoch
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Epoch {
+    /// Epoch number (the height of its first block over `EPOCH_LENGTH`)
+    pub number: u64,
+    /// Members and their effective stake, sorted by address
+    pub validators: Vec<(Address, HclawAmount)>,
+}
+
+impl Epoch {
+    /// Freeze a set of verifiers and their stake
+    ///
+    /// Verifiers without stake are left out.
+    #[must_use]
+    pub fn new<I>(number: u64, validators: I) -> Self
+    where
+        I: IntoIterator<Item = (Address, HclawAmount)>,
+    {
+        let mut validators: Vec<_> = validators
+            .into_iter()
+            .filter(|(_, stake)| *stake > HclawAmount::ZERO)
+            .collect();
+        validators.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+        Self { number, validators }
+    }
+
+    /// Stake of all members together
+    #[must_use]
+    pub fn total_stake(&self) -> HclawAmount {
+        self.validators
+            .iter()
+            .fold(HclawAmount::ZERO, |sum, (_, stake)| sum.saturating_add(*stake))
+    }
+
+    /// Commitment carried by the epoch's first block
+    #[must_use]
+    pub fn commitment(&self) -> EpochCommitment {
+        let leaves: Vec<Hash> = self
+            .validators
+            .iter()
+            .map(|(address, stake)| {
+                let mut data = address.as_bytes().to_vec();
+                data.extend_from_slice(&stake.raw().to_le_bytes());
+                hash_data(&data)
+            })
+            .collect();
+        EpochCommitment {
+            number: self.number,
+            validators_root: merkle_root(&leaves),
+            total_stake: self.total_stake(),
+        }
+    }
+
+    /// Proposer schedule over the members
+    #[must_use]
+    pub fn proposer_schedule(&self) -> ProposerSchedule {
+        ProposerSchedule::new(self.validators.iter().copied())
+    }
+
+    /// Attesting set of the members
+    #[must_use]
+    pub fn validator_set(&self) -> ValidatorSet {
+        ValidatorSet::new(self.validators.iter().copied())
+    }
+}
+
+impl ChainState {
+    /// Epoch in force for the block after the current tip
+    #[must_use]
+    pub fn epoch(&self) -> Epoch {
+        if self.height < EPOCH_LENGTH || self.height.is_multiple_of(EPOCH_LENGTH) {
+            Epoch::new(self.height / EPOCH_LENGTH, self.stake_manager().eligible_stakes())
+        } else {
+            self.epoch.clone()
+        }
+    }
+
+    /// Commitment the block after the current tip must carry, if it starts
+    /// an epoch
+    #[must_use]
+    pub fn epoch_commitment(&self) -> Option<EpochCommitment> {
+        (self.height > 0 && self.height.is_multiple_of(EPOCH_LENGTH)).then(|| self.epoch().commitment())
+    }
+
+    /// Check a block's epoch commitment, and freeze the new set if it starts
+    /// an epoch
+    ///
+    /// Must be called with the state at the block's parent.
+    pub(super) fn enter_epoch(&mut self, block: &Block, undo: &mut BlockUndo) -> Result<(), StateError> {
+        let expected = self.epoch_commitment();
+        if block.header.epoch != expected {
+            return Err(StateError::InvalidBlock(format!(
+                "epoch commitment does not match the validator set at height {}",
+                block.header.height
+            )));
+        }
+        if expected.is_some() {
+            let epoch = self.epoch();
+            undo.epoch = Some(std::mem::replace(&mut self.epoch, epoch));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+    use crate::state::tests::{draft, seal, signed};
+    use crate::types::VerifierAttestation;
+
+    fn stake(state: &mut ChainState, kp: &Keypair, hclaw: u64) {
+        state
+            .get_or_create_account(&Address::from_public_key(kp.public_key()))
+            .staked = HclawAmount::from_hclaw(hclaw);
+    }
+
+    /// Next block from `kp`, which also attests to it so it stays live
+    fn advance(state: &mut ChainState, kp: &Keypair) {
+        let block = draft(state, kp, Vec::new(), Vec::new()).with_parent_attestations(state.tip_attestations());
+        let mut block = seal(state, kp, block);
+        let mut attestation = VerifierAttestation::new(*kp.public_key(), block.hash, Vec::new());
+        attestation.signature = kp.sign(&attestation.signing_bytes());
+        block.add_attestation(attestation);
+        state.apply_block(block).unwrap();
+    }
+
+    #[test]
+    fn test_validator_set_changes_at_epoch_boundaries() {
+        let mut state = ChainState::new();
+        let (a, b) = (Keypair::generate(), Keypair::generate());
+        state.apply_block(Block::genesis(*a.public_key())).unwrap();
+
+        // The first epoch follows the current state
+        stake(&mut state, &a, 1000);
+        assert_eq!(state.validator_set().len(), 1);
+        while state.height() < EPOCH_LENGTH {
+            assert!(state.epoch_commitment().is_none());
+            advance(&mut state, &a);
+        }
+
+        // The first block of epoch 1 commits to the set it freezes
+        let expected = Epoch::new(1, [(Address::from_public_key(a.public_key()), HclawAmount::from_hclaw(1000))]);
+        assert_eq!(state.epoch_commitment(), Some(expected.commitment()));
+        let unmarked = signed(draft(&state, &a, Vec::new(), Vec::new()).with_epoch(None), &a);
+        assert!(matches!(state.import_block(unmarked), Err(StateError::InvalidBlock(_))));
+        advance(&mut state, &a);
+        assert_eq!(state.epoch(), expected);
+
+        // Stake added mid-epoch waits for the next boundary
+        stake(&mut state, &b, 1000);
+        assert_eq!(state.validator_set().len(), 1);
+        while state.height() < 2 * EPOCH_LENGTH {
+            advance(&mut state, &a);
+        }
+        assert_eq!(state.validator_set().len(), 2);
+        assert_eq!(state.epoch_commitment().unwrap().total_stake, HclawAmount::from_hclaw(2000));
+    }
+}
@@ -0,0 +1,84 @@
+//! Bounty escrow.
+//!
+//! Accepting a job burns its `burn_fee` and locks its `bounty` in the
+//! requester's account, where it stops counting as available balance. The
+//! lock is paid out through the `FeeDistributor` when a solution is verified,
+//! or handed back to the requester once block time passes the job's
+//! `expires_at` with no verified solution (unless it is forfeited to
+//! solvers who opened their locks, see `vectors`). Solvers' lock bonds are
+//! escrowed the same way.
+
+use crate::types::{Address, HclawAmount, Id, JobPacket, JobStatus, Timestamp};
+
+use super::{AccountState, ChainState, StateError};
+
+impl AccountState {
+    /// Burn a job's fee and lock its bounty
+    ///
+    /// # Errors
+    /// Returns error if the available balance does not cover the job's total cost
+    pub fn lock_bounty(&mut self, job: &JobPacket) -> Result<(), StateError> {
+        let need = job.total_cost();
+        if self.available_balance() < need {
+            return Err(StateError::InsufficientBalance {
+                have: self.available_balance(),
+                need,
+            });
+        }
+
+        self.balance = self.balance.saturating_sub(job.burn_fee);
+        self.total_spent = self.total_spent.saturating_add(job.burn_fee);
+        self.escrowed = self.escrowed.saturating_add(job.bounty);
+        Ok(())
+    }
+
+    /// Pay a locked bounty out of the account
+    ///
+    /// # Errors
+    /// Returns error if less than `amount` is locked
+    pub fn release_bounty(&mut self, amount: HclawAmount) -> Result<(), StateError> {
+        if self.escrowed < amount {
+            return Err(StateError::InsufficientEscrow {
+                have: self.escrowed,
+                need: amount,
+            });
+        }
+
+        self.escrowed = self.escrowed.saturating_sub(amount);
+        self.balance = self.balance.saturating_sub(amount);
+        self.total_spent = self.total_spent.saturating_add(amount);
+        Ok(())
+    }
+
+    /// Unlock a bounty back into the available balance
+    pub fn refund_bounty(&mut self, amount: HclawAmount) {
+        self.escrowed = self.escrowed.saturating_sub(amount);
+    }
+}
+
+impl ChainState {
+    /// Bounty an account has locked in open jobs
+    #[must_use]
+    pub fn escrowed_balance(&self, address: &Address) -> HclawAmount {
+        self.accounts
+            .get(address)
+            .map_or(HclawAmount::ZERO, |a| a.escrowed)
+    }
+
+    /// Open jobs whose deadline is before `now`, in ID order
+    pub(super) fn expired_jobs(&self, now: Timestamp) -> Vec<Id> {
+        let mut expired: Vec<Id> = self
+            .jobs
+            .values()
+            .filter(|job| holds_escrow(job) && job.expires_at < now)
+            .map(|job| job.id)
+            .collect();
+        expired.sort_unstable();
+        expired
+    }
+}
+
+/// Whether a job is still open, with its bounty locked
+pub(super) const fn holds_escrow(job: &JobPacket) -> bool {
+    !matches!(job.status, JobStatus::Completed | JobStatus::Expired | JobStatus::Forfeited)
+}
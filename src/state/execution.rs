@@ -0,0 +1,413 @@
+//! Deterministic block execution.
+//!
+//! Applying a block first checks its epoch commitment (see `epoch`), then
+//! records which verifiers signed its parent (see `liveness`), then runs
+//! its transactions in order (each must carry the sender's next nonce;
+//! evidence reports slash the offender), then runs each
+//! `VerificationResult` through the same state transition on every node:
+//! the escrowed bounty is split by the `FeeDistributor`, the burn share
+//! leaves circulation, and job/solution statuses advance. Last, open jobs
+//! past their deadline at the block's timestamp expire and their bounties
+//! are refunded (or forfeited, see `vectors`).
+//! Every touched record is journaled first so a block that fails part-way
+//! (or whose `state_root` does not match) can be rolled back exactly.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::Hash;
+
+use crate::types::{
+    Address, Block, HclawAmount, Id, JobPacket, JobStatus, SolutionCandidate, SolutionStatus,
+    Timestamp, Transaction, TransactionKind, VerificationResult,
+};
+
+use super::commitment::{job_key, solution_key};
+use super::escrow::holds_escrow;
+use super::vectors::{check_locked_in, forfeit_recipients};
+use super::{AccountState, ChainState, Epoch, ModuleRecord, StateError};
+
+/// Pre-images of every record a block touched
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BlockUndo {
+    /// Account states before the block (None if the account did not exist)
+    accounts: Vec<(Address, Option<AccountState>)>,
+    /// Jobs before the block
+    jobs: Vec<(Id, Option<JobPacket>)>,
+    /// Solutions before the block
+    solutions: Vec<(Id, Option<SolutionCandidate>)>,
+    /// Modules published by the block
+    modules: Vec<Hash>,
+    /// Total burned before the block
+    total_burned: HclawAmount,
+    /// Epoch the block replaced, if it started a new one
+    pub(super) epoch: Option<Epoch>,
+    /// Keys already journaled (first pre-image wins)
+    #[serde(skip)]
+    seen_accounts: HashSet<Address>,
+    #[serde(skip)]
+    seen_jobs: HashSet<Id>,
+    #[serde(skip)]
+    seen_solutions: HashSet<Id>,
+}
+
+impl BlockUndo {
+    /// Start a journal for a block executed on top of `state`
+    #[must_use]
+    pub fn new(state: &ChainState) -> Self {
+        Self {
+            total_burned: state.total_burned,
+            ..Self::default()
+        }
+    }
+
+    /// Restore the pre-images into detached copies of the state records
+    pub fn restore_into(&self, records: &mut StateRecords) {
+        for (address, account) in self.accounts.iter().rev() {
+            match account {
+                Some(account) => records.accounts.insert(*address, account.clone()),
+                None => records.accounts.remove(address),
+            };
+        }
+        for (id, job) in self.jobs.iter().rev() {
+            match job {
+                Some(job) => records.jobs.insert(*id, job.clone()),
+                None => records.jobs.remove(id),
+            };
+        }
+        for (id, solution) in self.solutions.iter().rev() {
+            match solution {
+                Some(solution) => records.solutions.insert(*id, solution.clone()),
+                None => records.solutions.remove(id),
+            };
+        }
+        for hash in &self.modules {
+            records.modules.remove(hash);
+        }
+        records.total_burned = self.total_burned;
+        if let Some(epoch) = &self.epoch {
+            records.epoch = epoch.clone();
+        }
+    }
+}
+
+/// Copies of the records the state root commits to
+#[derive(Clone, Debug, Default)]
+pub struct StateRecords {
+    /// Accounts by address
+    pub accounts: HashMap<Address, AccountState>,
+    /// Jobs by ID
+    pub jobs: HashMap<Id, JobPacket>,
+    /// Solutions by ID
+    pub solutions: HashMap<Id, SolutionCandidate>,
+    /// Published modules by hash
+    pub modules: HashMap<Hash, ModuleRecord>,
+    /// Leaves of records retired by pruning, by tree key
+    pub retired: HashMap<Hash, Hash>,
+    /// Total burned
+    pub total_burned: HclawAmount,
+    /// Validator set of the current epoch
+    pub epoch: Epoch,
+}
+
+impl ChainState {
+    /// Run the state transition for a block: its epoch commitment, liveness
+    /// of the parent's signers, transactions, then verifications, then
+    /// expiry of jobs past their deadline at the block's timestamp
+    ///
+    /// Records pre-images into `undo` as it goes; on error the caller is
+    /// expected to roll back with `revert`.
+    ///
+    /// # Errors
+    /// Returns error if the epoch commitment or parent attestations are
+    /// invalid, or any
+    /// transaction or verification cannot be executed
+    pub(super) fn execute_block(&mut self, block: &Block, undo: &mut BlockUndo) -> Result<(), StateError> {
+        self.execute_block_transactions(block, undo)?;
+        self.execute_verifications(&block.verifications, &block.solutions, undo)?;
+        self.expire_jobs(block.header.timestamp, undo)
+    }
+
+    /// The part of `execute_block` that runs ahead of the verifications
+    fn execute_block_transactions(&mut self, block: &Block, undo: &mut BlockUndo) -> Result<(), StateError> {
+        self.enter_epoch(block, undo)?;
+        self.record_liveness(block, undo)?;
+        for tx in &block.transactions {
+            self.execute_transaction(tx, block.header.timestamp, undo)?;
+        }
+        Ok(())
+    }
+
+    /// The job each of `block`'s verifications settles, as it stands once
+    /// the block's transactions have run, without changing state
+    ///
+    /// # Errors
+    /// Returns error if a transaction cannot be executed or a job is unknown
+    pub(super) fn settled_jobs(&mut self, block: &Block) -> Result<Vec<JobPacket>, StateError> {
+        let mut undo = BlockUndo::new(self);
+        let jobs = self.execute_block_transactions(block, &mut undo).and_then(|()| {
+            block
+                .verifications
+                .iter()
+                .map(|result| self.jobs.get(&result.job_id).cloned().ok_or(StateError::JobNotFound))
+                .collect()
+        });
+        self.revert(undo);
+        jobs
+    }
+
+    /// Run one transaction at block time `now`, consuming the sender's nonce
+    pub(super) fn execute_transaction(
+        &mut self,
+        tx: &Transaction,
+        now: Timestamp,
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        tx.validate()
+            .map_err(|e| StateError::InvalidTransaction(e.to_string()))?;
+
+        let sender_address = tx.sender_address();
+        let sender = self.journal_account(undo, &sender_address);
+        if tx.nonce != sender.nonce {
+            return Err(StateError::InvalidNonce {
+                expected: sender.nonce,
+                got: tx.nonce,
+            });
+        }
+        sender.nonce += 1;
+
+        match &tx.kind {
+            TransactionKind::Transfer { to, amount } => {
+                sender.debit(*amount)?;
+                self.journal_account(undo, to).credit(*amount);
+            }
+            TransactionKind::Stake { amount } => {
+                if sender.tombstoned {
+                    return Err(StateError::InvalidTransaction("account is tombstoned".to_string()));
+                }
+                if sender.available_balance() < *amount {
+                    return Err(StateError::InsufficientBalance {
+                        have: sender.available_balance(),
+                        need: *amount,
+                    });
+                }
+                sender.staked = sender.staked.saturating_add(*amount);
+            }
+            TransactionKind::Unstake { amount } => {
+                if sender.staked < *amount {
+                    return Err(StateError::InsufficientStake {
+                        have: sender.staked,
+                        need: *amount,
+                    });
+                }
+                sender.staked = sender.staked.saturating_sub(*amount);
+            }
+            TransactionKind::SubmitJob(job) => {
+                // `validate` made the requester the sender, so this is the
+                // job's escrow address
+                sender.lock_bounty(job)?;
+                self.check_job_admission(job)?;
+                if self.jobs.contains_key(&job.id) || self.retired.contains_key(&job_key(&job.id)) {
+                    return Err(StateError::InvalidTransaction("job already exists".to_string()));
+                }
+                self.total_burned = self.total_burned.saturating_add(job.burn_fee);
+                self.journal_new_job(undo, job.as_ref().clone());
+            }
+            TransactionKind::ReportEvidence(evidence) => self.apply_evidence(evidence, undo)?,
+            TransactionKind::Unjail => self.unjail(&sender_address, undo)?,
+            TransactionKind::UploadModule(wasm) => self.register_module(&sender_address, wasm, undo)?,
+            TransactionKind::LockSolution { job_id, commitment } => {
+                self.lock_solution(&sender_address, *job_id, *commitment, now, undo)?;
+            }
+            TransactionKind::OpenLock { job_id, output_hash, salt } => {
+                self.open_lock(&sender_address, *job_id, *output_hash, salt, now, undo)?;
+            }
+            TransactionKind::RevealVectors { job_id, vectors, nonce } => {
+                self.reveal_vectors(&sender_address, *job_id, vectors, nonce, now, undo)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Run the state transition for a list of verification results and the
+    /// solutions they settle
+    ///
+    /// Fails if a result references an unknown or closed job, its solution
+    /// is missing or already settled, or a requester has less escrowed than
+    /// the bounty.
+    fn execute_verifications(
+        &mut self,
+        verifications: &[VerificationResult],
+        solutions: &[SolutionCandidate],
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        if solutions.len() != verifications.len() {
+            return Err(StateError::InvalidBlock(format!(
+                "{} verifications settle {} solutions",
+                verifications.len(),
+                solutions.len()
+            )));
+        }
+        for (result, solution) in verifications.iter().zip(solutions) {
+            self.execute_verification(result, solution, undo)?;
+        }
+        Ok(())
+    }
+
+    /// Settle one solution, which enters state here with its verdict
+    fn execute_verification(
+        &mut self,
+        result: &VerificationResult,
+        solution: &SolutionCandidate,
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        if solution.id != result.solution_id {
+            return Err(StateError::SolutionNotFound);
+        }
+        let job = self.jobs.get(&result.job_id).ok_or(StateError::JobNotFound)?;
+        if solution.job_id != job.id {
+            return Err(StateError::SolutionJobMismatch);
+        }
+        if !holds_escrow(job) {
+            return Err(StateError::JobClosed);
+        }
+        if self.solutions.contains_key(&solution.id) || self.retired.contains_key(&solution_key(&solution.id)) {
+            return Err(StateError::SolutionAlreadySettled);
+        }
+        check_locked_in(job, solution)?;
+
+        let mut settled = solution.clone();
+        if !result.passed {
+            settled.status = SolutionStatus::Rejected;
+            self.journal_new_solution(undo, settled);
+            return self.burn_lock_bond(result.job_id, &solution.solver_address, undo);
+        }
+
+        let bounty = job.bounty;
+        let requester = job.escrow_address();
+        let distribution = self.fee_distributor.distribute(
+            bounty,
+            solution.solver_address,
+            Address::from_public_key(&result.verifier),
+        );
+
+        // The requester's escrow pays the full bounty
+        self.journal_account(undo, &requester).release_bounty(bounty)?;
+
+        // Solver share
+        let solver = self.journal_account(undo, &distribution.solver);
+        solver.credit(distribution.solver_amount);
+        solver.total_earned = solver.total_earned.saturating_add(distribution.solver_amount);
+
+        // Verifier share
+        let verifier = self.journal_account(undo, &distribution.verifier);
+        verifier.credit(distribution.verifier_amount);
+        verifier.total_rewards = verifier.total_rewards.saturating_add(distribution.verifier_amount);
+
+        // Burn share leaves circulation
+        self.total_burned = self.total_burned.saturating_add(distribution.burn_amount);
+
+        self.journal_job(undo, result.job_id).status = JobStatus::Completed;
+        self.settle_lock_bonds(result.job_id, undo)?;
+        settled.status = SolutionStatus::Verified;
+        self.journal_new_solution(undo, settled);
+
+        Ok(())
+    }
+
+    /// Close open jobs whose deadline passed and refund their bounties, or
+    /// forfeit them to solvers locked in to vectors that were never revealed
+    fn expire_jobs(&mut self, now: Timestamp, undo: &mut BlockUndo) -> Result<(), StateError> {
+        for id in self.expired_jobs(now) {
+            let job = self.journal_job(undo, id);
+            let (requester, bounty) = (job.escrow_address(), job.bounty);
+            let solvers = forfeit_recipients(job);
+            if solvers.is_empty() {
+                job.status = JobStatus::Expired;
+                self.journal_account(undo, &requester).refund_bounty(bounty);
+            } else {
+                job.status = JobStatus::Forfeited;
+                self.forfeit_bounty(&requester, bounty, &solvers, undo)?;
+            }
+            self.settle_lock_bonds(id, undo)?;
+        }
+        Ok(())
+    }
+
+    /// Roll back everything recorded in an undo journal
+    pub(super) fn revert(&mut self, undo: BlockUndo) {
+        for (address, account) in undo.accounts.into_iter().rev() {
+            self.dirty_accounts.insert(address);
+            match account {
+                Some(account) => self.accounts.insert(address, account),
+                None => self.accounts.remove(&address),
+            };
+        }
+        for (id, job) in undo.jobs.into_iter().rev() {
+            self.dirty_jobs.insert(id);
+            match job {
+                Some(job) => self.jobs.insert(id, job),
+                None => self.jobs.remove(&id),
+            };
+        }
+        for (id, solution) in undo.solutions.into_iter().rev() {
+            self.dirty_solutions.insert(id);
+            match solution {
+                Some(solution) => self.solutions.insert(id, solution),
+                None => self.solutions.remove(&id),
+            };
+        }
+        for hash in undo.modules {
+            self.dirty_modules.insert(hash);
+            self.modules.remove(&hash);
+        }
+        self.total_burned = undo.total_burned;
+        if let Some(epoch) = undo.epoch {
+            self.epoch = epoch;
+        }
+    }
+
+    /// Journal an account, then return it for mutation
+    pub(super) fn journal_account(&mut self, undo: &mut BlockUndo, address: &Address) -> &mut AccountState {
+        if undo.seen_accounts.insert(*address) {
+            undo.accounts.push((*address, self.accounts.get(address).cloned()));
+        }
+        self.get_or_create_account(address)
+    }
+
+    /// Journal a job (which must exist), then return it for mutation
+    pub(super) fn journal_job(&mut self, undo: &mut BlockUndo, id: Id) -> &mut JobPacket {
+        if undo.seen_jobs.insert(id) {
+            undo.jobs.push((id, self.jobs.get(&id).cloned()));
+        }
+        self.dirty_jobs.insert(id);
+        self.jobs.get_mut(&id).expect("journaled job exists")
+    }
+
+    /// Journal a job that does not exist yet, then insert it
+    fn journal_new_job(&mut self, undo: &mut BlockUndo, job: JobPacket) {
+        if undo.seen_jobs.insert(job.id) {
+            undo.jobs.push((job.id, None));
+        }
+        self.dirty_jobs.insert(job.id);
+        self.jobs.insert(job.id, job);
+    }
+
+    /// Journal a solution that does not exist yet, then insert it
+    fn journal_new_solution(&mut self, undo: &mut BlockUndo, solution: SolutionCandidate) {
+        if undo.seen_solutions.insert(solution.id) {
+            undo.solutions.push((solution.id, None));
+        }
+        self.dirty_solutions.insert(solution.id);
+        self.solutions.insert(solution.id, solution);
+    }
+
+    /// Journal a module that is not published yet, then insert it
+    pub(super) fn journal_new_module(&mut self, undo: &mut BlockUndo, hash: Hash, record: ModuleRecord) {
+        undo.modules.push(hash);
+        self.dirty_modules.insert(hash);
+        self.modules.insert(hash, record);
+    }
+}
@@ -0,0 +1,285 @@
+//! Finality.
+//!
+//! A canonical block is justified once the stake attesting to it reaches
+//! quorum in the validator set of its parent state. When a justified block
+//! gains a justified descendant, it is finalized together with everything
+//! below it: blocks at or under the finalized height are refused, and side
+//! branches forking below it are dropped, so it can never be reverted.
+//!
+//! Blocks are only connected with a quorum (see `proposer`), so once anyone
+//! is staked every canonical block is justified, and the tip's parent is
+//! finalized as soon as the tip connects. That leaves a one-block window:
+//! only the tip can be reorganised away, by a heavier sibling, and
+//! everything under it is final.
+//!
+//! Nothing is justified while no one is staked, so branches of any depth can
+//! still replace each other until then. The validator sets of blocks
+//! connected before a restart are not kept, so those blocks only become
+//! final through a justified descendant connected afterwards.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::Hash;
+use crate::types::Id;
+
+use super::tree::ChainUpdate;
+use super::ChainState;
+
+/// How settled a transaction or job payout is
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Confirmation {
+    /// Not in the canonical chain
+    Unconfirmed,
+    /// In a canonical block that a reorganisation could still revert
+    Confirmed {
+        /// Height of the block
+        height: u64,
+        /// Blocks on top of it, itself included
+        confirmations: u64,
+    },
+    /// In a finalized block; it can never be reverted
+    Finalized {
+        /// Height of the block
+        height: u64,
+    },
+}
+
+impl Confirmation {
+    /// Whether it is safe to act on
+    #[must_use]
+    pub const fn is_final(&self) -> bool {
+        matches!(self, Self::Finalized { .. })
+    }
+}
+
+impl fmt::Display for Confirmation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unconfirmed => write!(f, "unconfirmed"),
+            Self::Confirmed { height, confirmations } => {
+                write!(f, "{confirmations} confirmation(s) at height {height}, not final")
+            }
+            Self::Finalized { height } => write!(f, "finalized at height {height}"),
+        }
+    }
+}
+
+impl ChainState {
+    /// Highest canonical height that can never be reverted
+    ///
+    /// Genesis (height 0) is final from the start.
+    #[must_use]
+    pub const fn finalized_height(&self) -> u64 {
+        self.finalized
+    }
+
+    /// Status of the canonical block at `height`
+    #[must_use]
+    pub const fn confirmation_at(&self, height: u64) -> Confirmation {
+        if self.tip.is_none() || height >= self.height {
+            Confirmation::Unconfirmed
+        } else if height <= self.finalized {
+            Confirmation::Finalized { height }
+        } else {
+            Confirmation::Confirmed {
+                height,
+                confirmations: self.height - height,
+            }
+        }
+    }
+
+    /// Status of a transaction, or of the payout of a job, by ID
+    ///
+    /// A job counts from the block with the verification that paid it out.
+    #[must_use]
+    pub fn confirmation(&self, id: &Id) -> Confirmation {
+        self.index
+            .transaction_height(id)
+            .or_else(|| self.index.settlement_height(id))
+            .map_or(Confirmation::Unconfirmed, |height| self.confirmation_at(height))
+    }
+
+    /// Finalize the highest justified canonical block with a justified
+    /// descendant, and drop the side branches that conflict with it
+    ///
+    /// Only the canonical blocks above the finalized height are looked at,
+    /// from the tip down until two justified ones are found.
+    pub(super) fn advance_finality(&mut self, update: &mut ChainUpdate) {
+        let mut justified = Vec::with_capacity(2);
+        let mut height = self.height;
+        while justified.len() < 2 && height > self.finalized + 1 {
+            height -= 1;
+            let Some(hash) = self.height_index.get(&height) else {
+                break;
+            };
+            // Connecting took a quorum, so any set to attest it is enough
+            let attested = self
+                .validator_sets
+                .get(hash)
+                .is_some_and(|validators| !validators.is_empty());
+            if attested {
+                justified.push(height);
+            }
+        }
+        let [_, finalized] = justified[..] else {
+            return;
+        };
+        update.finalized = Some(finalized);
+
+        // Branches forking from the newly finalized stretch
+        let dead: Vec<Hash> = (self.finalized..finalized)
+            .filter_map(|height| self.height_index.get(&height))
+            .filter_map(|parent| self.children.get(parent))
+            .flatten()
+            .filter(|child| !self.is_canonical(child))
+            .copied()
+            .collect();
+        for hash in dead {
+            self.discard_branch(hash, update);
+        }
+    }
+
+    /// Adopt the finalized height of a committed update
+    pub(super) fn apply_finality(&mut self, update: &ChainUpdate) {
+        let Some(finalized) = update.finalized else {
+            return;
+        };
+        self.finalized = finalized;
+        let blocks = &self.blocks;
+        self.validator_sets
+            .retain(|hash, _| blocks.get(hash).is_some_and(|b| b.header.height > finalized));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+    use crate::state::tests::{draft, reopen, seal};
+    use crate::state::{ImportOutcome, StateError};
+    use crate::types::{
+        Address, Block, HclawAmount, Transaction, TransactionKind, VerifierAttestation,
+    };
+
+    fn next_block(state: &mut ChainState, proposer: &Keypair, transactions: Vec<Transaction>) -> Block {
+        let block = draft(state, proposer, transactions, Vec::new());
+        seal(state, proposer, block)
+    }
+
+    fn attested(mut block: Block, kp: &Keypair) -> Block {
+        let mut attestation = VerifierAttestation::new(*kp.public_key(), block.hash, Vec::new());
+        attestation.signature = kp.sign(&attestation.signing_bytes());
+        block.add_attestation(attestation);
+        block
+    }
+
+    /// Genesis, a single staked validator and a funded sender
+    fn staked_chain(state: &mut ChainState) -> (Keypair, Keypair) {
+        let validator = Keypair::generate();
+        state.apply_block(Block::genesis(*validator.public_key())).unwrap();
+        state
+            .get_or_create_account(&Address::from_public_key(validator.public_key()))
+            .staked = HclawAmount::from_hclaw(1000);
+        let sender = Keypair::generate();
+        state
+            .get_or_create_account(&Address::from_public_key(sender.public_key()))
+            .credit(HclawAmount::from_hclaw(100));
+        (validator, sender)
+    }
+
+    fn transfer(sender: &Keypair) -> Transaction {
+        let kind = TransactionKind::Transfer {
+            to: Address::from_public_key(Keypair::generate().public_key()),
+            amount: HclawAmount::from_hclaw(10),
+        };
+        let mut tx = Transaction::new(*sender.public_key(), 0, kind);
+        tx.signature = sender.sign(&tx.signing_bytes());
+        tx
+    }
+
+    #[test]
+    fn test_justified_descendant_finalizes() {
+        let mut state = ChainState::new();
+        let (validator, sender) = staked_chain(&mut state);
+        let payment = transfer(&sender);
+
+        let one = attested(next_block(&mut state, &validator, vec![payment.clone()]), &validator);
+        state.import_block(one.clone()).unwrap();
+        assert_eq!(state.finalized_height(), 0);
+        assert_eq!(
+            state.confirmation(&payment.id),
+            Confirmation::Confirmed { height: 1, confirmations: 1 }
+        );
+
+        // A competing child of `one`, held back for now
+        let side = next_block(&mut state, &Keypair::generate(), Vec::new());
+
+        // Blocks without quorum are not connected
+        let two = next_block(&mut state, &validator, Vec::new());
+        assert!(matches!(state.import_block(two.clone()), Err(StateError::InvalidBlock(_))));
+        assert_eq!(state.height(), 2);
+        assert_eq!(state.finalized_height(), 0);
+
+        // So every connected block is justified, and the tip's parent is
+        // final as soon as the tip connects: only the tip can be replaced
+        state.import_block(attested(two, &validator)).unwrap();
+        assert_eq!(state.finalized_height(), 1);
+        assert!(state.confirmation(&payment.id).is_final());
+        assert_eq!(state.confirmation_at(2), Confirmation::Confirmed { height: 2, confirmations: 1 });
+        assert_eq!(state.confirmation_at(9), Confirmation::Unconfirmed);
+
+        let three = attested(next_block(&mut state, &validator, Vec::new()), &validator);
+        state.import_block(three).unwrap();
+        assert_eq!(state.finalized_height(), 2);
+        assert_eq!(state.finalized_height(), state.tip().unwrap().header.height - 1);
+
+        // Nothing may replace a finalized block
+        assert!(matches!(
+            state.import_block(side),
+            Err(StateError::BelowFinalized { height: 2, finalized: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_finality_drops_conflicting_branches_and_persists() {
+        let dir = std::env::temp_dir().join(format!("hardclaw_test_finality_{}", rand::random::<u64>()));
+        let side = {
+            let mut state = ChainState::open(&dir).unwrap();
+            let (validator, sender) = staked_chain(&mut state);
+            let one = attested(next_block(&mut state, &validator, Vec::new()), &validator);
+
+            // An unattested sibling of `one` is kept on a side branch
+            let side = next_block(&mut state, &validator, vec![transfer(&sender)]);
+            state.import_block(one).unwrap();
+            assert_eq!(state.import_block(side.clone()).unwrap(), ImportOutcome::Stored);
+            assert!(state.get_block(&side.hash).is_some());
+
+            let two = attested(next_block(&mut state, &validator, Vec::new()), &validator);
+            state.import_block(two).unwrap();
+            assert_eq!(state.finalized_height(), 1);
+            assert!(state.get_block(&side.hash).is_none());
+            side
+        };
+
+        let reloaded = reopen(&dir);
+        assert_eq!(reloaded.finalized_height(), 1);
+        assert!(reloaded.get_block(&side.hash).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_no_finality_without_stake() {
+        let mut state = ChainState::new();
+        let proposer = Keypair::generate();
+        state.apply_block(Block::genesis(*proposer.public_key())).unwrap();
+        for _ in 0..3 {
+            let block = attested(next_block(&mut state, &proposer, Vec::new()), &proposer);
+            state.import_block(block).unwrap();
+        }
+        assert_eq!(state.finalized_height(), 0);
+        assert_eq!(state.confirmation_at(0), Confirmation::Finalized { height: 0 });
+    }
+}
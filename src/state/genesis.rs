@@ -0,0 +1,69 @@
+//! Starting a chain from a genesis spec.
+
+use crate::crypto::Hash;
+use crate::genesis::{GenesisError, GenesisSpec};
+use crate::tokenomics::FeeDistributor;
+use crate::types::Block;
+
+use super::{ChainState, StateError};
+
+impl ChainState {
+    /// Start the chain from a genesis spec, or check it already started there
+    ///
+    /// On an empty state the spec's allocations are written and the genesis
+    /// block committing to them is applied. On an existing chain the stored
+    /// genesis must be the one the spec derives. Either way the spec's fee
+    /// shares are used from now on. Returns the genesis hash.
+    ///
+    /// # Errors
+    /// Returns error if the spec is invalid, or the chain was started from
+    /// a different genesis
+    pub fn init_genesis(&mut self, spec: &GenesisSpec) -> Result<Hash, StateError> {
+        spec.validate()?;
+        let economics = &spec.economics;
+        self.fee_distributor = FeeDistributor::new(
+            economics.solver_share,
+            economics.verifier_share,
+            economics.burn_share,
+        );
+
+        if self.tip.is_some() {
+            let expected = spec.block()?.hash;
+            // A node that fast-synced from a snapshot holds no genesis to compare
+            return match self.height_index.get(&0) {
+                Some(found) if *found != expected => Err(StateError::GenesisMismatch {
+                    expected,
+                    found: *found,
+                }),
+                _ => Ok(expected),
+            };
+        }
+
+        let allocations = spec.allocations()?;
+        let validators = spec.validator_keys()?;
+        let spec_hash = spec.hash()?;
+
+        for (address, account) in allocations {
+            *self.get_or_create_account(&address) = account;
+        }
+        let block = Block::genesis_from_spec(
+            validators[0],
+            spec_hash,
+            spec.timestamp,
+            self.compute_state_root(),
+        );
+        let hash = block.hash;
+
+        if let Err(e) = self.apply_block(block) {
+            self.reset();
+            return Err(e);
+        }
+        Ok(hash)
+    }
+}
+
+impl From<GenesisError> for StateError {
+    fn from(e: GenesisError) -> Self {
+        Self::InvalidGenesis(e.to_string())
+    }
+}
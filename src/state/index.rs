@@ -0,0 +1,458 @@
+//! Secondary indices over chain state.
+//!
+//! Jobs are indexed by requester and status, solutions by solver, and the
+//! verifications in canonical blocks by verifier and block height. The
+//! canonical height of each transaction, and of the verification that
+//! settled each job, is kept for confirmation queries. Indices
+//! follow committed state: they are updated when a chain update is
+//! persisted (and when a job or solution is stored directly), so queries
+//! never see the effects of a block that was rolled back.
+//!
+//! Results are returned oldest first (jobs by creation time, solutions by
+//! submission time, verifications by height and position in the block) and
+//! paginated with `Pagination`.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+
+use crate::crypto::{Hash, PublicKey};
+use crate::types::{
+    Address, Block, Id, JobPacket, JobStatus, SolutionCandidate, Timestamp, VerificationResult,
+};
+
+use super::tree::ChainUpdate;
+use super::ChainState;
+
+/// Index entry for a job or solution: ordered by time, then ID
+type Entry = (Timestamp, Id);
+
+/// Position of a verification: block height, then index within the block
+type Position = (u64, u32);
+
+/// Which slice of a result set to return
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Pagination {
+    /// Number of results to skip
+    pub offset: usize,
+    /// Maximum number of results to return
+    pub limit: usize,
+}
+
+impl Pagination {
+    /// Default page size
+    pub const DEFAULT_LIMIT: usize = 50;
+
+    /// Page `number` (from zero) of `limit` results each
+    #[must_use]
+    pub const fn page(number: usize, limit: usize) -> Self {
+        Self {
+            offset: number.saturating_mul(limit),
+            limit,
+        }
+    }
+}
+
+impl Default for Pagination {
+    fn default() -> Self {
+        Self::page(0, Self::DEFAULT_LIMIT)
+    }
+}
+
+/// One page of query results
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Page<T> {
+    /// Results on this page
+    pub items: Vec<T>,
+    /// Total number of results across all pages
+    pub total: usize,
+}
+
+impl<T> Page<T> {
+    /// Whether results exist beyond this page
+    #[must_use]
+    pub const fn has_more(&self, pagination: Pagination) -> bool {
+        pagination.offset.saturating_add(self.items.len()) < self.total
+    }
+}
+
+/// A verification in a canonical block
+#[derive(Clone, Copy, Debug)]
+pub struct IndexedVerification<'a> {
+    /// Height of the block that includes it
+    pub height: u64,
+    /// Hash of that block
+    pub block_hash: Hash,
+    /// The verification
+    pub result: &'a VerificationResult,
+}
+
+/// Maintained lookup tables
+#[derive(Clone, Debug, Default)]
+pub struct StateIndex {
+    jobs_by_requester: HashMap<Address, BTreeSet<Entry>>,
+    jobs_by_status: HashMap<JobStatus, BTreeSet<Entry>>,
+    /// What each indexed job is filed under
+    jobs: HashMap<Id, (Address, JobStatus, Timestamp)>,
+    solutions_by_solver: HashMap<Address, BTreeSet<Entry>>,
+    /// What each indexed solution is filed under
+    solutions: HashMap<Id, (Address, Timestamp)>,
+    verifications_by_verifier: HashMap<PublicKey, BTreeSet<Position>>,
+    /// Number of verifications per canonical height (heights with none are absent)
+    verifications_by_height: BTreeMap<u64, u32>,
+    /// Canonical height of each included transaction
+    transaction_heights: HashMap<Id, u64>,
+    /// Canonical height of the passing verification that settled each job
+    settlement_heights: HashMap<Id, u64>,
+}
+
+impl StateIndex {
+    /// (Re)index a job under its current requester and status
+    pub fn index_job(&mut self, job: &JobPacket) {
+        self.unindex_job(&job.id);
+        let entry = (job.created_at, job.id);
+        self.jobs_by_requester
+            .entry(job.requester_address)
+            .or_default()
+            .insert(entry);
+        self.jobs_by_status.entry(job.status).or_default().insert(entry);
+        self.jobs
+            .insert(job.id, (job.requester_address, job.status, job.created_at));
+    }
+
+    /// Drop a job from the index
+    pub fn unindex_job(&mut self, id: &Id) {
+        let Some((requester, status, created_at)) = self.jobs.remove(id) else {
+            return;
+        };
+        let entry = (created_at, *id);
+        remove_entry(&mut self.jobs_by_requester, &requester, &entry);
+        remove_entry(&mut self.jobs_by_status, &status, &entry);
+    }
+
+    /// (Re)index a solution under its solver
+    pub fn index_solution(&mut self, solution: &SolutionCandidate) {
+        self.unindex_solution(&solution.id);
+        self.solutions_by_solver
+            .entry(solution.solver_address)
+            .or_default()
+            .insert((solution.submitted_at, solution.id));
+        self.solutions
+            .insert(solution.id, (solution.solver_address, solution.submitted_at));
+    }
+
+    /// Drop a solution from the index
+    pub fn unindex_solution(&mut self, id: &Id) {
+        if let Some((solver, submitted_at)) = self.solutions.remove(id) {
+            remove_entry(&mut self.solutions_by_solver, &solver, &(submitted_at, *id));
+        }
+    }
+
+    /// Index the verifications of a block that joined the canonical chain
+    pub fn index_block(&mut self, block: &Block) {
+        let height = block.header.height;
+        for (position, result) in (0u32..).zip(&block.verifications) {
+            self.verifications_by_verifier
+                .entry(result.verifier)
+                .or_default()
+                .insert((height, position));
+        }
+        if let Ok(count @ 1..) = u32::try_from(block.verifications.len()) {
+            self.verifications_by_height.insert(height, count);
+        }
+        for tx in &block.transactions {
+            self.transaction_heights.insert(tx.id, height);
+        }
+        for result in block.verifications.iter().filter(|r| r.passed) {
+            self.settlement_heights.insert(result.job_id, height);
+        }
+    }
+
+    /// Drop the verifications of a block that left the canonical chain
+    pub fn unindex_block(&mut self, block: &Block) {
+        let height = block.header.height;
+        for (position, result) in (0u32..).zip(&block.verifications) {
+            remove_entry(
+                &mut self.verifications_by_verifier,
+                &result.verifier,
+                &(height, position),
+            );
+        }
+        self.verifications_by_height.remove(&height);
+        for tx in &block.transactions {
+            self.transaction_heights.remove(&tx.id);
+        }
+        for result in block.verifications.iter().filter(|r| r.passed) {
+            self.settlement_heights.remove(&result.job_id);
+        }
+    }
+
+    /// Canonical height of the block that includes a transaction
+    #[must_use]
+    pub fn transaction_height(&self, id: &Id) -> Option<u64> {
+        self.transaction_heights.get(id).copied()
+    }
+
+    /// Canonical height of the block that settled a job
+    #[must_use]
+    pub fn settlement_height(&self, id: &Id) -> Option<u64> {
+        self.settlement_heights.get(id).copied()
+    }
+}
+
+impl ChainState {
+    /// Jobs submitted by a requester
+    #[must_use]
+    pub fn jobs_by_requester(&self, requester: &Address, pagination: Pagination) -> Page<&JobPacket> {
+        let entries = self.index.jobs_by_requester.get(requester);
+        paginate(entries, pagination, |(_, id)| self.jobs.get(id))
+    }
+
+    /// Jobs in a given status
+    #[must_use]
+    pub fn jobs_by_status(&self, status: JobStatus, pagination: Pagination) -> Page<&JobPacket> {
+        let entries = self.index.jobs_by_status.get(&status);
+        paginate(entries, pagination, |(_, id)| self.jobs.get(id))
+    }
+
+    /// Solutions submitted by a solver
+    #[must_use]
+    pub fn solutions_by_solver(
+        &self,
+        solver: &Address,
+        pagination: Pagination,
+    ) -> Page<&SolutionCandidate> {
+        let entries = self.index.solutions_by_solver.get(solver);
+        paginate(entries, pagination, |(_, id)| self.solutions.get(id))
+    }
+
+    /// Verifications signed by a verifier in canonical blocks
+    #[must_use]
+    pub fn verifications_by_verifier(
+        &self,
+        verifier: &PublicKey,
+        pagination: Pagination,
+    ) -> Page<IndexedVerification<'_>> {
+        let positions = self.index.verifications_by_verifier.get(verifier);
+        paginate(positions, pagination, |&(height, position)| {
+            self.indexed_verification(height, position)
+        })
+    }
+
+    /// Verifications in canonical blocks within a height range
+    #[must_use]
+    pub fn verifications_by_height(
+        &self,
+        heights: Range<u64>,
+        pagination: Pagination,
+    ) -> Page<IndexedVerification<'_>> {
+        let counts = || self.index.verifications_by_height.range(heights.clone());
+        let total = counts().map(|(_, &count)| count as usize).sum();
+        let items = counts()
+            .flat_map(|(&height, &count)| (0..count).map(move |position| (height, position)))
+            .skip(pagination.offset)
+            .take(pagination.limit)
+            .filter_map(|(height, position)| self.indexed_verification(height, position))
+            .collect();
+        Page { items, total }
+    }
+
+    fn indexed_verification(&self, height: u64, position: u32) -> Option<IndexedVerification<'_>> {
+        let block = self.get_block_at_height(height)?;
+        Some(IndexedVerification {
+            height,
+            block_hash: block.hash,
+            result: block.verifications.get(position as usize)?,
+        })
+    }
+
+    /// Build every index from scratch (after loading from disk)
+    pub(super) fn rebuild_index(&mut self) {
+        let mut index = StateIndex::default();
+        for job in self.jobs.values() {
+            index.index_job(job);
+        }
+        for solution in self.solutions.values() {
+            index.index_solution(solution);
+        }
+        for hash in self.height_index.values() {
+            if let Some(block) = self.blocks.get(hash) {
+                index.index_block(block);
+            }
+        }
+        self.index = index;
+    }
+
+    /// Bring the indices up to date with a chain update about to be committed
+    pub(super) fn index_update(&mut self, update: &ChainUpdate) {
+        for hash in &update.disconnected {
+            if let Some(block) = self.blocks.get(hash) {
+                self.index.unindex_block(block);
+            }
+        }
+        for hash in &update.connected {
+            if let Some(block) = self.blocks.get(hash) {
+                self.index.index_block(block);
+            }
+        }
+        self.reindex_dirty();
+    }
+
+    /// Reindex the records dirtied since the last commit
+    fn reindex_dirty(&mut self) {
+        for id in &self.dirty_jobs {
+            match self.jobs.get(id) {
+                Some(job) => self.index.index_job(job),
+                None => self.index.unindex_job(id),
+            }
+        }
+        for id in &self.dirty_solutions {
+            match self.solutions.get(id) {
+                Some(solution) => self.index.index_solution(solution),
+                None => self.index.unindex_solution(id),
+            }
+        }
+    }
+}
+
+fn remove_entry<K, V>(map: &mut HashMap<K, BTreeSet<V>>, key: &K, entry: &V)
+where
+    K: Eq + std::hash::Hash,
+    V: Ord,
+{
+    if let Some(set) = map.get_mut(key) {
+        set.remove(entry);
+        if set.is_empty() {
+            map.remove(key);
+        }
+    }
+}
+
+fn paginate<'a, K: 'a, T>(
+    entries: Option<&'a BTreeSet<K>>,
+    pagination: Pagination,
+    resolve: impl Fn(&'a K) -> Option<T>,
+) -> Page<T> {
+    let Some(entries) = entries else {
+        return Page { items: Vec::new(), total: 0 };
+    };
+    Page {
+        items: entries
+            .iter()
+            .skip(pagination.offset)
+            .take(pagination.limit)
+            .filter_map(resolve)
+            .collect(),
+        total: entries.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{hash_data, Keypair};
+    use crate::state::tests::{draft, seal, signed_result};
+    use crate::types::{HclawAmount, JobType, VerificationSpec};
+
+    fn job(requester: &Keypair, n: u8) -> JobPacket {
+        JobPacket::new(
+            JobType::Deterministic,
+            *requester.public_key(),
+            vec![n],
+            "Test".to_string(),
+            HclawAmount::from_hclaw(10),
+            HclawAmount::from_hclaw(1),
+            VerificationSpec::HashMatch { expected_hash: hash_data(b"output") },
+            3600,
+        )
+    }
+
+    fn next_block(state: &mut ChainState, proposer: &Keypair, settled: Vec<(VerificationResult, SolutionCandidate)>) -> Block {
+        let block = draft(state, proposer, Vec::new(), settled);
+        seal(state, proposer, block)
+    }
+
+    #[test]
+    fn test_jobs_by_requester_paginates() {
+        let mut state = ChainState::new();
+        let alice = Keypair::generate();
+        let bob = Keypair::generate();
+        for kp in [&alice, &bob] {
+            state
+                .get_or_create_account(&Address::from_public_key(kp.public_key()))
+                .credit(HclawAmount::from_hclaw(100));
+        }
+        for n in 0..5 {
+            state.store_job(job(&alice, n)).unwrap();
+        }
+        state.store_job(job(&bob, 0)).unwrap();
+
+        let alice_address = Address::from_public_key(alice.public_key());
+        let first = state.jobs_by_requester(&alice_address, Pagination::page(0, 2));
+        assert_eq!(first.total, 5);
+        assert_eq!(first.items.len(), 2);
+        assert!(first.has_more(Pagination::page(0, 2)));
+
+        let last = state.jobs_by_requester(&alice_address, Pagination::page(2, 2));
+        assert_eq!(last.items.len(), 1);
+        assert!(!last.has_more(Pagination::page(2, 2)));
+
+        // Pages do not overlap
+        let all = state.jobs_by_requester(&alice_address, Pagination::default());
+        let ids: BTreeSet<Id> = all.items.iter().map(|j| j.id).collect();
+        assert_eq!(ids.len(), 5);
+        assert_eq!(state.jobs_by_status(JobStatus::Pending, Pagination::default()).total, 6);
+    }
+
+    #[test]
+    fn test_indices_follow_blocks() {
+        let mut state = ChainState::new();
+        let proposer = Keypair::generate();
+        state.apply_block(Block::genesis(*proposer.public_key())).unwrap();
+
+        let requester = Keypair::generate();
+        let solver = Keypair::generate();
+        let job = job(&requester, 0);
+        let solution = SolutionCandidate::new(job.id, *solver.public_key(), b"output".to_vec());
+        state
+            .get_or_create_account(&job.requester_address)
+            .credit(HclawAmount::from_hclaw(100));
+        state.store_job(job.clone()).unwrap();
+
+        // Solutions are indexed once a block settles them
+        assert_eq!(state.solutions_by_solver(&solution.solver_address, Pagination::default()).total, 0);
+        let (result, carried) = signed_result(&proposer, &solution, true);
+
+        // A block that fails to apply leaves no trace in the indices
+        let bad = Block::new(
+            state.height(),
+            state.tip().unwrap().hash,
+            *proposer.public_key(),
+            vec![result.clone()],
+            Hash::ZERO,
+        )
+        .with_solutions(vec![carried.clone()]);
+        assert!(state.apply_block(bad).is_err());
+        assert_eq!(state.jobs_by_status(JobStatus::Completed, Pagination::default()).total, 0);
+        assert_eq!(state.verifications_by_verifier(proposer.public_key(), Pagination::default()).total, 0);
+        assert_eq!(state.solutions_by_solver(&solution.solver_address, Pagination::default()).total, 0);
+
+        let block = next_block(&mut state, &proposer, vec![(result, carried)]);
+        state.apply_block(block.clone()).unwrap();
+
+        let page = state.solutions_by_solver(&solution.solver_address, Pagination::default());
+        assert_eq!(page.items.first().map(|s| s.id), Some(solution.id));
+
+        let completed = state.jobs_by_status(JobStatus::Completed, Pagination::default());
+        assert_eq!(completed.items.first().map(|j| j.id), Some(job.id));
+        assert_eq!(state.jobs_by_status(JobStatus::Pending, Pagination::default()).total, 0);
+
+        let signed = state.verifications_by_verifier(proposer.public_key(), Pagination::default());
+        assert_eq!(signed.total, 1);
+        assert_eq!(signed.items[0].height, 1);
+        assert_eq!(signed.items[0].block_hash, block.hash);
+        assert_eq!(signed.items[0].result.solution_id, solution.id);
+
+        assert_eq!(state.verifications_by_height(0..1, Pagination::default()).total, 0);
+        assert_eq!(state.verifications_by_height(0..10, Pagination::default()).total, 1);
+    }
+}
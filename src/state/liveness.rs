@@ -0,0 +1,315 @@
+//! Verifier liveness and downtime slashing.
+//!
+//! Every block carries the attestations its proposer saw for the parent, and
+//! applying it records one bit per member of the validator set: the member
+//! signed if it attested to the parent and was not a proposer in line ahead
+//! of the one that produced the block (those missed their slot). Over the
+//! last `LIVENESS_WINDOW` blocks, a member that missed more than
+//! `MAX_MISSED_BLOCKS` is slashed for `Downtime` (burned like any slash) and
+//! jailed, which keeps it out of the proposer schedule and the validator set
+//! from the next epoch until it sends an `Unjail` transaction after
+//! `JAIL_BLOCKS` heights. A jailed member is not recorded for the rest of
+//! the epoch.
+
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Address, Block, Timestamp, VerifierAttestation};
+use crate::verifier::SlashingReason;
+
+use super::execution::BlockUndo;
+use super::{ChainState, StateError};
+
+/// Number of recent blocks liveness is judged over
+pub const LIVENESS_WINDOW: u32 = 100;
+
+/// Most blocks a verifier may miss in the window before it is jailed
+pub const MAX_MISSED_BLOCKS: u32 = 50;
+
+/// Heights a jailed verifier must wait before it may unjail
+pub const JAIL_BLOCKS: u64 = 1_000;
+
+/// A verifier's record over the most recent blocks
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Liveness {
+    /// One bit per recorded block, set if missed, newest in the lowest bit
+    pub missed: u128,
+    /// Blocks recorded so far, up to `LIVENESS_WINDOW`
+    pub recorded: u32,
+    /// Timestamp of the last block signed, or of the first one recorded
+    pub last_signed: Timestamp,
+}
+
+impl Liveness {
+    /// Shift in the verifier's record for a block at `timestamp`
+    pub fn record(&mut self, signed: bool, timestamp: Timestamp) {
+        if signed || self.recorded == 0 {
+            self.last_signed = timestamp;
+        }
+        let window = (1u128 << LIVENESS_WINDOW) - 1;
+        self.missed = ((self.missed << 1) | u128::from(!signed)) & window;
+        self.recorded = (self.recorded + 1).min(LIVENESS_WINDOW);
+    }
+
+    /// Blocks missed in the window
+    #[must_use]
+    pub const fn missed_blocks(&self) -> u32 {
+        self.missed.count_ones()
+    }
+
+    /// Whether the window is full and too many blocks in it were missed
+    #[must_use]
+    pub const fn is_down(&self) -> bool {
+        self.recorded >= LIVENESS_WINDOW && self.missed_blocks() > MAX_MISSED_BLOCKS
+    }
+}
+
+impl ChainState {
+    /// Record who signed the parent of `block`, and jail whoever was down
+    ///
+    /// Must be called with the state at the block's parent.
+    pub(super) fn record_liveness(&mut self, block: &Block, undo: &mut BlockUndo) -> Result<(), StateError> {
+        if block.header.height == 0 {
+            return Ok(());
+        }
+        let epoch = self.epoch();
+        let validators = epoch.validator_set();
+
+        let mut signers = HashSet::new();
+        for attestation in &block.parent_attestations {
+            if attestation.block_hash != block.header.parent_hash {
+                return Err(StateError::InvalidBlock("parent attestation is for another block".to_string()));
+            }
+            if validators.stake_of(&attestation.verifier).is_none() {
+                return Err(StateError::InvalidBlock("parent attestation from outside the validator set".to_string()));
+            }
+            if attestation.verify_signature().is_err() {
+                return Err(StateError::InvalidBlock("invalid parent attestation signature".to_string()));
+            }
+            if !signers.insert(Address::from_public_key(&attestation.verifier)) {
+                return Err(StateError::InvalidBlock("duplicate parent attestation".to_string()));
+            }
+        }
+
+        // Proposers in line ahead of this one let their slot pass
+        let proposer = Address::from_public_key(&block.header.proposer);
+        let line = epoch.proposer_schedule().proposers(&block.header.parent_hash, block.header.height);
+        let skipped: HashSet<Address> = line.into_iter().take_while(|address| *address != proposer).collect();
+
+        let timestamp = block.header.timestamp;
+        let jailed_until = block.header.height.saturating_add(JAIL_BLOCKS);
+        for &(address, _) in &epoch.validators {
+            if self.accounts.get(&address).is_some_and(|a| a.tombstoned || a.jailed_until.is_some()) {
+                continue;
+            }
+            let account = self.journal_account(undo, &address);
+            account.liveness.record(signers.contains(&address) && !skipped.contains(&address), timestamp);
+            if !account.liveness.is_down() {
+                continue;
+            }
+
+            let offline_ms = timestamp.saturating_sub(account.liveness.last_signed);
+            let offline_duration_secs = u64::try_from(offline_ms / 1000).unwrap_or(0);
+            let reason = SlashingReason::Downtime { offline_duration_secs };
+            let slashed = account.staked.percentage(reason.slash_percentage());
+            account.staked = account.staked.saturating_sub(slashed);
+            account.balance = account.balance.saturating_sub(slashed);
+            account.liveness = Liveness::default();
+            account.jailed_until = Some(jailed_until);
+            self.total_burned = self.total_burned.saturating_add(slashed);
+        }
+        Ok(())
+    }
+
+    /// Release the sender of an `Unjail` transaction from jail
+    pub(super) fn unjail(&mut self, address: &Address, undo: &mut BlockUndo) -> Result<(), StateError> {
+        let height = self.height;
+        let account = self.journal_account(undo, address);
+        match account.jailed_until {
+            None => Err(StateError::InvalidTransaction("account is not jailed".to_string())),
+            Some(until) if height < until => Err(StateError::InvalidTransaction(format!(
+                "jailed until height {until}"
+            ))),
+            Some(_) => {
+                account.jailed_until = None;
+                account.liveness = Liveness::default();
+                Ok(())
+            }
+        }
+    }
+
+    /// Attestations to the current tip from the validator set, for the next
+    /// block to carry
+    #[must_use]
+    pub fn tip_attestations(&self) -> Vec<VerifierAttestation> {
+        let Some(tip) = self.tip.and_then(|hash| self.blocks.get(&hash)) else {
+            return Vec::new();
+        };
+        let validators = self.validator_set();
+        let mut seen = HashSet::new();
+        tip.attestations
+            .iter()
+            .filter(|a| a.block_hash == tip.hash && validators.stake_of(&a.verifier).is_some())
+            .filter(|a| a.verify_signature().is_ok() && seen.insert(a.verifier))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::consensus::SLOT_DURATION_MS;
+    use crate::crypto::Keypair;
+    use crate::state::tests::{attest, draft, seal};
+    use crate::types::{HclawAmount, Transaction, TransactionKind};
+
+    /// Genesis and two verifiers staking the minimum
+    fn staked_chain(state: &mut ChainState) -> Vec<Keypair> {
+        let keys: Vec<_> = (0..2).map(|_| Keypair::generate()).collect();
+        state.apply_block(Block::genesis(*keys[0].public_key())).unwrap();
+        for kp in &keys {
+            let account = state.get_or_create_account(&Address::from_public_key(kp.public_key()));
+            account.credit(HclawAmount::from_hclaw(1500));
+            account.staked = HclawAmount::from_hclaw(1000);
+        }
+        keys
+    }
+
+    fn parent_attestation(state: &ChainState, kp: &Keypair) -> VerifierAttestation {
+        let parent = state.tip().unwrap().hash;
+        let mut attestation = VerifierAttestation::new(*kp.public_key(), parent, Vec::new());
+        attestation.signature = kp.sign(&attestation.signing_bytes());
+        attestation
+    }
+
+    /// Keys in line for the next block, leader first
+    fn line<'a>(state: &ChainState, keys: &'a [Keypair]) -> Vec<&'a Keypair> {
+        let parent = state.tip().unwrap().hash;
+        state
+            .proposer_schedule()
+            .proposers(&parent, state.height())
+            .iter()
+            .filter_map(|address| keys.iter().find(|kp| Address::from_public_key(kp.public_key()) == *address))
+            .collect()
+    }
+
+    /// Next block from the leader, attested by `signers` along with its parent
+    fn next_block(state: &mut ChainState, keys: &[Keypair], signers: &[&Keypair], transactions: Vec<Transaction>) -> Block {
+        let attestations = signers.iter().map(|kp| parent_attestation(state, kp)).collect();
+        let proposer = line(state, keys)[0];
+        let block = draft(state, proposer, transactions, Vec::new()).with_parent_attestations(attestations);
+        let mut block = seal(state, proposer, block);
+        for kp in signers {
+            attest(&mut block, kp);
+        }
+        block
+    }
+
+    fn signed_tx(kp: &Keypair, nonce: u64, kind: TransactionKind) -> Transaction {
+        let mut tx = Transaction::new(*kp.public_key(), nonce, kind);
+        tx.signature = kp.sign(&tx.signing_bytes());
+        tx
+    }
+
+    #[test]
+    fn test_liveness_window() {
+        let mut liveness = Liveness::default();
+        liveness.record(false, 10);
+        assert_eq!(liveness.last_signed, 10);
+        for _ in 0..MAX_MISSED_BLOCKS {
+            liveness.record(false, 20);
+        }
+        assert_eq!(liveness.last_signed, 10);
+        assert_eq!(liveness.missed_blocks(), MAX_MISSED_BLOCKS + 1);
+        // Too many misses only count once the window is full
+        assert!(!liveness.is_down());
+
+        for _ in 0..LIVENESS_WINDOW {
+            liveness.record(true, 30);
+        }
+        assert_eq!(liveness.recorded, LIVENESS_WINDOW);
+        assert_eq!(liveness.missed_blocks(), 0);
+        assert_eq!(liveness.last_signed, 30);
+
+        for _ in 0..=MAX_MISSED_BLOCKS {
+            liveness.record(false, 40);
+        }
+        assert!(liveness.is_down());
+    }
+
+    #[test]
+    fn test_skipped_proposer_misses_its_slot() {
+        let mut state = ChainState::new();
+        let keys = staked_chain(&mut state);
+        let order = line(&state, &keys);
+        let (leader, backup) = (order[0], order[1]);
+        let parent_time = state.tip().unwrap().header.timestamp;
+
+        let block = draft(&state, backup, Vec::new(), Vec::new())
+            .with_timestamp(parent_time + SLOT_DURATION_MS)
+            .with_parent_attestations(vec![parent_attestation(&state, leader), parent_attestation(&state, backup)]);
+        let mut block = seal(&mut state, backup, block);
+        attest(&mut block, leader);
+        attest(&mut block, backup);
+        state.import_block(block).unwrap();
+
+        let liveness = |kp: &Keypair| state.get_account(&Address::from_public_key(kp.public_key())).unwrap().liveness.clone();
+        assert_eq!(liveness(leader).missed_blocks(), 1);
+        assert_eq!(liveness(backup).missed_blocks(), 0);
+        assert_eq!(liveness(backup).recorded, 1);
+
+        // Only the validator set may vouch for the parent, once each
+        let outsider = parent_attestation(&state, &Keypair::generate());
+        let forged = draft(&state, line(&state, &keys)[0], Vec::new(), Vec::new()).with_parent_attestations(vec![outsider]);
+        assert!(matches!(state.state_root_after(&forged), Err(StateError::InvalidBlock(_))));
+        let twice = vec![parent_attestation(&state, leader), parent_attestation(&state, leader)];
+        let doubled = draft(&state, line(&state, &keys)[0], Vec::new(), Vec::new()).with_parent_attestations(twice);
+        assert!(matches!(state.state_root_after(&doubled), Err(StateError::InvalidBlock(_))));
+    }
+
+    #[test]
+    fn test_downtime_jails_until_unjailed() {
+        let mut state = ChainState::new();
+        let keys = staked_chain(&mut state);
+        let offline = Address::from_public_key(keys[1].public_key());
+        // Enough stake on keys[0] to carry the quorum alone
+        let online = state.get_or_create_account(&Address::from_public_key(keys[0].public_key()));
+        online.credit(HclawAmount::from_hclaw(2000));
+        online.staked = HclawAmount::from_hclaw(3000);
+
+        // keys[1] never attests
+        for _ in 0..LIVENESS_WINDOW {
+            let block = next_block(&mut state, &keys, &[&keys[0]], Vec::new());
+            state.import_block(block).unwrap();
+        }
+
+        let account = state.get_account(&offline).unwrap();
+        assert_eq!(account.jailed_until, Some(u64::from(LIVENESS_WINDOW) + JAIL_BLOCKS));
+        assert_eq!(account.staked, HclawAmount::from_hclaw(990));
+        assert_eq!(account.liveness, Liveness::default());
+        // The set only loses it at the next epoch
+        assert_eq!(state.validator_set().len(), 2);
+        assert_eq!(state.stake_manager().validator_set().len(), 1);
+        assert!(state.get_account(&Address::from_public_key(keys[0].public_key())).unwrap().jailed_until.is_none());
+
+        // Unjailing waits out the term
+        let unjail = vec![signed_tx(&keys[1], 0, TransactionKind::Unjail)];
+        let early = draft(&state, line(&state, &keys)[0], unjail, Vec::new());
+        assert!(matches!(state.state_root_after(&early), Err(StateError::InvalidTransaction(_))));
+
+        let height = state.height();
+        state.get_or_create_account(&offline).jailed_until = Some(height);
+        let topped_up = HclawAmount::from_hclaw(10);
+        let transactions = vec![
+            signed_tx(&keys[1], 0, TransactionKind::Unjail),
+            signed_tx(&keys[1], 1, TransactionKind::Stake { amount: topped_up }),
+        ];
+        let block = next_block(&mut state, &keys, &[&keys[0]], transactions);
+        state.import_block(block).unwrap();
+
+        assert!(state.get_account(&offline).unwrap().jailed_until.is_none());
+        assert_eq!(state.validator_set().len(), 2);
+    }
+}
@@ -1,16 +1,73 @@
 //! Blockchain state management.
 //!
 //! Tracks account balances, job states, and chain history.
+//!
+//! Blocks form a tree: side branches are kept and the canonical chain follows
+//! the heaviest branch by attesting stake (see `import_block`).
+//!
+//! State can live purely in memory (`ChainState::new`) or be backed by an
+//! on-disk store (`ChainState::open`) that survives node restarts. A fresh
+//! node can start from a `StateSnapshot` instead of replaying every block.
+//! How much block history is kept is set by the `RetentionMode`.
+//!
+//! The validator set is frozen for each epoch of `EPOCH_LENGTH` blocks.
+//! Blocks at or below the `finalized_height` can never be reverted.
+//! Verifiers caught signing two conflicting blocks lose their stake, and
+//! verifiers that stop signing blocks are slashed and jailed.
+//! Published verifier modules are tracked by hash (see `ModuleRecord`).
+
+mod commitment;
+mod epoch;
+mod escrow;
+mod execution;
+mod finality;
+mod genesis;
+mod index;
+mod liveness;
+mod modules;
+mod proposer;
+mod retention;
+mod slashing;
+mod smt;
+mod snapshot;
+mod storage;
+mod tree;
+mod validation;
+mod vectors;
+
+pub use commitment::{verify_account_proof, AccountProof};
+pub use epoch::{Epoch, EPOCH_LENGTH};
+pub use finality::Confirmation;
+pub use index::{IndexedVerification, Page, Pagination};
+pub use liveness::{Liveness, JAIL_BLOCKS, LIVENESS_WINDOW, MAX_MISSED_BLOCKS};
+pub use modules::ModuleRecord;
+pub use retention::RetentionMode;
+pub use smt::{SparseMerkleProof, SparseMerkleTree};
+pub use snapshot::{StateSnapshot, SNAPSHOT_VERSION};
+pub use storage::ChainStore;
+pub use tree::{ChainWeight, ImportOutcome, MAX_SIDE_BLOCKS_PER_HEIGHT};
+pub use validation::{MAX_FUTURE_DRIFT_MS, MEDIAN_TIME_SPAN};
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
 
-use crate::crypto::{hash_data, merkle_root, Hash};
+use serde::{Deserialize, Serialize};
+
+use crate::consensus::{BlockProducerConfig, ValidatorSet};
+use crate::crypto::Hash;
+use crate::tokenomics::FeeDistributor;
 use crate::types::{
-    Address, Block, Id, JobPacket, HclawAmount, SolutionCandidate,
+    Address, Block, BlockHeader, Id, JobPacket, HclawAmount, SolutionCandidate, now_millis,
+    Timestamp, Transaction,
 };
 
+use execution::BlockUndo;
+use index::StateIndex;
+use storage::StoreBatch;
+use tree::ChainUpdate;
+
 /// Account state
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
 pub struct AccountState {
     /// Account balance
     pub balance: HclawAmount,
@@ -18,12 +75,20 @@ pub struct AccountState {
     pub nonce: u64,
     /// Staked amount
     pub staked: HclawAmount,
+    /// Bounties locked in open jobs
+    pub escrowed: HclawAmount,
     /// Total rewards earned
     pub total_rewards: HclawAmount,
     /// Total spent on jobs
     pub total_spent: HclawAmount,
     /// Total earned from solving
     pub total_earned: HclawAmount,
+    /// Slashed for double signing and barred from the validator set for good
+    pub tombstoned: bool,
+    /// Recent blocks signed and missed as a verifier
+    pub liveness: Liveness,
+    /// Height from which a verifier jailed for downtime may unjail
+    pub jailed_until: Option<u64>,
 }
 
 impl AccountState {
@@ -34,16 +99,24 @@ impl AccountState {
             balance,
             nonce: 0,
             staked: HclawAmount::ZERO,
+            escrowed: HclawAmount::ZERO,
             total_rewards: HclawAmount::ZERO,
             total_spent: HclawAmount::ZERO,
             total_earned: HclawAmount::ZERO,
+            tombstoned: false,
+            liveness: Liveness {
+                missed: 0,
+                recorded: 0,
+                last_signed: 0,
+            },
+            jailed_until: None,
         }
     }
 
-    /// Get available balance (not staked)
+    /// Get available balance (neither staked nor escrowed)
     #[must_use]
     pub fn available_balance(&self) -> HclawAmount {
-        self.balance.saturating_sub(self.staked)
+        self.balance.saturating_sub(self.staked).saturating_sub(self.escrowed)
     }
 
     /// Credit balance
@@ -73,18 +146,61 @@ impl AccountState {
 pub struct ChainState {
     /// Account states
     accounts: HashMap<Address, AccountState>,
-    /// Block headers by hash
+    /// Blocks by hash, including side branches
     blocks: HashMap<Hash, Block>,
-    /// Block hash by height
+    /// Headers of canonical blocks whose bodies were pruned
+    headers: HashMap<Hash, BlockHeader>,
+    /// Lowest canonical height whose body is held
+    history_start: u64,
+    /// How much history to keep
+    retention: RetentionMode,
+    /// Size and solution limits for blocks
+    block_limits: BlockProducerConfig,
+    /// Blocks with no known children
+    heads: HashSet<Hash>,
+    /// Children of each block in the tree, by parent hash
+    children: HashMap<Hash, Vec<Hash>>,
+    /// Undo journals for canonical blocks
+    undo_logs: HashMap<Hash, BlockUndo>,
+    /// Canonical block hash by height
     height_index: HashMap<u64, Hash>,
     /// Current chain tip
     tip: Option<Hash>,
     /// Current height
     height: u64,
+    /// Highest canonical height that can no longer be reverted
+    finalized: u64,
+    /// Validator set at the parent of each unfinalized block in the tree,
+    /// which its attestations are counted against
+    validator_sets: HashMap<Hash, ValidatorSet>,
+    /// Validator set frozen at the start of the current epoch
+    epoch: Epoch,
     /// Jobs by ID
     jobs: HashMap<Id, JobPacket>,
     /// Solutions by ID
     solutions: HashMap<Id, SolutionCandidate>,
+    /// Published verifier modules by hash
+    modules: HashMap<Hash, ModuleRecord>,
+    /// State tree leaves of jobs and solutions dropped by pruning, by tree key
+    retired: HashMap<Hash, Hash>,
+    /// Authenticated tree behind the state root, as of the last commit
+    state_tree: SparseMerkleTree,
+    /// Secondary indices, as of the last commit
+    index: StateIndex,
+    /// Splits bounties between solver, verifier and burn
+    fee_distributor: FeeDistributor,
+    /// Total burned by block execution
+    total_burned: HclawAmount,
+    /// Backing store (None for in-memory state)
+    store: Option<ChainStore>,
+    /// Accounts modified since the last commit
+    dirty_accounts: HashSet<Address>,
+    /// Jobs modified since the last commit
+    dirty_jobs: HashSet<Id>,
+    /// Solutions modified since the last commit
+    dirty_solutions: HashSet<Id>,
+    /// Modules published or reverted since the last commit
+    dirty_modules: HashSet<Hash>,
 }
 
 impl Default for ChainState {
@@ -100,16 +216,117 @@ impl ChainState {
         Self {
             accounts: HashMap::new(),
             blocks: HashMap::new(),
+            headers: HashMap::new(),
+            history_start: 0,
+            retention: RetentionMode::Archive,
+            block_limits: BlockProducerConfig::default(),
+            heads: HashSet::new(),
+            children: HashMap::new(),
+            undo_logs: HashMap::new(),
             height_index: HashMap::new(),
             tip: None,
             height: 0,
+            finalized: 0,
+            validator_sets: HashMap::new(),
+            epoch: Epoch::default(),
             jobs: HashMap::new(),
             solutions: HashMap::new(),
+            modules: HashMap::new(),
+            retired: HashMap::new(),
+            state_tree: SparseMerkleTree::new(),
+            index: StateIndex::default(),
+            fee_distributor: FeeDistributor::default_shares(),
+            total_burned: HclawAmount::ZERO,
+            store: None,
+            dirty_accounts: HashSet::new(),
+            dirty_jobs: HashSet::new(),
+            dirty_solutions: HashSet::new(),
+            dirty_modules: HashSet::new(),
+        }
+    }
+
+    /// Open persistent state at the given directory
+    ///
+    /// Reloads the tip, height and all indices written by previous runs.
+    /// A fresh directory yields an empty state, as with `new`.
+    ///
+    /// # Errors
+    /// Returns error if the store cannot be opened or contains corrupt records
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StateError> {
+        Self::load(ChainStore::open(path)?)
+    }
+
+    /// Open persistent state for reading, even while a node holds it
+    ///
+    /// Works on a private copy of the store taken when it is opened, so it
+    /// does not see later blocks and nothing it does is written back.
+    ///
+    /// # Errors
+    /// Returns error if there is no store at `path`, it cannot be copied, or
+    /// it contains corrupt records
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, StateError> {
+        Self::load(ChainStore::open_copy(path)?)
+    }
+
+    /// Reload state from a store
+    fn load(store: ChainStore) -> Result<Self, StateError> {
+        let persisted = store.load()?;
+
+        let mut children: HashMap<Hash, Vec<Hash>> = HashMap::new();
+        for block in persisted.blocks.values().filter(|b| b.header.height > 0) {
+            children.entry(block.header.parent_hash).or_default().push(block.hash);
         }
+        let heads = persisted
+            .blocks
+            .keys()
+            .filter(|hash| !children.contains_key(hash))
+            .copied()
+            .collect();
+
+        let mut state = Self {
+            accounts: persisted.accounts,
+            blocks: persisted.blocks,
+            headers: persisted.headers,
+            history_start: persisted.history_start,
+            retention: RetentionMode::Archive,
+            block_limits: BlockProducerConfig::default(),
+            heads,
+            children,
+            undo_logs: persisted.undo_logs,
+            height_index: persisted.height_index,
+            tip: persisted.tip,
+            height: persisted.height,
+            finalized: persisted.finalized,
+            validator_sets: HashMap::new(),
+            epoch: persisted.epoch,
+            jobs: persisted.jobs,
+            solutions: persisted.solutions,
+            modules: persisted.modules,
+            retired: persisted.retired,
+            state_tree: SparseMerkleTree::new(),
+            index: StateIndex::default(),
+            fee_distributor: FeeDistributor::default_shares(),
+            total_burned: persisted.total_burned,
+            store: Some(store),
+            dirty_accounts: HashSet::new(),
+            dirty_jobs: HashSet::new(),
+            dirty_solutions: HashSet::new(),
+            dirty_modules: HashSet::new(),
+        };
+        state.rebuild_state_tree();
+        state.rebuild_index();
+        Ok(state)
+    }
+
+    /// Whether this state is backed by an on-disk store
+    #[must_use]
+    pub const fn is_persistent(&self) -> bool {
+        self.store.is_some()
     }
 
     /// Get or create account state
     pub fn get_or_create_account(&mut self, address: &Address) -> &mut AccountState {
+        self.dirty_accounts.insert(*address);
         self.accounts.entry(*address).or_default()
     }
 
@@ -147,47 +364,229 @@ impl ChainState {
         Ok(())
     }
 
-    /// Apply a block to the state
+    /// Apply a block on top of the current tip
+    ///
+    /// The state height counts applied blocks, so the next block must carry
+    /// `header.height == self.height()` (genesis is height 0). Blocks that
+    /// build anywhere else in the tree go through `import_block`.
+    ///
+    /// The block body is executed (transactions, payouts, burns, status
+    /// changes, expiries) and the resulting state root must equal
+    /// `header.state_root`; otherwise the state is left untouched.
     ///
     /// # Errors
-    /// Returns error if block is invalid
+    /// Returns error if block is invalid, its effects cannot be applied,
+    /// or it cannot be persisted
     pub fn apply_block(&mut self, block: Block) -> Result<(), StateError> {
         // Verify block follows current tip
         if let Some(tip) = &self.tip {
             if block.header.parent_hash != *tip {
                 return Err(StateError::InvalidParent);
             }
+        }
+
+        if block.header.height != self.height {
+            return Err(StateError::InvalidHeight {
+                expected: self.height,
+                got: block.header.height,
+            });
+        }
+
+        self.import_block(block).map(|_| ())
+    }
 
-            if block.header.height != self.height + 1 {
-                return Err(StateError::InvalidHeight {
-                    expected: self.height + 1,
-                    got: block.header.height,
-                });
+    /// Write a chain update and all records dirtied since the last commit in one batch
+    ///
+    /// Whatever the retention mode prunes at the new tip goes in the same batch.
+    fn persist(&mut self, update: &ChainUpdate) -> Result<(), StateError> {
+        let prune = self.prune_plan();
+        let Some(store) = &self.store else {
+            self.index_update(update);
+            self.clear_dirty();
+            self.apply_prune(prune);
+            self.apply_finality(update);
+            return Ok(());
+        };
+
+        let mut batch = StoreBatch::new();
+        for hash in &update.stored {
+            if let Some(block) = self.blocks.get(hash) {
+                batch.put_block(block)?;
+            }
+        }
+        for hash in &update.discarded {
+            batch.remove_block(hash);
+        }
+        for hash in &update.disconnected {
+            batch.remove_undo(hash);
+            if let Some(block) = self.blocks.get(hash) {
+                batch.remove_height(block.header.height);
+            }
+        }
+        for hash in &update.connected {
+            if let Some(block) = self.blocks.get(hash) {
+                batch.put_height(block.header.height, hash);
+            }
+            // A snapshot base block has no journal
+            if let Some(undo) = self.undo_logs.get(hash) {
+                batch.put_undo(hash, undo)?;
+            }
+        }
+        if let Some(tip) = &self.tip {
+            batch.put_tip(tip, self.height);
+        }
+        if let Some(height) = update.finalized {
+            batch.put_finalized(height);
+        }
+        batch.put_total_burned(self.total_burned);
+        batch.put_epoch(&self.epoch)?;
+        for key in &update.retired {
+            if let Some(leaf) = self.retired.get(key) {
+                batch.put_retired(key, leaf);
+            }
+        }
+
+        // Records missing from memory were removed by a revert
+        for address in &self.dirty_accounts {
+            match self.accounts.get(address) {
+                Some(account) => batch.put_account(address, account)?,
+                None => batch.remove_account(address),
+            }
+        }
+        for id in &self.dirty_jobs {
+            match self.jobs.get(id) {
+                Some(job) => batch.put_job(job)?,
+                None => batch.remove_job(id),
             }
-        } else if block.header.height != 0 {
+        }
+        for id in &self.dirty_solutions {
+            match self.solutions.get(id) {
+                Some(solution) => batch.put_solution(solution)?,
+                None => batch.remove_solution(id),
+            }
+        }
+        for hash in &self.dirty_modules {
+            match self.modules.get(hash) {
+                Some(module) => batch.put_module(hash, module)?,
+                None => batch.remove_module(hash),
+            }
+        }
+
+        self.write_prune(&prune, &mut batch)?;
+
+        store.commit(batch)?;
+        self.index_update(update);
+        self.clear_dirty();
+        self.apply_prune(prune);
+        self.apply_finality(update);
+        Ok(())
+    }
+
+    fn clear_dirty(&mut self) {
+        self.sync_state_tree();
+        self.dirty_accounts.clear();
+        self.dirty_jobs.clear();
+        self.dirty_solutions.clear();
+        self.dirty_modules.clear();
+    }
+
+    /// Compute the state root that would result from executing `block` on top
+    /// of the current state, without changing it
+    ///
+    /// The block's own `header.state_root` is ignored: block producers use
+    /// this to fill it in.
+    ///
+    /// # Errors
+    /// Returns error if the block body cannot be executed
+    pub fn state_root_after(&mut self, block: &Block) -> Result<Hash, StateError> {
+        let mut undo = BlockUndo::new(self);
+        let outcome = self.execute_block(block, &mut undo);
+        let root = self.compute_state_root();
+        self.revert(undo);
+        outcome.map(|()| root)
+    }
+
+    /// Re-check a candidate block built on the current tip, without applying it
+    ///
+    /// Verifiers run this before attesting: the block must pass
+    /// `validate_block`, extend the tip, come from a scheduled proposer and
+    /// execute to the state root it commits to. Every solution it settles is
+    /// checked again with `recheck`, which returns whether the solution
+    /// passes here, and must agree with the verdict the block carries.
+    ///
+    /// # Errors
+    /// Returns error for the first check that fails
+    pub fn check_candidate<F>(&mut self, block: &Block, mut recheck: F) -> Result<(), StateError>
+    where
+        F: FnMut(&JobPacket, &SolutionCandidate) -> bool,
+    {
+        self.validate_block(block, now_millis())?;
+        if self.tip != Some(block.header.parent_hash) {
+            return Err(StateError::InvalidParent);
+        }
+        if block.header.height != self.height {
             return Err(StateError::InvalidHeight {
-                expected: 0,
+                expected: self.height,
                 got: block.header.height,
             });
         }
+        self.check_proposer(block)?;
 
-        // Store block
-        let block_hash = block.hash;
-        self.blocks.insert(block_hash, block);
-        self.height_index.insert(self.height + 1, block_hash);
-        self.tip = Some(block_hash);
-        self.height += 1;
+        let computed = self.state_root_after(block)?;
+        if computed != block.header.state_root {
+            return Err(StateError::StateRootMismatch {
+                expected: block.header.state_root,
+                computed,
+            });
+        }
 
+        let jobs = self.settled_jobs(block)?;
+        for ((result, solution), job) in block.verifications.iter().zip(&block.solutions).zip(&jobs) {
+            if recheck(job, solution) != result.passed {
+                return Err(StateError::VerdictMismatch { solution: solution.id });
+            }
+        }
         Ok(())
     }
 
-    /// Get block by hash
+    /// Keep the transactions that still execute, in order, on top of the
+    /// current state, without changing it
+    ///
+    /// Block producers use this to drop mempool entries that stopped applying
+    /// (stale nonce, spent balance) before building a block from the rest,
+    /// at time `now`.
+    pub fn executable_transactions(&mut self, transactions: Vec<Transaction>, now: Timestamp) -> Vec<Transaction> {
+        let mut applied = Vec::new();
+        let mut executable = Vec::new();
+        for tx in transactions {
+            let mut undo = BlockUndo::new(self);
+            match self.execute_transaction(&tx, now, &mut undo) {
+                Ok(()) => {
+                    applied.push(undo);
+                    executable.push(tx);
+                }
+                Err(_) => self.revert(undo),
+            }
+        }
+        for undo in applied.into_iter().rev() {
+            self.revert(undo);
+        }
+        executable
+    }
+
+    /// Total amount burned by executed blocks
+    #[must_use]
+    pub const fn total_burned(&self) -> HclawAmount {
+        self.total_burned
+    }
+
+    /// Get block by hash (`None` once its body is pruned; see `get_header`)
     #[must_use]
     pub fn get_block(&self, hash: &Hash) -> Option<&Block> {
         self.blocks.get(hash)
     }
 
-    /// Get block by height
+    /// Get canonical block by height (`None` below `history_start`)
     #[must_use]
     pub fn get_block_at_height(&self, height: u64) -> Option<&Block> {
         self.height_index.get(&height).and_then(|h| self.blocks.get(h))
@@ -205,27 +604,23 @@ impl ChainState {
         self.height
     }
 
-    /// Compute state root (merkle root of all account states)
-    #[must_use]
-    pub fn compute_state_root(&self) -> Hash {
-        let mut hashes: Vec<Hash> = self.accounts
-            .iter()
-            .map(|(addr, state)| {
-                let mut data = Vec::new();
-                data.extend_from_slice(addr.as_bytes());
-                data.extend_from_slice(&state.balance.raw().to_le_bytes());
-                data.extend_from_slice(&state.nonce.to_le_bytes());
-                hash_data(&data)
-            })
-            .collect();
-
-        hashes.sort_by(|a, b| a.as_bytes().cmp(b.as_bytes()));
-        merkle_root(&hashes)
-    }
-
-    /// Store a job
-    pub fn store_job(&mut self, job: JobPacket) {
+    /// Accept a job outside block execution
+    ///
+    /// Burns the fee and locks the bounty like a `SubmitJob` transaction,
+    /// without consuming the requester's nonce.
+    ///
+    /// # Errors
+    /// Returns error if the job's verifier module is not published, or the
+    /// requester cannot cover the job's total cost
+    pub fn store_job(&mut self, job: JobPacket) -> Result<(), StateError> {
+        self.check_job_admission(&job)?;
+        self.get_or_create_account(&job.escrow_address())
+            .lock_bounty(&job)?;
+        self.total_burned = self.total_burned.saturating_add(job.burn_fee);
+        self.dirty_jobs.insert(job.id);
+        self.index.index_job(&job);
         self.jobs.insert(job.id, job);
+        Ok(())
     }
 
     /// Get job by ID
@@ -234,11 +629,6 @@ impl ChainState {
         self.jobs.get(id)
     }
 
-    /// Store a solution
-    pub fn store_solution(&mut self, solution: SolutionCandidate) {
-        self.solutions.insert(solution.id, solution);
-    }
-
     /// Get solution by ID
     #[must_use]
     pub fn get_solution(&self, id: &Id) -> Option<&SolutionCandidate> {
@@ -271,21 +661,266 @@ pub enum StateError {
     /// Block not found
     #[error("block not found")]
     BlockNotFound,
+    /// Block failed integrity checks
+    #[error("invalid block: {0}")]
+    InvalidBlock(String),
+    /// Canonical block has no undo journal, so it cannot be reverted
+    #[error("missing undo journal for canonical block")]
+    MissingUndo,
     /// Account not found
     #[error("account not found")]
     AccountNotFound,
+    /// Persistent storage failure
+    #[error("storage error: {0}")]
+    Storage(String),
+    /// Block references a job this state does not know
+    #[error("job not found")]
+    JobNotFound,
+    /// A verification does not carry the solution it settles
+    #[error("solution not found")]
+    SolutionNotFound,
+    /// Solution belongs to a different job than the verification claims
+    #[error("solution does not belong to job")]
+    SolutionJobMismatch,
+    /// Job is already completed or expired
+    #[error("job is already closed")]
+    JobClosed,
+    /// Solution was already verified or rejected
+    #[error("solution already settled")]
+    SolutionAlreadySettled,
+    /// Block state root does not match the executed state
+    #[error("state root mismatch: header {expected}, computed {computed}")]
+    StateRootMismatch {
+        /// Root committed in the block header
+        expected: Hash,
+        /// Root after executing the block
+        computed: Hash,
+    },
+    /// Transaction nonce is not the sender's next nonce
+    #[error("invalid nonce: expected {expected}, got {got}")]
+    InvalidNonce {
+        /// Sender's next nonce
+        expected: u64,
+        /// Nonce the transaction carries
+        got: u64,
+    },
+    /// Transaction failed validation or cannot apply
+    #[error("invalid transaction: {0}")]
+    InvalidTransaction(String),
+    /// Payout exceeds the bounty held in escrow
+    #[error("insufficient escrow: have {have}, need {need}")]
+    InsufficientEscrow {
+        /// Currently escrowed
+        have: HclawAmount,
+        /// Bounty to pay out
+        need: HclawAmount,
+    },
+    /// Unstake exceeds the staked amount
+    #[error("insufficient stake: have {have}, need {need}")]
+    InsufficientStake {
+        /// Currently staked
+        have: HclawAmount,
+        /// Amount to unstake
+        need: HclawAmount,
+    },
+    /// Block proposer was not in line for its slot
+    #[error("proposer {proposer} not scheduled at height {height}")]
+    UnscheduledProposer {
+        /// Height of the block
+        height: u64,
+        /// Address of the proposer
+        proposer: Address,
+    },
+    /// A settled solution does not get the block's verdict when checked here
+    #[error("verdict on solution {solution} does not match ours")]
+    VerdictMismatch {
+        /// ID of the solution
+        solution: Hash,
+    },
+    /// Snapshot failed verification
+    #[error("invalid snapshot: {0}")]
+    InvalidSnapshot(String),
+    /// Snapshot was written by an incompatible version
+    #[error("unsupported snapshot version {0}")]
+    UnsupportedSnapshotVersion(u32),
+    /// History at this height was pruned
+    #[error("history pruned at height {0}")]
+    Pruned(u64),
+    /// Genesis spec is invalid
+    #[error("invalid genesis: {0}")]
+    InvalidGenesis(String),
+    /// Stored chain started from a different genesis than the spec
+    #[error("genesis mismatch: spec derives {expected}, chain has {found}")]
+    GenesisMismatch {
+        /// Genesis hash the spec derives
+        expected: Hash,
+        /// Genesis hash of the stored chain
+        found: Hash,
+    },
+    /// Snapshots can only be imported into an empty state
+    #[error("state already has blocks")]
+    StateNotEmpty,
+    /// Double-signing evidence cannot be applied
+    #[error("invalid evidence: {0}")]
+    InvalidEvidence(String),
+    /// Block conflicts with a finalized block
+    #[error("block at height {height} conflicts with finalized height {finalized}")]
+    BelowFinalized {
+        /// Height of the block
+        height: u64,
+        /// Finalized height
+        finalized: u64,
+    },
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::crypto::Keypair;
+    use crate::consensus::{VerifierRegistry, SLOT_DURATION_MS};
+    use crate::crypto::{hash_data, Keypair};
+    use crate::types::{
+        now_millis, JobStatus, JobType, SolutionStatus, Timestamp, TransactionKind,
+        VerificationResult, VerificationSpec, VerifierAttestation,
+    };
 
     fn test_address() -> Address {
         let kp = Keypair::generate();
         Address::from_public_key(kp.public_key())
     }
 
+    /// Reopen a store whose previous handle was just dropped
+    ///
+    /// sled's background threads can hold the file lock for a moment after drop.
+    pub fn reopen(dir: &Path) -> ChainState {
+        for _ in 0..50 {
+            if let Ok(state) = ChainState::open(dir) {
+                return state;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(20));
+        }
+        ChainState::open(dir).unwrap()
+    }
+
+    /// Next block on top of the tip, before its state root is filled in
+    ///
+    /// Its timestamp is kept ahead of the parent's, however fast blocks are built.
+    pub fn draft(
+        state: &ChainState,
+        proposer: &Keypair,
+        transactions: Vec<Transaction>,
+        settled: Vec<(VerificationResult, SolutionCandidate)>,
+    ) -> Block {
+        let parent = state.tip().unwrap();
+        let timestamp = now_millis().max(parent.header.timestamp + 1);
+        let (verifications, solutions) = settled.into_iter().unzip();
+        Block::with_transactions(state.height(), parent.hash, *proposer.public_key(), transactions, verifications, Hash::ZERO)
+            .with_solutions(solutions)
+            .with_timestamp(timestamp)
+            .with_epoch(state.epoch_commitment())
+    }
+
+    /// Fill in the state root `block` executes to, and sign it
+    pub fn seal(state: &mut ChainState, proposer: &Keypair, block: Block) -> Block {
+        let root = state.state_root_after(&block).unwrap();
+        signed(block.with_state_root(root), proposer)
+    }
+
+    /// Sign `block` as its proposer
+    pub fn signed(mut block: Block, proposer: &Keypair) -> Block {
+        block.proposer_signature = proposer.sign(&block.signing_bytes());
+        block
+    }
+
+    /// A verification result signed by `verifier`, with the solution it settles
+    pub fn signed_result(
+        verifier: &Keypair,
+        solution: &SolutionCandidate,
+        passed: bool,
+    ) -> (VerificationResult, SolutionCandidate) {
+        let mut result = VerificationResult::new(solution.id, solution.job_id, *verifier.public_key(), passed, None, 1);
+        result.signature = verifier.sign(&result.signing_bytes());
+        (result, solution.clone())
+    }
+
+    /// State at height 1 with a funded requester, an open job and a solution
+    /// that is not settled yet
+    fn state_with_job(bounty: u64) -> (ChainState, Keypair, JobPacket, SolutionCandidate) {
+        let mut state = ChainState::new();
+        let (proposer, job, solution) = setup_job(&mut state, bounty, 3600);
+        (state, proposer, job, solution)
+    }
+
+    /// Apply genesis to an empty state, then fund a requester and open a job
+    fn setup_job(state: &mut ChainState, bounty: u64, ttl_secs: u64) -> (Keypair, JobPacket, SolutionCandidate) {
+        let proposer = Keypair::generate();
+        state.apply_block(Block::genesis(*proposer.public_key())).unwrap();
+
+        let requester = Keypair::generate();
+        let solver = Keypair::generate();
+        let job = test_job(&requester, bounty, 1, ttl_secs);
+        let solution = SolutionCandidate::new(job.id, *solver.public_key(), b"output".to_vec());
+
+        state
+            .get_or_create_account(&job.requester_address)
+            .credit(HclawAmount::from_hclaw(1000));
+        state.store_job(job.clone()).unwrap();
+
+        (proposer, job, solution)
+    }
+
+    /// A signed job expiring `ttl_secs` from now
+    fn test_job(requester: &Keypair, bounty: u64, burn_fee: u64, ttl_secs: u64) -> JobPacket {
+        let mut job = JobPacket::new(
+            JobType::Deterministic,
+            *requester.public_key(),
+            b"input".to_vec(),
+            "Test".to_string(),
+            HclawAmount::from_hclaw(bounty),
+            HclawAmount::from_hclaw(burn_fee),
+            VerificationSpec::HashMatch { expected_hash: hash_data(b"output") },
+            ttl_secs,
+        );
+        job.signature = requester.sign(&job.signing_bytes());
+        job
+    }
+
+    /// Attest to `block` as `kp`
+    pub fn attest(block: &mut Block, kp: &Keypair) {
+        let mut attestation = VerifierAttestation::new(*kp.public_key(), block.hash, Vec::new());
+        attestation.signature = kp.sign(&attestation.signing_bytes());
+        block.add_attestation(attestation);
+    }
+
+    /// A staked verifier, registered before any competing blocks are built
+    fn staked_verifier(state: &mut ChainState, stake: u64) -> Keypair {
+        let kp = Keypair::generate();
+        state
+            .get_or_create_account(&Address::from_public_key(kp.public_key()))
+            .staked = HclawAmount::from_hclaw(stake);
+        kp
+    }
+
+    /// Whichever of `keys` leads the line for the block after the tip
+    fn leader<'a>(state: &ChainState, keys: impl IntoIterator<Item = &'a Keypair>) -> &'a Keypair {
+        let parent = state.tip().unwrap().hash;
+        let leader = state.proposer_schedule().leader(&parent, state.height()).unwrap();
+        keys.into_iter().find(|kp| Address::from_public_key(kp.public_key()) == leader).unwrap()
+    }
+
+    fn next_block(state: &mut ChainState, proposer: &Keypair, settled: Vec<(VerificationResult, SolutionCandidate)>) -> Block {
+        seal(state, proposer, draft(state, proposer, Vec::new(), settled))
+    }
+
+    fn block_at(
+        state: &mut ChainState,
+        proposer: &Keypair,
+        timestamp: Timestamp,
+        settled: Vec<(VerificationResult, SolutionCandidate)>,
+    ) -> Block {
+        let block = draft(state, proposer, Vec::new(), settled).with_timestamp(timestamp);
+        seal(state, proposer, block)
+    }
+
     #[test]
     fn test_account_state() {
         let mut account = AccountState::new(HclawAmount::from_hclaw(100));
@@ -335,5 +970,553 @@ mod tests {
 
         assert_eq!(state.height(), 1);
         assert!(state.tip().is_some());
+        assert_eq!(state.get_block_at_height(0).map(|b| b.hash), state.tip().map(|b| b.hash));
+
+        // Next block must build on genesis at height 1
+        let next = draft(&state, &kp, Vec::new(), Vec::new());
+        let next = seal(&mut state, &kp, next);
+        state.apply_block(next).unwrap();
+        assert_eq!(state.height(), 2);
+    }
+
+    #[test]
+    fn test_persistent_state_reload() {
+        let dir = std::env::temp_dir().join(format!("hardclaw_test_state_{}", rand::random::<u64>()));
+        let kp = Keypair::generate();
+        let alice = test_address();
+        let genesis = Block::genesis(*kp.public_key());
+
+        {
+            let mut state = ChainState::open(&dir).unwrap();
+            assert!(state.is_persistent());
+            assert_eq!(state.height(), 0);
+
+            state.apply_block(genesis.clone()).unwrap();
+            state.get_or_create_account(&alice).credit(HclawAmount::from_hclaw(42));
+
+            let root = state.compute_state_root();
+            let next = signed(draft(&state, &kp, Vec::new(), Vec::new()).with_state_root(root), &kp);
+            state.apply_block(next).unwrap();
+        }
+
+        let reloaded = reopen(&dir);
+        assert_eq!(reloaded.height(), 2);
+        assert_eq!(reloaded.get_block_at_height(0).map(|b| b.hash), Some(genesis.hash));
+        assert_eq!(reloaded.balance_of(&alice).whole_hclaw(), 42);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_read_only_open_while_held() {
+        let dir = std::env::temp_dir().join(format!("hardclaw_test_read_only_{}", rand::random::<u64>()));
+        assert!(ChainState::open_read_only(&dir).is_err());
+
+        let kp = Keypair::generate();
+        let mut state = ChainState::open(&dir).unwrap();
+        state.apply_block(Block::genesis(*kp.public_key())).unwrap();
+
+        // The running node keeps its store locked
+        let mut copy = ChainState::open_read_only(&dir).unwrap();
+        assert_eq!(copy.tip().map(|t| t.hash), state.tip().map(|t| t.hash));
+        let next = signed(draft(&copy, &kp, Vec::new(), Vec::new()), &kp);
+        copy.apply_block(next).unwrap();
+        drop(copy);
+
+        drop(state);
+        assert_eq!(reopen(&dir).height(), 1);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_block_executes_payout() {
+        let (mut state, proposer, job, solution) = state_with_job(100);
+        let result = signed_result(&proposer, &solution, true);
+
+        let block = next_block(&mut state, &proposer, vec![result]);
+        state.apply_block(block).unwrap();
+
+        let distribution = FeeDistributor::default_shares().distribute(
+            job.bounty,
+            solution.solver_address,
+            Address::from_public_key(proposer.public_key()),
+        );
+        assert_eq!(state.balance_of(&job.requester_address).whole_hclaw(), 899);
+        assert_eq!(state.escrowed_balance(&job.requester_address), HclawAmount::ZERO);
+        assert_eq!(state.balance_of(&solution.solver_address), distribution.solver_amount);
+        assert_eq!(state.balance_of(&distribution.verifier), distribution.verifier_amount);
+        assert_eq!(state.total_burned(), distribution.burn_amount.saturating_add(job.burn_fee));
+        assert_eq!(state.get_job(&job.id).unwrap().status, JobStatus::Completed);
+        assert_eq!(state.get_solution(&solution.id).unwrap().status, SolutionStatus::Verified);
+
+        // The same result cannot settle twice
+        let replay = signed_result(&proposer, &solution, true);
+        assert!(matches!(
+            state.state_root_after(&draft(&state, &proposer, Vec::new(), vec![replay])),
+            Err(StateError::JobClosed)
+        ));
+    }
+
+    #[test]
+    fn test_block_rejects_failed_solution() {
+        let (mut state, proposer, job, solution) = state_with_job(100);
+        let result = signed_result(&proposer, &solution, false);
+
+        let block = next_block(&mut state, &proposer, vec![result]);
+        state.apply_block(block).unwrap();
+
+        assert_eq!(state.balance_of(&job.requester_address).whole_hclaw(), 999);
+        assert_eq!(state.escrowed_balance(&job.requester_address), job.bounty);
+        assert_eq!(state.get_solution(&solution.id).unwrap().status, SolutionStatus::Rejected);
+        assert_ne!(state.get_job(&job.id).unwrap().status, JobStatus::Completed);
+    }
+
+    #[test]
+    fn test_job_escrow_follows_requester_key() {
+        let (mut state, _, _, _) = state_with_job(100);
+        let requester = Keypair::generate();
+        let other = test_address();
+        state.get_or_create_account(&other).credit(HclawAmount::from_hclaw(1000));
+
+        // Naming another funded account does not make it pay for the job
+        let mut job = test_job(&requester, 10, 1, 3600);
+        job.requester_address = other;
+        job.signature = requester.sign(&job.signing_bytes());
+        assert!(matches!(state.store_job(job), Err(StateError::InvalidTransaction(_))));
+        assert_eq!(state.escrowed_balance(&other), HclawAmount::ZERO);
+        assert_eq!(state.balance_of(&other).whole_hclaw(), 1000);
+    }
+
+    #[test]
+    fn test_candidate_verdicts_are_rechecked() {
+        let (mut state, proposer, job, solution) = state_with_job(100);
+        let registry = VerifierRegistry::new();
+        let recheck = |job: &JobPacket, solution: &SolutionCandidate| {
+            registry.verify(job, solution).is_ok_and(|verdict| verdict.passed)
+        };
+
+        let block = next_block(&mut state, &proposer, vec![signed_result(&proposer, &solution, true)]);
+        assert!(state.check_candidate(&block, recheck).is_ok());
+
+        // A wrong output waved through executes fine, but our backend fails it
+        let wrong = SolutionCandidate::new(job.id, solution.solver, b"wrong".to_vec());
+        let block = next_block(&mut state, &proposer, vec![signed_result(&proposer, &wrong, true)]);
+        assert!(matches!(
+            state.check_candidate(&block, recheck),
+            Err(StateError::VerdictMismatch { solution }) if solution == wrong.id
+        ));
+    }
+
+    #[test]
+    fn test_block_state_root_mismatch_reverts() {
+        let (mut state, proposer, job, solution) = state_with_job(100);
+        let result = signed_result(&proposer, &solution, true);
+        let root_before = state.compute_state_root();
+
+        let block = draft(&state, &proposer, Vec::new(), vec![result]).with_state_root(root_before);
+        assert!(matches!(
+            state.apply_block(signed(block, &proposer)),
+            Err(StateError::StateRootMismatch { .. })
+        ));
+
+        // Nothing moved
+        assert_eq!(state.height(), 1);
+        assert_eq!(state.compute_state_root(), root_before);
+        assert_eq!(state.balance_of(&job.requester_address).whole_hclaw(), 999);
+        assert!(state.get_solution(&solution.id).is_none());
+        assert_eq!(state.total_burned(), job.burn_fee);
+    }
+
+    #[test]
+    fn test_unfunded_job_is_not_accepted() {
+        let (mut state, _, requester) = funded_state(100);
+        let address = Address::from_public_key(requester.public_key());
+        let job = test_job(&requester, 100, 1, 3600);
+
+        assert!(matches!(state.store_job(job.clone()), Err(StateError::InsufficientBalance { .. })));
+        assert!(state.get_job(&job.id).is_none());
+        assert_eq!(state.balance_of(&address).whole_hclaw(), 100);
+        assert_eq!(state.total_burned(), HclawAmount::ZERO);
+    }
+
+    #[test]
+    fn test_expired_job_refunds_bounty() {
+        // A short deadline, so blocks past it are not too far ahead of the clock
+        let mut state = ChainState::new();
+        let (proposer, job, solution) = setup_job(&mut state, 100, 1);
+        let requester = job.requester_address;
+        assert_eq!(state.get_account(&requester).unwrap().available_balance().whole_hclaw(), 899);
+
+        // Still open at its deadline
+        let block = block_at(&mut state, &proposer, job.expires_at, Vec::new());
+        state.apply_block(block).unwrap();
+        assert_eq!(state.get_job(&job.id).unwrap().status, JobStatus::Pending);
+
+        let block = block_at(&mut state, &proposer, job.expires_at + 1, Vec::new());
+        state.apply_block(block).unwrap();
+        assert_eq!(state.get_job(&job.id).unwrap().status, JobStatus::Expired);
+        assert_eq!(state.escrowed_balance(&requester), HclawAmount::ZERO);
+        assert_eq!(state.get_account(&requester).unwrap().available_balance().whole_hclaw(), 999);
+
+        // A late solution can no longer claim the bounty
+        let result = signed_result(&proposer, &solution, true);
+        assert!(matches!(
+            state.state_root_after(
+                &draft(&state, &proposer, Vec::new(), vec![result]).with_timestamp(job.expires_at + 2)
+            ),
+            Err(StateError::JobClosed)
+        ));
+    }
+
+    pub fn signed_tx(kp: &Keypair, nonce: u64, kind: TransactionKind) -> Transaction {
+        let mut tx = Transaction::new(*kp.public_key(), nonce, kind);
+        tx.signature = kp.sign(&tx.signing_bytes());
+        tx
+    }
+
+    fn tx_block(state: &mut ChainState, proposer: &Keypair, transactions: Vec<Transaction>) -> Block {
+        let block = draft(state, proposer, transactions, Vec::new());
+        seal(state, proposer, block)
+    }
+
+    /// Genesis plus one funded account
+    fn funded_state(balance: u64) -> (ChainState, Keypair, Keypair) {
+        let mut state = ChainState::new();
+        let proposer = Keypair::generate();
+        state.apply_block(Block::genesis(*proposer.public_key())).unwrap();
+
+        let sender = Keypair::generate();
+        state
+            .get_or_create_account(&Address::from_public_key(sender.public_key()))
+            .credit(HclawAmount::from_hclaw(balance));
+        (state, proposer, sender)
+    }
+
+    #[test]
+    fn test_transfer_transaction_consumes_nonce() {
+        let (mut state, proposer, sender) = funded_state(100);
+        let from = Address::from_public_key(sender.public_key());
+        let to = test_address();
+        let tx = signed_tx(&sender, 0, TransactionKind::Transfer { to, amount: HclawAmount::from_hclaw(40) });
+
+        let block = tx_block(&mut state, &proposer, vec![tx.clone()]);
+        state.apply_block(block).unwrap();
+
+        assert_eq!(state.balance_of(&from).whole_hclaw(), 60);
+        assert_eq!(state.balance_of(&to).whole_hclaw(), 40);
+        assert_eq!(state.get_account(&from).unwrap().nonce, 1);
+
+        // Replaying the same signed transaction fails on its spent nonce
+        assert!(matches!(
+            state.state_root_after(&draft(&state, &proposer, vec![tx], Vec::new())),
+            Err(StateError::InvalidNonce { expected: 1, got: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_stake_and_unstake_transactions() {
+        let (mut state, proposer, sender) = funded_state(100);
+        let address = Address::from_public_key(sender.public_key());
+        let amount = HclawAmount::from_hclaw(70);
+
+        let stake = signed_tx(&sender, 0, TransactionKind::Stake { amount });
+        let block = tx_block(&mut state, &proposer, vec![stake]);
+        state.apply_block(block).unwrap();
+        assert_eq!(state.get_account(&address).unwrap().staked, amount);
+        assert_eq!(state.get_account(&address).unwrap().available_balance().whole_hclaw(), 30);
+
+        // Staked funds cannot be transferred, and unstake is bounded by the stake
+        let to = test_address();
+        let transfer = signed_tx(&sender, 1, TransactionKind::Transfer { to, amount: HclawAmount::from_hclaw(50) });
+        assert!(matches!(
+            state.state_root_after(&draft(&state, &proposer, vec![transfer], Vec::new())),
+            Err(StateError::InsufficientBalance { .. })
+        ));
+        let too_much = signed_tx(&sender, 1, TransactionKind::Unstake { amount: HclawAmount::from_hclaw(80) });
+        assert!(matches!(
+            state.state_root_after(&draft(&state, &proposer, vec![too_much], Vec::new())),
+            Err(StateError::InsufficientStake { .. })
+        ));
+
+        let unstake = signed_tx(&sender, 1, TransactionKind::Unstake { amount });
+        let block = tx_block(&mut state, &proposer, vec![unstake]);
+        state.apply_block(block).unwrap();
+        assert_eq!(state.get_account(&address).unwrap().staked, HclawAmount::ZERO);
+    }
+
+    #[test]
+    fn test_submit_job_transaction() {
+        let (mut state, proposer, requester) = funded_state(100);
+        let job = test_job(&requester, 10, 2, 3600);
+        let submit = |nonce| signed_tx(&requester, nonce, TransactionKind::SubmitJob(Box::new(job.clone())));
+
+        let block = tx_block(&mut state, &proposer, vec![submit(0)]);
+        state.apply_block(block).unwrap();
+
+        assert!(state.get_job(&job.id).is_some());
+        assert_eq!(state.total_burned().whole_hclaw(), 2);
+        assert_eq!(state.balance_of(&job.requester_address).whole_hclaw(), 98);
+        assert_eq!(state.escrowed_balance(&job.requester_address), job.bounty);
+        assert_eq!(state.get_account(&job.requester_address).unwrap().available_balance().whole_hclaw(), 88);
+
+        // The same job cannot be posted twice under a fresh nonce
+        assert!(matches!(
+            state.state_root_after(&draft(&state, &proposer, vec![submit(1)], Vec::new())),
+            Err(StateError::InvalidTransaction(_))
+        ));
+    }
+
+    #[test]
+    fn test_executable_transactions_skips_failures() {
+        let (mut state, _, sender) = funded_state(100);
+        let to = test_address();
+        let root = state.compute_state_root();
+
+        let first = signed_tx(&sender, 0, TransactionKind::Transfer { to, amount: HclawAmount::from_hclaw(60) });
+        let overdraw = signed_tx(&sender, 1, TransactionKind::Transfer { to, amount: HclawAmount::from_hclaw(60) });
+        let executable = state.executable_transactions(vec![first.clone(), overdraw], now_millis());
+
+        assert_eq!(executable.len(), 1);
+        assert_eq!(executable[0].id, first.id);
+        assert_eq!(state.compute_state_root(), root);
+    }
+
+    #[test]
+    fn test_heavier_branch_reorganizes() {
+        let (mut state, _, job, solution) = state_with_job(100);
+        let [x, y, z] = [2000, 1000, 1000].map(|stake| staked_verifier(&mut state, stake));
+        let proposer = leader(&state, [&x, &y, &z]);
+        let mut a = next_block(&mut state, proposer, vec![signed_result(proposer, &solution, true)]);
+        let mut b = next_block(&mut state, proposer, Vec::new());
+        attest(&mut a, &x);
+        attest(&mut a, &y);
+
+        assert_eq!(state.import_block(a.clone()).unwrap(), ImportOutcome::Extended);
+        assert!(state.balance_of(&solution.solver_address) > HclawAmount::ZERO);
+        let verified = |state: &ChainState| {
+            state.verifications_by_verifier(proposer.public_key(), Pagination::default()).total
+        };
+        assert_eq!(verified(&state), 1);
+
+        // Lighter side branch is kept but not followed
+        assert_eq!(state.import_block(b.clone()).unwrap(), ImportOutcome::Stored);
+        assert_eq!(state.tip().map(|t| t.hash), Some(a.hash));
+        assert_eq!(state.known_block_count(), 3);
+
+        // Only the validator set may attest, once each
+        let mut forged = b.clone();
+        attest(&mut forged, &Keypair::generate());
+        assert!(matches!(
+            state.add_attestation(forged.attestations[0].clone()),
+            Err(StateError::InvalidBlock(_))
+        ));
+        for kp in [&z, &y] {
+            attest(&mut b, kp);
+            let attestation = b.attestations.last().unwrap().clone();
+            assert_eq!(state.add_attestation(attestation.clone()).unwrap(), ImportOutcome::Stored);
+            assert_eq!(state.add_attestation(attestation).unwrap(), ImportOutcome::Duplicate);
+        }
+        assert_eq!(state.tip().map(|t| t.hash), Some(a.hash));
+
+        // The last staked attestation makes `b` heavier; `a`'s effects are undone
+        attest(&mut b, &x);
+        let attestation = b.attestations.last().unwrap().clone();
+        let outcome = state.add_attestation(attestation).unwrap();
+        assert_eq!(
+            outcome,
+            ImportOutcome::Reorganized { disconnected: vec![a.hash], connected: vec![b.hash] }
+        );
+        assert_eq!(state.tip().map(|t| t.hash), Some(b.hash));
+        assert_eq!(state.height(), 2);
+        assert!(state.is_canonical(&b.hash));
+        assert!(!state.is_canonical(&a.hash));
+        assert_eq!(state.balance_of(&solution.solver_address), HclawAmount::ZERO);
+        assert_eq!(state.balance_of(&job.requester_address).whole_hclaw(), 999);
+        assert_eq!(state.escrowed_balance(&job.requester_address), job.bounty);
+        assert_ne!(state.get_job(&job.id).unwrap().status, JobStatus::Completed);
+        assert!(state.get_solution(&solution.id).is_none());
+        assert_eq!(state.total_burned(), job.burn_fee);
+        assert_eq!(verified(&state), 0);
+
+        // Branches are weighed with the stake of their parent state, not today's
+        let whale = HclawAmount::from_hclaw(1_000_000);
+        state.get_or_create_account(&Address::from_public_key(z.public_key())).staked = whale;
+        assert_eq!(state.block_weight(&b).stake, HclawAmount::from_hclaw(4000));
+        assert_eq!(state.block_weight(&a).stake, HclawAmount::from_hclaw(3000));
+
+        // Extending the lighter branch does not win it back, even late enough
+        // for anyone in the set to propose
+        let root = state.compute_state_root();
+        let c = Block::new(2, a.hash, *proposer.public_key(), Vec::new(), root)
+            .with_timestamp(a.header.timestamp + 2 * SLOT_DURATION_MS);
+        assert_eq!(state.import_block(signed(c, proposer)).unwrap(), ImportOutcome::Stored);
+        assert_eq!(state.tip().map(|t| t.hash), Some(b.hash));
+    }
+
+    #[test]
+    fn test_invalid_heavier_branch_is_discarded() {
+        let (mut state, _, _, solution) = state_with_job(100);
+        let keys = [2000, 1000, 1000].map(|stake| staked_verifier(&mut state, stake));
+        let proposer = leader(&state, &keys);
+        let mut a = next_block(&mut state, proposer, vec![signed_result(proposer, &solution, true)]);
+        attest(&mut a, &keys[0]);
+        attest(&mut a, &keys[1]);
+        state.import_block(a.clone()).unwrap();
+
+        // Heavier sibling whose state root is wrong
+        let bad = Block::new(1, a.header.parent_hash, *proposer.public_key(), Vec::new(), Hash::ZERO)
+            .with_timestamp(a.header.timestamp);
+        let mut bad = signed(bad, proposer);
+        for kp in &keys {
+            attest(&mut bad, kp);
+        }
+        assert!(matches!(
+            state.import_block(bad.clone()),
+            Err(StateError::StateRootMismatch { .. })
+        ));
+
+        assert_eq!(state.tip().map(|t| t.hash), Some(a.hash));
+        assert!(state.get_block(&bad.hash).is_none());
+        assert!(state.balance_of(&solution.solver_address) > HclawAmount::ZERO);
+    }
+
+    #[test]
+    fn test_discard_branch_takes_descendants() {
+        let (mut state, _, _, _) = state_with_job(100);
+        let keys = [2000, 1000, 1000].map(|stake| staked_verifier(&mut state, stake));
+        let proposer = leader(&state, &keys);
+        let genesis = state.tip().unwrap().hash;
+        let mut a = next_block(&mut state, proposer, Vec::new());
+        attest(&mut a, &keys[0]);
+        attest(&mut a, &keys[1]);
+        let side = Block::new(1, genesis, *proposer.public_key(), Vec::new(), Hash::ZERO)
+            .with_timestamp(a.header.timestamp + 1);
+        let side = signed(side, proposer);
+        // Late enough for anyone in the set to propose on top of `side`
+        let tail = Block::new(2, side.hash, *proposer.public_key(), Vec::new(), Hash::ZERO)
+            .with_timestamp(side.header.timestamp + 2 * SLOT_DURATION_MS);
+        let tail = signed(tail, proposer);
+        state.import_block(a.clone()).unwrap();
+        assert_eq!(state.import_block(side.clone()).unwrap(), ImportOutcome::Stored);
+        assert_eq!(state.import_block(tail.clone()).unwrap(), ImportOutcome::Stored);
+
+        assert_eq!(state.heads, HashSet::from([a.hash, tail.hash]));
+
+        // Dropping a branch takes its descendants with it, and nothing else
+        let mut update = ChainUpdate::default();
+        state.discard_branch(side.hash, &mut update);
+        assert_eq!(update.discarded, vec![side.hash, tail.hash]);
+        assert_eq!(state.heads, HashSet::from([a.hash]));
+        assert_eq!(state.children.get(&genesis), Some(&vec![a.hash]));
+        assert!(!state.children.contains_key(&side.hash));
+        assert_eq!(state.known_block_count(), 2);
+    }
+
+    #[test]
+    fn test_side_blocks_need_scheduled_proposer_and_room() {
+        let (mut state, _, _, _) = state_with_job(100);
+        let keys = [2000, 1000, 1000].map(|stake| staked_verifier(&mut state, stake));
+        let proposer = leader(&state, &keys);
+        let genesis = state.tip().unwrap().hash;
+        let mut a = next_block(&mut state, proposer, Vec::new());
+        attest(&mut a, &keys[0]);
+        attest(&mut a, &keys[1]);
+        state.import_block(a.clone()).unwrap();
+        let side = |kp: &Keypair, offset: Timestamp| {
+            let block = Block::new(1, genesis, *kp.public_key(), Vec::new(), Hash::ZERO)
+                .with_timestamp(a.header.timestamp + offset);
+            signed(block, kp)
+        };
+
+        // A validly signed block from outside the validator set is not kept
+        assert!(matches!(
+            state.import_block(side(&Keypair::generate(), 1)),
+            Err(StateError::UnscheduledProposer { height: 1, .. })
+        ));
+        assert_eq!(state.known_block_count(), 2);
+
+        // Nor is one past the cap of side blocks at its height
+        for offset in (1..).take(MAX_SIDE_BLOCKS_PER_HEIGHT) {
+            assert_eq!(state.import_block(side(proposer, offset)).unwrap(), ImportOutcome::Stored);
+        }
+        assert!(matches!(state.import_block(side(proposer, 0)), Err(StateError::InvalidBlock(_))));
+        assert_eq!(state.known_block_count(), 2 + MAX_SIDE_BLOCKS_PER_HEIGHT);
+    }
+
+    #[test]
+    fn test_only_scheduled_proposer_extends_chain() {
+        let mut state = ChainState::new();
+        let genesis = Block::genesis(*Keypair::generate().public_key());
+        state.apply_block(genesis.clone()).unwrap();
+        let a = staked_verifier(&mut state, 1000);
+        let b = staked_verifier(&mut state, 1000);
+
+        let line = state.proposer_schedule().proposers(&genesis.hash, 1);
+        let (leader, backup) = if line[0] == Address::from_public_key(a.public_key()) { (a, b) } else { (b, a) };
+        let backup_address = Address::from_public_key(backup.public_key());
+        let start = genesis.header.timestamp;
+        assert!(state.can_propose(&line[0], start + 1));
+        assert!(!state.can_propose(&backup_address, start + 1));
+
+        // The backup may not step in while the leader's slot is open, even
+        // with the whole set attesting, nor an outsider ever
+        let mut early = block_at(&mut state, &backup, start + 1, Vec::new());
+        attest(&mut early, &leader);
+        attest(&mut early, &backup);
+        assert!(matches!(
+            state.apply_block(early),
+            Err(StateError::UnscheduledProposer { height: 1, proposer }) if proposer == backup_address
+        ));
+        assert_eq!(state.height(), 1);
+        let outsider = block_at(&mut state, &Keypair::generate(), start + SLOT_DURATION_MS, Vec::new());
+        assert!(matches!(state.apply_block(outsider), Err(StateError::UnscheduledProposer { .. })));
+
+        // Once the slot times out the same backup is accepted
+        assert!(state.can_propose(&backup_address, start + SLOT_DURATION_MS));
+        let mut fallback = block_at(&mut state, &backup, start + SLOT_DURATION_MS, Vec::new());
+        attest(&mut fallback, &leader);
+        attest(&mut fallback, &backup);
+        state.apply_block(fallback).unwrap();
+        assert_eq!(state.height(), 2);
+        assert_eq!(state.tip().unwrap().header.proposer, *backup.public_key());
+    }
+
+    #[test]
+    fn test_import_rejects_unknown_parent() {
+        let (mut state, proposer, _, _) = state_with_job(100);
+        let orphan = Block::new(5, hash_data(b"unknown"), *proposer.public_key(), Vec::new(), Hash::ZERO);
+        assert!(matches!(state.import_block(signed(orphan, &proposer)), Err(StateError::InvalidParent)));
+    }
+
+    #[test]
+    fn test_persistent_reorg_reload() {
+        let dir = std::env::temp_dir().join(format!("hardclaw_test_reorg_{}", rand::random::<u64>()));
+        let (a, b, solver) = {
+            let mut state = ChainState::open(&dir).unwrap();
+            let (_, _, solution) = setup_job(&mut state, 100, 3600);
+            let keys = [2000, 1000, 1000].map(|stake| staked_verifier(&mut state, stake));
+            let proposer = leader(&state, &keys);
+            let mut a = next_block(&mut state, proposer, vec![signed_result(proposer, &solution, true)]);
+            let mut b = next_block(&mut state, proposer, Vec::new());
+            attest(&mut a, &keys[0]);
+            attest(&mut a, &keys[1]);
+            for kp in &keys {
+                attest(&mut b, kp);
+            }
+
+            state.import_block(a.clone()).unwrap();
+            assert!(matches!(state.import_block(b.clone()).unwrap(), ImportOutcome::Reorganized { .. }));
+            (a, b, solution.solver_address)
+        };
+
+        let reloaded = reopen(&dir);
+        assert_eq!(reloaded.tip().map(|t| t.hash), Some(b.hash));
+        assert_eq!(reloaded.height(), 2);
+        assert!(reloaded.get_block(&a.hash).is_some());
+        assert!(!reloaded.is_canonical(&a.hash));
+        // The solver account only existed because of `a`
+        assert!(reloaded.get_account(&solver).is_none());
+
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
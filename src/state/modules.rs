@@ -0,0 +1,187 @@
+//! Verifier module registry.
+//!
+//! `UploadModule` transactions publish WASM verifier modules. The chain keeps
+//! a `ModuleRecord` per module hash (who uploaded it, its size and the entry
+//! points it exports) and commits to it in the state root; the bytes live in
+//! the upload transaction and in each node's `ModuleCache`. Uploading burns
+//! `storage_burn` of the module's size.
+//!
+//! A job with a `WasmVerifier` spec, or an `ObjectiveThreshold` spec scored
+//! by a module, is only admitted if its module is registered and exports the
+//! function it names.
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{hash_data, Hash};
+use crate::types::{Address, JobPacket, Scorer, VerificationSpec};
+use crate::wasm::{storage_burn, WasmRuntime};
+
+use super::execution::BlockUndo;
+use super::{ChainState, StateError};
+
+/// What the chain records about a published module
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ModuleRecord {
+    /// Account that uploaded it
+    pub uploader: Address,
+    /// Size in bytes
+    pub size: u64,
+    /// Exports with the entry point signature, sorted
+    pub entry_points: Vec<String>,
+    /// Exports with the scoring signature, sorted
+    pub scorers: Vec<String>,
+}
+
+impl ChainState {
+    /// Record of a published module
+    #[must_use]
+    pub fn module(&self, hash: &Hash) -> Option<&ModuleRecord> {
+        self.modules.get(hash)
+    }
+
+    /// Hashes of every published module
+    pub fn module_hashes(&self) -> impl Iterator<Item = &Hash> {
+        self.modules.keys()
+    }
+
+    /// Check that a job can be admitted: its `requester_address` must be its
+    /// escrow address, and its verifier must run. A `WasmVerifier` job must
+    /// name a published module and one of its entry points, and an
+    /// `ObjectiveThreshold` job scored by a module one of its scoring
+    /// functions
+    ///
+    /// # Errors
+    /// Returns error if the requester address is not the requester key's, or
+    /// the module is unknown or lacks the function
+    pub fn check_job_admission(&self, job: &JobPacket) -> Result<(), StateError> {
+        if job.requester_address != job.escrow_address() {
+            return Err(StateError::InvalidTransaction(
+                "requester address does not match the requester key".to_string(),
+            ));
+        }
+        let (module_hash, function, scoring) = match &job.verification {
+            VerificationSpec::WasmVerifier { module_hash, entry_point } => (module_hash, entry_point, false),
+            VerificationSpec::ObjectiveThreshold { scorer: Scorer::Wasm { module_hash, function }, .. } => {
+                (module_hash, function, true)
+            }
+            _ => return Ok(()),
+        };
+        let record = self
+            .modules
+            .get(module_hash)
+            .ok_or_else(|| StateError::InvalidTransaction(format!("unknown verifier module {module_hash}")))?;
+        let (exports, what) = if scoring {
+            (&record.scorers, "scoring function")
+        } else {
+            (&record.entry_points, "entry point")
+        };
+        if !exports.contains(function) {
+            return Err(StateError::InvalidTransaction(format!(
+                "module {module_hash} has no {what} `{function}`"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Publish a module for `uploader`, burning its storage fee
+    pub(super) fn register_module(
+        &mut self,
+        uploader: &Address,
+        wasm: &[u8],
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        let hash = hash_data(wasm);
+        if self.modules.contains_key(&hash) {
+            return Err(StateError::InvalidTransaction(format!("module {hash} already published")));
+        }
+        let exports = WasmRuntime::default()
+            .check_module(wasm)
+            .map_err(|e| StateError::InvalidTransaction(e.to_string()))?;
+
+        let burn = storage_burn(wasm.len());
+        let account = self.journal_account(undo, uploader);
+        account.debit(burn)?;
+        account.total_spent = account.total_spent.saturating_add(burn);
+        self.total_burned = self.total_burned.saturating_add(burn);
+
+        let record = ModuleRecord {
+            uploader: *uploader,
+            size: wasm.len() as u64,
+            entry_points: exports.entry_points,
+            scorers: exports.scorers,
+        };
+        self.journal_new_module(undo, hash, record);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+    use crate::state::tests::{draft, seal, signed_tx};
+    use crate::types::{Block, HclawAmount, JobType, TransactionKind};
+
+    const MODULE: &str = r#"(module
+        (memory (export "memory") 1)
+        (func (export "alloc") (param i32) (result i32) (i32.const 0))
+        (func (export "verify") (param i32 i32 i32 i32) (result i32) (i32.const 1)))"#;
+
+    fn wasm_job(requester: &Keypair, module_hash: Hash, entry_point: &str) -> JobPacket {
+        let mut job = JobPacket::new(
+            JobType::Deterministic,
+            *requester.public_key(),
+            b"input".to_vec(),
+            "Wasm job".to_string(),
+            HclawAmount::from_hclaw(10),
+            HclawAmount::from_hclaw(1),
+            VerificationSpec::WasmVerifier { module_hash, entry_point: entry_point.to_string() },
+            3600,
+        );
+        job.signature = requester.sign(&job.signing_bytes());
+        job
+    }
+
+    #[test]
+    fn test_upload_module_then_submit_job() {
+        let mut state = ChainState::new();
+        let kp = Keypair::generate();
+        state.apply_block(Block::genesis(*kp.public_key())).unwrap();
+        let address = Address::from_public_key(kp.public_key());
+        state.get_or_create_account(&address).balance = HclawAmount::from_hclaw(1000);
+
+        let wasm = wat::parse_str(MODULE).unwrap();
+        let module_hash = hash_data(&wasm);
+        let job = wasm_job(&kp, module_hash, "verify");
+
+        // Unknown module: the job is turned away
+        assert!(matches!(state.check_job_admission(&job), Err(StateError::InvalidTransaction(_))));
+        let early = signed_tx(&kp, 0, TransactionKind::SubmitJob(Box::new(job.clone())));
+        let block = draft(&state, &kp, vec![early], Vec::new());
+        assert!(state.state_root_after(&block).is_err());
+
+        let burned = state.total_burned();
+        let upload = signed_tx(&kp, 0, TransactionKind::UploadModule(wasm.clone()));
+        let block = draft(&state, &kp, vec![upload], Vec::new());
+        let block = seal(&mut state, &kp, block);
+        state.apply_block(block).unwrap();
+
+        let record = state.module(&module_hash).unwrap();
+        assert_eq!(record.entry_points, vec!["verify".to_string()]);
+        assert_eq!(state.total_burned(), burned.saturating_add(storage_burn(wasm.len())));
+        assert!(state.check_job_admission(&job).is_ok());
+        assert!(state.check_job_admission(&wasm_job(&kp, module_hash, "missing")).is_err());
+
+        // Published once only
+        let again = signed_tx(&kp, 1, TransactionKind::UploadModule(wasm));
+        let block = draft(&state, &kp, vec![again], Vec::new());
+        assert!(state.state_root_after(&block).is_err());
+
+        let job_id = job.id;
+        let submit = signed_tx(&kp, 1, TransactionKind::SubmitJob(Box::new(job)));
+        let block = draft(&state, &kp, vec![submit], Vec::new());
+        let block = seal(&mut state, &kp, block);
+        state.apply_block(block).unwrap();
+        assert!(state.get_job(&job_id).is_some());
+    }
+}
@@ -0,0 +1,100 @@
+//! Proposer eligibility and attestation quorum.
+//!
+//! Whose turn it is to propose, and whose attestations count, is decided by
+//! the epoch a block falls in: every account staking at least the
+//! `StakeManager` minimum and neither tombstoned nor jailed when the epoch
+//! started joins the `ProposerSchedule` and the `ValidatorSet`, and the line
+//! is seeded by the parent hash. Proposers and quorums are checked when
+//! blocks are connected, so the state is always the parent's, whichever
+//! path (gossip, sync or a reorganisation) the block arrived by.
+
+use crate::consensus::{ProposerSchedule, ValidatorSet};
+use crate::types::{Address, Block, Timestamp};
+use crate::verifier::StakeManager;
+
+use super::{ChainState, StateError};
+
+impl ChainState {
+    /// Staked verifier set in the current state
+    ///
+    /// Changes to it join the validator set at the next epoch boundary.
+    #[must_use]
+    pub fn stake_manager(&self) -> StakeManager {
+        let mut manager = StakeManager::new();
+        let active = self.accounts.iter().filter(|(_, a)| !a.tombstoned && a.jailed_until.is_none());
+        for (address, account) in active {
+            // Stakes under the minimum are refused, which is what we want
+            let _ = manager.stake(*address, account.staked);
+        }
+        manager
+    }
+
+    /// Proposer schedule for the block after the current tip
+    #[must_use]
+    pub fn proposer_schedule(&self) -> ProposerSchedule {
+        self.epoch().proposer_schedule()
+    }
+
+    /// Verifiers whose attestations count for the block after the current tip
+    #[must_use]
+    pub fn validator_set(&self) -> ValidatorSet {
+        self.epoch().validator_set()
+    }
+
+    /// Check that a block on top of the current tip carries a valid quorum
+    ///
+    /// Genesis, and any block before anyone has staked, has no set to attest
+    /// it and passes. Must be called with the state at the block's parent.
+    pub(super) fn check_quorum(&self, block: &Block) -> Result<(), StateError> {
+        let validators = self.validator_set();
+        if block.header.height == 0 || validators.is_empty() {
+            return Ok(());
+        }
+        validators
+            .check_block(block)
+            .map_err(|e| StateError::InvalidBlock(e.to_string()))
+    }
+
+    /// Whether `proposer` may produce the block after the current tip at `timestamp`
+    #[must_use]
+    pub fn can_propose(&self, proposer: &Address, timestamp: Timestamp) -> bool {
+        let Some((hash, header)) = self.tip.and_then(|hash| self.get_header(&hash).map(|h| (hash, h)))
+        else {
+            return true;
+        };
+        self.proposer_schedule().is_eligible(
+            &hash,
+            self.height,
+            proposer,
+            timestamp.saturating_sub(header.timestamp),
+        )
+    }
+
+    /// Check that a block's proposer was in line for its slot
+    ///
+    /// Must be called with the state at the block's parent.
+    pub(super) fn check_proposer(&self, block: &Block) -> Result<(), StateError> {
+        if block.header.height == 0 {
+            return Ok(());
+        }
+        let parent = self
+            .get_header(&block.header.parent_hash)
+            .ok_or(StateError::InvalidParent)?;
+        let proposer = Address::from_public_key(&block.header.proposer);
+        let elapsed = block.header.timestamp.saturating_sub(parent.timestamp);
+
+        if self.proposer_schedule().is_eligible(
+            &block.header.parent_hash,
+            block.header.height,
+            &proposer,
+            elapsed,
+        ) {
+            Ok(())
+        } else {
+            Err(StateError::UnscheduledProposer {
+                height: block.header.height,
+                proposer,
+            })
+        }
+    }
+}
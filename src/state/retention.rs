@@ -0,0 +1,361 @@
+//! Block history retention.
+//!
+//! An archive node keeps every block body it has seen and can serve its
+//! whole history to peers. A pruned node keeps the last N canonical bodies
+//! and only the headers before them. Once a body is pruned:
+//!
+//! - `get_block` / `get_block_at_height` return `None` for it, while
+//!   `get_header` / `get_header_at_height` still answer;
+//! - its undo journal is gone, so the chain cannot reorganise below
+//!   `history_start`, and side branches forking there are dropped;
+//! - jobs it closed (completed or expired), and their solutions, are dropped
+//!   too; only their state tree leaves remain, so the state root is the same
+//!   in both modes;
+//! - snapshots can no longer be exported at its height.
+
+use std::collections::HashSet;
+
+use crate::crypto::Hash;
+use crate::types::{BlockHeader, Id, JobStatus};
+
+use super::commitment::{job_entry, solution_entry};
+use super::storage::StoreBatch;
+use super::{ChainState, StateError};
+
+/// How much block history a node keeps
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RetentionMode {
+    /// Keep every block body and record
+    #[default]
+    Archive,
+    /// Keep headers, the last `keep_blocks` bodies (at least the tip) and
+    /// records still referenced by open jobs
+    Pruned {
+        /// Number of most recent canonical bodies to keep
+        keep_blocks: u64,
+    },
+}
+
+/// Everything one pruning pass drops
+#[derive(Debug, Default)]
+pub struct PrunePlan {
+    /// New `history_start`
+    horizon: u64,
+    /// Canonical blocks whose bodies are dropped
+    bodies: Vec<Hash>,
+    /// Side-branch blocks that can no longer become canonical
+    dead: Vec<Hash>,
+    /// Closed jobs to retire
+    jobs: Vec<Id>,
+    /// Solutions of those jobs
+    solutions: Vec<Id>,
+}
+
+impl PrunePlan {
+    const fn is_empty(&self) -> bool {
+        self.bodies.is_empty() && self.dead.is_empty()
+    }
+}
+
+impl ChainState {
+    /// Current retention mode
+    #[must_use]
+    pub const fn retention(&self) -> RetentionMode {
+        self.retention
+    }
+
+    /// Change the retention mode
+    ///
+    /// Takes effect on the next block. Switching a pruned node back to
+    /// `Archive` stops further pruning; history already pruned is not
+    /// recovered.
+    pub const fn set_retention(&mut self, mode: RetentionMode) {
+        self.retention = mode;
+    }
+
+    /// Lowest canonical height whose body this node holds
+    ///
+    /// Zero for an archive node that started at genesis. Blocks, undo
+    /// journals and snapshots are only available from here on.
+    #[must_use]
+    pub const fn history_start(&self) -> u64 {
+        self.history_start
+    }
+
+    /// Get a block header by hash, including pruned canonical blocks
+    #[must_use]
+    pub fn get_header(&self, hash: &Hash) -> Option<&BlockHeader> {
+        self.blocks
+            .get(hash)
+            .map(|b| &b.header)
+            .or_else(|| self.headers.get(hash))
+    }
+
+    /// Get a canonical block header by height, including pruned blocks
+    #[must_use]
+    pub fn get_header_at_height(&self, height: u64) -> Option<&BlockHeader> {
+        self.height_index.get(&height).and_then(|h| self.get_header(h))
+    }
+
+    /// Work out what the retention mode drops at the current tip
+    pub(super) fn prune_plan(&self) -> PrunePlan {
+        let RetentionMode::Pruned { keep_blocks } = self.retention else {
+            return PrunePlan::default();
+        };
+        let horizon = self.height.saturating_sub(keep_blocks.max(1));
+        if horizon <= self.history_start {
+            return PrunePlan::default();
+        }
+
+        let bodies: Vec<Hash> = (self.history_start..horizon)
+            .filter_map(|height| self.height_index.get(&height))
+            .filter(|hash| self.blocks.contains_key(hash))
+            .copied()
+            .collect();
+
+        let closed: HashSet<Id> = bodies
+            .iter()
+            .filter_map(|hash| self.blocks.get(hash))
+            .flat_map(|block| &block.verifications)
+            .filter_map(|v| self.jobs.get(&v.job_id))
+            .filter(|job| matches!(job.status, JobStatus::Completed | JobStatus::Expired | JobStatus::Forfeited))
+            .map(|job| job.id)
+            .collect();
+        let solutions = self
+            .solutions
+            .values()
+            .filter(|s| closed.contains(&s.job_id))
+            .map(|s| s.id)
+            .collect();
+
+        // Switching to a side branch disconnects every canonical block above
+        // its fork point, which needs their undo journals
+        let dead = self
+            .blocks
+            .values()
+            .filter(|b| !self.is_canonical(&b.hash))
+            .filter(|b| self.fork_height(b.hash).is_none_or(|fork| fork + 1 < horizon))
+            .map(|b| b.hash)
+            .collect();
+
+        PrunePlan {
+            horizon,
+            bodies,
+            dead,
+            jobs: closed.into_iter().collect(),
+            solutions,
+        }
+    }
+
+    /// Add the deletions of a plan to a batch
+    ///
+    /// # Errors
+    /// Returns error if a header cannot be serialized
+    pub(super) fn write_prune(&self, plan: &PrunePlan, batch: &mut StoreBatch) -> Result<(), StateError> {
+        if plan.is_empty() {
+            return Ok(());
+        }
+        for hash in &plan.bodies {
+            if let Some(block) = self.blocks.get(hash) {
+                batch.put_header(hash, &block.header)?;
+            }
+            batch.remove_block(hash);
+            batch.remove_undo(hash);
+        }
+        for hash in &plan.dead {
+            batch.remove_block(hash);
+        }
+        for job in plan.jobs.iter().filter_map(|id| self.jobs.get(id)) {
+            let (key, leaf) = job_entry(job);
+            batch.put_retired(&key, &leaf);
+            batch.remove_job(&job.id);
+        }
+        for solution in plan.solutions.iter().filter_map(|id| self.solutions.get(id)) {
+            let (key, leaf) = solution_entry(solution);
+            batch.put_retired(&key, &leaf);
+            batch.remove_solution(&solution.id);
+        }
+        batch.put_history_start(plan.horizon);
+        Ok(())
+    }
+
+    /// Drop everything in a plan from memory (after it was committed)
+    ///
+    /// Retired records are not marked dirty: their leaves stay in the tree.
+    pub(super) fn apply_prune(&mut self, plan: PrunePlan) {
+        if plan.is_empty() {
+            return;
+        }
+        for hash in plan.bodies {
+            self.undo_logs.remove(&hash);
+            if let Some(block) = self.blocks.remove(&hash) {
+                self.index.unindex_block(&block);
+                self.unlink_block(&hash, &block.header.parent_hash);
+                self.headers.insert(hash, block.header);
+            }
+        }
+        for hash in plan.dead {
+            if let Some(block) = self.blocks.remove(&hash) {
+                self.unlink_block(&hash, &block.header.parent_hash);
+            }
+            self.heads.remove(&hash);
+        }
+        for job in plan.jobs.iter().filter_map(|id| self.jobs.remove(id)) {
+            self.index.unindex_job(&job.id);
+            let (key, leaf) = job_entry(&job);
+            self.retired.insert(key, leaf);
+        }
+        for solution in plan.solutions.iter().filter_map(|id| self.solutions.remove(id)) {
+            self.index.unindex_solution(&solution.id);
+            let (key, leaf) = solution_entry(&solution);
+            self.retired.insert(key, leaf);
+        }
+        self.history_start = plan.horizon;
+    }
+
+    /// Height of the canonical block a side block descends from
+    pub(super) fn fork_height(&self, hash: Hash) -> Option<u64> {
+        let mut cursor = hash;
+        loop {
+            let header = self.get_header(&cursor)?;
+            if self.height_index.get(&header.height) == Some(&cursor) {
+                return Some(header.height);
+            }
+            if header.height == 0 {
+                return None;
+            }
+            cursor = header.parent_hash;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{hash_data, Keypair};
+    use crate::state::tests::{draft, reopen, seal, signed_result};
+    use crate::types::{
+        Block, HclawAmount, JobPacket, JobType, SolutionCandidate, VerificationResult,
+        VerificationSpec,
+    };
+
+    fn next_block(state: &mut ChainState, proposer: &Keypair, settled: Vec<(VerificationResult, SolutionCandidate)>) -> Block {
+        let block = draft(state, proposer, Vec::new(), settled);
+        seal(state, proposer, block)
+    }
+
+    fn job(requester: &Keypair, description: &str) -> JobPacket {
+        JobPacket::new(
+            JobType::Deterministic,
+            *requester.public_key(),
+            b"input".to_vec(),
+            description.to_string(),
+            HclawAmount::from_hclaw(10),
+            HclawAmount::from_hclaw(1),
+            VerificationSpec::HashMatch { expected_hash: hash_data(b"output") },
+            3600,
+        )
+    }
+
+    /// Settle one job in block 1, leave another open, then add empty blocks
+    /// until the tip is at `tip_height`
+    fn chain(
+        state: &mut ChainState,
+        tip_height: u64,
+    ) -> (Vec<Block>, JobPacket, SolutionCandidate, JobPacket) {
+        let proposer = Keypair::generate();
+        let requester = Keypair::generate();
+        let genesis = Block::genesis(*proposer.public_key());
+        state.apply_block(genesis.clone()).unwrap();
+
+        let settled = job(&requester, "Settled");
+        let open = job(&requester, "Still open");
+        let solution = SolutionCandidate::new(settled.id, *Keypair::generate().public_key(), b"output".to_vec());
+        state
+            .get_or_create_account(&settled.requester_address)
+            .credit(HclawAmount::from_hclaw(1000));
+        state.store_job(settled.clone()).unwrap();
+        state.store_job(open.clone()).unwrap();
+        let rejected = SolutionCandidate::new(open.id, *Keypair::generate().public_key(), b"wrong".to_vec());
+
+        let mut blocks = vec![genesis];
+        let results = vec![
+            signed_result(&proposer, &solution, true),
+            signed_result(&proposer, &rejected, false),
+        ];
+        let block = next_block(state, &proposer, results);
+        state.apply_block(block.clone()).unwrap();
+        blocks.push(block);
+        while state.height() <= tip_height {
+            let block = next_block(state, &proposer, Vec::new());
+            state.apply_block(block.clone()).unwrap();
+            blocks.push(block);
+        }
+        (blocks, settled, solution, open)
+    }
+
+    #[test]
+    fn test_archive_keeps_everything() {
+        let mut state = ChainState::new();
+        let (blocks, settled, solution, _) = chain(&mut state, 6);
+
+        assert_eq!(state.retention(), RetentionMode::Archive);
+        assert_eq!(state.history_start(), 0);
+        assert!(blocks.iter().all(|b| state.get_block(&b.hash).is_some()));
+        assert!(state.get_job(&settled.id).is_some());
+        assert!(state.get_solution(&solution.id).is_some());
+        assert!(state.export_snapshot(1).is_ok());
+    }
+
+    #[test]
+    fn test_pruned_state_reloads() {
+        let dir = std::env::temp_dir().join(format!("hardclaw_test_pruned_{}", rand::random::<u64>()));
+        let (blocks, settled) = {
+            let mut state = ChainState::open(&dir).unwrap();
+            state.set_retention(RetentionMode::Pruned { keep_blocks: 2 });
+            let (blocks, settled, _, _) = chain(&mut state, 5);
+            (blocks, settled)
+        };
+
+        let reloaded = reopen(&dir);
+        assert_eq!(reloaded.retention(), RetentionMode::Archive);
+        assert_eq!(reloaded.history_start(), 4);
+        assert!(reloaded.get_block(&blocks[3].hash).is_none());
+        assert_eq!(reloaded.get_header_at_height(3).map(|h| h.height), Some(3));
+        assert!(reloaded.get_job(&settled.id).is_none());
+        assert_eq!(reloaded.compute_state_root(), blocks[5].header.state_root);
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_pruned_keeps_headers_and_open_jobs() {
+        let mut pruned = ChainState::new();
+        pruned.set_retention(RetentionMode::Pruned { keep_blocks: 3 });
+        let (blocks, settled, solution, open) = chain(&mut pruned, 6);
+
+        // Tip is height 6: bodies 4..=6 remain, headers for everything
+        assert_eq!(pruned.history_start(), 4);
+        assert!(pruned.get_block_at_height(3).is_none());
+        assert!(pruned.get_block_at_height(4).is_some());
+        for block in &blocks {
+            assert_eq!(pruned.get_header(&block.hash).map(BlockHeader::compute_hash), Some(block.hash));
+            assert!(pruned.is_canonical(&block.hash));
+        }
+
+        // The settled job and its solution are gone; the open job and its
+        // rejected solution stay
+        assert!(pruned.get_job(&settled.id).is_none());
+        assert!(pruned.get_solution(&solution.id).is_none());
+        assert!(pruned.get_job(&open.id).is_some());
+        assert_eq!(pruned.solutions.len(), 1);
+
+        // Dropping records does not move the state root
+        assert_eq!(pruned.compute_state_root(), blocks[6].header.state_root);
+        pruned.rebuild_state_tree();
+        assert_eq!(pruned.compute_state_root(), blocks[6].header.state_root);
+
+        assert!(matches!(pruned.export_snapshot(3), Err(StateError::Pruned(3))));
+        assert!(pruned.export_snapshot(4).is_ok());
+    }
+}
@@ -0,0 +1,147 @@
+//! Double-signing slashing.
+//!
+//! Anyone can report `Evidence` that a verifier signed two blocks in one
+//! height and round in a `ReportEvidence` transaction. Applying it runs the
+//! offender's stake through `StakeManager::slash`: the slashed amount is
+//! burned, and the account is tombstoned, which keeps it out of the proposer
+//! schedule and the validator set for good from the next epoch. Each
+//! offender can be slashed only once.
+
+use crate::types::{Address, Evidence};
+use crate::verifier::SlashingReason;
+
+use super::execution::BlockUndo;
+use super::{ChainState, StateError};
+
+impl ChainState {
+    /// Slash the verifier that `evidence` convicts
+    ///
+    /// The evidence itself is checked with the transaction that carries it.
+    pub(super) fn apply_evidence(
+        &mut self,
+        evidence: &Evidence,
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        let address = Address::from_public_key(evidence.offender());
+        if self.accounts.get(&address).is_some_and(|account| account.tombstoned) {
+            return Err(StateError::InvalidEvidence("offender was already slashed".to_string()));
+        }
+
+        let (block_hash_1, block_hash_2) = evidence.block_hashes();
+        let slashed = self
+            .stake_manager()
+            .slash(&address, SlashingReason::DoubleSigning { block_hash_1, block_hash_2 })
+            .map_err(|_| StateError::InvalidEvidence("offender is not a verifier".to_string()))?;
+
+        let offender = self.journal_account(undo, &address);
+        offender.staked = offender.staked.saturating_sub(slashed);
+        offender.balance = offender.balance.saturating_sub(slashed);
+        offender.tombstoned = true;
+        self.total_burned = self.total_burned.saturating_add(slashed);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{hash_data, Keypair};
+    use crate::state::tests::{attest, draft, seal};
+    use crate::types::{
+        Block, HclawAmount, SignedProposal, Transaction, TransactionKind,
+    };
+
+    /// Genesis and two verifiers staking the minimum
+    fn staked_chain(state: &mut ChainState) -> Vec<Keypair> {
+        let keys: Vec<_> = (0..2).map(|_| Keypair::generate()).collect();
+        state.apply_block(Block::genesis(*keys[0].public_key())).unwrap();
+        for kp in &keys {
+            let account = state.get_or_create_account(&Address::from_public_key(kp.public_key()));
+            account.credit(HclawAmount::from_hclaw(1500));
+            account.staked = HclawAmount::from_hclaw(1000);
+        }
+        keys
+    }
+
+    /// Build the next block, proposed by whichever of `keys` leads
+    fn next_block(state: &mut ChainState, keys: &[Keypair], transactions: Vec<Transaction>) -> Block {
+        let parent = state.tip().unwrap().hash;
+        let leader = state.proposer_schedule().leader(&parent, state.height()).unwrap();
+        let proposer = keys
+            .iter()
+            .find(|kp| Address::from_public_key(kp.public_key()) == leader)
+            .unwrap();
+        let block = draft(state, proposer, transactions, Vec::new());
+        let mut block = seal(state, proposer, block);
+        let validators = state.validator_set();
+        for kp in keys.iter().filter(|kp| validators.stake_of(kp.public_key()).is_some()) {
+            attest(&mut block, kp);
+        }
+        block
+    }
+
+    fn double_proposal(kp: &Keypair, height: u64) -> Evidence {
+        let sign = |parent: &[u8]| {
+            let mut block = Block::new(height, hash_data(parent), *kp.public_key(), Vec::new(), hash_data(b"root"));
+            block.proposer_signature = kp.sign(&block.signing_bytes());
+            SignedProposal::from_block(&block)
+        };
+        Evidence::DoubleProposal { first: sign(b"a"), second: sign(b"b") }
+    }
+
+    fn report(reporter: &Keypair, nonce: u64, evidence: Evidence) -> Transaction {
+        let mut tx = Transaction::new(*reporter.public_key(), nonce, TransactionKind::ReportEvidence(Box::new(evidence)));
+        tx.signature = reporter.sign(&tx.signing_bytes());
+        tx
+    }
+
+    #[test]
+    fn test_evidence_slashes_and_tombstones() {
+        let mut state = ChainState::new();
+        let keys = staked_chain(&mut state);
+        let offender = Address::from_public_key(keys[1].public_key());
+        let reporter = Keypair::generate();
+        assert_eq!(state.validator_set().len(), 2);
+
+        let burned = state.total_burned();
+        let block = next_block(&mut state, &keys, vec![report(&reporter, 0, double_proposal(&keys[1], 1))]);
+        state.import_block(block).unwrap();
+
+        let account = state.get_account(&offender).unwrap();
+        assert!(account.tombstoned);
+        assert_eq!(account.staked, HclawAmount::ZERO);
+        assert_eq!(account.balance, HclawAmount::from_hclaw(500));
+        assert_eq!(state.total_burned(), burned.saturating_add(HclawAmount::from_hclaw(1000)));
+        assert_eq!(state.validator_set().len(), 1);
+        assert!(state.validator_set().stake_of(keys[1].public_key()).is_none());
+
+        // The same offence, or another one, cannot slash twice
+        let again = report(&reporter, 1, double_proposal(&keys[1], 2));
+        assert!(matches!(
+            state.state_root_after(&draft(&state, &keys[0], vec![again], Vec::new())),
+            Err(StateError::InvalidEvidence(_))
+        ));
+
+        // Nor can the offender stake back in
+        let mut restake = Transaction::new(
+            *keys[1].public_key(),
+            0,
+            TransactionKind::Stake { amount: HclawAmount::from_hclaw(100) },
+        );
+        restake.signature = keys[1].sign(&restake.signing_bytes());
+        assert!(state.state_root_after(&draft(&state, &keys[0], vec![restake], Vec::new())).is_err());
+    }
+
+    #[test]
+    fn test_evidence_needs_a_staked_offender() {
+        let mut state = ChainState::new();
+        let keys = staked_chain(&mut state);
+        let outsider = double_proposal(&Keypair::generate(), 1);
+
+        assert!(matches!(
+            state.state_root_after(&draft(&state, &keys[0], vec![report(&Keypair::generate(), 0, outsider)], Vec::new())),
+            Err(StateError::InvalidEvidence(_))
+        ));
+    }
+}
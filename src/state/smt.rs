@@ -0,0 +1,345 @@
+//! Sparse Merkle tree over 256-bit keys.
+//!
+//! A subtree holding a single leaf collapses into that leaf, so a tree with
+//! n leaves has O(n) nodes and paths of O(log n) depth on average. The empty
+//! subtree hashes to `Hash::ZERO`.
+//!
+//! Nodes are shared behind `Arc`: an update copies only the path it touches,
+//! which keeps clones cheap enough to compute a prospective root without
+//! mutating the original tree.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{Hash, Hasher};
+
+/// Domain tag for leaf hashes
+const LEAF_DOMAIN: u8 = 0;
+/// Domain tag for internal node hashes
+const NODE_DOMAIN: u8 = 1;
+/// Key length in bits (and so the maximum depth)
+const KEY_BITS: usize = 256;
+
+type Link = Option<Arc<Node>>;
+
+#[derive(Debug)]
+enum Node {
+    Leaf {
+        key: Hash,
+        value: Hash,
+        hash: Hash,
+    },
+    Internal {
+        left: Link,
+        right: Link,
+        hash: Hash,
+    },
+}
+
+impl Node {
+    fn leaf(key: Hash, value: Hash) -> Arc<Self> {
+        Arc::new(Self::Leaf {
+            key,
+            value,
+            hash: leaf_hash(&key, &value),
+        })
+    }
+
+    fn internal(left: Link, right: Link) -> Arc<Self> {
+        let hash = node_hash(&link_hash(&left), &link_hash(&right));
+        Arc::new(Self::Internal { left, right, hash })
+    }
+
+    const fn hash(&self) -> &Hash {
+        match self {
+            Self::Leaf { hash, .. } | Self::Internal { hash, .. } => hash,
+        }
+    }
+}
+
+/// Authenticated key-value map
+#[derive(Clone, Debug, Default)]
+pub struct SparseMerkleTree {
+    root: Link,
+}
+
+impl SparseMerkleTree {
+    /// Create an empty tree
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { root: None }
+    }
+
+    /// Root hash
+    #[must_use]
+    pub fn root(&self) -> Hash {
+        link_hash(&self.root)
+    }
+
+    /// Value stored under a key
+    #[must_use]
+    pub fn get(&self, key: &Hash) -> Option<Hash> {
+        let mut link = &self.root;
+        let mut depth = 0;
+        loop {
+            match link.as_deref()? {
+                Node::Leaf { key: k, value, .. } => return (k == key).then_some(*value),
+                Node::Internal { left, right, .. } => {
+                    link = if bit(key, depth) { right } else { left };
+                    depth += 1;
+                }
+            }
+        }
+    }
+
+    /// Insert or replace a value
+    pub fn insert(&mut self, key: Hash, value: Hash) {
+        self.root = Some(insert_at(&self.root, key, value, 0));
+    }
+
+    /// Remove a key (no-op if absent)
+    pub fn remove(&mut self, key: &Hash) {
+        self.root = remove_at(&self.root, key, 0);
+    }
+
+    /// Prove the presence or absence of a key
+    #[must_use]
+    pub fn prove(&self, key: &Hash) -> SparseMerkleProof {
+        let mut siblings = Vec::new();
+        let mut link = &self.root;
+        let mut depth = 0;
+        loop {
+            match link.as_deref() {
+                None => {
+                    return SparseMerkleProof {
+                        siblings,
+                        other_leaf: None,
+                    }
+                }
+                Some(Node::Leaf { key: k, value, .. }) => {
+                    return SparseMerkleProof {
+                        siblings,
+                        other_leaf: (k != key).then_some((*k, *value)),
+                    }
+                }
+                Some(Node::Internal { left, right, .. }) => {
+                    let (next, sibling) = if bit(key, depth) {
+                        (right, left)
+                    } else {
+                        (left, right)
+                    };
+                    siblings.push(link_hash(sibling));
+                    link = next;
+                    depth += 1;
+                }
+            }
+        }
+    }
+}
+
+/// Merkle path for one key
+///
+/// Proves inclusion when checked with the key's value, or absence when
+/// checked with `None` (the path then ends in an empty subtree or in a
+/// leaf for a different key).
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SparseMerkleProof {
+    /// Sibling hashes from the root down to the leaf
+    pub siblings: Vec<Hash>,
+    /// Leaf found at the end of the path when it holds a different key
+    pub other_leaf: Option<(Hash, Hash)>,
+}
+
+impl SparseMerkleProof {
+    /// Check that `key` maps to `value` (or is absent, for `None`) under `root`
+    #[must_use]
+    pub fn verify(&self, root: &Hash, key: &Hash, value: Option<&Hash>) -> bool {
+        if self.siblings.len() > KEY_BITS {
+            return false;
+        }
+
+        let leaf = match (value, &self.other_leaf) {
+            (Some(value), None) => leaf_hash(key, value),
+            (None, None) => Hash::ZERO,
+            (None, Some((other, other_value))) => {
+                // The other leaf must sit exactly where `key` would
+                let same_path = (0..self.siblings.len()).all(|d| bit(other, d) == bit(key, d));
+                if other == key || !same_path {
+                    return false;
+                }
+                leaf_hash(other, other_value)
+            }
+            (Some(_), Some(_)) => return false,
+        };
+
+        let computed = self
+            .siblings
+            .iter()
+            .enumerate()
+            .rev()
+            .fold(leaf, |acc, (depth, sibling)| {
+                if bit(key, depth) {
+                    node_hash(sibling, &acc)
+                } else {
+                    node_hash(&acc, sibling)
+                }
+            });
+
+        computed == *root
+    }
+}
+
+fn insert_at(link: &Link, key: Hash, value: Hash, depth: usize) -> Arc<Node> {
+    let Some(node) = link else {
+        return Node::leaf(key, value);
+    };
+    match &**node {
+        Node::Leaf { key: existing, .. } if *existing == key => Node::leaf(key, value),
+        Node::Leaf { .. } => split(Arc::clone(node), Node::leaf(key, value), depth),
+        Node::Internal { left, right, .. } => {
+            if bit(&key, depth) {
+                Node::internal(left.clone(), Some(insert_at(right, key, value, depth + 1)))
+            } else {
+                Node::internal(Some(insert_at(left, key, value, depth + 1)), right.clone())
+            }
+        }
+    }
+}
+
+/// Build the smallest subtree holding two leaves with distinct keys
+fn split(existing: Arc<Node>, new: Arc<Node>, depth: usize) -> Arc<Node> {
+    let (Node::Leaf { key: a, .. }, Node::Leaf { key: b, .. }) = (&*existing, &*new) else {
+        unreachable!("split is only called with leaves");
+    };
+    let (existing_bit, new_bit) = (bit(a, depth), bit(b, depth));
+
+    if existing_bit == new_bit {
+        let child = Some(split(existing, new, depth + 1));
+        if new_bit {
+            Node::internal(None, child)
+        } else {
+            Node::internal(child, None)
+        }
+    } else if new_bit {
+        Node::internal(Some(existing), Some(new))
+    } else {
+        Node::internal(Some(new), Some(existing))
+    }
+}
+
+fn remove_at(link: &Link, key: &Hash, depth: usize) -> Link {
+    match link.as_deref() {
+        None => None,
+        Some(Node::Leaf { key: existing, .. }) => {
+            if existing == key {
+                None
+            } else {
+                link.clone()
+            }
+        }
+        Some(Node::Internal { left, right, .. }) => {
+            let (left, right) = if bit(key, depth) {
+                (left.clone(), remove_at(right, key, depth + 1))
+            } else {
+                (remove_at(left, key, depth + 1), right.clone())
+            };
+            collapse(left, right)
+        }
+    }
+}
+
+/// Rebuild an internal node, collapsing it if it now holds a single leaf
+fn collapse(left: Link, right: Link) -> Link {
+    match (left, right) {
+        (None, None) => None,
+        (Some(only), None) | (None, Some(only)) if matches!(*only, Node::Leaf { .. }) => Some(only),
+        (left, right) => Some(Node::internal(left, right)),
+    }
+}
+
+fn link_hash(link: &Link) -> Hash {
+    link.as_deref().map_or(Hash::ZERO, |node| *node.hash())
+}
+
+fn leaf_hash(key: &Hash, value: &Hash) -> Hash {
+    let mut hasher = Hasher::new();
+    hasher.update(&[LEAF_DOMAIN]).update(key.as_bytes()).update(value.as_bytes());
+    hasher.finalize()
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Hasher::new();
+    hasher.update(&[NODE_DOMAIN]).update(left.as_bytes()).update(right.as_bytes());
+    hasher.finalize()
+}
+
+/// Bit of `key` at `depth`, most significant first
+const fn bit(key: &Hash, depth: usize) -> bool {
+    (key.as_bytes()[depth / 8] >> (7 - depth % 8)) & 1 == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::hash_data;
+
+    fn key(n: u32) -> Hash {
+        hash_data(&n.to_le_bytes())
+    }
+
+    #[test]
+    fn test_root_is_order_independent() {
+        let mut a = SparseMerkleTree::new();
+        let mut b = SparseMerkleTree::new();
+        for n in 0..50 {
+            a.insert(key(n), key(n + 1000));
+        }
+        for n in (0..50).rev() {
+            b.insert(key(n), key(n + 1000));
+        }
+        assert_eq!(a.root(), b.root());
+        assert_eq!(a.get(&key(7)), Some(key(1007)));
+
+        // Removing everything returns to the empty root
+        for n in 0..50 {
+            a.remove(&key(n));
+        }
+        assert_eq!(a.root(), Hash::ZERO);
+    }
+
+    #[test]
+    fn test_remove_restores_previous_root() {
+        let mut tree = SparseMerkleTree::new();
+        for n in 0..20 {
+            tree.insert(key(n), key(n));
+        }
+        let before = tree.root();
+        let snapshot = tree.clone();
+
+        tree.insert(key(99), key(99));
+        assert_ne!(tree.root(), before);
+        assert_eq!(snapshot.root(), before);
+
+        tree.remove(&key(99));
+        assert_eq!(tree.root(), before);
+    }
+
+    #[test]
+    fn test_inclusion_and_absence_proofs() {
+        let mut tree = SparseMerkleTree::new();
+        for n in 0..30 {
+            tree.insert(key(n), key(n + 1));
+        }
+        let root = tree.root();
+
+        let proof = tree.prove(&key(3));
+        assert!(proof.verify(&root, &key(3), Some(&key(4))));
+        assert!(!proof.verify(&root, &key(3), Some(&key(5))));
+        assert!(!proof.verify(&root, &key(3), None));
+
+        let absent = tree.prove(&key(500));
+        assert!(absent.verify(&root, &key(500), None));
+        assert!(!absent.verify(&root, &key(500), Some(&key(1))));
+    }
+}
@@ -0,0 +1,402 @@
+//! State snapshots for fast sync.
+//!
+//! A snapshot holds every record the state root commits to as of one
+//! canonical block, plus that block so later blocks can attach to it. A
+//! fresh node checks the block against a header it trusts, checks the
+//! records against the header's `state_root`, and then only needs the
+//! blocks that follow.
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::Hash;
+use crate::types::{Address, Block, BlockHeader, HclawAmount, JobPacket, SolutionCandidate};
+
+use super::commitment::build_tree;
+use super::execution::StateRecords;
+use super::tree::ChainUpdate;
+use super::{AccountState, ChainState, Epoch, ModuleRecord, StateError};
+
+/// Current snapshot format version
+pub const SNAPSHOT_VERSION: u32 = 13;
+
+/// Full state as of one block
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    /// Format version
+    pub version: u32,
+    /// Block the snapshot was taken at
+    pub block: Block,
+    /// Accounts, including stakes
+    pub accounts: Vec<(Address, AccountState)>,
+    /// Jobs; closed jobs are included because the state root commits to them
+    pub jobs: Vec<JobPacket>,
+    /// Solutions
+    pub solutions: Vec<SolutionCandidate>,
+    /// Published module records by module hash
+    pub modules: Vec<(Hash, ModuleRecord)>,
+    /// Leaves of closed jobs and solutions a pruned node no longer holds
+    pub retired: Vec<(Hash, Hash)>,
+    /// Total burned as of the block
+    pub total_burned: HclawAmount,
+    /// Validator set of the epoch the block is in
+    pub epoch: Epoch,
+}
+
+impl StateSnapshot {
+    /// Height of the snapshot block
+    #[must_use]
+    pub const fn height(&self) -> u64 {
+        self.block.header.height
+    }
+
+    /// Hash of the snapshot block
+    #[must_use]
+    pub const fn block_hash(&self) -> Hash {
+        self.block.hash
+    }
+
+    /// State root the records must hash to
+    #[must_use]
+    pub const fn state_root(&self) -> Hash {
+        self.block.header.state_root
+    }
+
+    /// Check the snapshot against a trusted block header
+    ///
+    /// # Errors
+    /// Returns error if the version is unsupported, the block is not the
+    /// trusted one, or the records do not hash to its state root
+    pub fn verify(&self, trusted: &BlockHeader) -> Result<(), StateError> {
+        if self.version != SNAPSHOT_VERSION {
+            return Err(StateError::UnsupportedSnapshotVersion(self.version));
+        }
+
+        let trusted_hash = trusted.compute_hash();
+        if self.block.header.compute_hash() != self.block.hash || self.block.hash != trusted_hash {
+            return Err(StateError::InvalidSnapshot(format!(
+                "block {} does not match trusted header {trusted_hash}",
+                self.block.hash
+            )));
+        }
+
+        let records = self.records();
+        let root = build_tree(
+            &records.accounts,
+            &records.jobs,
+            &records.solutions,
+            &records.modules,
+            &records.retired,
+            &records.epoch,
+            records.total_burned,
+        )
+        .root();
+        if root != trusted.state_root {
+            return Err(StateError::InvalidSnapshot(format!(
+                "records hash to {root}, header commits to {}",
+                trusted.state_root
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn records(&self) -> StateRecords {
+        StateRecords {
+            accounts: self.accounts.iter().cloned().collect(),
+            jobs: self.jobs.iter().map(|j| (j.id, j.clone())).collect(),
+            solutions: self.solutions.iter().map(|s| (s.id, s.clone())).collect(),
+            modules: self.modules.iter().cloned().collect(),
+            retired: self.retired.iter().copied().collect(),
+            total_burned: self.total_burned,
+            epoch: self.epoch.clone(),
+        }
+    }
+}
+
+impl ChainState {
+    /// Export a snapshot at a canonical height
+    ///
+    /// Later blocks are rolled back on a copy of the records using their
+    /// undo journals, so the live state is not touched.
+    ///
+    /// # Errors
+    /// Returns error if `height` is below `history_start`, there is no
+    /// canonical block at it, an undo journal is missing, or state changed
+    /// outside blocks since `height` (the records would not match the
+    /// block's state root)
+    pub fn export_snapshot(&self, height: u64) -> Result<StateSnapshot, StateError> {
+        if height < self.history_start {
+            return Err(StateError::Pruned(height));
+        }
+        let block = self
+            .get_block_at_height(height)
+            .ok_or(StateError::BlockNotFound)?
+            .clone();
+
+        let mut records = StateRecords {
+            accounts: self.accounts.clone(),
+            jobs: self.jobs.clone(),
+            solutions: self.solutions.clone(),
+            modules: self.modules.clone(),
+            retired: self.retired.clone(),
+            total_burned: self.total_burned,
+            epoch: self.epoch.clone(),
+        };
+        for later in (height + 1..self.height).rev() {
+            let hash = self.height_index.get(&later).ok_or(StateError::BlockNotFound)?;
+            let undo = self.undo_logs.get(hash).ok_or(StateError::MissingUndo)?;
+            undo.restore_into(&mut records);
+        }
+
+        let root = build_tree(
+            &records.accounts,
+            &records.jobs,
+            &records.solutions,
+            &records.modules,
+            &records.retired,
+            &records.epoch,
+            records.total_burned,
+        )
+        .root();
+        if root != block.header.state_root {
+            return Err(StateError::InvalidSnapshot(format!(
+                "state at height {height} does not match its block; it changed outside blocks"
+            )));
+        }
+
+        Ok(StateSnapshot {
+            version: SNAPSHOT_VERSION,
+            block,
+            accounts: records.accounts.into_iter().collect(),
+            jobs: records.jobs.into_values().collect(),
+            solutions: records.solutions.into_values().collect(),
+            modules: records.modules.into_iter().collect(),
+            retired: records.retired.into_iter().collect(),
+            total_burned: records.total_burned,
+            epoch: records.epoch,
+        })
+    }
+
+    /// Replace an empty state with a verified snapshot
+    ///
+    /// The snapshot block becomes the canonical tip and the start of this
+    /// node's history; blocks after it can then be imported as usual. It has
+    /// no undo journal, so the chain can never be reorganised below it.
+    ///
+    /// # Errors
+    /// Returns error if this state already has blocks, the snapshot fails
+    /// verification against `trusted`, or it cannot be persisted
+    pub fn import_snapshot(
+        &mut self,
+        snapshot: StateSnapshot,
+        trusted: &BlockHeader,
+    ) -> Result<(), StateError> {
+        if self.tip.is_some() || !self.blocks.is_empty() {
+            return Err(StateError::StateNotEmpty);
+        }
+        snapshot.verify(trusted)?;
+
+        let records = snapshot.records();
+        let hash = snapshot.block.hash;
+        let height = snapshot.block.header.height;
+
+        self.dirty_accounts.extend(records.accounts.keys().copied());
+        self.dirty_jobs.extend(records.jobs.keys().copied());
+        self.dirty_solutions.extend(records.solutions.keys().copied());
+        self.dirty_modules.extend(records.modules.keys().copied());
+        self.accounts = records.accounts;
+        self.jobs = records.jobs;
+        self.solutions = records.solutions;
+        self.modules = records.modules;
+        self.retired = records.retired;
+        self.total_burned = records.total_burned;
+        self.epoch = records.epoch;
+        self.heads.insert(hash);
+        self.blocks.insert(hash, snapshot.block);
+        self.height_index.insert(height, hash);
+        self.tip = Some(hash);
+        self.height = height + 1;
+        self.history_start = height;
+
+        // Trusted, so nothing before or at it is ever up for debate
+        let update = ChainUpdate {
+            stored: vec![hash],
+            connected: vec![hash],
+            retired: self.retired.keys().copied().collect(),
+            finalized: Some(height),
+            ..ChainUpdate::default()
+        };
+        if let Err(e) = self.persist(&update) {
+            self.reset();
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    /// Drop all in-memory chain data (the store and configuration are kept)
+    pub(super) fn reset(&mut self) {
+        let fresh = Self {
+            store: self.store.take(),
+            retention: self.retention,
+            fee_distributor: self.fee_distributor.clone(),
+            ..Self::new()
+        };
+        *self = fresh;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{hash_data, Keypair};
+    use crate::state::tests::{draft, reopen, seal, signed_result};
+    use crate::state::ImportOutcome;
+    use crate::types::{JobStatus, JobType, VerificationResult, VerificationSpec};
+
+    fn next_block(state: &mut ChainState, proposer: &Keypair, settled: Vec<(VerificationResult, SolutionCandidate)>) -> Block {
+        let block = draft(state, proposer, Vec::new(), settled);
+        seal(state, proposer, block)
+    }
+
+    /// Chain of four blocks; the job is opened before block 1 and settled in block 2
+    fn chain() -> (ChainState, Vec<Block>, JobPacket) {
+        let proposer = Keypair::generate();
+        let mut state = ChainState::new();
+        let genesis = Block::genesis(*proposer.public_key());
+        state.apply_block(genesis.clone()).unwrap();
+
+        let requester = Keypair::generate();
+        let job = JobPacket::new(
+            JobType::Deterministic,
+            *requester.public_key(),
+            b"input".to_vec(),
+            "Test".to_string(),
+            HclawAmount::from_hclaw(100),
+            HclawAmount::from_hclaw(1),
+            VerificationSpec::HashMatch { expected_hash: hash_data(b"output") },
+            3600,
+        );
+        let solution = SolutionCandidate::new(job.id, *Keypair::generate().public_key(), b"output".to_vec());
+        state
+            .get_or_create_account(&job.requester_address)
+            .credit(HclawAmount::from_hclaw(1000));
+        state.store_job(job.clone()).unwrap();
+
+        let mut blocks = vec![genesis];
+        let result = signed_result(&proposer, &solution, true);
+        for results in [Vec::new(), vec![result], Vec::new()] {
+            let block = next_block(&mut state, &proposer, results);
+            state.apply_block(block.clone()).unwrap();
+            blocks.push(block);
+        }
+        (state, blocks, job)
+    }
+
+    #[test]
+    fn test_snapshot_fast_sync() {
+        let (source, blocks, job) = chain();
+
+        // Taken below the tip: blocks 2 and 3 are rolled back on a copy
+        let snapshot = source.export_snapshot(1).unwrap();
+        assert_eq!(snapshot.block_hash(), blocks[1].hash);
+        assert_eq!(source.get_job(&job.id).unwrap().status, JobStatus::Completed);
+
+        let mut fresh = ChainState::new();
+        fresh.import_snapshot(snapshot, &blocks[1].header).unwrap();
+        assert_eq!(fresh.height(), 2);
+        assert_eq!(fresh.compute_state_root(), blocks[1].header.state_root);
+        assert_ne!(fresh.get_job(&job.id).unwrap().status, JobStatus::Completed);
+
+        // Only the later blocks are needed
+        for block in &blocks[2..] {
+            assert_eq!(fresh.import_block(block.clone()).unwrap(), ImportOutcome::Extended);
+        }
+        assert_eq!(fresh.tip().map(|t| t.hash), source.tip().map(|t| t.hash));
+        assert_eq!(fresh.compute_state_root(), source.compute_state_root());
+        assert_eq!(fresh.total_burned(), source.total_burned());
+        assert_eq!(
+            fresh.balance_of(&job.requester_address),
+            source.balance_of(&job.requester_address)
+        );
+    }
+
+    #[test]
+    fn test_snapshot_rejects_tampering() {
+        let (source, blocks, _) = chain();
+        let snapshot = source.export_snapshot(3).unwrap();
+
+        let mut forged = snapshot.clone();
+        for (_, account) in &mut forged.accounts {
+            account.balance = HclawAmount::from_hclaw(1_000_000);
+        }
+        assert!(matches!(
+            forged.verify(&blocks[3].header),
+            Err(StateError::InvalidSnapshot(_))
+        ));
+
+        // An importer given it is left as it was
+        let mut fresh = ChainState::new();
+        let empty_root = fresh.compute_state_root();
+        assert!(matches!(
+            fresh.import_snapshot(forged, &blocks[3].header),
+            Err(StateError::InvalidSnapshot(_))
+        ));
+        assert!(fresh.tip().is_none());
+        assert_eq!(fresh.height(), 0);
+        assert_eq!(fresh.known_block_count(), 0);
+        assert_eq!(fresh.compute_state_root(), empty_root);
+
+        // The burned total is committed too, not taken on trust
+        let mut inflated = snapshot.clone();
+        inflated.total_burned = inflated.total_burned.saturating_add(HclawAmount::from_hclaw(1));
+        assert!(matches!(
+            inflated.verify(&blocks[3].header),
+            Err(StateError::InvalidSnapshot(_))
+        ));
+
+        // Not the header the node trusts
+        assert!(matches!(
+            snapshot.verify(&blocks[2].header),
+            Err(StateError::InvalidSnapshot(_))
+        ));
+
+        let mut future = snapshot.clone();
+        future.version = SNAPSHOT_VERSION + 1;
+        assert!(matches!(
+            future.verify(&blocks[3].header),
+            Err(StateError::UnsupportedSnapshotVersion(_))
+        ));
+
+        // Only empty states can be replaced
+        let (mut busy, _, _) = chain();
+        let (tip, root) = (busy.tip().map(|t| t.hash), busy.compute_state_root());
+        assert!(matches!(
+            busy.import_snapshot(snapshot, &blocks[3].header),
+            Err(StateError::StateNotEmpty)
+        ));
+        assert_eq!(busy.tip().map(|t| t.hash), tip);
+        assert_eq!(busy.compute_state_root(), root);
+    }
+
+    #[test]
+    fn test_snapshot_import_persists() {
+        let dir = std::env::temp_dir().join(format!("hardclaw_test_snapshot_{}", rand::random::<u64>()));
+        let (source, blocks, _) = chain();
+        let snapshot = source.export_snapshot(2).unwrap();
+
+        {
+            let mut state = ChainState::open(&dir).unwrap();
+            state.import_snapshot(snapshot, &blocks[2].header).unwrap();
+        }
+
+        let mut reloaded = reopen(&dir);
+        assert_eq!(reloaded.height(), 3);
+        assert_eq!(reloaded.get_block_at_height(2).map(|b| b.hash), Some(blocks[2].hash));
+        assert_eq!(reloaded.compute_state_root(), blocks[2].header.state_root);
+        reloaded.import_block(blocks[3].clone()).unwrap();
+        assert_eq!(reloaded.compute_state_root(), source.compute_state_root());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
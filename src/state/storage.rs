@@ -0,0 +1,424 @@
+//! On-disk persistence for chain state.
+//!
+//! Backed by sled. Every record touched while applying a block (or a whole
+//! reorganisation) is written in a single atomic batch, so a crash can never
+//! leave a half-applied block on disk.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::crypto::Hash;
+use crate::types::{Address, Block, BlockHeader, HclawAmount, Id, JobPacket, SolutionCandidate};
+
+use super::{AccountState, BlockUndo, Epoch, ModuleRecord, StateError};
+
+/// Key prefix for blocks (by hash)
+const PREFIX_BLOCK: &[u8] = b"block/";
+/// Key prefix for the height index (big-endian height -> block hash)
+const PREFIX_HEIGHT: &[u8] = b"height/";
+/// Key prefix for accounts (by address)
+const PREFIX_ACCOUNT: &[u8] = b"account/";
+/// Key prefix for jobs (by ID)
+const PREFIX_JOB: &[u8] = b"job/";
+/// Key prefix for solutions (by ID)
+const PREFIX_SOLUTION: &[u8] = b"solution/";
+/// Key prefix for published module records (by module hash)
+const PREFIX_MODULE: &[u8] = b"module/";
+/// Key prefix for undo journals of canonical blocks (by block hash)
+const PREFIX_UNDO: &[u8] = b"undo/";
+/// Key prefix for headers of pruned canonical blocks (by block hash)
+const PREFIX_HEADER: &[u8] = b"header/";
+/// Key prefix for state tree leaves of pruned records (by tree key)
+const PREFIX_RETIRED: &[u8] = b"retired/";
+/// Key for the current chain tip
+const KEY_TIP: &[u8] = b"meta/tip";
+/// Key for the current chain height
+const KEY_HEIGHT: &[u8] = b"meta/height";
+/// Key for the running burn total
+const KEY_BURNED: &[u8] = b"meta/burned";
+/// Key for the lowest height whose block body is kept
+const KEY_HISTORY_START: &[u8] = b"meta/history_start";
+/// Key for the finalized height
+const KEY_FINALIZED: &[u8] = b"meta/finalized";
+/// Key for the validator set of the current epoch
+const KEY_EPOCH: &[u8] = b"meta/epoch";
+
+/// Everything needed to rebuild a `ChainState` after a restart
+#[derive(Default)]
+pub struct PersistedState {
+    /// Account states
+    pub accounts: HashMap<Address, AccountState>,
+    /// Blocks by hash (canonical and side branches)
+    pub blocks: HashMap<Hash, Block>,
+    /// Headers of pruned canonical blocks
+    pub headers: HashMap<Hash, BlockHeader>,
+    /// Lowest height whose block body is kept
+    pub history_start: u64,
+    /// Highest finalized height
+    pub finalized: u64,
+    /// Undo journals for canonical blocks
+    pub undo_logs: HashMap<Hash, BlockUndo>,
+    /// Canonical block hash by height
+    pub height_index: HashMap<u64, Hash>,
+    /// Current chain tip
+    pub tip: Option<Hash>,
+    /// Current height
+    pub height: u64,
+    /// Jobs by ID
+    pub jobs: HashMap<Id, JobPacket>,
+    /// Solutions by ID
+    pub solutions: HashMap<Id, SolutionCandidate>,
+    /// Published modules by hash
+    pub modules: HashMap<Hash, ModuleRecord>,
+    /// State tree leaves of pruned records, by tree key
+    pub retired: HashMap<Hash, Hash>,
+    /// Total burned by block execution
+    pub total_burned: HclawAmount,
+    /// Validator set of the current epoch
+    pub epoch: Epoch,
+}
+
+/// A set of writes that is committed atomically
+pub struct StoreBatch {
+    inner: sled::Batch,
+}
+
+impl StoreBatch {
+    /// Create an empty batch
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            inner: sled::Batch::default(),
+        }
+    }
+
+    /// Store a block
+    ///
+    /// # Errors
+    /// Returns error if the block cannot be serialized
+    pub fn put_block(&mut self, block: &Block) -> Result<(), StateError> {
+        self.put(&prefixed(PREFIX_BLOCK, block.hash.as_bytes()), block)
+    }
+
+    /// Delete a block
+    pub fn remove_block(&mut self, hash: &Hash) {
+        self.inner.remove(prefixed(PREFIX_BLOCK, hash.as_bytes()));
+    }
+
+    /// Index a block as canonical at a height
+    pub fn put_height(&mut self, height: u64, hash: &Hash) {
+        self.inner.insert(
+            prefixed(PREFIX_HEIGHT, &height.to_be_bytes()),
+            hash.as_bytes().to_vec(),
+        );
+    }
+
+    /// Drop the canonical index entry for a height
+    pub fn remove_height(&mut self, height: u64) {
+        self.inner.remove(prefixed(PREFIX_HEIGHT, &height.to_be_bytes()));
+    }
+
+    /// Store the undo journal of a canonical block
+    ///
+    /// # Errors
+    /// Returns error if the journal cannot be serialized
+    pub fn put_undo(&mut self, hash: &Hash, undo: &BlockUndo) -> Result<(), StateError> {
+        self.put(&prefixed(PREFIX_UNDO, hash.as_bytes()), undo)
+    }
+
+    /// Delete the undo journal of a block
+    pub fn remove_undo(&mut self, hash: &Hash) {
+        self.inner.remove(prefixed(PREFIX_UNDO, hash.as_bytes()));
+    }
+
+    /// Store the header of a block whose body is pruned
+    ///
+    /// # Errors
+    /// Returns error if the header cannot be serialized
+    pub fn put_header(&mut self, hash: &Hash, header: &BlockHeader) -> Result<(), StateError> {
+        self.put(&prefixed(PREFIX_HEADER, hash.as_bytes()), header)
+    }
+
+    /// Store the state tree leaf of a pruned record
+    pub fn put_retired(&mut self, key: &Hash, leaf: &Hash) {
+        self.inner
+            .insert(prefixed(PREFIX_RETIRED, key.as_bytes()), leaf.as_bytes().to_vec());
+    }
+
+    /// Record the lowest height whose block body is kept
+    pub fn put_history_start(&mut self, height: u64) {
+        self.inner.insert(KEY_HISTORY_START, height.to_be_bytes().to_vec());
+    }
+
+    /// Store an account
+    ///
+    /// # Errors
+    /// Returns error if the account cannot be serialized
+    pub fn put_account(&mut self, address: &Address, account: &AccountState) -> Result<(), StateError> {
+        self.put(&prefixed(PREFIX_ACCOUNT, address.as_bytes()), account)
+    }
+
+    /// Delete an account
+    pub fn remove_account(&mut self, address: &Address) {
+        self.inner.remove(prefixed(PREFIX_ACCOUNT, address.as_bytes()));
+    }
+
+    /// Store a job
+    ///
+    /// # Errors
+    /// Returns error if the job cannot be serialized
+    pub fn put_job(&mut self, job: &JobPacket) -> Result<(), StateError> {
+        self.put(&prefixed(PREFIX_JOB, job.id.as_bytes()), job)
+    }
+
+    /// Delete a job
+    pub fn remove_job(&mut self, id: &Id) {
+        self.inner.remove(prefixed(PREFIX_JOB, id.as_bytes()));
+    }
+
+    /// Store a solution
+    ///
+    /// # Errors
+    /// Returns error if the solution cannot be serialized
+    pub fn put_solution(&mut self, solution: &SolutionCandidate) -> Result<(), StateError> {
+        self.put(&prefixed(PREFIX_SOLUTION, solution.id.as_bytes()), solution)
+    }
+
+    /// Delete a solution
+    pub fn remove_solution(&mut self, id: &Id) {
+        self.inner.remove(prefixed(PREFIX_SOLUTION, id.as_bytes()));
+    }
+
+    /// Store a published module's record
+    ///
+    /// # Errors
+    /// Returns error if the record cannot be serialized
+    pub fn put_module(&mut self, hash: &Hash, module: &ModuleRecord) -> Result<(), StateError> {
+        self.put(&prefixed(PREFIX_MODULE, hash.as_bytes()), module)
+    }
+
+    /// Delete a module's record
+    pub fn remove_module(&mut self, hash: &Hash) {
+        self.inner.remove(prefixed(PREFIX_MODULE, hash.as_bytes()));
+    }
+
+    /// Record the finalized height
+    pub fn put_finalized(&mut self, height: u64) {
+        self.inner.insert(KEY_FINALIZED, height.to_be_bytes().to_vec());
+    }
+
+    /// Record the chain tip and height
+    pub fn put_tip(&mut self, tip: &Hash, height: u64) {
+        self.inner.insert(KEY_TIP, tip.as_bytes().to_vec());
+        self.inner.insert(KEY_HEIGHT, height.to_be_bytes().to_vec());
+    }
+
+    /// Record the running burn total
+    pub fn put_total_burned(&mut self, total: HclawAmount) {
+        self.inner.insert(KEY_BURNED, total.raw().to_be_bytes().to_vec());
+    }
+
+    /// Record the validator set of the current epoch
+    ///
+    /// # Errors
+    /// Returns error if serialization fails
+    pub fn put_epoch(&mut self, epoch: &Epoch) -> Result<(), StateError> {
+        self.put(KEY_EPOCH, epoch)
+    }
+
+    fn put<T: Serialize>(&mut self, key: &[u8], value: &T) -> Result<(), StateError> {
+        let bytes = bincode::serialize(value)
+            .map_err(|e| StateError::Storage(e.to_string()))?;
+        self.inner.insert(key, bytes);
+        Ok(())
+    }
+}
+
+impl Default for StoreBatch {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Persistent chain store
+#[derive(Clone, Debug)]
+pub struct ChainStore {
+    db: sled::Db,
+}
+
+impl ChainStore {
+    /// Open (or create) a store at the given directory
+    ///
+    /// # Errors
+    /// Returns error if the database cannot be opened
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, StateError> {
+        let db = sled::open(path).map_err(|e| StateError::Storage(e.to_string()))?;
+        Ok(Self { db })
+    }
+
+    /// Open a private copy of the store at the given directory
+    ///
+    /// The store may be locked by a running node. Its files are copied as
+    /// they are on disk, which sled recovers like a crashed store, into a
+    /// temporary directory removed again when the copy is dropped; nothing
+    /// written to the copy reaches the original.
+    ///
+    /// # Errors
+    /// Returns error if there is no store at `path` or it cannot be copied
+    pub fn open_copy<P: AsRef<Path>>(path: P) -> Result<Self, StateError> {
+        let path = path.as_ref();
+        if !path.join("db").is_file() {
+            return Err(StateError::Storage(format!("no chain store at {}", path.display())));
+        }
+        let copy = std::env::temp_dir().join(format!("hardclaw_store_{}", rand::random::<u64>()));
+        copy_dir(path, &copy).map_err(|e| StateError::Storage(e.to_string()))?;
+        let db = sled::Config::new()
+            .path(&copy)
+            .temporary(true)
+            .open()
+            .map_err(|e| StateError::Storage(e.to_string()))?;
+        Ok(Self { db })
+    }
+
+    /// Atomically commit a batch and flush it to disk
+    ///
+    /// # Errors
+    /// Returns error if the write fails
+    pub fn commit(&self, batch: StoreBatch) -> Result<(), StateError> {
+        self.db
+            .apply_batch(batch.inner)
+            .map_err(|e| StateError::Storage(e.to_string()))?;
+        self.db
+            .flush()
+            .map_err(|e| StateError::Storage(e.to_string()))?;
+        Ok(())
+    }
+
+    /// Load everything previously committed
+    ///
+    /// # Errors
+    /// Returns error if the database is unreadable or holds corrupt records
+    pub fn load(&self) -> Result<PersistedState, StateError> {
+        let mut state = PersistedState::default();
+
+        for (key, block) in self.scan::<Block>(PREFIX_BLOCK)? {
+            state.blocks.insert(Hash::from_bytes(fixed_key(&key)?), block);
+        }
+
+        for (key, undo) in self.scan::<BlockUndo>(PREFIX_UNDO)? {
+            state.undo_logs.insert(Hash::from_bytes(fixed_key(&key)?), undo);
+        }
+
+        for (key, header) in self.scan::<BlockHeader>(PREFIX_HEADER)? {
+            state.headers.insert(Hash::from_bytes(fixed_key(&key)?), header);
+        }
+
+        for entry in self.db.scan_prefix(PREFIX_RETIRED) {
+            let (key, value) = entry.map_err(|e| StateError::Storage(e.to_string()))?;
+            state.retired.insert(
+                Hash::from_bytes(fixed_key(&key[PREFIX_RETIRED.len()..])?),
+                Hash::from_bytes(fixed_key(&value)?),
+            );
+        }
+
+        for entry in self.db.scan_prefix(PREFIX_HEIGHT) {
+            let (key, value) = entry.map_err(|e| StateError::Storage(e.to_string()))?;
+            let height = u64::from_be_bytes(fixed_key(&key[PREFIX_HEIGHT.len()..])?);
+            state.height_index.insert(height, Hash::from_bytes(fixed_key(&value)?));
+        }
+
+        for (key, account) in self.scan::<AccountState>(PREFIX_ACCOUNT)? {
+            state.accounts.insert(Address::from_bytes(fixed_key(&key)?), account);
+        }
+
+        for (_, job) in self.scan::<JobPacket>(PREFIX_JOB)? {
+            state.jobs.insert(job.id, job);
+        }
+
+        for (_, solution) in self.scan::<SolutionCandidate>(PREFIX_SOLUTION)? {
+            state.solutions.insert(solution.id, solution);
+        }
+
+        for (key, module) in self.scan::<ModuleRecord>(PREFIX_MODULE)? {
+            state.modules.insert(Hash::from_bytes(fixed_key(&key)?), module);
+        }
+
+        if let Some(tip) = self.get_raw(KEY_TIP)? {
+            state.tip = Some(Hash::from_bytes(fixed_key(&tip)?));
+        }
+
+        if let Some(height) = self.get_raw(KEY_HEIGHT)? {
+            state.height = u64::from_be_bytes(fixed_key(&height)?);
+        }
+
+        if let Some(start) = self.get_raw(KEY_HISTORY_START)? {
+            state.history_start = u64::from_be_bytes(fixed_key(&start)?);
+        }
+
+        if let Some(finalized) = self.get_raw(KEY_FINALIZED)? {
+            state.finalized = u64::from_be_bytes(fixed_key(&finalized)?);
+        }
+
+        if let Some(burned) = self.get_raw(KEY_BURNED)? {
+            state.total_burned = HclawAmount::from_raw(u128::from_be_bytes(fixed_key(&burned)?));
+        }
+
+        if let Some(epoch) = self.get_raw(KEY_EPOCH)? {
+            state.epoch = bincode::deserialize(&epoch)
+                .map_err(|e| StateError::Storage(e.to_string()))?;
+        }
+
+        Ok(state)
+    }
+
+    /// Decode every record under a prefix, returning (key without prefix, value)
+    fn scan<T: DeserializeOwned>(&self, prefix: &[u8]) -> Result<Vec<(Vec<u8>, T)>, StateError> {
+        self.db
+            .scan_prefix(prefix)
+            .map(|entry| {
+                let (key, value) = entry.map_err(|e| StateError::Storage(e.to_string()))?;
+                let decoded = bincode::deserialize(&value)
+                    .map_err(|e| StateError::Storage(e.to_string()))?;
+                Ok((key[prefix.len()..].to_vec(), decoded))
+            })
+            .collect()
+    }
+
+    fn get_raw(&self, key: &[u8]) -> Result<Option<sled::IVec>, StateError> {
+        self.db.get(key).map_err(|e| StateError::Storage(e.to_string()))
+    }
+}
+
+/// Build a prefixed key
+fn prefixed(prefix: &[u8], key: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(prefix.len() + key.len());
+    out.extend_from_slice(prefix);
+    out.extend_from_slice(key);
+    out
+}
+
+/// Convert a stored key or value into a fixed-size array
+fn fixed_key<const N: usize>(bytes: &[u8]) -> Result<[u8; N], StateError> {
+    bytes.try_into().map_err(|_| {
+        StateError::Storage(format!("corrupt record: expected {N} bytes, got {}", bytes.len()))
+    })
+}
+
+/// Copy a directory tree
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            fs::copy(entry.path(), target)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,604 @@
+//! Block tree and fork choice.
+//!
+//! Every block whose parent is known and whose proposer was in line for its
+//! slot is kept, whether or not it is on the canonical chain, up to
+//! `MAX_SIDE_BLOCKS_PER_HEIGHT` off the canonical chain at any one height. The canonical head is the leaf whose branch carries the
+//! most attesting stake; ties fall back to the number of unique attesters,
+//! then branch length, then the lower block hash so every node settles on
+//! the same head.
+//!
+//! Each block's attestations are counted against the validator set of its
+//! own parent state, so a branch is weighed by the stake it gives its
+//! validators rather than by the canonical chain's. A block is given that
+//! set when it is connected; until then it takes the set of the canonical
+//! block it competes with, or its parent's deeper in a side branch, which is
+//! exact within an epoch.
+//!
+//! Switching heads disconnects the abandoned blocks with their undo journals
+//! and executes the new branch block by block. A block that fails to execute
+//! is dropped together with its descendants. Once anyone is staked, finality
+//! trails the tip by one block (see `finality`), so a switch disconnects at
+//! most the tip; deeper switches only happen before then.
+
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+
+use crate::consensus::ValidatorSet;
+use crate::crypto::Hash;
+use crate::types::{now_millis, Address, Block, HclawAmount, VerifierAttestation};
+
+use super::{BlockUndo, ChainState, StateError};
+
+/// Most blocks kept off the canonical chain at any one height
+pub const MAX_SIDE_BLOCKS_PER_HEIGHT: usize = 8;
+
+/// Accumulated fork-choice weight of a run of blocks
+///
+/// Compared field by field, in declaration order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ChainWeight {
+    /// Stake of each block's unique attesters, summed over blocks
+    pub stake: HclawAmount,
+    /// Unique attesters, summed over blocks
+    pub attestations: u64,
+    /// Number of blocks
+    pub blocks: u64,
+}
+
+impl ChainWeight {
+    fn add(&mut self, other: Self) {
+        self.stake = self.stake.saturating_add(other.stake);
+        self.attestations += other.attestations;
+        self.blocks += other.blocks;
+    }
+}
+
+/// What importing a block or attestation did to the chain
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ImportOutcome {
+    /// Already known; nothing changed
+    Duplicate,
+    /// Recorded without moving the canonical head (side-branch block or
+    /// extra attestation)
+    Stored,
+    /// The canonical chain grew on top of its previous tip
+    Extended,
+    /// The canonical head moved to another branch
+    Reorganized {
+        /// Blocks removed from the canonical chain, old tip first
+        disconnected: Vec<Hash>,
+        /// Blocks added to the canonical chain, in order
+        connected: Vec<Hash>,
+    },
+}
+
+/// Tree and chain changes to be committed in one batch
+#[derive(Debug, Default)]
+pub struct ChainUpdate {
+    /// Blocks that are new or changed (e.g. gained an attestation)
+    pub stored: Vec<Hash>,
+    /// Blocks dropped from the tree
+    pub discarded: Vec<Hash>,
+    /// Blocks removed from the canonical chain, old tip first
+    pub disconnected: Vec<Hash>,
+    /// Blocks added to the canonical chain, in order
+    pub connected: Vec<Hash>,
+    /// Retired leaves to write (by tree key)
+    pub retired: Vec<Hash>,
+    /// New finalized height, if it advanced
+    pub finalized: Option<u64>,
+}
+
+impl ChainUpdate {
+    fn outcome(&self) -> ImportOutcome {
+        if !self.disconnected.is_empty() {
+            ImportOutcome::Reorganized {
+                disconnected: self.disconnected.clone(),
+                connected: self.connected.clone(),
+            }
+        } else if self.connected.is_empty() {
+            ImportOutcome::Stored
+        } else {
+            ImportOutcome::Extended
+        }
+    }
+}
+
+impl ChainState {
+    /// Import a block anywhere in the block tree
+    ///
+    /// The block is kept even if it does not extend the canonical chain, as
+    /// long as its proposer was in line under the validator set at its
+    /// parent and its height has room for another side block. If its branch
+    /// becomes the heaviest, state is reorganised onto it.
+    ///
+    /// # Errors
+    /// Returns error if the block is malformed, its parent is unknown, its
+    /// proposer was not in line, its height already holds
+    /// `MAX_SIDE_BLOCKS_PER_HEIGHT` side blocks, or it failed to execute when
+    /// its branch was selected (it is then dropped)
+    pub fn import_block(&mut self, block: Block) -> Result<ImportOutcome, StateError> {
+        let hash = block.hash;
+        if self.blocks.contains_key(&hash) {
+            return Ok(ImportOutcome::Duplicate);
+        }
+
+        self.validate_block(&block, now_millis())?;
+
+        let expected = if self.tip.is_none() {
+            0
+        } else {
+            let parent = self
+                .blocks
+                .get(&block.header.parent_hash)
+                .ok_or(StateError::InvalidParent)?;
+            parent.header.height + 1
+        };
+        if block.header.height != expected {
+            return Err(StateError::InvalidHeight {
+                expected,
+                got: block.header.height,
+            });
+        }
+        if self.tip.is_some() && block.header.height <= self.finalized {
+            return Err(StateError::BelowFinalized {
+                height: block.header.height,
+                finalized: self.finalized,
+            });
+        }
+        self.check_side_proposer(&block)?;
+        if self.side_blocks_at(block.header.height) >= MAX_SIDE_BLOCKS_PER_HEIGHT {
+            return Err(StateError::InvalidBlock(format!(
+                "height {} already has {MAX_SIDE_BLOCKS_PER_HEIGHT} side blocks",
+                block.header.height
+            )));
+        }
+
+        self.insert_block(block);
+        let mut update = ChainUpdate {
+            stored: vec![hash],
+            ..ChainUpdate::default()
+        };
+        let failures = self.update_head(&mut update);
+        self.advance_finality(&mut update);
+
+        if let Err(e) = self.persist(&update) {
+            self.rollback(&update);
+            self.discard_branch(hash, &mut ChainUpdate::default());
+            return Err(e);
+        }
+
+        if !self.blocks.contains_key(&hash) {
+            // Dropped because it (or an ancestor) failed to execute
+            return Err(failures
+                .into_iter()
+                .next()
+                .unwrap_or(StateError::InvalidParent));
+        }
+
+        Ok(update.outcome())
+    }
+
+    /// Record an attestation for a known block and re-run fork choice
+    ///
+    /// # Errors
+    /// Returns error if the block is unknown, the verifier is not in the
+    /// block's validator set, the signature is invalid, or the resulting
+    /// update cannot be persisted
+    pub fn add_attestation(
+        &mut self,
+        attestation: VerifierAttestation,
+    ) -> Result<ImportOutcome, StateError> {
+        let hash = attestation.block_hash;
+        let block = self.blocks.get(&hash).ok_or(StateError::BlockNotFound)?;
+
+        if block.attestations.iter().any(|a| a.verifier == attestation.verifier) {
+            return Ok(ImportOutcome::Duplicate);
+        }
+        self.block_validators(&hash)
+            .check_attestation(block, &attestation)
+            .map_err(|e| StateError::InvalidBlock(e.to_string()))?;
+        self.blocks
+            .get_mut(&hash)
+            .ok_or(StateError::BlockNotFound)?
+            .add_attestation(attestation);
+
+        let mut update = ChainUpdate {
+            stored: vec![hash],
+            ..ChainUpdate::default()
+        };
+        self.update_head(&mut update);
+        self.advance_finality(&mut update);
+
+        if let Err(e) = self.persist(&update) {
+            self.rollback(&update);
+            return Err(e);
+        }
+
+        Ok(update.outcome())
+    }
+
+    /// Fork-choice weight of a single block
+    ///
+    /// Only members of the block's own validator set count, each once, with
+    /// the stake they held in its parent state.
+    #[must_use]
+    pub fn block_weight(&self, block: &Block) -> ChainWeight {
+        let validators = self.block_validators(&block.hash);
+        let mut seen = HashSet::new();
+        let mut weight = ChainWeight {
+            blocks: 1,
+            ..ChainWeight::default()
+        };
+
+        for attestation in &block.attestations {
+            if attestation.block_hash != block.hash || !seen.insert(attestation.verifier) {
+                continue;
+            }
+            let Some(stake) = validators.stake_of(&attestation.verifier) else {
+                continue;
+            };
+            weight.attestations += 1;
+            weight.stake = weight.stake.saturating_add(stake);
+        }
+
+        weight
+    }
+
+    /// Validator set a block's attestations are counted against
+    ///
+    /// Blocks connected before a restart have none recorded and fall back to
+    /// the set after the current tip.
+    fn block_validators(&self, hash: &Hash) -> Cow<'_, ValidatorSet> {
+        self.validator_sets
+            .get(hash)
+            .map_or_else(|| Cow::Owned(self.validator_set()), Cow::Borrowed)
+    }
+
+    /// Number of blocks in the tree, including side branches
+    #[must_use]
+    pub fn known_block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// Whether a block is on the canonical chain
+    #[must_use]
+    pub fn is_canonical(&self, hash: &Hash) -> bool {
+        self.get_header(hash)
+            .is_some_and(|h| self.height_index.get(&h.height) == Some(hash))
+    }
+
+    /// Add a block to the tree (no execution)
+    fn insert_block(&mut self, block: Block) {
+        if block.header.height > 0 {
+            self.heads.remove(&block.header.parent_hash);
+            self.children.entry(block.header.parent_hash).or_default().push(block.hash);
+        }
+        let validators = self.parent_validators(&block);
+        self.validator_sets.insert(block.hash, validators);
+        self.heads.insert(block.hash);
+        self.blocks.insert(block.hash, block);
+    }
+
+    /// Remove a block from its parent's children
+    pub(super) fn unlink_block(&mut self, hash: &Hash, parent: &Hash) {
+        if let Some(siblings) = self.children.get_mut(parent) {
+            siblings.retain(|child| child != hash);
+            if siblings.is_empty() {
+                self.children.remove(parent);
+            }
+        }
+    }
+
+    /// Check a block's proposer against the best known validator set at its
+    /// parent, before the block is kept anywhere in the tree
+    ///
+    /// `check_proposer` runs again with the exact set when the block is
+    /// connected.
+    fn check_side_proposer(&self, block: &Block) -> Result<(), StateError> {
+        if block.header.height == 0 {
+            return Ok(());
+        }
+        let parent = self
+            .get_header(&block.header.parent_hash)
+            .ok_or(StateError::InvalidParent)?;
+        let proposer = Address::from_public_key(&block.header.proposer);
+        let elapsed = block.header.timestamp.saturating_sub(parent.timestamp);
+
+        if self.parent_validators(block).proposer_schedule().is_eligible(
+            &block.header.parent_hash,
+            block.header.height,
+            &proposer,
+            elapsed,
+        ) {
+            Ok(())
+        } else {
+            Err(StateError::UnscheduledProposer {
+                height: block.header.height,
+                proposer,
+            })
+        }
+    }
+
+    /// Number of blocks off the canonical chain at `height`
+    ///
+    /// Only blocks above the finalized height can be off it, so the tree is
+    /// walked down from the finalized block.
+    fn side_blocks_at(&self, height: u64) -> usize {
+        let Some(finalized) = self.height_index.get(&self.finalized) else {
+            return 0;
+        };
+        let mut level = vec![*finalized];
+        for _ in self.finalized..height {
+            level = level
+                .iter()
+                .filter_map(|hash| self.children.get(hash))
+                .flatten()
+                .copied()
+                .collect();
+        }
+        level.iter().filter(|hash| !self.is_canonical(hash)).count()
+    }
+
+    /// Best known validator set at the parent of a block not yet connected
+    ///
+    /// Exact when the parent is canonical: the current set if it is the tip,
+    /// else the set of the canonical block at the same height. Deeper in a
+    /// side branch the parent's own set is inherited, which is exact within
+    /// an epoch.
+    fn parent_validators(&self, block: &Block) -> ValidatorSet {
+        let parent = block.header.parent_hash;
+        if self.tip == Some(parent) {
+            return self.validator_set();
+        }
+        let sibling = self
+            .height_index
+            .get(&block.header.height)
+            .filter(|hash| self.get_header(hash).is_some_and(|h| h.parent_hash == parent));
+        sibling.map_or_else(
+            || self.block_validators(&parent),
+            |hash| self.block_validators(hash),
+        )
+        .into_owned()
+    }
+
+    /// Move the canonical head to the heaviest leaf
+    ///
+    /// Branches that fail to execute are discarded and fork choice is re-run;
+    /// their errors are returned in the order they occurred.
+    fn update_head(&mut self, update: &mut ChainUpdate) -> Vec<StateError> {
+        let mut failures = Vec::new();
+        loop {
+            let Some(best) = self.best_head() else {
+                return failures;
+            };
+            if Some(best) == self.tip {
+                return failures;
+            }
+            match self.switch_to(best, update) {
+                Ok(()) => return failures,
+                Err((bad, e)) => {
+                    self.discard_branch(bad, update);
+                    failures.push(e);
+                }
+            }
+        }
+    }
+
+    /// The leaf that fork choice currently prefers
+    fn best_head(&self) -> Option<Hash> {
+        self.heads.iter().fold(self.tip, |best, head| match best {
+            Some(best) if !self.is_heavier(head, &best) => Some(best),
+            _ => Some(*head),
+        })
+    }
+
+    /// Whether branch `a` beats branch `b`
+    fn is_heavier(&self, a: &Hash, b: &Hash) -> bool {
+        if a == b {
+            return false;
+        }
+        let Some(ancestor) = self.common_ancestor(a, b) else {
+            return false;
+        };
+        match self
+            .branch_weight(a, &ancestor)
+            .cmp(&self.branch_weight(b, &ancestor))
+        {
+            Ordering::Greater => true,
+            Ordering::Less => false,
+            Ordering::Equal => a.as_bytes() < b.as_bytes(),
+        }
+    }
+
+    /// Weight of the blocks from `head` back to (excluding) `ancestor`
+    fn branch_weight(&self, head: &Hash, ancestor: &Hash) -> ChainWeight {
+        let mut weight = ChainWeight::default();
+        let mut cursor = *head;
+        while cursor != *ancestor {
+            let Some(block) = self.blocks.get(&cursor) else {
+                break;
+            };
+            weight.add(self.block_weight(block));
+            cursor = block.header.parent_hash;
+        }
+        weight
+    }
+
+    /// Closest block that both `a` and `b` descend from (or are)
+    ///
+    /// Only the parts of the two branches off the canonical chain are walked.
+    fn common_ancestor(&self, a: &Hash, b: &Hash) -> Option<Hash> {
+        let (side_a, fork_a) = self.side_branch(a)?;
+        let (side_b, fork_b) = self.side_branch(b)?;
+        let side_a: HashSet<Hash> = side_a.into_iter().collect();
+        if let Some(shared) = side_b.into_iter().find(|hash| side_a.contains(hash)) {
+            return Some(shared);
+        }
+        // Both branches meet the canonical chain; the lower fork is shared
+        let height = |hash: &Hash| self.get_header(hash).map(|h| h.height);
+        Some(if height(&fork_a) <= height(&fork_b) { fork_a } else { fork_b })
+    }
+
+    /// Blocks from `hash` back to the canonical chain, closest first, and
+    /// the canonical block they fork from
+    fn side_branch(&self, hash: &Hash) -> Option<(Vec<Hash>, Hash)> {
+        let mut side = Vec::new();
+        let mut cursor = *hash;
+        loop {
+            let header = self.get_header(&cursor)?;
+            if self.height_index.get(&header.height) == Some(&cursor) {
+                return Some((side, cursor));
+            }
+            if header.height == 0 {
+                return None;
+            }
+            side.push(cursor);
+            cursor = header.parent_hash;
+        }
+    }
+
+    /// Make `target` the canonical head
+    ///
+    /// On failure the previous canonical chain is restored and the first
+    /// block of the new branch that could not be reached is returned.
+    fn switch_to(
+        &mut self,
+        target: Hash,
+        update: &mut ChainUpdate,
+    ) -> Result<(), (Hash, StateError)> {
+        let ancestor = self.tip.and_then(|tip| self.common_ancestor(&tip, &target));
+
+        let mut path = Vec::new();
+        let mut cursor = target;
+        while Some(cursor) != ancestor {
+            let Some(block) = self.blocks.get(&cursor) else {
+                break;
+            };
+            path.push(cursor);
+            if block.header.height == 0 {
+                break;
+            }
+            cursor = block.header.parent_hash;
+        }
+        path.reverse();
+        let first = path.first().copied().unwrap_or(target);
+
+        let mut disconnected = Vec::new();
+        while self.tip != ancestor {
+            match self.disconnect_tip() {
+                Ok(hash) => disconnected.push(hash),
+                Err(e) => {
+                    self.restore(&[], &disconnected);
+                    return Err((first, e));
+                }
+            }
+        }
+
+        let mut connected = Vec::new();
+        for hash in path {
+            if let Err(e) = self.connect_block(&hash) {
+                self.restore(&connected, &disconnected);
+                return Err((hash, e));
+            }
+            connected.push(hash);
+        }
+
+        update.disconnected.extend(disconnected);
+        update.connected.extend(connected);
+        Ok(())
+    }
+
+    /// Undo a partially applied head switch
+    fn restore(&mut self, connected: &[Hash], disconnected: &[Hash]) {
+        for _ in connected {
+            // Just connected, so the undo journal is present
+            let _ = self.disconnect_tip();
+        }
+        for hash in disconnected.iter().rev() {
+            // Re-executing a previously valid block on the same state cannot fail
+            let _ = self.connect_block(hash);
+        }
+    }
+
+    /// Undo an in-memory update whose commit failed
+    pub(super) fn rollback(&mut self, update: &ChainUpdate) {
+        self.restore(&update.connected, &update.disconnected);
+    }
+
+    /// Execute a known block on top of the current tip
+    fn connect_block(&mut self, hash: &Hash) -> Result<(), StateError> {
+        let block = self.blocks.get(hash).ok_or(StateError::BlockNotFound)?;
+        self.check_proposer(block)?;
+        self.check_quorum(block)?;
+        let validators = self.validator_set();
+        let height = block.header.height;
+        let state_root = block.header.state_root;
+        let block = block.clone();
+
+        let mut undo = BlockUndo::new(self);
+        if let Err(e) = self.execute_block(&block, &mut undo) {
+            self.revert(undo);
+            return Err(e);
+        }
+
+        let computed = self.compute_state_root();
+        if computed != state_root {
+            self.revert(undo);
+            return Err(StateError::StateRootMismatch {
+                expected: state_root,
+                computed,
+            });
+        }
+
+        self.undo_logs.insert(*hash, undo);
+        self.validator_sets.insert(*hash, validators);
+        self.height_index.insert(height, *hash);
+        self.tip = Some(*hash);
+        self.height = height + 1;
+        Ok(())
+    }
+
+    /// Revert the canonical tip, returning its hash
+    fn disconnect_tip(&mut self) -> Result<Hash, StateError> {
+        let tip = self.tip.ok_or(StateError::BlockNotFound)?;
+        let undo = self.undo_logs.remove(&tip).ok_or(StateError::MissingUndo)?;
+        self.revert(undo);
+
+        let header = &self.blocks.get(&tip).ok_or(StateError::BlockNotFound)?.header;
+        let (height, parent) = (header.height, header.parent_hash);
+        self.height_index.remove(&height);
+        self.height = height;
+        self.tip = (height > 0).then_some(parent);
+        Ok(tip)
+    }
+
+    /// Drop a non-canonical block and all of its descendants from the tree
+    pub(super) fn discard_branch(&mut self, root: Hash, update: &mut ChainUpdate) {
+        let Some(block) = self.blocks.get(&root) else {
+            return;
+        };
+        let parent = (block.header.height > 0).then_some(block.header.parent_hash);
+        if let Some(parent) = parent {
+            self.unlink_block(&root, &parent);
+        }
+
+        let mut stack = vec![root];
+        while let Some(hash) = stack.pop() {
+            if self.blocks.remove(&hash).is_none() {
+                continue;
+            }
+            self.heads.remove(&hash);
+            self.validator_sets.remove(&hash);
+            update.discarded.push(hash);
+            stack.extend(self.children.remove(&hash).unwrap_or_default());
+        }
+
+        if let Some(parent) = parent {
+            if self.blocks.contains_key(&parent) && !self.children.contains_key(&parent) {
+                self.heads.insert(parent);
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Block validation.
+//!
+//! Every block goes through the same checks before it joins the block tree,
+//! and verifiers run them again before attesting to a candidate:
+//!
+//! - structure: hash, merkle roots, `verification_count`, no solution
+//!   settled twice and each verification carrying its solution intact
+//!   (`Block::verify_integrity`);
+//! - signatures: the proposer's, and each verification result, which must be
+//!   the proposer's own (`Block::verify_signatures`);
+//! - limits: verifications and serialized size, from the `BlockProducerConfig`
+//!   the node was set up with;
+//! - time: strictly after the median of the last `MEDIAN_TIME_SPAN` blocks,
+//!   and at most `MAX_FUTURE_DRIFT_MS` ahead of the local clock.
+//!
+//! Execution (scheduled proposer, state root) is checked when the block is
+//! connected.
+
+use crate::consensus::BlockProducerConfig;
+use crate::crypto::Hash;
+use crate::types::{Block, Timestamp};
+
+use super::{ChainState, StateError};
+
+/// Number of ancestors whose median timestamp a block must exceed
+pub const MEDIAN_TIME_SPAN: usize = 11;
+
+/// How far ahead of the local clock a block timestamp may be
+pub const MAX_FUTURE_DRIFT_MS: i64 = 15_000;
+
+impl ChainState {
+    /// Size and solution limits blocks are checked against
+    #[must_use]
+    pub const fn block_limits(&self) -> &BlockProducerConfig {
+        &self.block_limits
+    }
+
+    /// Change the size and solution limits
+    ///
+    /// Blocks already in the tree are not re-checked.
+    pub const fn set_block_limits(&mut self, limits: BlockProducerConfig) {
+        self.block_limits = limits;
+    }
+
+    /// Median timestamp of the last `MEDIAN_TIME_SPAN` blocks up to `parent`
+    ///
+    /// `None` if `parent` is unknown.
+    #[must_use]
+    pub fn median_time_past(&self, parent: &Hash) -> Option<Timestamp> {
+        let mut timestamps = Vec::with_capacity(MEDIAN_TIME_SPAN);
+        let mut header = self.get_header(parent)?;
+        loop {
+            timestamps.push(header.timestamp);
+            if timestamps.len() == MEDIAN_TIME_SPAN || header.height == 0 {
+                break;
+            }
+            match self.get_header(&header.parent_hash) {
+                Some(parent) => header = parent,
+                None => break,
+            }
+        }
+        timestamps.sort_unstable();
+        Some(timestamps[timestamps.len() / 2])
+    }
+
+    /// Run every check a block must pass before it can be connected, with
+    /// `now` as the local time
+    ///
+    /// # Errors
+    /// Returns error for the first check that fails
+    pub fn validate_block(&self, block: &Block, now: Timestamp) -> Result<(), StateError> {
+        let invalid = |e: crate::types::BlockError| StateError::InvalidBlock(e.to_string());
+        block.verify_integrity().map_err(invalid)?;
+        block.verify_signatures().map_err(invalid)?;
+        self.block_limits.check_limits(block).map_err(invalid)?;
+
+        let median = (block.header.height > 0)
+            .then(|| self.median_time_past(&block.header.parent_hash))
+            .flatten();
+        block.check_timestamp(median, now, MAX_FUTURE_DRIFT_MS).map_err(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+    use crate::state::tests::{draft, seal, signed};
+    use crate::types::now_millis;
+
+    #[test]
+    fn test_median_time_past() {
+        let mut state = ChainState::new();
+        let kp = Keypair::generate();
+        state.apply_block(Block::genesis(*kp.public_key()).with_timestamp(1_000)).unwrap();
+        assert_eq!(state.median_time_past(&state.tip().unwrap().hash), Some(1_000));
+        assert_eq!(state.median_time_past(&Hash::ZERO), None);
+
+        let start = now_millis();
+        for i in 0..MEDIAN_TIME_SPAN {
+            let offset = i64::try_from(i).unwrap();
+            let block = draft(&state, &kp, Vec::new(), Vec::new()).with_timestamp(start + offset);
+            let block = seal(&mut state, &kp, block);
+            state.apply_block(block).unwrap();
+        }
+        // Genesis has dropped out of the span
+        let median = start + i64::try_from(MEDIAN_TIME_SPAN / 2).unwrap();
+        assert_eq!(state.median_time_past(&state.tip().unwrap().hash), Some(median));
+
+        // A block at the median is refused, however it is signed
+        let early = draft(&state, &kp, Vec::new(), Vec::new()).with_timestamp(median);
+        assert!(matches!(state.validate_block(&early, now_millis()), Err(StateError::InvalidBlock(_))));
+        let early = signed(early, &kp);
+        assert!(matches!(state.import_block(early), Err(StateError::InvalidBlock(_))));
+
+        let late = signed(draft(&state, &kp, Vec::new(), Vec::new()).with_timestamp(median + 1), &kp);
+        assert!(state.validate_block(&late, now_millis()).is_ok());
+        let ahead = signed(late.clone().with_timestamp(now_millis() + 2 * MAX_FUTURE_DRIFT_MS), &kp);
+        assert!(matches!(state.validate_block(&ahead, now_millis()), Err(StateError::InvalidBlock(_))));
+    }
+
+    #[test]
+    fn test_block_limits_apply_on_import() {
+        let mut state = ChainState::new();
+        let kp = Keypair::generate();
+        state.apply_block(Block::genesis(*kp.public_key())).unwrap();
+        state.set_block_limits(BlockProducerConfig { max_block_size: 64, ..BlockProducerConfig::default() });
+        assert_eq!(state.block_limits().max_block_size, 64);
+
+        let block = signed(draft(&state, &kp, Vec::new(), Vec::new()), &kp);
+        assert!(matches!(state.import_block(block), Err(StateError::InvalidBlock(_))));
+    }
+}
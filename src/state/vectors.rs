@@ -0,0 +1,514 @@
+//! Commit-reveal for `TestVectors` jobs.
+//!
+//! A `TestVectors` job commits to its hidden vectors when it is submitted,
+//! under the requester's signature along with its `reveal_at`. Until then,
+//! solvers lock in solutions on chain with `LockSolution`, naming a
+//! `SolutionLock::commitment` that binds their address to their output's hash
+//! under a secret salt, and escrowing a `lock_bond`. From then on the
+//! requester key can publish the vectors with `RevealVectors`, which every
+//! node checks against the job's commitment, and solvers open their locks
+//! with `OpenLock`. Only solutions whose lock was opened with their output
+//! are settled, so no solution can be fitted to vectors its solver has seen,
+//! and a copied lock cannot be opened by the copier.
+//!
+//! A job that expires with opened locks and no reveal is forfeited: its
+//! bounty is split evenly between the solvers who opened them.
+//!
+//! Bonds are returned when the job closes to solvers who opened their lock,
+//! unless the vectors were revealed and no solution passed. Every other bond
+//! is burned, as is the bond of a lock whose solution is rejected, so junk
+//! locks cost their senders and filling every slot costs at least the
+//! bounty.
+
+use crate::crypto::{hash_data, Commitment, Hash};
+use crate::types::{
+    Address, HclawAmount, Id, JobPacket, JobStatus, SolutionCandidate, SolutionLock, TestVector, Timestamp,
+    VerificationSpec,
+};
+
+use super::escrow::holds_escrow;
+use super::execution::BlockUndo;
+use super::{ChainState, StateError};
+
+/// Most solutions that can be locked in to one job
+pub const MAX_LOCKED_SOLUTIONS: usize = 64;
+
+/// Bond escrowed per lock on `job`: its bounty over `MAX_LOCKED_SOLUTIONS`,
+/// rounded up
+#[must_use]
+pub fn lock_bond(job: &JobPacket) -> HclawAmount {
+    HclawAmount::from_raw(job.bounty.raw().div_ceil(MAX_LOCKED_SOLUTIONS as u128).max(1))
+}
+
+impl ChainState {
+    /// Lock in `solver`'s solution to an open `TestVectors` job, escrowing
+    /// its bond
+    pub(super) fn lock_solution(
+        &mut self,
+        solver: &Address,
+        job_id: Id,
+        commitment: Hash,
+        now: Timestamp,
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        let job = self.jobs.get(&job_id).ok_or(StateError::JobNotFound)?;
+        let (_, reveal_at) = open_test_vectors(job)?;
+        if now >= reveal_at {
+            return Err(invalid(format!("lock-in closed at {reveal_at}")));
+        }
+        if job.escrow_address() == *solver {
+            return Err(invalid("requester cannot lock in a solution".to_string()));
+        }
+        if job.locked_solutions.iter().any(|lock| lock.solver == *solver) {
+            return Err(invalid("solution already locked in".to_string()));
+        }
+        if job.locked_solutions.len() >= MAX_LOCKED_SOLUTIONS {
+            return Err(invalid(format!("job already has {MAX_LOCKED_SOLUTIONS} locked-in solutions")));
+        }
+        let bond = lock_bond(job);
+
+        let account = self.journal_account(undo, solver);
+        if account.available_balance() < bond {
+            return Err(StateError::InsufficientBalance { have: account.available_balance(), need: bond });
+        }
+        account.escrowed = account.escrowed.saturating_add(bond);
+        self.journal_job(undo, job_id).locked_solutions.push(SolutionLock {
+            solver: *solver,
+            commitment,
+            bond,
+            opened: None,
+        });
+        Ok(())
+    }
+
+    /// Open `solver`'s lock on an open `TestVectors` job whose lock-in has
+    /// closed
+    pub(super) fn open_lock(
+        &mut self,
+        solver: &Address,
+        job_id: Id,
+        output_hash: Hash,
+        salt: &[u8; 32],
+        now: Timestamp,
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        let job = self.jobs.get(&job_id).ok_or(StateError::JobNotFound)?;
+        let (_, reveal_at) = open_test_vectors(job)?;
+        if now < reveal_at {
+            return Err(invalid(format!("locks cannot be opened before {reveal_at}")));
+        }
+        let index = job
+            .locked_solutions
+            .iter()
+            .position(|lock| lock.solver == *solver)
+            .ok_or_else(|| invalid("no solution locked in".to_string()))?;
+        let lock = &job.locked_solutions[index];
+        if lock.opened.is_some() {
+            return Err(invalid("lock already opened".to_string()));
+        }
+        if SolutionLock::commitment(solver, &output_hash, salt) != lock.commitment {
+            return Err(invalid("output does not open the lock".to_string()));
+        }
+        self.journal_job(undo, job_id).locked_solutions[index].opened = Some(output_hash);
+        Ok(())
+    }
+
+    /// Publish the vectors of an open `TestVectors` job, checked against its
+    /// commitment
+    pub(super) fn reveal_vectors(
+        &mut self,
+        requester: &Address,
+        job_id: Id,
+        vectors: &[TestVector],
+        nonce: &[u8; 32],
+        now: Timestamp,
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        let job = self.jobs.get(&job_id).ok_or(StateError::JobNotFound)?;
+        let (commitment, reveal_at) = open_test_vectors(job)?;
+        if job.escrow_address() != *requester {
+            return Err(invalid("only the requester can reveal vectors".to_string()));
+        }
+        if now < reveal_at {
+            return Err(invalid(format!("vectors cannot be revealed before {reveal_at}")));
+        }
+        if job.revealed_vectors.is_some() {
+            return Err(invalid("vectors already revealed".to_string()));
+        }
+        if vectors.is_empty() {
+            return Err(invalid("no vectors revealed".to_string()));
+        }
+        commitment
+            .verify(TestVector::encode_all(vectors), nonce)
+            .map_err(|_| invalid("vectors do not match the job's commitment".to_string()))?;
+        self.journal_job(undo, job_id).revealed_vectors = Some(vectors.to_vec());
+        Ok(())
+    }
+
+    /// Pay the bounty of an expired job that was never revealed out to the
+    /// solvers who opened their locks, the first taking any indivisible
+    /// remainder
+    pub(super) fn forfeit_bounty(
+        &mut self,
+        requester: &Address,
+        bounty: HclawAmount,
+        solvers: &[Address],
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        self.journal_account(undo, requester).release_bounty(bounty)?;
+        let count = solvers.len() as u128;
+        let share = HclawAmount::from_raw(bounty.raw() / count);
+        let remainder = HclawAmount::from_raw(bounty.raw() % count);
+        for (i, solver) in solvers.iter().enumerate() {
+            let amount = if i == 0 { share.saturating_add(remainder) } else { share };
+            let account = self.journal_account(undo, solver);
+            account.credit(amount);
+            account.total_earned = account.total_earned.saturating_add(amount);
+        }
+        Ok(())
+    }
+
+    /// Burn the bond of `solver`'s lock on a job, whose solution was rejected
+    pub(super) fn burn_lock_bond(&mut self, job_id: Id, solver: &Address, undo: &mut BlockUndo) -> Result<(), StateError> {
+        let job = self.journal_job(undo, job_id);
+        let Some(lock) = job.locked_solutions.iter_mut().find(|lock| lock.solver == *solver) else {
+            return Ok(());
+        };
+        let bond = std::mem::take(&mut lock.bond);
+        self.release_lock_bond(solver, bond, false, undo)
+    }
+
+    /// Return or burn the bonds still escrowed for a job that just closed
+    pub(super) fn settle_lock_bonds(&mut self, job_id: Id, undo: &mut BlockUndo) -> Result<(), StateError> {
+        let job = self.journal_job(undo, job_id);
+        let kept = job.status == JobStatus::Completed || job.revealed_vectors.is_none();
+        let bonds: Vec<_> = job
+            .locked_solutions
+            .iter_mut()
+            .filter(|lock| lock.bond > HclawAmount::ZERO)
+            .map(|lock| (lock.solver, std::mem::take(&mut lock.bond), kept && lock.opened.is_some()))
+            .collect();
+        for (solver, bond, returned) in bonds {
+            self.release_lock_bond(&solver, bond, returned, undo)?;
+        }
+        Ok(())
+    }
+
+    /// Return a lock bond to its solver, or burn it
+    fn release_lock_bond(
+        &mut self,
+        solver: &Address,
+        bond: HclawAmount,
+        returned: bool,
+        undo: &mut BlockUndo,
+    ) -> Result<(), StateError> {
+        let account = self.journal_account(undo, solver);
+        if returned {
+            account.refund_bounty(bond);
+        } else {
+            account.release_bounty(bond)?;
+            self.total_burned = self.total_burned.saturating_add(bond);
+        }
+        Ok(())
+    }
+}
+
+/// Check that a solution may be settled: for a `TestVectors` job, the
+/// vectors must be revealed and the solver's lock opened with its output
+pub(super) fn check_locked_in(job: &JobPacket, solution: &SolutionCandidate) -> Result<(), StateError> {
+    if !matches!(job.verification, VerificationSpec::TestVectors { .. }) {
+        return Ok(());
+    }
+    signed_test_vectors(job)?;
+    if job.revealed_vectors.is_none() {
+        return Err(invalid("test vectors are not revealed yet".to_string()));
+    }
+    if !job.is_locked_in(&solution.solver_address, &hash_data(&solution.output)) {
+        return Err(invalid("solution was not locked in".to_string()));
+    }
+    Ok(())
+}
+
+/// Solvers an expiring job forfeits its bounty to: those who opened their
+/// lock on a `TestVectors` job whose vectors were never revealed
+pub(super) fn forfeit_recipients(job: &JobPacket) -> Vec<Address> {
+    if !matches!(job.verification, VerificationSpec::TestVectors { .. }) || job.revealed_vectors.is_some() {
+        return Vec::new();
+    }
+    job.locked_solutions
+        .iter()
+        .filter(|lock| lock.opened.is_some())
+        .map(|lock| lock.solver)
+        .collect()
+}
+
+/// Commitment and reveal time of an open `TestVectors` job
+fn open_test_vectors(job: &JobPacket) -> Result<(&Commitment, Timestamp), StateError> {
+    if !holds_escrow(job) {
+        return Err(StateError::JobClosed);
+    }
+    signed_test_vectors(job)
+}
+
+/// Commitment and reveal time of a `TestVectors` job, trusted only as far
+/// as the requester's signature over the job's `signed_hash` covers them
+fn signed_test_vectors(job: &JobPacket) -> Result<(&Commitment, Timestamp), StateError> {
+    let VerificationSpec::TestVectors { commitment, reveal_at, .. } = &job.verification else {
+        return Err(invalid("job does not use test vectors".to_string()));
+    };
+    job.verify_signature()
+        .map_err(|_| invalid("test vectors are not signed by the requester".to_string()))?;
+    Ok((commitment, *reveal_at))
+}
+
+const fn invalid(reason: String) -> StateError {
+    StateError::InvalidTransaction(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::{hash_data, Keypair};
+    use crate::state::tests::{draft, seal, signed_result, signed_tx};
+    use crate::types::{now_millis, Block, JobType, Transaction, TransactionKind};
+
+    const NONCE: [u8; 32] = [7; 32];
+    const SALT: [u8; 32] = [9; 32];
+    /// Raw units each solver starts with, enough for one bond
+    const FUNDS: u128 = 10;
+
+    fn vectors() -> Vec<TestVector> {
+        vec![TestVector { input: b"2".to_vec(), expected: b"4".to_vec() }]
+    }
+
+    fn address(kp: &Keypair) -> Address {
+        Address::from_public_key(kp.public_key())
+    }
+
+    /// State with an open `TestVectors` job whose lock-in closes in 5s and
+    /// which expires in 10s, so every block stays near the clock
+    fn state_with_job() -> (ChainState, Keypair, Id, Timestamp) {
+        let mut state = ChainState::new();
+        let requester = Keypair::generate();
+        state.apply_block(Block::genesis(*requester.public_key())).unwrap();
+        state.get_or_create_account(&address(&requester)).balance = HclawAmount::from_hclaw(100);
+
+        let reveal_at = now_millis() + 5_000;
+        let mut job = JobPacket::new(
+            JobType::Deterministic,
+            *requester.public_key(),
+            b"double a number".to_vec(),
+            "Vectors job".to_string(),
+            HclawAmount::from_raw(11),
+            HclawAmount::ZERO,
+            VerificationSpec::TestVectors {
+                commitment: TestVector::commit(&vectors(), &NONCE),
+                entry_point: "run".to_string(),
+                min_pass_percent: 100,
+                reveal_at,
+            },
+            10,
+        );
+        job.signature = requester.sign(&job.signing_bytes());
+        let job_id = job.id;
+        apply(&mut state, &requester, None, vec![signed_tx(&requester, 0, TransactionKind::SubmitJob(Box::new(job)))]);
+        (state, requester, job_id, reveal_at)
+    }
+
+    fn fund<'a>(state: &mut ChainState, solvers: impl IntoIterator<Item = &'a Keypair>) {
+        for solver in solvers {
+            state.get_or_create_account(&address(solver)).credit(HclawAmount::from_raw(FUNDS));
+        }
+    }
+
+    fn apply(state: &mut ChainState, proposer: &Keypair, timestamp: Option<Timestamp>, transactions: Vec<Transaction>) {
+        let mut block = draft(state, proposer, transactions, Vec::new());
+        if let Some(timestamp) = timestamp {
+            block = block.with_timestamp(timestamp);
+        }
+        let block = seal(state, proposer, block);
+        state.apply_block(block).unwrap();
+    }
+
+    fn lock(solver: &Keypair, job_id: Id, output: &[u8]) -> Transaction {
+        let commitment = SolutionLock::commitment(&address(solver), &hash_data(output), &SALT);
+        signed_tx(solver, 0, TransactionKind::LockSolution { job_id, commitment })
+    }
+
+    fn open(solver: &Keypair, job_id: Id, output: &[u8]) -> Transaction {
+        signed_tx(solver, 1, TransactionKind::OpenLock { job_id, output_hash: hash_data(output), salt: SALT })
+    }
+
+    fn reveal(requester: &Keypair, job_id: Id, vectors: Vec<TestVector>) -> Transaction {
+        signed_tx(requester, 1, TransactionKind::RevealVectors { job_id, vectors, nonce: NONCE })
+    }
+
+    fn fails(state: &mut ChainState, proposer: &Keypair, timestamp: Timestamp, transactions: Vec<Transaction>) -> bool {
+        let block = draft(state, proposer, transactions, Vec::new()).with_timestamp(timestamp);
+        state.state_root_after(&block).is_err()
+    }
+
+    fn expire(state: &mut ChainState, requester: &Keypair, job_id: Id) {
+        let expires_at = state.get_job(&job_id).unwrap().expires_at;
+        apply(state, requester, Some(expires_at + 1), Vec::new());
+    }
+
+    #[test]
+    fn test_reveal_settles_locked_solutions() {
+        let (mut state, requester, job_id, reveal_at) = state_with_job();
+        let (locked, late) = (Keypair::generate(), Keypair::generate());
+        fund(&mut state, [&locked, &late]);
+        let now = now_millis();
+
+        // Too early to reveal, and the requester cannot lock in
+        assert!(fails(&mut state, &requester, now, vec![reveal(&requester, job_id, vectors())]));
+        assert!(fails(&mut state, &requester, now, vec![lock(&requester, job_id, b"program")]));
+
+        apply(&mut state, &requester, None, vec![lock(&locked, job_id, b"program")]);
+        let bond = lock_bond(state.get_job(&job_id).unwrap());
+        assert_eq!(state.escrowed_balance(&address(&locked)), bond);
+        let again = signed_tx(&locked, 1, TransactionKind::LockSolution { job_id, commitment: Hash::ZERO });
+        assert!(fails(&mut state, &requester, now_millis(), vec![again]));
+        assert!(fails(&mut state, &requester, now_millis(), vec![open(&locked, job_id, b"program")]));
+        assert!(fails(&mut state, &requester, reveal_at, vec![lock(&late, job_id, b"program")]));
+
+        let solution = SolutionCandidate::new(job_id, *locked.public_key(), b"program".to_vec());
+        let unlocked = SolutionCandidate::new(job_id, *late.public_key(), b"program".to_vec());
+        let settle = |state: &ChainState, solution: &SolutionCandidate, timestamp: Timestamp| {
+            draft(state, &requester, Vec::new(), vec![signed_result(&requester, solution, true)])
+                .with_timestamp(timestamp)
+        };
+
+        // Only the committed vectors reveal
+        let wrong = vec![TestVector { input: b"2".to_vec(), expected: b"5".to_vec() }];
+        assert!(fails(&mut state, &requester, reveal_at, vec![reveal(&requester, job_id, wrong)]));
+        apply(&mut state, &requester, Some(reveal_at), vec![reveal(&requester, job_id, vectors())]);
+        assert_eq!(state.get_job(&job_id).unwrap().revealed_vectors, Some(vectors()));
+
+        // Settled only once the lock is opened with the solution's output
+        let block = settle(&state, &solution, reveal_at + 1);
+        assert!(state.state_root_after(&block).is_err());
+        assert!(fails(&mut state, &requester, reveal_at + 1, vec![open(&locked, job_id, b"other")]));
+        apply(&mut state, &requester, Some(reveal_at + 1), vec![open(&locked, job_id, b"program")]);
+
+        let block = settle(&state, &unlocked, reveal_at + 2);
+        assert!(state.state_root_after(&block).is_err());
+        let block = settle(&state, &solution, reveal_at + 2);
+        let block = seal(&mut state, &requester, block);
+        state.apply_block(block).unwrap();
+        assert_eq!(state.get_job(&job_id).unwrap().status, JobStatus::Completed);
+        assert_eq!(state.escrowed_balance(&address(&locked)), HclawAmount::ZERO);
+        assert!(state.balance_of(&address(&locked)).raw() > FUNDS);
+    }
+
+    #[test]
+    fn test_reveal_time_is_signed() {
+        let (mut state, requester, job_id, _) = state_with_job();
+        let early = now_millis();
+        let move_reveal = |state: &mut ChainState| {
+            let job = state.jobs.get_mut(&job_id).unwrap();
+            if let VerificationSpec::TestVectors { reveal_at, .. } = &mut job.verification {
+                *reveal_at = early;
+            }
+            job.clone()
+        };
+
+        // A reveal time moved up after signing does not open the reveal
+        move_reveal(&mut state);
+        assert!(fails(&mut state, &requester, early, vec![reveal(&requester, job_id, vectors())]));
+
+        // It would, had the requester signed it
+        let mut job = move_reveal(&mut state);
+        job.signature = requester.sign(&job.signing_bytes());
+        state.jobs.insert(job_id, job);
+        assert!(!fails(&mut state, &requester, early, vec![reveal(&requester, job_id, vectors())]));
+    }
+
+    #[test]
+    fn test_unrevealed_job_forfeits_bounty() {
+        let (mut state, requester, job_id, reveal_at) = state_with_job();
+        let solvers = [Keypair::generate(), Keypair::generate()];
+        fund(&mut state, &solvers);
+        apply(&mut state, &requester, None, vec![lock(&solvers[0], job_id, b"a"), lock(&solvers[1], job_id, b"b")]);
+        apply(&mut state, &requester, Some(reveal_at), vec![open(&solvers[0], job_id, b"a"), open(&solvers[1], job_id, b"b")]);
+
+        // Nothing revealed by the deadline: the solvers who opened their
+        // locks split the bounty and get their bonds back
+        expire(&mut state, &requester, job_id);
+        assert_eq!(state.get_job(&job_id).unwrap().status, JobStatus::Forfeited);
+        assert_eq!(state.escrowed_balance(&address(&requester)), HclawAmount::ZERO);
+        let earned = |kp: &Keypair| state.balance_of(&address(kp)).raw() - FUNDS;
+        assert_eq!((earned(&solvers[0]), earned(&solvers[1])), (6, 5));
+        assert_eq!(state.escrowed_balance(&address(&solvers[0])), HclawAmount::ZERO);
+    }
+
+    #[test]
+    fn test_junk_locks_only_burn_bonds() {
+        let (mut state, requester, job_id, reveal_at) = state_with_job();
+        let junk: Vec<Keypair> = (0..MAX_LOCKED_SOLUTIONS).map(|_| Keypair::generate()).collect();
+        let honest = Keypair::generate();
+        fund(&mut state, &junk);
+        fund(&mut state, [&honest]);
+        let bounty = state.get_job(&job_id).unwrap().bounty;
+        let burned = state.total_burned();
+
+        // Filling every slot escrows at least the bounty in bonds
+        let locks = junk.iter().map(|kp| lock(kp, job_id, b"junk")).collect();
+        apply(&mut state, &requester, None, locks);
+        assert!(fails(&mut state, &requester, now_millis(), vec![lock(&honest, job_id, b"program")]));
+        let bond = lock_bond(state.get_job(&job_id).unwrap());
+        assert!(bond.raw() * MAX_LOCKED_SOLUTIONS as u128 >= bounty.raw());
+
+        // Revealed, and none of them delivers: every bond is burned, none of
+        // the bounty goes to the junk lockers
+        apply(&mut state, &requester, Some(reveal_at), vec![reveal(&requester, job_id, vectors())]);
+        expire(&mut state, &requester, job_id);
+        assert_eq!(state.get_job(&job_id).unwrap().status, JobStatus::Expired);
+        assert_eq!(state.escrowed_balance(&address(&requester)), HclawAmount::ZERO);
+        assert_eq!(state.balance_of(&address(&requester)), HclawAmount::from_hclaw(100));
+        assert_eq!(state.total_burned().raw() - burned.raw(), bond.raw() * MAX_LOCKED_SOLUTIONS as u128);
+        for kp in &junk {
+            assert_eq!(state.balance_of(&address(kp)).raw(), FUNDS - bond.raw());
+            assert_eq!(state.escrowed_balance(&address(kp)), HclawAmount::ZERO);
+        }
+    }
+
+    #[test]
+    fn test_sybil_and_copied_locks_capture_nothing() {
+        let (mut state, requester, job_id, reveal_at) = state_with_job();
+        let sybils = [Keypair::generate(), Keypair::generate(), Keypair::generate()];
+        let (honest, copier) = (Keypair::generate(), Keypair::generate());
+        fund(&mut state, &sybils);
+        fund(&mut state, [&honest, &copier]);
+        let bounty = state.get_job(&job_id).unwrap().bounty;
+
+        // The requester locks junk under other addresses; a copier replays
+        // the honest solver's commitment
+        let mut locks: Vec<Transaction> = sybils
+            .iter()
+            .map(|kp| signed_tx(kp, 0, TransactionKind::LockSolution { job_id, commitment: hash_data(b"junk") }))
+            .collect();
+        let honest_lock = lock(&honest, job_id, b"program");
+        let TransactionKind::LockSolution { commitment, .. } = honest_lock.kind else { unreachable!() };
+        locks.push(honest_lock);
+        locks.push(signed_tx(&copier, 0, TransactionKind::LockSolution { job_id, commitment }));
+        apply(&mut state, &requester, None, locks);
+
+        // Only the honest solver can open, even with the output in hand
+        assert!(fails(&mut state, &requester, reveal_at, vec![open(&copier, job_id, b"program")]));
+        apply(&mut state, &requester, Some(reveal_at), vec![open(&honest, job_id, b"program")]);
+
+        // The requester skips the reveal and gets nothing back
+        expire(&mut state, &requester, job_id);
+        assert_eq!(state.get_job(&job_id).unwrap().status, JobStatus::Forfeited);
+        assert_eq!(
+            state.balance_of(&address(&requester)),
+            HclawAmount::from_hclaw(100).saturating_sub(bounty)
+        );
+        assert_eq!(state.balance_of(&address(&honest)).raw(), FUNDS + bounty.raw());
+        let bond = lock_bond(state.get_job(&job_id).unwrap());
+        for kp in sybils.iter().chain([&copier]) {
+            assert_eq!(state.balance_of(&address(kp)).raw(), FUNDS - bond.raw());
+        }
+    }
+}
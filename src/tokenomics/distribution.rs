@@ -28,6 +28,7 @@ impl FeeDistribution {
 }
 
 /// Distributes fees according to protocol rules
+#[derive(Clone, Debug)]
 pub struct FeeDistributor {
     /// Solver share percentage
     solver_share: u8,
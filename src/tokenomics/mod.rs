@@ -21,10 +21,12 @@ pub use distribution::{FeeDistributor, FeeDistribution};
 pub use burn::{BurnManager, BurnReason};
 pub use supply::{SupplyManager, SupplyMetrics};
 
-use crate::types::{Address, HclawAmount};
+use serde::{Deserialize, Serialize};
+
+use crate::types::{serde_decimal, Address, HclawAmount};
 
 /// Token economics configuration
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TokenEconomicsConfig {
     /// Percentage to solver (0-100)
     pub solver_share: u8,
@@ -33,8 +35,10 @@ pub struct TokenEconomicsConfig {
     /// Percentage to burn (0-100)
     pub burn_share: u8,
     /// Minimum burn for job submission (anti-Sybil)
+    #[serde(with = "serde_decimal")]
     pub min_burn_to_request: HclawAmount,
     /// Target block reward (adjusted by difficulty)
+    #[serde(with = "serde_decimal")]
     pub target_block_reward: HclawAmount,
 }
 
@@ -55,6 +55,7 @@ impl SupplyMetrics {
 }
 
 /// Manages token supply and difficulty adjustment
+#[derive(Debug)]
 pub struct SupplyManager {
     /// Current metrics
     metrics: SupplyMetrics,
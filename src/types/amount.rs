@@ -204,6 +204,33 @@ impl Div<u128> for HclawAmount {
     }
 }
 
+/// Serde helpers that write an amount as a decimal HCLAW string (`"0.001"`)
+///
+/// For human-edited files such as the genesis spec; use with
+/// `#[serde(with = "crate::types::serde_decimal")]`.
+pub mod serde_decimal {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::HclawAmount;
+
+    /// Serialize as a decimal string
+    ///
+    /// # Errors
+    /// Returns the serializer's error
+    pub fn serialize<S: Serializer>(amount: &HclawAmount, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&amount.to_decimal_string())
+    }
+
+    /// Deserialize from a decimal string
+    ///
+    /// # Errors
+    /// Returns error if the string is not a valid amount
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HclawAmount, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        HclawAmount::from_decimal_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
 /// Amount parsing/arithmetic errors
 #[derive(Debug, Clone, thiserror::Error)]
 pub enum AmountError {
@@ -3,10 +3,23 @@
 //! A block contains verified solutions and state transitions.
 //! Blocks are valid only with 66% consensus from verifiers.
 
+use std::collections::HashSet;
+
 use serde::{Deserialize, Serialize};
 
 use crate::crypto::{hash_data, merkle_root, Hash, PublicKey, Signature};
-use super::{Id, Timestamp, now_millis, VerificationResult};
+use super::{HclawAmount, Id, Timestamp, now_millis, SolutionCandidate, Transaction, VerificationResult};
+
+/// Validator set an epoch starts with, committed in its first block
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EpochCommitment {
+    /// Epoch number
+    pub number: u64,
+    /// Merkle root of the members and their stake, sorted by address
+    pub validators_root: Hash,
+    /// Stake of all members together
+    pub total_stake: HclawAmount,
+}
 
 /// Block header containing metadata and commitments
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -17,6 +30,10 @@ pub struct BlockHeader {
     pub parent_hash: Hash,
     /// Merkle root of verified solutions in this block
     pub solutions_root: Hash,
+    /// Merkle root of transaction IDs in this block
+    pub transactions_root: Hash,
+    /// Merkle root of the parent's attestations carried in this block
+    pub parent_attestations_root: Hash,
     /// Merkle root of state transitions
     pub state_root: Hash,
     /// Timestamp of block creation
@@ -25,8 +42,12 @@ pub struct BlockHeader {
     pub proposer: PublicKey,
     /// Number of verifications in this block
     pub verification_count: u32,
+    /// Attestation round the proposer signed this candidate in
+    pub round: u32,
     /// Protocol version
     pub version: u32,
+    /// Validator set of the epoch this block starts, if it starts one
+    pub epoch: Option<EpochCommitment>,
 }
 
 impl BlockHeader {
@@ -37,11 +58,19 @@ impl BlockHeader {
         data.extend_from_slice(&self.height.to_le_bytes());
         data.extend_from_slice(self.parent_hash.as_bytes());
         data.extend_from_slice(self.solutions_root.as_bytes());
+        data.extend_from_slice(self.transactions_root.as_bytes());
+        data.extend_from_slice(self.parent_attestations_root.as_bytes());
         data.extend_from_slice(self.state_root.as_bytes());
         data.extend_from_slice(&self.timestamp.to_le_bytes());
         data.extend_from_slice(self.proposer.as_bytes());
         data.extend_from_slice(&self.verification_count.to_le_bytes());
+        data.extend_from_slice(&self.round.to_le_bytes());
         data.extend_from_slice(&self.version.to_le_bytes());
+        if let Some(epoch) = &self.epoch {
+            data.extend_from_slice(&epoch.number.to_le_bytes());
+            data.extend_from_slice(epoch.validators_root.as_bytes());
+            data.extend_from_slice(&epoch.total_stake.raw().to_le_bytes());
+        }
 
         hash_data(&data)
     }
@@ -93,6 +122,14 @@ impl VerifierAttestation {
     pub fn verify_signature(&self) -> Result<(), crate::crypto::CryptoError> {
         crate::crypto::verify(&self.verifier, &self.signing_bytes(), &self.signature)
     }
+
+    /// Hash of the signed attestation
+    #[must_use]
+    pub fn id(&self) -> Hash {
+        let mut data = self.signing_bytes();
+        data.extend_from_slice(self.signature.as_bytes());
+        hash_data(&data)
+    }
 }
 
 /// A complete block in the HardClaw blockchain
@@ -102,8 +139,16 @@ pub struct Block {
     pub header: BlockHeader,
     /// Block hash (computed from header)
     pub hash: Hash,
+    /// Transactions, executed before the verifications
+    pub transactions: Vec<Transaction>,
     /// Verified solutions included in this block
     pub verifications: Vec<VerificationResult>,
+    /// The solution each verification settles, in the same order
+    ///
+    /// Solutions only enter chain state with the block that settles them.
+    pub solutions: Vec<SolutionCandidate>,
+    /// Attestations to the parent block, from which verifier liveness is recorded
+    pub parent_attestations: Vec<VerifierAttestation>,
     /// Attestations from verifiers (must have 66%+ agreement)
     pub attestations: Vec<VerifierAttestation>,
     /// Proposer's signature over the block
@@ -111,7 +156,7 @@ pub struct Block {
 }
 
 impl Block {
-    /// Create a new block
+    /// Create a new block without transactions
     #[must_use]
     pub fn new(
         height: u64,
@@ -119,19 +164,38 @@ impl Block {
         proposer: PublicKey,
         verifications: Vec<VerificationResult>,
         state_root: Hash,
+    ) -> Self {
+        Self::with_transactions(height, parent_hash, proposer, Vec::new(), verifications, state_root)
+    }
+
+    /// Create a new block carrying transactions
+    #[must_use]
+    pub fn with_transactions(
+        height: u64,
+        parent_hash: Hash,
+        proposer: PublicKey,
+        transactions: Vec<Transaction>,
+        verifications: Vec<VerificationResult>,
+        state_root: Hash,
     ) -> Self {
         let solutions_root = Self::compute_solutions_root(&verifications);
+        let transactions_root = Self::compute_transactions_root(&transactions);
+        let parent_attestations_root = merkle_root(&[]);
         let timestamp = now_millis();
 
         let header = BlockHeader {
             height,
             parent_hash,
             solutions_root,
+            transactions_root,
+            parent_attestations_root,
             state_root,
             timestamp,
             proposer,
             verification_count: verifications.len() as u32,
+            round: 0,
             version: 1,
+            epoch: None,
         };
 
         let hash = header.compute_hash();
@@ -139,7 +203,10 @@ impl Block {
         Self {
             header,
             hash,
+            transactions,
             verifications,
+            solutions: Vec::new(),
+            parent_attestations: Vec::new(),
             attestations: Vec::new(),
             proposer_signature: Signature::from_bytes([0u8; 64]),
         }
@@ -157,6 +224,71 @@ impl Block {
         )
     }
 
+    /// Create the genesis block described by a chain spec
+    ///
+    /// Nothing comes from the local clock: `spec_hash` takes the parent slot
+    /// and `timestamp` is fixed, so every node derives the same hash.
+    #[must_use]
+    pub fn genesis_from_spec(
+        proposer: PublicKey,
+        spec_hash: Hash,
+        timestamp: Timestamp,
+        state_root: Hash,
+    ) -> Self {
+        Self::new(0, spec_hash, proposer, Vec::new(), state_root).with_timestamp(timestamp)
+    }
+
+    /// Replace the header timestamp (block execution depends on it)
+    #[must_use]
+    pub fn with_timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.header.timestamp = timestamp;
+        self.hash = self.header.compute_hash();
+        self
+    }
+
+    /// Carry attestations to the parent block
+    #[must_use]
+    pub fn with_parent_attestations(mut self, attestations: Vec<VerifierAttestation>) -> Self {
+        self.header.parent_attestations_root = Self::compute_attestations_root(&attestations);
+        self.parent_attestations = attestations;
+        self.hash = self.header.compute_hash();
+        self
+    }
+
+    /// Carry the solutions the verifications settle, in the same order
+    ///
+    /// They are committed through their IDs in the solutions root, so the
+    /// hash does not change.
+    #[must_use]
+    pub fn with_solutions(mut self, solutions: Vec<SolutionCandidate>) -> Self {
+        self.solutions = solutions;
+        self
+    }
+
+    /// Replace the header state root
+    #[must_use]
+    pub fn with_state_root(mut self, state_root: Hash) -> Self {
+        self.header.state_root = state_root;
+        self.hash = self.header.compute_hash();
+        self
+    }
+
+    /// Commit to the validator set of the epoch the block starts
+    #[must_use]
+    pub fn with_epoch(mut self, epoch: Option<EpochCommitment>) -> Self {
+        self.header.epoch = epoch;
+        self.hash = self.header.compute_hash();
+        self
+    }
+
+    /// Set the attestation round the candidate is proposed in
+    #[must_use]
+    pub fn with_round(mut self, round: u32) -> Self {
+        self.header.round = round;
+        self.hash = self.header.compute_hash();
+        self
+    }
+
     /// Compute the merkle root of solutions
     fn compute_solutions_root(verifications: &[VerificationResult]) -> Hash {
         let hashes: Vec<Hash> = verifications
@@ -167,13 +299,38 @@ impl Block {
         merkle_root(&hashes)
     }
 
+    /// Compute the merkle root of transactions
+    fn compute_transactions_root(transactions: &[Transaction]) -> Hash {
+        let hashes: Vec<Hash> = transactions.iter().map(|tx| tx.id).collect();
+        merkle_root(&hashes)
+    }
+
+    /// Compute the merkle root of attestations
+    fn compute_attestations_root(attestations: &[VerifierAttestation]) -> Hash {
+        let hashes: Vec<Hash> = attestations.iter().map(VerifierAttestation::id).collect();
+        merkle_root(&hashes)
+    }
+
     /// Add an attestation from a verifier
     pub fn add_attestation(&mut self, attestation: VerifierAttestation) {
         self.attestations.push(attestation);
     }
 
+    /// Number of distinct verifiers that attested to this block
+    #[must_use]
+    pub fn unique_attesters(&self) -> usize {
+        let mut seen = HashSet::new();
+        self.attestations
+            .iter()
+            .filter(|a| a.block_hash == self.hash && seen.insert(a.verifier))
+            .count()
+    }
+
     /// Check if the block has reached consensus (66%+ attestations)
     ///
+    /// Each verifier counts once. This does not check who the attesters
+    /// are; use `ValidatorSet::check_block` for a stake-weighted quorum.
+    ///
     /// # Arguments
     /// * `total_verifiers` - Total number of active verifiers in the network
     #[must_use]
@@ -183,7 +340,7 @@ impl Block {
         }
 
         let threshold = (total_verifiers as f64 * crate::CONSENSUS_THRESHOLD).ceil() as usize;
-        self.attestations.len() >= threshold
+        self.unique_attesters() >= threshold
     }
 
     /// Get consensus percentage
@@ -193,7 +350,7 @@ impl Block {
             return 0.0;
         }
 
-        self.attestations.len() as f64 / total_verifiers as f64
+        self.unique_attesters() as f64 / total_verifiers as f64
     }
 
     /// Verify block integrity
@@ -213,6 +370,47 @@ impl Block {
             return Err(BlockError::SolutionsRootMismatch);
         }
 
+        // Check transactions root
+        let computed_root = Self::compute_transactions_root(&self.transactions);
+        if computed_root != self.header.transactions_root {
+            return Err(BlockError::TransactionsRootMismatch);
+        }
+
+        // Check parent attestations root
+        let computed_root = Self::compute_attestations_root(&self.parent_attestations);
+        if computed_root != self.header.parent_attestations_root {
+            return Err(BlockError::ParentAttestationsRootMismatch);
+        }
+
+        // Check the header counts what the body carries
+        if usize::try_from(self.header.verification_count).ok() != Some(self.verifications.len()) {
+            return Err(BlockError::VerificationCountMismatch {
+                header: self.header.verification_count,
+                body: self.verifications.len(),
+            });
+        }
+
+        // Each solution may be settled once per block
+        let mut seen = HashSet::new();
+        if let Some(v) = self.verifications.iter().find(|v| !seen.insert(v.solution_id)) {
+            return Err(BlockError::DuplicateSolution(v.solution_id));
+        }
+
+        // Each verification carries the solution it settles, intact
+        if self.solutions.len() != self.verifications.len() {
+            return Err(BlockError::MissingSolutions {
+                verifications: self.verifications.len(),
+                solutions: self.solutions.len(),
+            });
+        }
+        for (v, solution) in self.verifications.iter().zip(&self.solutions) {
+            let settles = (solution.id, solution.job_id) == (v.solution_id, v.job_id);
+            let intact = solution.compute_id() == solution.id && hash_data(&solution.output) == solution.output_hash;
+            if !settles || !intact {
+                return Err(BlockError::SolutionMismatch(v.solution_id));
+            }
+        }
+
         // Verify attestation signatures
         for attestation in &self.attestations {
             attestation.verify_signature()
@@ -222,6 +420,64 @@ impl Block {
         Ok(())
     }
 
+    /// Verify the proposer's signature and every verification result it carries
+    ///
+    /// Results must be signed by the proposer, who ran them. Genesis is fixed
+    /// by the chain spec and carries no signature.
+    ///
+    /// # Errors
+    /// Returns error for the first signature that does not verify
+    pub fn verify_signatures(&self) -> Result<(), BlockError> {
+        if self.header.height > 0 {
+            crate::crypto::verify(&self.header.proposer, &self.signing_bytes(), &self.proposer_signature)
+                .map_err(|_| BlockError::InvalidProposerSignature)?;
+        }
+
+        for v in &self.verifications {
+            if v.verifier != self.header.proposer {
+                return Err(BlockError::VerifierMismatch(v.solution_id));
+            }
+            v.verify_signature()
+                .map_err(|_| BlockError::InvalidVerification(v.solution_id))?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the timestamp against the median of recent blocks and local time
+    ///
+    /// The timestamp must be strictly after `median_time_past` (if the block
+    /// has ancestors) and no more than `max_drift_ms` ahead of `now`.
+    ///
+    /// # Errors
+    /// Returns error if the timestamp is too early or too far in the future
+    pub const fn check_timestamp(
+        &self,
+        median_time_past: Option<Timestamp>,
+        now: Timestamp,
+        max_drift_ms: i64,
+    ) -> Result<(), BlockError> {
+        let timestamp = self.header.timestamp;
+        if let Some(median) = median_time_past {
+            if timestamp <= median {
+                return Err(BlockError::TimestampTooEarly { timestamp, median });
+            }
+        }
+        if timestamp > now.saturating_add(max_drift_ms) {
+            return Err(BlockError::FutureTimestamp);
+        }
+        Ok(())
+    }
+
+    /// Size of the block in its serialized form, in bytes
+    #[must_use]
+    pub fn size(&self) -> usize {
+        bincode::serialized_size(self)
+            .ok()
+            .and_then(|size| usize::try_from(size).ok())
+            .unwrap_or(usize::MAX)
+    }
+
     /// Get bytes to sign (for proposer signature)
     #[must_use]
     pub fn signing_bytes(&self) -> Vec<u8> {
@@ -245,21 +501,91 @@ pub enum BlockError {
     /// Solutions merkle root mismatch
     #[error("solutions root mismatch")]
     SolutionsRootMismatch,
+    /// Transactions merkle root mismatch
+    #[error("transactions root mismatch")]
+    TransactionsRootMismatch,
+    /// Parent attestations merkle root mismatch
+    #[error("parent attestations root mismatch")]
+    ParentAttestationsRootMismatch,
     /// Invalid parent reference
     #[error("invalid parent hash")]
     InvalidParent,
     /// Block height mismatch
     #[error("invalid block height: expected {expected}, got {got}")]
-    InvalidHeight { expected: u64, got: u64 },
+    InvalidHeight {
+        /// Height the block should have
+        expected: u64,
+        /// Height the block claims
+        got: u64,
+    },
     /// Insufficient consensus
     #[error("insufficient consensus: {percentage}% < 66%")]
-    InsufficientConsensus { percentage: f64 },
+    InsufficientConsensus {
+        /// Share of verifiers that attested
+        percentage: f64,
+    },
     /// Invalid attestation signature
     #[error("invalid attestation signature")]
     InvalidAttestation,
     /// Block timestamp too far in future
     #[error("block timestamp in future")]
     FutureTimestamp,
+    /// Block timestamp not after the median of recent blocks
+    #[error("block timestamp {timestamp} not after median of recent blocks {median}")]
+    TimestampTooEarly {
+        /// Block timestamp
+        timestamp: Timestamp,
+        /// Median timestamp of the blocks before it
+        median: Timestamp,
+    },
+    /// Header verification count does not match the body
+    #[error("header counts {header} verifications, body has {body}")]
+    VerificationCountMismatch {
+        /// Count in the header
+        header: u32,
+        /// Verifications in the body
+        body: usize,
+    },
+    /// The same solution is settled twice
+    #[error("duplicate solution {0}")]
+    DuplicateSolution(Id),
+    /// Verifications and the solutions they settle do not pair up
+    #[error("block has {verifications} verifications but carries {solutions} solutions")]
+    MissingSolutions {
+        /// Verifications in the body
+        verifications: usize,
+        /// Solutions in the body
+        solutions: usize,
+    },
+    /// A carried solution is not the one its verification settles, or its
+    /// ID or output hash does not match its contents
+    #[error("block does not carry solution {0} intact")]
+    SolutionMismatch(Id),
+    /// Proposer signature does not verify
+    #[error("invalid proposer signature")]
+    InvalidProposerSignature,
+    /// A verification result's signature does not verify
+    #[error("invalid signature on verification of solution {0}")]
+    InvalidVerification(Id),
+    /// A verification result was not run by the proposer
+    #[error("verification of solution {0} is not the proposer's")]
+    VerifierMismatch(Id),
+    /// Block is over the size limit
+    #[error("block is {size} bytes, limit is {limit}")]
+    TooLarge {
+        /// Serialized size
+        size: usize,
+        /// Largest size allowed
+        limit: usize,
+    },
+    /// Block carries more verifications than allowed
+    #[error("block has {count} verifications, limit is {limit}")]
+    TooManySolutions {
+        /// Verifications in the block
+        count: usize,
+        /// Most verifications allowed
+        limit: usize,
+    },
 }
 
 #[cfg(test)]
@@ -267,6 +593,7 @@ mod tests {
     use super::*;
     use crate::crypto::Keypair;
     use crate::types::verification::VoteResult;
+    use crate::types::{HclawAmount, TransactionKind};
 
     #[test]
     fn test_genesis_block() {
@@ -321,6 +648,21 @@ mod tests {
         assert!(block.has_consensus(10));
     }
 
+    #[test]
+    fn test_consensus_counts_each_verifier_once() {
+        let kp = Keypair::generate();
+        let mut block = Block::new(1, Hash::ZERO, *kp.public_key(), Vec::new(), Hash::ZERO);
+
+        let mut attestation = VerifierAttestation::new(*kp.public_key(), block.hash, Vec::new());
+        attestation.signature = kp.sign(&attestation.signing_bytes());
+        for _ in 0..3 {
+            block.add_attestation(attestation.clone());
+        }
+
+        assert_eq!(block.unique_attesters(), 1);
+        assert!(!block.has_consensus(3));
+    }
+
     #[test]
     fn test_block_integrity() {
         let kp = Keypair::generate();
@@ -334,4 +676,104 @@ mod tests {
 
         assert!(block.verify_integrity().is_ok());
     }
+
+    #[test]
+    fn test_transactions_root_commits_transactions() {
+        let kp = Keypair::generate();
+        let to = crate::types::Address::from_public_key(Keypair::generate().public_key());
+        let kind = TransactionKind::Transfer { to, amount: HclawAmount::from_hclaw(1) };
+        let mut tx = Transaction::new(*kp.public_key(), 0, kind);
+        tx.signature = kp.sign(&tx.signing_bytes());
+
+        let mut block = Block::with_transactions(
+            1,
+            Hash::ZERO,
+            *kp.public_key(),
+            vec![tx],
+            Vec::new(),
+            Hash::ZERO,
+        );
+        assert!(block.verify_integrity().is_ok());
+
+        // Dropping a transaction breaks the commitment
+        block.transactions.clear();
+        assert!(matches!(block.verify_integrity(), Err(BlockError::TransactionsRootMismatch)));
+    }
+
+    fn signed_result(kp: &Keypair, solution_id: Id) -> VerificationResult {
+        let mut result = VerificationResult::new(solution_id, Hash::ZERO, *kp.public_key(), true, None, 1);
+        result.signature = kp.sign(&result.signing_bytes());
+        result
+    }
+
+    #[test]
+    fn test_block_signatures() {
+        let kp = Keypair::generate();
+        let solution = hash_data(b"solution");
+        let mut block = Block::new(1, Hash::ZERO, *kp.public_key(), vec![signed_result(&kp, solution)], Hash::ZERO);
+        assert!(matches!(block.verify_signatures(), Err(BlockError::InvalidProposerSignature)));
+
+        block.proposer_signature = kp.sign(&block.signing_bytes());
+        assert!(block.verify_signatures().is_ok());
+
+        // Results must be the proposer's own, and correctly signed
+        let other = Keypair::generate();
+        let mut theirs = Block::new(1, Hash::ZERO, *kp.public_key(), vec![signed_result(&other, solution)], Hash::ZERO);
+        theirs.proposer_signature = kp.sign(&theirs.signing_bytes());
+        assert!(matches!(theirs.verify_signatures(), Err(BlockError::VerifierMismatch(_))));
+
+        let mut forged = signed_result(&kp, solution);
+        forged.passed = false;
+        let mut lying = Block::new(1, Hash::ZERO, *kp.public_key(), vec![forged], Hash::ZERO);
+        lying.proposer_signature = kp.sign(&lying.signing_bytes());
+        assert!(matches!(lying.verify_signatures(), Err(BlockError::InvalidVerification(_))));
+
+        // Genesis is unsigned
+        assert!(Block::genesis(*kp.public_key()).verify_signatures().is_ok());
+    }
+
+    #[test]
+    fn test_block_body_checks() {
+        let kp = Keypair::generate();
+        let result = signed_result(&kp, hash_data(b"solution"));
+        let twice = Block::new(1, Hash::ZERO, *kp.public_key(), vec![result.clone(), result], Hash::ZERO);
+        assert!(matches!(twice.verify_integrity(), Err(BlockError::DuplicateSolution(_))));
+
+        // Results travel with the solutions they settle
+        let solution = SolutionCandidate::new(Hash::ZERO, *kp.public_key(), b"output".to_vec());
+        let result = signed_result(&kp, solution.id);
+        let bare = Block::new(1, Hash::ZERO, *kp.public_key(), vec![result], Hash::ZERO);
+        assert!(matches!(
+            bare.verify_integrity(),
+            Err(BlockError::MissingSolutions { verifications: 1, solutions: 0 })
+        ));
+        let carried = bare.with_solutions(vec![solution.clone()]);
+        assert!(carried.verify_integrity().is_ok());
+        let mut tampered = solution;
+        tampered.output = b"other".to_vec();
+        let tampered = carried.with_solutions(vec![tampered]);
+        assert!(matches!(tampered.verify_integrity(), Err(BlockError::SolutionMismatch(_))));
+
+        let mut miscounted = Block::new(1, Hash::ZERO, *kp.public_key(), Vec::new(), Hash::ZERO);
+        miscounted.header.verification_count = 1;
+        miscounted.hash = miscounted.header.compute_hash();
+        assert!(matches!(
+            miscounted.verify_integrity(),
+            Err(BlockError::VerificationCountMismatch { header: 1, body: 0 })
+        ));
+    }
+
+    #[test]
+    fn test_block_timestamp_bounds() {
+        let kp = Keypair::generate();
+        let block = Block::new(1, Hash::ZERO, *kp.public_key(), Vec::new(), Hash::ZERO).with_timestamp(1_000);
+
+        assert!(block.check_timestamp(Some(999), 1_000, 0).is_ok());
+        assert!(block.check_timestamp(None, 900, 100).is_ok());
+        assert!(matches!(
+            block.check_timestamp(Some(1_000), 1_000, 0),
+            Err(BlockError::TimestampTooEarly { timestamp: 1_000, median: 1_000 })
+        ));
+        assert!(matches!(block.check_timestamp(None, 899, 100), Err(BlockError::FutureTimestamp)));
+    }
 }
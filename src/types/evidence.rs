@@ -0,0 +1,336 @@
+//! Double-signing evidence.
+//!
+//! A verifier may sign at most one block proposal and attest to at most one
+//! block per height and attestation round (a candidate that times out is
+//! re-proposed in the next round). Two conflicting signatures by the same
+//! key are proof of misbehaviour that anyone can check without any chain
+//! state: the evidence carries the signed headers themselves, so the
+//! signatures, heights and rounds can be verified directly.
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{hash_data, Hash, PublicKey, Signature};
+use super::{Block, BlockHeader, Id, VerifierAttestation};
+
+/// A block header with the proposer's signature over it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedProposal {
+    /// The proposed header
+    pub header: BlockHeader,
+    /// Solution IDs of the block's verifications, which the signature covers
+    pub solutions: Vec<Id>,
+    /// Proposer's signature over the block
+    pub signature: Signature,
+}
+
+impl SignedProposal {
+    /// Take the signed parts of a block
+    #[must_use]
+    pub fn from_block(block: &Block) -> Self {
+        Self {
+            header: block.header.clone(),
+            solutions: block.verifications.iter().map(|v| v.solution_id).collect(),
+            signature: block.proposer_signature,
+        }
+    }
+
+    /// Bytes the proposer signed (the same as `Block::signing_bytes`)
+    #[must_use]
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(self.header.compute_hash().as_bytes());
+        for id in &self.solutions {
+            data.extend_from_slice(id.as_bytes());
+        }
+        data
+    }
+
+    /// Check the proposer's signature
+    ///
+    /// # Errors
+    /// Returns error if the signature is invalid
+    pub fn verify_signature(&self) -> Result<(), crate::crypto::CryptoError> {
+        crate::crypto::verify(&self.header.proposer, &self.signing_bytes(), &self.signature)
+    }
+}
+
+/// An attestation together with the header it attests to
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttestedHeader {
+    /// Header of the attested block
+    pub header: BlockHeader,
+    /// The verifier's attestation
+    pub attestation: VerifierAttestation,
+}
+
+impl AttestedHeader {
+    /// Check that the attestation is for this header and correctly signed
+    ///
+    /// # Errors
+    /// Returns error if the attestation names another block or its signature
+    /// is invalid
+    pub fn verify(&self) -> Result<(), EvidenceError> {
+        if self.header.compute_hash() != self.attestation.block_hash {
+            return Err(EvidenceError::HeaderMismatch);
+        }
+        self.attestation
+            .verify_signature()
+            .map_err(|_| EvidenceError::InvalidSignature)
+    }
+}
+
+/// Proof that a verifier signed two blocks in the same height and round
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Evidence {
+    /// Two proposals by the same proposer
+    DoubleProposal {
+        /// First proposal
+        first: SignedProposal,
+        /// Conflicting proposal
+        second: SignedProposal,
+    },
+    /// Two attestations by the same verifier
+    DoubleAttestation {
+        /// First attestation
+        first: AttestedHeader,
+        /// Conflicting attestation
+        second: AttestedHeader,
+    },
+}
+
+impl Evidence {
+    /// Key that signed both blocks
+    #[must_use]
+    pub const fn offender(&self) -> &PublicKey {
+        match self {
+            Self::DoubleProposal { first, .. } => &first.header.proposer,
+            Self::DoubleAttestation { first, .. } => &first.attestation.verifier,
+        }
+    }
+
+    /// Height both blocks claim
+    #[must_use]
+    pub const fn height(&self) -> u64 {
+        match self {
+            Self::DoubleProposal { first, .. } => first.header.height,
+            Self::DoubleAttestation { first, .. } => first.header.height,
+        }
+    }
+
+    /// Round both blocks claim
+    #[must_use]
+    pub const fn round(&self) -> u32 {
+        match self {
+            Self::DoubleProposal { first, .. } => first.header.round,
+            Self::DoubleAttestation { first, .. } => first.header.round,
+        }
+    }
+
+    /// Hashes of the two conflicting blocks, in the order given
+    #[must_use]
+    pub fn block_hashes(&self) -> (Hash, Hash) {
+        match self {
+            Self::DoubleProposal { first, second } => {
+                (first.header.compute_hash(), second.header.compute_hash())
+            }
+            Self::DoubleAttestation { first, second } => {
+                (first.attestation.block_hash, second.attestation.block_hash)
+            }
+        }
+    }
+
+    /// Identifier of the offence, the same whichever way round it was reported
+    #[must_use]
+    pub fn id(&self) -> Id {
+        let (a, b) = self.block_hashes();
+        let (low, high) = if a.as_bytes() <= b.as_bytes() { (a, b) } else { (b, a) };
+        let tag: &[u8] = match self {
+            Self::DoubleProposal { .. } => b"evidence/proposal/",
+            Self::DoubleAttestation { .. } => b"evidence/attestation/",
+        };
+
+        let mut data = tag.to_vec();
+        data.extend_from_slice(self.offender().as_bytes());
+        data.extend_from_slice(low.as_bytes());
+        data.extend_from_slice(high.as_bytes());
+        hash_data(&data)
+    }
+
+    /// Bytes that commit to the whole evidence, for transaction signing
+    #[must_use]
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut data = self.id().as_bytes().to_vec();
+        match self {
+            Self::DoubleProposal { first, second } => {
+                for proposal in [first, second] {
+                    data.extend_from_slice(&proposal.signing_bytes());
+                    data.extend_from_slice(proposal.signature.as_bytes());
+                }
+            }
+            Self::DoubleAttestation { first, second } => {
+                for attested in [first, second] {
+                    data.extend_from_slice(&attested.attestation.signing_bytes());
+                    data.extend_from_slice(attested.attestation.signature.as_bytes());
+                }
+            }
+        }
+        data
+    }
+
+    /// Check that both signatures are valid and by the same key, for two
+    /// different blocks in the same height and round
+    ///
+    /// # Errors
+    /// Returns error if the evidence does not prove double signing
+    pub fn verify(&self) -> Result<(), EvidenceError> {
+        let (first, second) = match self {
+            Self::DoubleProposal { first, second } => {
+                if first.header.proposer != second.header.proposer {
+                    return Err(EvidenceError::DifferentSigners);
+                }
+                for proposal in [first, second] {
+                    proposal
+                        .verify_signature()
+                        .map_err(|_| EvidenceError::InvalidSignature)?;
+                }
+                (&first.header, &second.header)
+            }
+            Self::DoubleAttestation { first, second } => {
+                if first.attestation.verifier != second.attestation.verifier {
+                    return Err(EvidenceError::DifferentSigners);
+                }
+                first.verify()?;
+                second.verify()?;
+                (&first.header, &second.header)
+            }
+        };
+
+        if first.height != second.height {
+            return Err(EvidenceError::DifferentHeights);
+        }
+        if first.round != second.round {
+            return Err(EvidenceError::DifferentRounds);
+        }
+        let (a, b) = self.block_hashes();
+        if a == b {
+            return Err(EvidenceError::SameBlock);
+        }
+        Ok(())
+    }
+}
+
+/// Evidence validation errors
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum EvidenceError {
+    /// Both signatures are for the same block
+    #[error("evidence names the same block twice")]
+    SameBlock,
+    /// The two blocks are at different heights
+    #[error("evidence blocks are at different heights")]
+    DifferentHeights,
+    /// The two blocks were proposed in different rounds
+    #[error("evidence blocks are from different rounds")]
+    DifferentRounds,
+    /// The two signatures are by different keys
+    #[error("evidence is signed by different keys")]
+    DifferentSigners,
+    /// An attestation is not for the header it comes with
+    #[error("attestation does not match its header")]
+    HeaderMismatch,
+    /// A signature does not verify
+    #[error("invalid evidence signature")]
+    InvalidSignature,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+
+    fn proposal(kp: &Keypair, height: u64, parent: Hash) -> Block {
+        let mut block = Block::new(height, parent, *kp.public_key(), Vec::new(), Hash::ZERO);
+        block.proposer_signature = kp.sign(&block.signing_bytes());
+        block
+    }
+
+    fn attested(kp: &Keypair, block: &Block) -> AttestedHeader {
+        let mut attestation = VerifierAttestation::new(*kp.public_key(), block.hash, Vec::new());
+        attestation.signature = kp.sign(&attestation.signing_bytes());
+        AttestedHeader { header: block.header.clone(), attestation }
+    }
+
+    #[test]
+    fn test_double_proposal() {
+        let kp = Keypair::generate();
+        let a = proposal(&kp, 5, Hash::ZERO);
+        let b = proposal(&kp, 5, crate::crypto::hash_data(b"other"));
+
+        let evidence = Evidence::DoubleProposal {
+            first: SignedProposal::from_block(&a),
+            second: SignedProposal::from_block(&b),
+        };
+        assert!(evidence.verify().is_ok());
+        assert_eq!(evidence.offender(), kp.public_key());
+        assert_eq!(evidence.height(), 5);
+
+        // The same offence reported the other way round has the same ID
+        let swapped = Evidence::DoubleProposal {
+            first: SignedProposal::from_block(&b),
+            second: SignedProposal::from_block(&a),
+        };
+        assert_eq!(swapped.id(), evidence.id());
+
+        let same = Evidence::DoubleProposal {
+            first: SignedProposal::from_block(&a),
+            second: SignedProposal::from_block(&a),
+        };
+        assert!(matches!(same.verify(), Err(EvidenceError::SameBlock)));
+
+        let later = proposal(&kp, 6, Hash::ZERO);
+        let apart = Evidence::DoubleProposal {
+            first: SignedProposal::from_block(&a),
+            second: SignedProposal::from_block(&later),
+        };
+        assert!(matches!(apart.verify(), Err(EvidenceError::DifferentHeights)));
+
+        // Re-proposing after a round times out is allowed
+        let mut retry = Block::new(5, Hash::ZERO, *kp.public_key(), Vec::new(), Hash::ZERO).with_round(1);
+        retry.proposer_signature = kp.sign(&retry.signing_bytes());
+        let retried = Evidence::DoubleProposal {
+            first: SignedProposal::from_block(&a),
+            second: SignedProposal::from_block(&retry),
+        };
+        assert!(matches!(retried.verify(), Err(EvidenceError::DifferentRounds)));
+
+        // An unsigned block proves nothing
+        let mut unsigned = SignedProposal::from_block(&b);
+        unsigned.signature = Signature::from_bytes([0u8; 64]);
+        let forged = Evidence::DoubleProposal { first: SignedProposal::from_block(&a), second: unsigned };
+        assert!(matches!(forged.verify(), Err(EvidenceError::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_double_attestation() {
+        let kp = Keypair::generate();
+        let proposer = Keypair::generate();
+        let a = proposal(&proposer, 5, Hash::ZERO);
+        let b = proposal(&proposer, 5, crate::crypto::hash_data(b"other"));
+
+        let evidence = Evidence::DoubleAttestation { first: attested(&kp, &a), second: attested(&kp, &b) };
+        assert!(evidence.verify().is_ok());
+        assert_eq!(evidence.offender(), kp.public_key());
+        assert_eq!(evidence.block_hashes(), (a.hash, b.hash));
+
+        let others = Evidence::DoubleAttestation {
+            first: attested(&kp, &a),
+            second: attested(&Keypair::generate(), &b),
+        };
+        assert!(matches!(others.verify(), Err(EvidenceError::DifferentSigners)));
+
+        // The header must be the one attested to
+        let mut mismatched = attested(&kp, &b);
+        mismatched.header = a.header.clone();
+        let lying = Evidence::DoubleAttestation { first: attested(&kp, &a), second: mismatched };
+        assert!(matches!(lying.verify(), Err(EvidenceError::HeaderMismatch)));
+    }
+}
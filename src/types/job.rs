@@ -7,7 +7,7 @@
 
 use serde::{Deserialize, Serialize};
 
-use crate::crypto::{hash_data, Hash, PublicKey, Signature};
+use crate::crypto::{hash_data, Commitment, Hash, Hasher, PublicKey, Signature};
 use super::{Address, Id, HclawAmount, Timestamp, now_millis};
 
 /// Type of job (determines verification method)
@@ -34,6 +34,9 @@ pub enum JobStatus {
     Expired,
     /// Disputed (for subjective jobs under Schelling Point consensus)
     Disputed,
+    /// Test vectors were not revealed in time; the bounty went to the
+    /// solvers who had opened their locks
+    Forfeited,
 }
 
 /// Specification for how to verify the solution
@@ -60,6 +63,222 @@ pub enum VerificationSpec {
         /// Quality threshold (0-100)
         quality_threshold: u8,
     },
+
+    /// JSON output checked against a registered schema
+    JsonSchema {
+        /// Hash of the schema's bytes
+        schema_hash: Hash,
+    },
+
+    /// Output scored by a deterministic function, passing if the score
+    /// compares to the target (for optimisation tasks)
+    ///
+    /// The score is recorded in the `VerificationResult`, so solutions can be
+    /// ranked against each other as well.
+    ObjectiveThreshold {
+        /// Function computing the score of the output against the input
+        scorer: Scorer,
+        /// How the score must compare to the target
+        comparison: Comparison,
+        /// Target score
+        target: i64,
+    },
+
+    /// Hidden test vectors, committed to up front and revealed once
+    /// solutions are locked in (for code-generation tasks)
+    ///
+    /// The output is a WASM program (see the `wasm` module docs) that passes
+    /// if it produces the expected output for enough of the vectors. Its pass
+    /// ratio is recorded in the `VerificationResult` score, in basis points.
+    TestVectors {
+        /// Commitment to the vectors (see `TestVector::commit`)
+        commitment: Commitment,
+        /// Export of the solution program to call on each input
+        entry_point: String,
+        /// Share of vectors, in percent, a solution must pass
+        min_pass_percent: u8,
+        /// Solutions must be locked in before this time; the requester
+        /// reveals the vectors after it and before the job expires
+        reveal_at: Timestamp,
+    },
+}
+
+/// One hidden test case of a `TestVectors` job
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestVector {
+    /// Input handed to the solution program
+    pub input: Vec<u8>,
+    /// Output the program must produce
+    pub expected: Vec<u8>,
+}
+
+impl TestVector {
+    /// Canonical bytes of a set of vectors, which the commitment covers
+    #[must_use]
+    pub fn encode_all(vectors: &[Self]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(vectors.len() as u64).to_le_bytes());
+        for vector in vectors {
+            for bytes in [&vector.input, &vector.expected] {
+                data.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+                data.extend_from_slice(bytes);
+            }
+        }
+        data
+    }
+
+    /// Commit to a set of vectors with a secret nonce
+    #[must_use]
+    pub fn commit(vectors: &[Self], nonce: &[u8; 32]) -> Commitment {
+        Commitment::create(Self::encode_all(vectors), nonce)
+    }
+}
+
+/// A solver's lock on a `TestVectors` job
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolutionLock {
+    /// Solver that locked in
+    pub solver: Address,
+    /// `SolutionLock::commitment` to the solver's output
+    pub commitment: Hash,
+    /// Bond still escrowed from the solver for this lock
+    pub bond: HclawAmount,
+    /// Output hash the lock was opened with, once it has been
+    pub opened: Option<Hash>,
+}
+
+impl SolutionLock {
+    /// Commitment binding `solver` to an output hash under a secret salt
+    ///
+    /// Nobody else can open it, so copying a lock gains nothing.
+    #[must_use]
+    pub fn commitment(solver: &Address, output_hash: &Hash, salt: &[u8; 32]) -> Hash {
+        let mut hasher = Hasher::new();
+        hasher.update(solver.as_bytes()).update(output_hash.as_bytes()).update(salt);
+        hasher.finalize()
+    }
+}
+
+/// Deterministic scoring function for `ObjectiveThreshold` jobs
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Scorer {
+    /// A scoring function built into every node
+    Builtin(BuiltinScorer),
+    /// A scoring function exported by a published WASM module
+    Wasm {
+        /// Hash of the WASM module bytecode
+        module_hash: Hash,
+        /// Exported scoring function
+        function: String,
+    },
+}
+
+/// Scoring functions built into every node
+///
+/// Inputs and outputs are JSON.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum BuiltinScorer {
+    /// Length of a closed tour. Input: a square matrix of non-negative
+    /// integer distances. Output: an array visiting every index exactly once.
+    TourLength,
+    /// Total value of a packing. Input: `{"capacity": c, "items":
+    /// [{"weight": w, "value": v}, ...]}`. Output: an array of distinct item
+    /// indices whose weights fit the capacity.
+    KnapsackValue,
+}
+
+/// How a score must compare to its target
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Comparison {
+    /// Score below the target
+    LessThan,
+    /// Score at or below the target
+    AtMost,
+    /// Score above the target
+    GreaterThan,
+    /// Score at or above the target
+    AtLeast,
+}
+
+impl Comparison {
+    /// Whether `score` compares to `target` this way
+    #[must_use]
+    pub const fn holds(self, score: i64, target: i64) -> bool {
+        match self {
+            Self::LessThan => score < target,
+            Self::AtMost => score <= target,
+            Self::GreaterThan => score > target,
+            Self::AtLeast => score >= target,
+        }
+    }
+
+    /// Whether score `a` is better than score `b` for a job with this
+    /// comparison
+    #[must_use]
+    pub const fn prefers(self, a: i64, b: i64) -> bool {
+        match self {
+            Self::LessThan | Self::AtMost => a < b,
+            Self::GreaterThan | Self::AtLeast => a > b,
+        }
+    }
+}
+
+impl std::fmt::Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let symbol = match self {
+            Self::LessThan => "<",
+            Self::AtMost => "<=",
+            Self::GreaterThan => ">",
+            Self::AtLeast => ">=",
+        };
+        f.write_str(symbol)
+    }
+}
+
+impl VerificationSpec {
+    /// Which kind of spec this is, without its parameters
+    #[must_use]
+    pub const fn kind(&self) -> VerificationKind {
+        match self {
+            Self::HashMatch { .. } => VerificationKind::HashMatch,
+            Self::WasmVerifier { .. } => VerificationKind::WasmVerifier,
+            Self::SchellingPoint { .. } => VerificationKind::SchellingPoint,
+            Self::JsonSchema { .. } => VerificationKind::JsonSchema,
+            Self::ObjectiveThreshold { .. } => VerificationKind::ObjectiveThreshold,
+            Self::TestVectors { .. } => VerificationKind::TestVectors,
+        }
+    }
+}
+
+/// Kind of a `VerificationSpec`, which selects the backend that checks it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum VerificationKind {
+    /// `VerificationSpec::HashMatch`
+    HashMatch,
+    /// `VerificationSpec::WasmVerifier`
+    WasmVerifier,
+    /// `VerificationSpec::SchellingPoint`
+    SchellingPoint,
+    /// `VerificationSpec::JsonSchema`
+    JsonSchema,
+    /// `VerificationSpec::ObjectiveThreshold`
+    ObjectiveThreshold,
+    /// `VerificationSpec::TestVectors`
+    TestVectors,
+}
+
+impl std::fmt::Display for VerificationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::HashMatch => "hash match",
+            Self::WasmVerifier => "WASM verifier",
+            Self::SchellingPoint => "Schelling point",
+            Self::JsonSchema => "JSON schema",
+            Self::ObjectiveThreshold => "objective threshold",
+            Self::TestVectors => "test vectors",
+        };
+        f.write_str(name)
+    }
 }
 
 /// A Job Packet submitted by a Requester
@@ -91,6 +310,10 @@ pub struct JobPacket {
     pub expires_at: Timestamp,
     /// Requester's signature over the job data
     pub signature: Signature,
+    /// Solutions locked in on chain for a `TestVectors` job
+    pub locked_solutions: Vec<SolutionLock>,
+    /// Test vectors, once the requester has revealed them on chain
+    pub revealed_vectors: Option<Vec<TestVector>>,
 }
 
 impl JobPacket {
@@ -124,6 +347,8 @@ impl JobPacket {
             created_at: now,
             expires_at,
             signature: Signature::from_bytes([0u8; 64]), // Placeholder
+            locked_solutions: Vec::new(),
+            revealed_vectors: None,
         };
 
         job.id = job.compute_id();
@@ -143,20 +368,34 @@ impl JobPacket {
         hash_data(&data)
     }
 
+    /// Hash of every field the requester signs
+    ///
+    /// Covers the whole job as submitted, verification spec and
+    /// `requester_address` included. The status, signature and the lock and
+    /// reveal records the chain fills in later are left out.
+    #[must_use]
+    pub fn signed_hash(&self) -> Hash {
+        let signed = (
+            &self.id,
+            self.job_type,
+            &self.requester,
+            &self.requester_address,
+            &self.input,
+            &self.description,
+            self.bounty,
+            self.burn_fee,
+            &self.verification,
+            self.created_at,
+            self.expires_at,
+        );
+        // Serializing plain fields into a Vec cannot fail
+        hash_data(&bincode::serialize(&signed).unwrap_or_default())
+    }
+
     /// Get the bytes to sign
     #[must_use]
     pub fn signing_bytes(&self) -> Vec<u8> {
-        // Sign everything except the signature itself
-        let mut data = Vec::new();
-        data.extend_from_slice(self.id.as_bytes());
-        data.extend_from_slice(&[self.job_type as u8]);
-        data.extend_from_slice(self.requester.as_bytes());
-        data.extend_from_slice(&self.input);
-        data.extend_from_slice(&self.bounty.raw().to_le_bytes());
-        data.extend_from_slice(&self.burn_fee.raw().to_le_bytes());
-        data.extend_from_slice(&self.created_at.to_le_bytes());
-        data.extend_from_slice(&self.expires_at.to_le_bytes());
-        data
+        self.signed_hash().as_bytes().to_vec()
     }
 
     /// Check if the job has expired
@@ -165,6 +404,14 @@ impl JobPacket {
         now_millis() > self.expires_at
     }
 
+    /// Whether `solver` opened a lock on this job with `output_hash`
+    #[must_use]
+    pub fn is_locked_in(&self, solver: &Address, output_hash: &Hash) -> bool {
+        self.locked_solutions
+            .iter()
+            .any(|lock| lock.solver == *solver && lock.opened.as_ref() == Some(output_hash))
+    }
+
     /// Check if the job is still valid for processing
     #[must_use]
     pub fn is_valid(&self) -> bool {
@@ -177,6 +424,15 @@ impl JobPacket {
         self.bounty.saturating_add(self.burn_fee)
     }
 
+    /// Account that escrows the bounty, pays it out and gets it back
+    ///
+    /// Derived from the `requester` key that signs the job, never taken from
+    /// `requester_address`.
+    #[must_use]
+    pub fn escrow_address(&self) -> Address {
+        Address::from_public_key(&self.requester)
+    }
+
     /// Verify the job packet signature
     ///
     /// # Errors
@@ -228,6 +484,22 @@ mod tests {
         assert!(job.verify_signature().is_ok());
     }
 
+    #[test]
+    fn test_job_signature_covers_spec_and_address() {
+        let (job, _) = create_test_job();
+
+        let mut swapped_spec = job.clone();
+        swapped_spec.verification = VerificationSpec::HashMatch {
+            expected_hash: hash_data(b"other output"),
+        };
+        assert!(swapped_spec.verify_signature().is_err());
+
+        let mut swapped_address = job;
+        swapped_address.requester_address =
+            Address::from_public_key(Keypair::generate().public_key());
+        assert!(swapped_address.verify_signature().is_err());
+    }
+
     #[test]
     fn test_job_id_deterministic() {
         let kp = Keypair::generate();
@@ -5,13 +5,20 @@ mod amount;
 mod job;
 mod solution;
 mod block;
+mod evidence;
+mod transaction;
 mod verification;
 
 pub use address::Address;
-pub use amount::HclawAmount;
-pub use job::{JobPacket, JobType, JobStatus, VerificationSpec};
+pub use amount::{serde_decimal, HclawAmount, MAX_SUPPLY};
+pub use job::{
+    BuiltinScorer, Comparison, JobPacket, JobType, JobStatus, Scorer, SolutionLock, TestVector,
+    VerificationKind, VerificationSpec,
+};
 pub use solution::{SolutionCandidate, SolutionStatus};
-pub use block::{Block, BlockHeader, VerifierAttestation};
+pub use block::{Block, BlockError, BlockHeader, EpochCommitment, VerifierAttestation};
+pub use evidence::{AttestedHeader, Evidence, EvidenceError, SignedProposal};
+pub use transaction::{Transaction, TransactionError, TransactionKind};
 pub use verification::{VerificationResult, VerificationVote, VoteResult, VotingResults};
 
 use chrono::{DateTime, Utc};
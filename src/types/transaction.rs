@@ -0,0 +1,321 @@
+//! Signed account transactions.
+//!
+//! A transaction moves HCLAW, changes an account's stake, publishes a job
+//! or verifier module on chain, or takes part in a `TestVectors` job's
+//! commit-reveal (solvers lock in solutions, then the requester reveals the
+//! vectors). Each one carries the sender's next nonce, so a signed
+//! transaction can be included at most once and only in order.
+
+use serde::{Deserialize, Serialize};
+
+use crate::crypto::{hash_data, Hash, PublicKey, Signature};
+use crate::wasm::{WasmError, WasmRuntime};
+use super::{Address, Evidence, EvidenceError, HclawAmount, Id, JobPacket, JobStatus, TestVector};
+
+/// What a transaction does
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum TransactionKind {
+    /// Move available balance to another account
+    Transfer {
+        /// Recipient
+        to: Address,
+        /// Amount to send
+        amount: HclawAmount,
+    },
+    /// Lock available balance as stake
+    Stake {
+        /// Amount to stake
+        amount: HclawAmount,
+    },
+    /// Release staked balance
+    Unstake {
+        /// Amount to unstake
+        amount: HclawAmount,
+    },
+    /// Post a job on chain, burning its `burn_fee` and escrowing its `bounty`
+    SubmitJob(Box<JobPacket>),
+    /// Report a verifier that signed two conflicting blocks, slashing its stake
+    ReportEvidence(Box<Evidence>),
+    /// Return the sender to the validator set once its jail term is served
+    Unjail,
+    /// Publish a WASM verifier module, burning `wasm::storage_burn` of its size
+    UploadModule(Vec<u8>),
+    /// Lock in a solution to a `TestVectors` job before its vectors are
+    /// revealed, escrowing a bond
+    LockSolution {
+        /// Job being solved
+        job_id: Id,
+        /// `SolutionLock::commitment` to the solution's output
+        commitment: Hash,
+    },
+    /// Reveal the vectors a `TestVectors` job committed to (requester only)
+    RevealVectors {
+        /// Job the vectors belong to
+        job_id: Id,
+        /// The vectors
+        vectors: Vec<TestVector>,
+        /// Nonce the commitment was made with
+        nonce: [u8; 32],
+    },
+    /// Open the sender's lock on a `TestVectors` job once lock-in has closed
+    OpenLock {
+        /// Job the lock is on
+        job_id: Id,
+        /// Hash of the locked-in output
+        output_hash: Hash,
+        /// Salt the lock was committed with
+        salt: [u8; 32],
+    },
+}
+
+impl TransactionKind {
+    /// Stable tag used in signing bytes
+    const fn tag(&self) -> u8 {
+        match self {
+            Self::Transfer { .. } => 0,
+            Self::Stake { .. } => 1,
+            Self::Unstake { .. } => 2,
+            Self::SubmitJob(_) => 3,
+            Self::ReportEvidence(_) => 4,
+            Self::Unjail => 5,
+            Self::UploadModule(_) => 6,
+            Self::LockSolution { .. } => 7,
+            Self::RevealVectors { .. } => 8,
+            Self::OpenLock { .. } => 9,
+        }
+    }
+}
+
+/// A signed transaction from one account
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Transaction {
+    /// Transaction ID (hash of the signed contents)
+    pub id: Id,
+    /// Sender's public key
+    pub sender: PublicKey,
+    /// Sender's account nonce this transaction consumes
+    pub nonce: u64,
+    /// The operation
+    pub kind: TransactionKind,
+    /// Sender's signature over the transaction
+    pub signature: Signature,
+}
+
+impl Transaction {
+    /// Create a new transaction (unsigned)
+    #[must_use]
+    pub fn new(sender: PublicKey, nonce: u64, kind: TransactionKind) -> Self {
+        let mut tx = Self {
+            id: Id::ZERO,
+            sender,
+            nonce,
+            kind,
+            signature: Signature::from_bytes([0u8; 64]),
+        };
+        tx.id = hash_data(&tx.signing_bytes());
+        tx
+    }
+
+    /// Get the bytes to sign
+    #[must_use]
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(self.sender.as_bytes());
+        data.extend_from_slice(&self.nonce.to_le_bytes());
+        data.push(self.kind.tag());
+
+        match &self.kind {
+            TransactionKind::Transfer { to, amount } => {
+                data.extend_from_slice(to.as_bytes());
+                data.extend_from_slice(&amount.raw().to_le_bytes());
+            }
+            TransactionKind::Stake { amount } | TransactionKind::Unstake { amount } => {
+                data.extend_from_slice(&amount.raw().to_le_bytes());
+            }
+            TransactionKind::SubmitJob(job) => {
+                data.extend_from_slice(&job.signing_bytes());
+                data.extend_from_slice(job.signature.as_bytes());
+            }
+            TransactionKind::ReportEvidence(evidence) => {
+                data.extend_from_slice(&evidence.signing_bytes());
+            }
+            TransactionKind::Unjail => {}
+            TransactionKind::UploadModule(wasm) => {
+                data.extend_from_slice(hash_data(wasm).as_bytes());
+            }
+            TransactionKind::LockSolution { job_id, commitment } => {
+                data.extend_from_slice(job_id.as_bytes());
+                data.extend_from_slice(commitment.as_bytes());
+            }
+            TransactionKind::RevealVectors { job_id, vectors, nonce } => {
+                data.extend_from_slice(job_id.as_bytes());
+                data.extend_from_slice(hash_data(&TestVector::encode_all(vectors)).as_bytes());
+                data.extend_from_slice(nonce);
+            }
+            TransactionKind::OpenLock { job_id, output_hash, salt } => {
+                data.extend_from_slice(job_id.as_bytes());
+                data.extend_from_slice(output_hash.as_bytes());
+                data.extend_from_slice(salt);
+            }
+        }
+
+        data
+    }
+
+    /// Address of the sending account
+    #[must_use]
+    pub fn sender_address(&self) -> Address {
+        Address::from_public_key(&self.sender)
+    }
+
+    /// Available balance the sender needs for this transaction
+    #[must_use]
+    pub fn cost(&self) -> HclawAmount {
+        match &self.kind {
+            TransactionKind::Transfer { amount, .. } | TransactionKind::Stake { amount } => *amount,
+            TransactionKind::Unstake { .. }
+            | TransactionKind::ReportEvidence(_)
+            | TransactionKind::Unjail
+            | TransactionKind::LockSolution { .. }
+            | TransactionKind::RevealVectors { .. }
+            | TransactionKind::OpenLock { .. } => HclawAmount::ZERO,
+            TransactionKind::SubmitJob(job) => job.total_cost(),
+            TransactionKind::UploadModule(wasm) => crate::wasm::storage_burn(wasm.len()),
+        }
+    }
+
+    /// Verify the transaction signature
+    ///
+    /// # Errors
+    /// Returns error if signature is invalid
+    pub fn verify_signature(&self) -> Result<(), crate::crypto::CryptoError> {
+        crate::crypto::verify(&self.sender, &self.signing_bytes(), &self.signature)
+    }
+
+    /// Check everything about the transaction that does not depend on state
+    ///
+    /// # Errors
+    /// Returns error if the ID or a signature is wrong, an embedded job is
+    /// not a fresh job signed by the sender, embedded evidence does not
+    /// prove double signing, or an uploaded module fails static validation
+    pub fn validate(&self) -> Result<(), TransactionError> {
+        if hash_data(&self.signing_bytes()) != self.id {
+            return Err(TransactionError::IdMismatch);
+        }
+        self.verify_signature()
+            .map_err(|_| TransactionError::InvalidSignature)?;
+
+        if let TransactionKind::SubmitJob(job) = &self.kind {
+            if job.requester != self.sender {
+                return Err(TransactionError::InvalidJob("requester is not the sender".to_string()));
+            }
+            if job.requester_address != Address::from_public_key(&job.requester) {
+                return Err(TransactionError::InvalidJob(
+                    "requester address does not match the requester key".to_string(),
+                ));
+            }
+            if job.compute_id() != job.id {
+                return Err(TransactionError::InvalidJob("job id mismatch".to_string()));
+            }
+            if job.status != JobStatus::Pending {
+                return Err(TransactionError::InvalidJob("job is not pending".to_string()));
+            }
+            if !job.locked_solutions.is_empty() || job.revealed_vectors.is_some() {
+                return Err(TransactionError::InvalidJob("job carries chain-side records".to_string()));
+            }
+            job.verify_signature()
+                .map_err(|_| TransactionError::InvalidJob("invalid job signature".to_string()))?;
+        }
+        if let TransactionKind::ReportEvidence(evidence) = &self.kind {
+            evidence.verify().map_err(TransactionError::InvalidEvidence)?;
+        }
+        if let TransactionKind::UploadModule(wasm) = &self.kind {
+            WasmRuntime::default()
+                .check_module(wasm)
+                .map_err(TransactionError::InvalidModule)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Transaction validation errors
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum TransactionError {
+    /// ID does not match the contents
+    #[error("transaction id mismatch")]
+    IdMismatch,
+    /// Sender signature does not verify
+    #[error("invalid transaction signature")]
+    InvalidSignature,
+    /// Embedded job is malformed
+    #[error("invalid job: {0}")]
+    InvalidJob(String),
+    /// Embedded evidence is not proof of double signing
+    #[error("invalid evidence: {0}")]
+    InvalidEvidence(EvidenceError),
+    /// Uploaded module is not a valid verifier module
+    #[error("invalid module: {0}")]
+    InvalidModule(WasmError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::Keypair;
+    use crate::types::{JobType, VerificationSpec};
+
+    fn signed(kp: &Keypair, nonce: u64, kind: TransactionKind) -> Transaction {
+        let mut tx = Transaction::new(*kp.public_key(), nonce, kind);
+        tx.signature = kp.sign(&tx.signing_bytes());
+        tx
+    }
+
+    #[test]
+    fn test_transaction_signature() {
+        let kp = Keypair::generate();
+        let to = Address::from_public_key(Keypair::generate().public_key());
+        let tx = signed(&kp, 0, TransactionKind::Transfer { to, amount: HclawAmount::from_hclaw(5) });
+
+        assert!(tx.validate().is_ok());
+        assert_eq!(tx.cost(), HclawAmount::from_hclaw(5));
+
+        // Bumping the nonce invalidates both the ID and the signature
+        let mut replay = tx.clone();
+        replay.nonce = 1;
+        assert!(matches!(replay.validate(), Err(TransactionError::IdMismatch)));
+        replay.id = hash_data(&replay.signing_bytes());
+        assert!(matches!(replay.validate(), Err(TransactionError::InvalidSignature)));
+    }
+
+    #[test]
+    fn test_submit_job_requires_sender_as_requester() {
+        let kp = Keypair::generate();
+        let other = Keypair::generate();
+
+        let mut job = JobPacket::new(
+            JobType::Deterministic,
+            *other.public_key(),
+            b"input".to_vec(),
+            "Test".to_string(),
+            HclawAmount::from_hclaw(10),
+            HclawAmount::from_hclaw(1),
+            VerificationSpec::HashMatch { expected_hash: Hash::ZERO },
+            3600,
+        );
+        job.signature = other.sign(&job.signing_bytes());
+
+        let tx = signed(&kp, 0, TransactionKind::SubmitJob(Box::new(job.clone())));
+        assert!(matches!(tx.validate(), Err(TransactionError::InvalidJob(_))));
+
+        let tx = signed(&other, 0, TransactionKind::SubmitJob(Box::new(job.clone())));
+        assert!(tx.validate().is_ok());
+        assert_eq!(tx.cost(), HclawAmount::from_hclaw(11));
+
+        // A re-signed job naming someone else's address is still refused
+        job.requester_address = Address::from_public_key(kp.public_key());
+        job.signature = other.sign(&job.signing_bytes());
+        let tx = signed(&other, 0, TransactionKind::SubmitJob(Box::new(job)));
+        assert!(matches!(tx.validate(), Err(TransactionError::InvalidJob(_))));
+    }
+}
@@ -18,6 +18,8 @@ pub struct VerificationResult {
     pub passed: bool,
     /// Optional error message if verification failed
     pub error: Option<String>,
+    /// Score of the output, for jobs that score solutions
+    pub score: Option<i64>,
     /// Time taken to verify (in milliseconds)
     pub verification_time_ms: u64,
     /// When the verification was completed
@@ -43,12 +45,20 @@ impl VerificationResult {
             verifier,
             passed,
             error,
+            score: None,
             verification_time_ms,
             verified_at: now_millis(),
             signature: Signature::from_bytes([0u8; 64]),
         }
     }
 
+    /// Attach the output's score
+    #[must_use]
+    pub const fn with_score(mut self, score: Option<i64>) -> Self {
+        self.score = score;
+        self
+    }
+
     /// Get bytes to sign
     #[must_use]
     pub fn signing_bytes(&self) -> Vec<u8> {
@@ -57,6 +67,13 @@ impl VerificationResult {
         data.extend_from_slice(self.job_id.as_bytes());
         data.extend_from_slice(self.verifier.as_bytes());
         data.push(if self.passed { 1 } else { 0 });
+        match self.score {
+            Some(score) => {
+                data.push(1);
+                data.extend_from_slice(&score.to_le_bytes());
+            }
+            None => data.push(0),
+        }
         data.extend_from_slice(&self.verified_at.to_le_bytes());
         data
     }
@@ -13,12 +13,17 @@ mod stake;
 pub use honey_pot::{HoneyPotGenerator, HoneyPotDetector};
 pub use stake::{StakeManager, SlashingReason, StakeInfo};
 
+use std::sync::Arc;
 
 use crate::crypto::{Hash, Keypair, PublicKey};
 use crate::types::{
-    Address, Block, JobPacket, HclawAmount, SolutionCandidate, VerificationResult,
+    Address, Block, EpochCommitment, JobPacket, HclawAmount, SolutionCandidate, Transaction,
+    VerificationKind, VerificationResult, VerifierAttestation,
+};
+use crate::consensus::{
+    BlockProducer, BlockProducerConfig, Completed, ConsensusError, ExecutorConfig, SolutionVerifier,
+    VerificationExecutor,
 };
-use crate::consensus::{BlockProducer, BlockProducerConfig};
 
 /// Verifier node configuration
 #[derive(Clone, Debug)]
@@ -31,6 +36,8 @@ pub struct VerifierConfig {
     pub generate_honey_pots: bool,
     /// Honey pot injection rate (0.0 - 1.0)
     pub honey_pot_rate: f64,
+    /// Worker pool and per-solution budgets for verification
+    pub executor: ExecutorConfig,
 }
 
 impl Default for VerifierConfig {
@@ -40,6 +47,7 @@ impl Default for VerifierConfig {
             block_config: BlockProducerConfig::default(),
             generate_honey_pots: false,
             honey_pot_rate: 0.01, // 1% of solutions are honey pots
+            executor: ExecutorConfig::default(),
         }
     }
 }
@@ -54,8 +62,8 @@ pub struct Verifier {
     config: VerifierConfig,
     /// Block producer
     block_producer: BlockProducer,
-    /// Stake manager
-    stake_manager: StakeManager,
+    /// Runs verification off the caller's thread
+    executor: VerificationExecutor,
     /// Honey pot generator (if enabled)
     honey_pot_generator: Option<HoneyPotGenerator>,
     /// Honey pot detector
@@ -66,6 +74,9 @@ pub struct Verifier {
 
 impl Verifier {
     /// Create a new verifier node
+    ///
+    /// # Panics
+    /// Never in practice: every 32-byte string is a valid Ed25519 secret key
     #[must_use]
     pub fn new(keypair: Keypair, config: VerifierConfig) -> Self {
         let address = Address::from_public_key(keypair.public_key());
@@ -76,18 +87,18 @@ impl Verifier {
             None
         };
 
+        // Blocks are signed with the verifier's own key, so the proposer
+        // schedule sees its stake
+        let producer_key = crate::crypto::SecretKey::from_bytes(keypair.secret_key().to_bytes())
+            .map(Keypair::from_secret)
+            .expect("secret key bytes round-trip");
+
         Self {
-            block_producer: BlockProducer::new(
-                Keypair::from_secret(
-                    crate::crypto::SecretKey::from_bytes(keypair.public_key().as_bytes().clone())
-                        .unwrap_or_else(|_| crate::crypto::SecretKey::generate())
-                ),
-                config.block_config.clone(),
-            ),
+            block_producer: BlockProducer::new(producer_key, config.block_config.clone()),
+            executor: VerificationExecutor::new(config.executor.clone()),
             address,
             config,
             keypair,
-            stake_manager: StakeManager::new(),
             honey_pot_generator,
             honey_pot_detector: HoneyPotDetector::new(),
             stats: VerifierStats::default(),
@@ -106,6 +117,17 @@ impl Verifier {
         self.keypair.public_key()
     }
 
+    /// Check specs of `kind` with `backend` from now on
+    ///
+    /// Returns the backend it replaces, if any.
+    pub fn register_verifier(
+        &mut self,
+        kind: VerificationKind,
+        backend: Box<dyn SolutionVerifier>,
+    ) -> Option<Arc<dyn SolutionVerifier>> {
+        self.block_producer.register_verifier(kind, backend)
+    }
+
     /// Process a solution candidate
     ///
     /// Returns the verification result and whether this was a honey pot.
@@ -123,6 +145,66 @@ impl Verifier {
         let result = self.block_producer.verify_solution(job, solution)
             .map_err(|e| VerifierError::VerificationFailed(e.to_string()))?;
 
+        self.tally(&result, is_honey_pot, false);
+
+        Ok((result, is_honey_pot))
+    }
+
+    /// Number of solutions the verification workers can take now
+    ///
+    /// Take no more than this from the mempool; the rest waits there.
+    #[must_use]
+    pub fn verification_capacity(&self) -> usize {
+        self.executor.capacity()
+    }
+
+    /// Queue a solution for verification on the worker pool
+    ///
+    /// Its result comes back from a later `collect_verifications`.
+    ///
+    /// # Errors
+    /// Returns error if the solution cannot be queued
+    pub fn submit_solution(
+        &mut self,
+        job: JobPacket,
+        solution: SolutionCandidate,
+    ) -> Result<(), VerifierError> {
+        self.executor
+            .submit(self.block_producer.verifiers(), job, solution)
+            .map_err(|e| VerifierError::VerificationFailed(e.to_string()))
+    }
+
+    /// Results of queued solutions that have finished or run out of time
+    ///
+    /// Each is signed and, if it passed, queued for our next block, as with
+    /// `process_solution`.
+    pub fn collect_verifications(&mut self) -> Vec<Result<(VerificationResult, bool), VerifierError>> {
+        self.executor
+            .poll()
+            .into_iter()
+            .map(|completed| self.settle(completed))
+            .collect()
+    }
+
+    /// Turn a verdict from the worker pool into a result
+    fn settle(&mut self, completed: Completed) -> Result<(VerificationResult, bool), VerifierError> {
+        let Completed { job, solution, verdict, verification_time_ms, timed_out } = completed;
+        self.stats.solutions_processed += 1;
+        let is_honey_pot = self.honey_pot_detector.is_honey_pot(&solution.id);
+
+        let verdict = verdict.map_err(|e| VerifierError::VerificationFailed(e.to_string()))?;
+        let result = self.block_producer.record_verdict(&job, &solution, verdict, verification_time_ms);
+        self.tally(&result, is_honey_pot, timed_out);
+
+        Ok((result, is_honey_pot))
+    }
+
+    /// Count a result in the stats
+    const fn tally(&mut self, result: &VerificationResult, is_honey_pot: bool, timed_out: bool) {
+        self.stats.verification_time_ms += result.verification_time_ms;
+        if timed_out {
+            self.stats.solutions_timed_out += 1;
+        }
         if result.passed {
             self.stats.solutions_verified += 1;
         } else {
@@ -133,8 +215,6 @@ impl Verifier {
                 self.stats.honey_pots_caught += 1;
             }
         }
-
-        Ok((result, is_honey_pot))
     }
 
     /// Generate a honey pot solution for a job
@@ -179,16 +259,74 @@ impl Verifier {
         Ok(Some(block))
     }
 
-    /// Get verifier statistics
+    /// Try to produce a block, computing the state root from the included body
+    ///
+    /// Any pending transaction is reason enough to produce a block. See
+    /// [`BlockProducer::produce_block_with`].
+    ///
+    /// # Errors
+    /// Returns error if the state root cannot be computed
+    pub fn try_produce_block_with<F>(
+        &mut self,
+        transactions: Vec<Transaction>,
+        parent_attestations: Vec<VerifierAttestation>,
+        epoch: Option<EpochCommitment>,
+        state_root_for: F,
+    ) -> Result<Option<Block>, VerifierError>
+    where
+        F: FnMut(&Block) -> Result<Hash, ConsensusError>,
+    {
+        if transactions.is_empty() && !self.block_producer.should_produce_block() {
+            return Ok(None);
+        }
+
+        let block = self.block_producer.produce_block_with(transactions, parent_attestations, epoch, state_root_for)
+            .map_err(|e| VerifierError::BlockProductionFailed(e.to_string()))?;
+
+        self.stats.blocks_produced += 1;
+
+        Ok(Some(block))
+    }
+
+    /// Give up on a candidate block that missed its attestation threshold
+    ///
+    /// Its verifications go into the next candidate.
+    pub fn abandon_block(&mut self, block: Block) {
+        self.block_producer.requeue(block);
+    }
+
+    /// Whether `solution` passes our own backend for its job's spec
+    ///
+    /// Used to re-check the verdicts in a block before attesting to it. A
+    /// solution the backend cannot check counts as failing.
     #[must_use]
-    pub const fn stats(&self) -> &VerifierStats {
-        &self.stats
+    pub fn recheck(&self, job: &JobPacket, solution: &SolutionCandidate) -> bool {
+        self.block_producer
+            .verifiers()
+            .verify(job, solution)
+            .is_ok_and(|verdict| verdict.passed)
     }
 
-    /// Get stake info for an address
+    /// Sign an attestation for a block that was re-checked against our state
     #[must_use]
-    pub fn get_stake(&self, address: &Address) -> Option<&StakeInfo> {
-        self.stake_manager.get_stake(address)
+    pub fn attest(&self, block: &Block) -> VerifierAttestation {
+        self.block_producer.attest(block)
+    }
+
+    /// Point block production at the current chain tip
+    pub fn set_chain_tip(&mut self, tip_height: u64, tip_hash: Hash) {
+        self.block_producer.set_chain_state(tip_height, tip_hash);
+    }
+
+    /// Set the attestation round for the next block we propose
+    pub const fn set_round(&mut self, round: u32) {
+        self.block_producer.set_round(round);
+    }
+
+    /// Get verifier statistics
+    #[must_use]
+    pub const fn stats(&self) -> &VerifierStats {
+        &self.stats
     }
 }
 
@@ -201,6 +339,10 @@ pub struct VerifierStats {
     pub solutions_verified: u64,
     /// Solutions that failed verification
     pub solutions_rejected: u64,
+    /// Solutions that failed for running out of time
+    pub solutions_timed_out: u64,
+    /// Time spent verifying solutions, in milliseconds
+    pub verification_time_ms: u64,
     /// Honey pots correctly caught
     pub honey_pots_caught: u64,
     /// Blocks produced
@@ -291,4 +433,44 @@ mod tests {
         assert!(!is_honey_pot);
         assert_eq!(verifier.stats().solutions_verified, 1);
     }
+
+    #[test]
+    fn test_solutions_verified_on_worker_pool() {
+        let mut verifier = create_test_verifier();
+        let (job, solution) = create_test_job_solution();
+        let capacity = verifier.verification_capacity();
+        verifier.submit_solution(job, solution).unwrap();
+        assert_eq!(verifier.verification_capacity(), capacity - 1);
+
+        let mut outcomes = Vec::new();
+        while outcomes.is_empty() {
+            outcomes = verifier.collect_verifications();
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        let (result, is_honey_pot) = outcomes.pop().unwrap().unwrap();
+        assert!(result.passed);
+        assert!(!is_honey_pot);
+        assert_eq!(verifier.stats().solutions_verified, 1);
+        assert_eq!(verifier.stats().solutions_timed_out, 0);
+
+        // The result goes into our next block
+        let block = verifier.try_produce_block(Hash::ZERO).unwrap().unwrap();
+        assert_eq!(block.verifications[0].solution_id, result.solution_id);
+    }
+
+    #[test]
+    fn test_blocks_proposed_under_verifier_key() {
+        let mut verifier = create_test_verifier();
+        let (job, solution) = create_test_job_solution();
+        verifier.process_solution(&job, &solution).unwrap();
+
+        let block = verifier.try_produce_block(Hash::ZERO).unwrap().unwrap();
+        assert_eq!(block.header.proposer, *verifier.public_key());
+        assert!(crate::crypto::verify(
+            verifier.public_key(),
+            &block.signing_bytes(),
+            &block.proposer_signature
+        )
+        .is_ok());
+    }
 }
@@ -5,6 +5,7 @@
 
 use std::collections::HashMap;
 
+use crate::consensus::{ProposerSchedule, ValidatorSet};
 use crate::crypto::Hash;
 use crate::types::{Address, HclawAmount, Timestamp, now_millis};
 
@@ -38,7 +39,7 @@ pub enum SlashingReason {
 impl SlashingReason {
     /// Get the slashing percentage for this reason
     #[must_use]
-    pub const fn slash_percentage(&self) -> u8 {
+    pub fn slash_percentage(&self) -> u8 {
         match self {
             // Honey pot approval = 100% slash (entire stake)
             Self::HoneyPotApproval { .. } => 100,
@@ -46,8 +47,11 @@ impl SlashingReason {
             Self::InvalidVerification { .. } => 10,
             // Double signing = 100% slash
             Self::DoubleSigning { .. } => 100,
-            // Downtime = 1% per hour (handled elsewhere)
-            Self::Downtime { .. } => 1,
+            // Downtime = 1% per started hour offline, up to 10%
+            Self::Downtime { offline_duration_secs } => {
+                let hours = offline_duration_secs.div_ceil(3600).clamp(1, 10);
+                u8::try_from(hours).unwrap_or(10)
+            }
         }
     }
 }
@@ -300,6 +304,26 @@ impl StakeManager {
     pub fn active_verifiers(&self) -> Vec<&StakeInfo> {
         self.stakes.values().filter(|s| s.is_active).collect()
     }
+
+    /// Proposer schedule over the verifiers that can verify, weighted by effective stake
+    #[must_use]
+    pub fn proposer_schedule(&self) -> ProposerSchedule {
+        ProposerSchedule::new(self.eligible_stakes())
+    }
+
+    /// Attesting set of the verifiers that can verify, weighted by effective stake
+    #[must_use]
+    pub fn validator_set(&self) -> ValidatorSet {
+        ValidatorSet::new(self.eligible_stakes())
+    }
+
+    /// Verifiers that can verify, with their effective stake
+    pub fn eligible_stakes(&self) -> impl Iterator<Item = (Address, HclawAmount)> + '_ {
+        self.stakes
+            .values()
+            .filter(|s| s.can_verify(self.min_stake))
+            .map(|s| (s.address, s.effective_stake()))
+    }
 }
 
 /// Staking errors
@@ -369,8 +393,9 @@ mod tests {
 
         assert_eq!(slashed.whole_hclaw(), 1000);
 
-        // Should no longer be active
+        // Should no longer be active, nor scheduled to propose
         assert!(!manager.can_verify(&addr));
+        assert!(manager.proposer_schedule().is_empty());
     }
 
     #[test]
@@ -396,6 +421,15 @@ mod tests {
         assert_eq!(stake.effective_stake().whole_hclaw(), 900);
     }
 
+    #[test]
+    fn test_downtime_slash_grows_with_time_offline() {
+        let downtime = |secs| SlashingReason::Downtime { offline_duration_secs: secs };
+        assert_eq!(downtime(0).slash_percentage(), 1);
+        assert_eq!(downtime(3600).slash_percentage(), 1);
+        assert_eq!(downtime(3601).slash_percentage(), 2);
+        assert_eq!(downtime(u64::MAX).slash_percentage(), 10);
+    }
+
     #[test]
     fn test_unstaking() {
         let mut manager = StakeManager::with_min_stake(HclawAmount::from_hclaw(100));
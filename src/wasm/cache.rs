@@ -0,0 +1,85 @@
+//! Content-addressed module cache on disk.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::crypto::{hash_data, Hash};
+
+use super::ModuleSource;
+
+/// Module bytes stored as `<hash>.wasm` files in one directory
+///
+/// Files are checked against their name when loaded, so a corrupt file is
+/// treated as missing.
+#[derive(Clone, Debug)]
+pub struct ModuleCache {
+    dir: PathBuf,
+}
+
+impl ModuleCache {
+    /// Open (or create) a cache in `dir`
+    ///
+    /// # Errors
+    /// Returns error if the directory cannot be created
+    pub fn open<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir: dir.as_ref().to_path_buf() })
+    }
+
+    /// Store a module, returning its hash
+    ///
+    /// # Errors
+    /// Returns error if the file cannot be written
+    pub fn insert(&self, wasm: &[u8]) -> io::Result<Hash> {
+        let hash = hash_data(wasm);
+        let path = self.path(&hash);
+        if !path.exists() {
+            // Write under a temporary name so a crash never leaves a partial module
+            let partial = path.with_extension("partial");
+            fs::write(&partial, wasm)?;
+            fs::rename(&partial, &path)?;
+        }
+        Ok(hash)
+    }
+
+    /// Whether a module with this hash is stored
+    #[must_use]
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.path(hash).exists()
+    }
+
+    fn path(&self, hash: &Hash) -> PathBuf {
+        self.dir.join(format!("{}.wasm", hash.to_hex()))
+    }
+}
+
+impl ModuleSource for ModuleCache {
+    fn load(&self, hash: &Hash) -> Option<Vec<u8>> {
+        fs::read(self.path(hash))
+            .ok()
+            .filter(|wasm| hash_data(wasm) == *hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_is_content_addressed() {
+        let dir = std::env::temp_dir().join(format!("hardclaw_test_modules_{}", rand::random::<u64>()));
+        let cache = ModuleCache::open(&dir).unwrap();
+
+        let hash = cache.insert(b"module bytes").unwrap();
+        assert!(cache.contains(&hash));
+        assert_eq!(cache.load(&hash), Some(b"module bytes".to_vec()));
+
+        // A file that no longer matches its name is not served
+        fs::write(cache.path(&hash), b"tampered").unwrap();
+        assert_eq!(cache.load(&hash), None);
+        assert_eq!(cache.load(&Hash::ZERO), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+//! Sandboxed WebAssembly verifiers.
+//!
+//! Jobs with a `VerificationSpec::WasmVerifier` spec are checked by a WASM
+//! module chosen by the requester and named by its content hash. Every
+//! verifier must reach the same verdict, so modules run in a deterministic
+//! interpreter:
+//! - no host imports at all, so no clocks, randomness or I/O;
+//! - no floating point;
+//! - a fixed fuel allowance and memory limit (see `WasmLimits`).
+//!
+//! ## ABI
+//!
+//! A verifier module exports:
+//! - `memory`: its linear memory;
+//! - `alloc(len: i32) -> i32`: reserve `len` bytes and return their address;
+//! - the job's entry point, `(input_ptr: i32, input_len: i32, output_ptr: i32,
+//!   output_len: i32) -> i32`.
+//!
+//! The runtime copies `job.input` and `solution.output` into memory reserved
+//! through `alloc`, then calls the entry point, which returns 1 to accept the
+//! output and 0 to reject it.
+//!
+//! Modules can also export scoring functions for `ObjectiveThreshold` jobs.
+//! These take the same arguments but return the output's score as an `i64`.
+//!
+//! ## Solution programs
+//!
+//! The output of a `TestVectors` job is itself a WASM program, run in the
+//! same sandbox. It exports `memory`, `alloc` and the job's entry point,
+//! `(input_ptr: i32, input_len: i32) -> i64`, which returns the address of
+//! its output in the high 32 bits and the output's length in the low 32.
+//!
+//! ## Publishing modules
+//!
+//! Modules are published on chain with an `UploadModule` transaction, which
+//! burns `storage_burn` of the module's size. Nodes keep the bytes in a
+//! `ModuleCache` and fetch modules they missed from peers.
+
+mod cache;
+mod runtime;
+
+pub use cache::ModuleCache;
+pub use runtime::{ModuleExports, WasmError, WasmLimits, WasmRuntime, ALLOC_EXPORT, MEMORY_EXPORT};
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use crate::crypto::{hash_data, Hash};
+use crate::types::HclawAmount;
+
+/// Burned per byte of an uploaded module (0.001 HCLAW)
+pub const STORAGE_BURN_PER_BYTE: HclawAmount = HclawAmount::from_raw(HclawAmount::from_hclaw(1).raw() / 1_000);
+
+/// Burn charged for uploading a module of `len` bytes
+#[must_use]
+pub const fn storage_burn(len: usize) -> HclawAmount {
+    HclawAmount::from_raw(STORAGE_BURN_PER_BYTE.raw().saturating_mul(len as u128))
+}
+
+/// Where verifier modules are loaded from, by content hash
+pub trait ModuleSource: Send + Sync {
+    /// Bytes of the module with this hash, if known
+    fn load(&self, hash: &Hash) -> Option<Vec<u8>>;
+}
+
+/// Modules held in memory
+#[derive(Debug, Default)]
+pub struct MemoryModules {
+    modules: RwLock<HashMap<Hash, Vec<u8>>>,
+}
+
+impl MemoryModules {
+    /// Create an empty set of modules
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a module, returning its hash
+    pub fn insert(&self, wasm: Vec<u8>) -> Hash {
+        let hash = hash_data(&wasm);
+        if let Ok(mut modules) = self.modules.write() {
+            modules.insert(hash, wasm);
+        }
+        hash
+    }
+}
+
+impl ModuleSource for MemoryModules {
+    fn load(&self, hash: &Hash) -> Option<Vec<u8>> {
+        self.modules.read().ok()?.get(hash).cloned()
+    }
+}
@@ -0,0 +1,543 @@
+//! Deterministic WASM execution with fuel metering and memory limits.
+
+use wasmi::core::{TrapCode, ValType};
+use wasmi::{
+    Config, Engine, ExternType, FuncType, Instance, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+    TypedFunc, WasmParams, WasmResults,
+};
+
+use crate::crypto::{hash_data, Hash};
+
+/// Export holding the module's linear memory
+pub const MEMORY_EXPORT: &str = "memory";
+
+/// Export the runtime reserves memory for the arguments through
+pub const ALLOC_EXPORT: &str = "alloc";
+
+/// Resources a single verification may use
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WasmLimits {
+    /// Fuel for instantiation and the entry point together (about one unit
+    /// per instruction)
+    pub fuel: u64,
+    /// Largest linear memory, in bytes
+    pub max_memory_bytes: usize,
+    /// Largest module, in bytes; an upload must fit in one block
+    pub max_module_bytes: usize,
+}
+
+impl Default for WasmLimits {
+    fn default() -> Self {
+        Self {
+            fuel: 50_000_000,
+            max_memory_bytes: 16 * 1024 * 1024,
+            max_module_bytes: 512 * 1024,
+        }
+    }
+}
+
+/// Why a module could not be loaded or did not reach a verdict
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum WasmError {
+    /// No module with this hash is known locally
+    #[error("module {0} not found")]
+    ModuleNotFound(Hash),
+    /// The module bytes do not hash to the hash they were loaded by
+    #[error("module hash mismatch: expected {expected}, got {actual}")]
+    HashMismatch {
+        /// Hash the module was loaded by
+        expected: Hash,
+        /// Hash of the bytes
+        actual: Hash,
+    },
+    /// The module is larger than allowed
+    #[error("module is {size} bytes, limit is {limit}")]
+    ModuleTooLarge {
+        /// Module size
+        size: usize,
+        /// Limit
+        limit: usize,
+    },
+    /// The bytes are not a valid module, or use a disabled feature
+    #[error("invalid module: {0}")]
+    InvalidModule(String),
+    /// The module imports from the host
+    #[error("module imports {module}::{name}; verifier modules may not import anything")]
+    ForbiddenImport {
+        /// Import module
+        module: String,
+        /// Import name
+        name: String,
+    },
+    /// A required export is missing
+    #[error("module does not export `{0}`")]
+    MissingExport(String),
+    /// An export does not have the type the ABI requires
+    #[error("export `{0}` does not match the verifier ABI")]
+    BadSignature(String),
+    /// No export has the entry point or scoring signature
+    #[error("module exports no entry point or scoring function")]
+    NoEntryPoint,
+    /// Fuel ran out before the entry point returned
+    #[error("out of fuel")]
+    OutOfFuel,
+    /// The module tried to use more memory than allowed, or an argument
+    /// does not fit
+    #[error("memory limit exceeded")]
+    MemoryLimit,
+    /// Execution trapped
+    #[error("trap: {0}")]
+    Trap(String),
+    /// The entry point returned something other than 0 or 1
+    #[error("entry point returned {0}, expected 0 or 1")]
+    BadReturn(i32),
+    /// A program's output lies outside its memory
+    #[error("program output is out of bounds")]
+    OutputOutOfBounds,
+}
+
+impl WasmError {
+    /// Stable identifier of the error kind, for results and logs
+    #[must_use]
+    pub const fn code(&self) -> &'static str {
+        match self {
+            Self::ModuleNotFound(_) => "module_not_found",
+            Self::HashMismatch { .. } => "hash_mismatch",
+            Self::ModuleTooLarge { .. } => "module_too_large",
+            Self::InvalidModule(_) => "invalid_module",
+            Self::ForbiddenImport { .. } => "forbidden_import",
+            Self::MissingExport(_) => "missing_export",
+            Self::BadSignature(_) => "bad_signature",
+            Self::NoEntryPoint => "no_entry_point",
+            Self::OutOfFuel => "out_of_fuel",
+            Self::MemoryLimit => "memory_limit",
+            Self::Trap(_) => "trap",
+            Self::BadReturn(_) => "bad_return",
+            Self::OutputOutOfBounds => "output_out_of_bounds",
+        }
+    }
+
+    /// Whether the error says nothing about the solution, only that this
+    /// node could not get hold of the right module
+    #[must_use]
+    pub const fn is_local(&self) -> bool {
+        matches!(self, Self::ModuleNotFound(_) | Self::HashMismatch { .. })
+    }
+}
+
+impl From<wasmi::Error> for WasmError {
+    fn from(e: wasmi::Error) -> Self {
+        match e.as_trap_code() {
+            Some(TrapCode::OutOfFuel) => Self::OutOfFuel,
+            Some(TrapCode::GrowthOperationLimited) => Self::MemoryLimit,
+            Some(code) => Self::Trap(code.to_string()),
+            None => Self::Trap(e.to_string()),
+        }
+    }
+}
+
+/// Functions a module exports that jobs can name, by kind
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ModuleExports {
+    /// Exports with the entry point signature, sorted
+    pub entry_points: Vec<String>,
+    /// Exports with the scoring signature, sorted
+    pub scorers: Vec<String>,
+}
+
+/// One call into a module
+struct Call<'a> {
+    wasm: &'a [u8],
+    module_hash: &'a Hash,
+    func: &'a str,
+    input: &'a [u8],
+    output: &'a [u8],
+}
+
+/// Runs verifier modules under the ABI described in the module docs
+#[derive(Debug)]
+pub struct WasmRuntime {
+    engine: Engine,
+    limits: WasmLimits,
+}
+
+impl Default for WasmRuntime {
+    fn default() -> Self {
+        Self::new(WasmLimits::default())
+    }
+}
+
+impl WasmRuntime {
+    /// Create a runtime enforcing `limits`
+    #[must_use]
+    pub fn new(limits: WasmLimits) -> Self {
+        let mut config = Config::default();
+        config.consume_fuel(true).floats(false);
+        Self { engine: Engine::new(&config), limits }
+    }
+
+    /// Limits every run is held to
+    #[must_use]
+    pub const fn limits(&self) -> &WasmLimits {
+        &self.limits
+    }
+
+    /// Check a module statically: size, validity, no imports, and the exports
+    /// the ABI requires, with `entry_point` among them
+    ///
+    /// # Errors
+    /// Returns the first problem found
+    pub fn validate(&self, wasm: &[u8], entry_point: &str) -> Result<(), WasmError> {
+        self.compile(wasm, entry_point).map(|_| ())
+    }
+
+    /// Check a module statically as for `validate`, and list the exports a
+    /// job could name as its entry point or scoring function
+    ///
+    /// # Errors
+    /// Returns the first problem found, or `NoEntryPoint` if no export has
+    /// the entry point or scoring signature
+    pub fn check_module(&self, wasm: &[u8]) -> Result<ModuleExports, WasmError> {
+        let module = self.load(wasm)?;
+        let exports = ModuleExports {
+            entry_points: funcs_of_type(&module, &entry_point_type()),
+            scorers: funcs_of_type(&module, &scorer_type()),
+        };
+        if exports.entry_points.is_empty() && exports.scorers.is_empty() {
+            return Err(WasmError::NoEntryPoint);
+        }
+        Ok(exports)
+    }
+
+    /// Run `entry_point` of the module with hash `module_hash` over a job's
+    /// input and a solution's output
+    ///
+    /// Returns whether the module accepted the output.
+    ///
+    /// # Errors
+    /// Returns error if the module does not match its hash, is not a valid
+    /// verifier module, or fails to return a verdict within the limits
+    pub fn run(
+        &self,
+        wasm: &[u8],
+        module_hash: &Hash,
+        entry_point: &str,
+        input: &[u8],
+        output: &[u8],
+    ) -> Result<bool, WasmError> {
+        let call = Call { wasm, module_hash, func: entry_point, input, output };
+        match self.call::<i32>(&call, &entry_point_type())? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => Err(WasmError::BadReturn(other)),
+        }
+    }
+
+    /// Run the scoring function `scorer` of the module with hash
+    /// `module_hash` over a job's input and a solution's output
+    ///
+    /// Returns the score.
+    ///
+    /// # Errors
+    /// Returns error if the module does not match its hash, is not a valid
+    /// module with that scoring function, or fails to return a score within
+    /// the limits
+    pub fn score(
+        &self,
+        wasm: &[u8],
+        module_hash: &Hash,
+        scorer: &str,
+        input: &[u8],
+        output: &[u8],
+    ) -> Result<i64, WasmError> {
+        let call = Call { wasm, module_hash, func: scorer, input, output };
+        self.call::<i64>(&call, &scorer_type())
+    }
+
+    /// Check a solution program statically: size, validity, no imports, and
+    /// the exports the program ABI requires, with `entry_point` among them
+    ///
+    /// # Errors
+    /// Returns the first problem found
+    pub fn check_program(&self, program: &[u8], entry_point: &str) -> Result<(), WasmError> {
+        let module = self.load(program)?;
+        check_func(&module, entry_point, &program_type())
+    }
+
+    /// Run `entry_point` of a solution program over one input and return the
+    /// output it produced
+    ///
+    /// Each run gets a fresh instance and the full fuel allowance.
+    ///
+    /// # Errors
+    /// Returns error if the program is not valid under the program ABI, fails
+    /// to return within the limits, or points outside its memory
+    pub fn execute(&self, program: &[u8], entry_point: &str, input: &[u8]) -> Result<Vec<u8>, WasmError> {
+        let module = self.load(program)?;
+        check_func(&module, entry_point, &program_type())?;
+        let mut sandbox = self.instantiate(&module)?;
+        let func = sandbox.func::<(i32, i32), i64>(entry_point)?;
+        let (input_ptr, input_len) = sandbox.pass(input)?;
+        let packed = func.call(&mut sandbox.store, (input_ptr, input_len))?;
+
+        // Address in the high 32 bits, length in the low 32
+        let ptr = usize::try_from(packed >> 32).map_err(|_| WasmError::OutputOutOfBounds)?;
+        let len = usize::try_from(packed & 0xFFFF_FFFF).map_err(|_| WasmError::OutputOutOfBounds)?;
+        let end = ptr.checked_add(len).ok_or(WasmError::OutputOutOfBounds)?;
+        sandbox
+            .memory
+            .data(&sandbox.store)
+            .get(ptr..end)
+            .map(<[u8]>::to_vec)
+            .ok_or(WasmError::OutputOutOfBounds)
+    }
+
+    /// Instantiate a module, copy the arguments in and call a function of
+    /// type `ty` with them
+    fn call<R: WasmResults>(&self, call: &Call<'_>, ty: &FuncType) -> Result<R, WasmError> {
+        let actual = hash_data(call.wasm);
+        if actual != *call.module_hash {
+            return Err(WasmError::HashMismatch { expected: *call.module_hash, actual });
+        }
+        let module = self.load(call.wasm)?;
+        check_func(&module, call.func, ty)?;
+
+        let mut sandbox = self.instantiate(&module)?;
+        let func = sandbox.func::<(i32, i32, i32, i32), R>(call.func)?;
+        let (input_ptr, input_len) = sandbox.pass(call.input)?;
+        let (output_ptr, output_len) = sandbox.pass(call.output)?;
+
+        Ok(func.call(&mut sandbox.store, (input_ptr, input_len, output_ptr, output_len))?)
+    }
+
+    /// Instantiate a loaded module in a fresh store with its own fuel and
+    /// memory limit
+    fn instantiate(&self, module: &Module) -> Result<Sandbox, WasmError> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.limits.max_memory_bytes)
+            .instances(1)
+            .trap_on_grow_failure(true)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits: &mut StoreLimits| limits);
+        store.set_fuel(self.limits.fuel).map_err(|e| WasmError::Trap(e.to_string()))?;
+
+        let instance = Linker::<StoreLimits>::new(&self.engine)
+            .instantiate(&mut store, module)?
+            .start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, MEMORY_EXPORT)
+            .ok_or_else(|| WasmError::MissingExport(MEMORY_EXPORT.to_string()))?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, ALLOC_EXPORT)
+            .map_err(|_| WasmError::BadSignature(ALLOC_EXPORT.to_string()))?;
+        Ok(Sandbox { store, instance, memory, alloc })
+    }
+
+    /// Compile a module and check it against the ABI, with `entry_point`
+    fn compile(&self, wasm: &[u8], entry_point: &str) -> Result<Module, WasmError> {
+        let module = self.load(wasm)?;
+        check_func(&module, entry_point, &entry_point_type())?;
+        Ok(module)
+    }
+
+    /// Compile a module and check the parts of the ABI every module shares
+    fn load(&self, wasm: &[u8]) -> Result<Module, WasmError> {
+        if wasm.len() > self.limits.max_module_bytes {
+            return Err(WasmError::ModuleTooLarge {
+                size: wasm.len(),
+                limit: self.limits.max_module_bytes,
+            });
+        }
+        let module = Module::new(&self.engine, wasm).map_err(|e| WasmError::InvalidModule(e.to_string()))?;
+
+        if let Some(import) = module.imports().next() {
+            return Err(WasmError::ForbiddenImport {
+                module: import.module().to_string(),
+                name: import.name().to_string(),
+            });
+        }
+
+        match module.get_export(MEMORY_EXPORT) {
+            Some(ExternType::Memory(_)) => {}
+            Some(_) => return Err(WasmError::BadSignature(MEMORY_EXPORT.to_string())),
+            None => return Err(WasmError::MissingExport(MEMORY_EXPORT.to_string())),
+        }
+        check_func(&module, ALLOC_EXPORT, &FuncType::new([ValType::I32], [ValType::I32]))?;
+        Ok(module)
+    }
+}
+
+/// A module instance with its own store, fuel and memory
+struct Sandbox {
+    store: Store<StoreLimits>,
+    instance: Instance,
+    memory: Memory,
+    alloc: TypedFunc<i32, i32>,
+}
+
+impl Sandbox {
+    /// Exported function `name`, typed
+    fn func<P: WasmParams, R: WasmResults>(&self, name: &str) -> Result<TypedFunc<P, R>, WasmError> {
+        self.instance
+            .get_typed_func::<P, R>(&self.store, name)
+            .map_err(|_| WasmError::BadSignature(name.to_string()))
+    }
+
+    /// Copy `bytes` into memory reserved through `alloc`, returning their
+    /// address and length
+    fn pass(&mut self, bytes: &[u8]) -> Result<(i32, i32), WasmError> {
+        let len = i32::try_from(bytes.len()).map_err(|_| WasmError::MemoryLimit)?;
+        let ptr = self.alloc.call(&mut self.store, len)?;
+        let offset = usize::try_from(ptr).map_err(|_| WasmError::MemoryLimit)?;
+        self.memory.write(&mut self.store, offset, bytes).map_err(|_| WasmError::MemoryLimit)?;
+        Ok((ptr, len))
+    }
+}
+
+/// Type of an entry point: `(input_ptr, input_len, output_ptr, output_len) -> verdict`
+fn entry_point_type() -> FuncType {
+    FuncType::new([ValType::I32; 4], [ValType::I32])
+}
+
+/// Type of a scoring function: `(input_ptr, input_len, output_ptr, output_len) -> score`
+fn scorer_type() -> FuncType {
+    FuncType::new([ValType::I32; 4], [ValType::I64])
+}
+
+/// Type of a solution program's entry point: `(input_ptr, input_len) -> (output_ptr << 32) | output_len`
+fn program_type() -> FuncType {
+    FuncType::new([ValType::I32; 2], [ValType::I64])
+}
+
+/// Names of the functions `module` exports with type `ty`, sorted
+fn funcs_of_type(module: &Module, ty: &FuncType) -> Vec<String> {
+    let mut names: Vec<String> = module
+        .exports()
+        .filter(|export| matches!(export.ty(), ExternType::Func(found) if found == ty))
+        .map(|export| export.name().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Check that `module` exports a function `name` of type `expected`
+fn check_func(module: &Module, name: &str, expected: &FuncType) -> Result<(), WasmError> {
+    match module.get_export(name) {
+        Some(ExternType::Func(ty)) if ty == *expected => Ok(()),
+        Some(_) => Err(WasmError::BadSignature(name.to_string())),
+        None => Err(WasmError::MissingExport(name.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts an output equal to its input, with a bump allocator
+    const ECHO: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (local.get $ptr))
+          (func (export "verify") (param $in i32) (param $in_len i32) (param $out i32) (param $out_len i32) (result i32)
+            (local $i i32)
+            (if (i32.ne (local.get $in_len) (local.get $out_len)) (then (return (i32.const 0))))
+            (block $done
+              (loop $next
+                (br_if $done (i32.ge_u (local.get $i) (local.get $in_len)))
+                (if (i32.ne (i32.load8_u (i32.add (local.get $in) (local.get $i)))
+                            (i32.load8_u (i32.add (local.get $out) (local.get $i))))
+                  (then (return (i32.const 0))))
+                (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                (br $next)))
+            (i32.const 1))
+          (func (export "spin") (param i32 i32 i32 i32) (result i32)
+            (loop $forever (br $forever))
+            (i32.const 1)))
+    "#;
+
+    fn module(wat: &str) -> (Vec<u8>, Hash) {
+        let wasm = wat::parse_str(wat).unwrap();
+        let hash = hash_data(&wasm);
+        (wasm, hash)
+    }
+
+    #[test]
+    fn test_runs_entry_point_over_input_and_output() {
+        let runtime = WasmRuntime::new(WasmLimits { fuel: 100_000, ..WasmLimits::default() });
+        let (wasm, hash) = module(ECHO);
+        assert!(runtime.validate(&wasm, "verify").is_ok());
+        let exports = runtime.check_module(&wasm).unwrap();
+        assert_eq!(exports.entry_points, vec!["spin".to_string(), "verify".to_string()]);
+        assert!(exports.scorers.is_empty());
+
+        assert_eq!(runtime.run(&wasm, &hash, "verify", b"same", b"same"), Ok(true));
+        assert_eq!(runtime.run(&wasm, &hash, "verify", b"same", b"diff"), Ok(false));
+        assert_eq!(runtime.run(&wasm, &hash, "spin", b"", b""), Err(WasmError::OutOfFuel));
+        assert_eq!(
+            runtime.run(&wasm, &hash, "missing", b"", b""),
+            Err(WasmError::MissingExport("missing".to_string()))
+        );
+        assert!(matches!(
+            runtime.run(&wasm, &Hash::ZERO, "verify", b"", b""),
+            Err(WasmError::HashMismatch { .. })
+        ));
+
+        // An argument larger than memory may grow to
+        let small = WasmRuntime::new(WasmLimits { max_memory_bytes: 65_536, ..WasmLimits::default() });
+        let big = vec![0u8; 70_000];
+        assert_eq!(small.run(&wasm, &hash, "verify", &big, &big), Err(WasmError::MemoryLimit));
+    }
+
+    #[test]
+    fn test_executes_program_and_reads_its_output() {
+        let runtime = WasmRuntime::new(WasmLimits { fuel: 100_000, ..WasmLimits::default() });
+        // `echo` returns its input in place; `wild` points past the end of memory
+        let (program, _) = module(
+            r#"(module
+                 (memory (export "memory") 1)
+                 (func (export "alloc") (param i32) (result i32) (i32.const 16))
+                 (func (export "echo") (param $ptr i32) (param $len i32) (result i64)
+                   (i64.or (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                           (i64.extend_i32_u (local.get $len))))
+                 (func (export "wild") (param i32 i32) (result i64)
+                   (i64.const 0x0001_0000_0000_0001)))"#,
+        );
+        assert!(runtime.check_program(&program, "echo").is_ok());
+        assert_eq!(runtime.execute(&program, "echo", b"hello"), Ok(b"hello".to_vec()));
+        assert_eq!(runtime.execute(&program, "wild", b""), Err(WasmError::OutputOutOfBounds));
+        assert_eq!(
+            runtime.check_program(&program, "missing"),
+            Err(WasmError::MissingExport("missing".to_string()))
+        );
+        assert_eq!(runtime.check_program(&program, "alloc"), Err(WasmError::BadSignature("alloc".to_string())));
+    }
+
+    #[test]
+    fn test_rejects_nondeterministic_modules() {
+        let runtime = WasmRuntime::default();
+
+        let (clock, _) = module(
+            r#"(module
+                 (import "env" "now" (func (result i64)))
+                 (memory (export "memory") 1)
+                 (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                 (func (export "verify") (param i32 i32 i32 i32) (result i32) (i32.const 1)))"#,
+        );
+        assert!(matches!(runtime.validate(&clock, "verify"), Err(WasmError::ForbiddenImport { .. })));
+
+        let (floats, _) = module(
+            r#"(module
+                 (memory (export "memory") 1)
+                 (func (export "alloc") (param i32) (result i32) (i32.const 0))
+                 (func (export "verify") (param i32 i32 i32 i32) (result i32)
+                   (i32.trunc_f64_s (f64.const 1.0))))"#,
+        );
+        assert!(matches!(runtime.validate(&floats, "verify"), Err(WasmError::InvalidModule(_))));
+    }
+}